@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+use actix_web::rt::System;
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::{web, App, HttpResponse};
+use beeline::bench;
+use beeline_actix_web::BeelineMiddleware;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// `bench_request` compares requests served without and with the middleware, the
+/// difference being its overhead per request
+fn bench_request(c: &mut Criterion) {
+    let mut group = c.benchmark_group("actix");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("bare", |b| {
+        b.iter_custom(|iters| {
+            System::new("bench").block_on(async move {
+                let mut app = init_service(
+                    App::new().service(web::resource("/users/{id}").to(HttpResponse::Ok)),
+                )
+                .await;
+                let start = Instant::now();
+                for _ in 0..iters {
+                    call_service(&mut app, TestRequest::with_uri("/users/42").to_request()).await;
+                }
+                start.elapsed()
+            })
+        });
+    });
+    group.bench_function("instrumented", |b| {
+        let client = bench::client();
+        b.iter_custom(|iters| {
+            bench::reset(&client);
+            let middleware = BeelineMiddleware::new(client.clone());
+            System::new("bench").block_on(async move {
+                let mut app = init_service(
+                    App::new()
+                        .wrap(middleware)
+                        .service(web::resource("/users/{id}").to(HttpResponse::Ok)),
+                )
+                .await;
+                let start = Instant::now();
+                for _ in 0..iters {
+                    call_service(&mut app, TestRequest::with_uri("/users/42").to_request()).await;
+                }
+                start.elapsed()
+            })
+        });
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(5));
+    targets = bench_request
+}
+criterion_main!(benches);
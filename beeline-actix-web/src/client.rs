@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use actix_web::rt::time::delay_for;
+use awc::{ClientRequest, SendClientRequest};
+use beeline::semconv::http_client;
+use beeline::trace::SafeSpan;
+use beeline::{keys, Client, Sender};
+use futures::Future;
+use serde_json::json;
+
+use crate::{RequestSpan, TRACE_HEADER};
+
+impl<T: Sender> RequestSpan<T> {
+    /// `send_request` sends `request`, made with `awc`, in a child span of the request of
+    /// type `http_client` named after its method. The request carries the trace header, so
+    /// the downstream service joins the trace, unless `Config::propagation_hosts` keeps it
+    /// from the host of the request. The span gets `peer.service` when
+    /// `Config::peer_services` knows the host, and the status of the response, or `error`
+    /// when the request fails. It is sent once the response headers arrive.
+    pub async fn send_request(
+        &self,
+        request: ClientRequest,
+    ) -> <SendClientRequest as Future>::Output {
+        let mut client = Client(self.client.0.clone());
+        let span = call_span(&mut client, &self.span, &request);
+        let request = with_trace_header(&client, request, span.as_ref());
+
+        let result = request.send().await;
+        if let Some(span) = span {
+            record_result(&span, &result);
+            span.lock().send(&mut client);
+        }
+        result
+    }
+
+    /// `send_request_with_retries` sends the request `make_request` returns like
+    /// `send_request`, up to `max_attempts` times while it fails or gets a response with a
+    /// status of 500 or more, waiting 100ms before the second attempt and twice as long
+    /// before each next one. Every attempt is a child of the span of the call, with
+    /// `retry.attempt` and `retry.reason`, and carries a trace header of its own. Only
+    /// retry requests that are safe to send more than once.
+    pub async fn send_request_with_retries<F>(
+        &self,
+        make_request: F,
+        max_attempts: u32,
+    ) -> <SendClientRequest as Future>::Output
+    where
+        F: Fn() -> ClientRequest,
+    {
+        let mut client = Client(self.client.0.clone());
+        let request = make_request();
+        let call = call_span(&mut client, &self.span, &request);
+
+        let mut request = Some(request);
+        let mut reason = None;
+        let mut backoff = Duration::from_millis(100);
+        let mut attempt = 1;
+        let result = loop {
+            let request = request.take().unwrap_or_else(&make_request);
+            let span = call.as_ref().and_then(|call| {
+                http_client::attempt_span(&mut client, call, attempt, reason.as_deref())
+            });
+            let result = with_trace_header(&client, request, span.as_ref())
+                .send()
+                .await;
+            if let Some(span) = span {
+                record_result(&span, &result);
+                span.lock().send(&mut client);
+            }
+            reason = match &result {
+                Ok(response) if response.status().is_server_error() => {
+                    Some(format!("status {}", response.status().as_u16()))
+                }
+                Ok(_) => None,
+                Err(e) => Some(e.to_string()),
+            };
+            if reason.is_none() || attempt >= max_attempts {
+                break result;
+            }
+            delay_for(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        };
+        if let Some(call) = call {
+            record_result(&call, &result);
+            call.lock().send(&mut client);
+        }
+        result
+    }
+}
+
+/// `call_span` creates the `http_client` span of `request`, a child of `parent`, with the
+/// `peer.service` `Config::peer_services` gives its host
+fn call_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    request: &ClientRequest,
+) -> Option<SafeSpan> {
+    let peer_service = request
+        .get_uri()
+        .host()
+        .and_then(|host| client.peer_service(host));
+    http_client::http_client_span(
+        client,
+        parent,
+        request.get_method().as_str(),
+        &request.get_uri().to_string(),
+        peer_service.as_deref(),
+    )
+}
+
+/// `with_trace_header` adds to `request` the trace header with `span` as the parent, unless
+/// `Config::propagation_hosts` keeps it from the host of the request
+fn with_trace_header<T: Sender>(
+    client: &Client<T>,
+    request: ClientRequest,
+    span: Option<&SafeSpan>,
+) -> ClientRequest {
+    let host = request.get_uri().host().unwrap_or_default();
+    let headers = match span {
+        Some(span) if client.propagates_to(host) => span.lock().serialize_headers(),
+        _ => return request,
+    };
+    if headers.is_empty() {
+        request
+    } else {
+        request.set_header(TRACE_HEADER, headers)
+    }
+}
+
+/// `record_result` adds the status of the response, or the error, to `span`
+fn record_result(span: &SafeSpan, result: &<SendClientRequest as Future>::Output) {
+    match result {
+        Ok(response) => http_client::record_response(span, response.status().as_u16()),
+        Err(e) => span.lock().add_field(keys::ERROR, json!(e.to_string())),
+    }
+}
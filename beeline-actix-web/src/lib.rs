@@ -1,13 +1,51 @@
 /*! Honeycomb support for actix-web.
 
+Requests carrying a `X-Honeycomb-Trace` header join the trace of the upstream service. When
+the header can't be parsed, the request starts a trace of its own, and the reason is
+recorded as `meta.propagation_error`.
+
 By default, the following fields are added to the trace:
  - `meta.type` (always "http_request")
+ - `meta.instrumentation` and `meta.instrumentation_version` (always "beeline-actix-web" and
+   the version of this crate)
  - `request.method`
  - `request.path`
+ - `request.http_version` (e.g. `HTTP/1.1` or `HTTP/2.0`)
+ - `request.route` (the pattern of the resource the request was routed to, e.g. `/users/{id}`)
+ - `name` (the name of that resource, as set with `web::resource(...).name(...)`, or else
+   its pattern)
  - `request.header.<name>` (name is the same as the original header name but with dashes replaced with underscores)
    - example: `request.header.content_type`
+   - the `Cookie` header is left out when a cookie recorder is set
+ - `tls.version` and `tls.cipher`, when the connection is described by a `TlsInfo`
  - `response.status`
  - `response.body.size`
+ - `response.body.compressed` (only when the body was compressed before it reached the
+   middleware, see [the ordering](#ordering-with-other-middlewares))
+ - `request.cancelled` (only when the client went away before the response was fully
+   sent, `duration_ms` being the time until then)
+ - `request.deadline_ms` and `meta.deadline_exceeded`, when the request carries a
+   `X-Request-Timeout` or `grpc-timeout` header, see `beeline::deadline`
+
+Responses with a status of 400 or more can carry a short description of the error in
+`response.error.summary`, see `BeelineMiddleware::with_error_body_summary` and
+`BeelineMiddleware::with_error_code_header`.
+
+What counts as a failed request is up to you: with a classifier set through
+`BeelineMiddleware::with_response_classifier`, the responses it fails (e.g. a GraphQL
+response carrying errors with a status of 200) get `error` and `span.status` set, and
+`app.error`.
+
+With a user extractor set through `BeelineMiddleware::with_user_extractor`, the caller of
+the request is added as well:
+ - `user.id`
+ - `user.tenant`
+ - `auth.scheme`
+
+Traces can be sampled heavily and still give cheap SLO metrics: with a `RouteLatency` set
+through `BeelineMiddleware::with_route_latency`, every request is rolled up in a
+`route_latency` event per route and interval, with latency percentiles and counts by status
+class.
 
 # Usage
 
@@ -45,72 +83,377 @@ fn main() -> std::io::Result<()> {
 }
 ```
 
+To find out who made each request, pass a function reading the request headers:
+
+```rust
+use actix_web::http::{header, HeaderMap};
+use beeline::{init, Config, UserInfo};
+use beeline_actix_web::BeelineMiddleware;
+
+fn user(headers: &HeaderMap) -> Option<UserInfo> {
+    let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    Some(UserInfo::from_authorization(authorization, "sub", "org_id"))
+}
+
+# if false {
+let client = init(Config::default());
+let beeline = BeelineMiddleware::new(client).with_user_extractor(user);
+# }
+```
+
+actix-web doesn't tell the middleware about TLS connections. Describe them with a
+`TlsInfo` added to the connection extensions in `HttpServer::on_connect`, where the TLS
+stream (e.g. the rustls session) is at hand:
+
+```rust
+use actix_web::{web, App, HttpResponse, HttpServer};
+use beeline::{init, Config};
+use beeline_actix_web::{BeelineMiddleware, TlsInfo};
+
+# if false {
+let beeline = BeelineMiddleware::new(init(Config::default()));
+HttpServer::new(move || {
+    App::new()
+        .wrap(beeline.clone())
+        .service(web::resource("/health").to(HttpResponse::Ok))
+})
+.on_connect(|connection, extensions| {
+    // downcast `connection` to the TLS stream and read these from its session
+    extensions.insert(TlsInfo {
+        version: "TLSv1.3".to_string(),
+        cipher: "TLS13_AES_128_GCM_SHA256".to_string(),
+    });
+});
+# }
+```
+
+Cookies are only recorded through an allowlist, without their values:
+
+```rust
+use beeline::{init, Config, CookieMode, CookieRecorder};
+use beeline_actix_web::BeelineMiddleware;
+
+# if false {
+let cookies = CookieRecorder::new()
+    .with_cookie("session_id", CookieMode::Hash)
+    .with_cookie("beta_opt_in", CookieMode::Presence);
+let beeline = BeelineMiddleware::new(init(Config::default())).with_cookie_recorder(cookies);
+# }
+```
+
+Handlers can time parts of their work by taking the span of the request and creating
+children of it. `RequestSpan::child` returns a guard sending the child when dropped:
+
+```rust
+use actix_web::HttpResponse;
+use beeline::Transmission;
+use beeline_actix_web::RequestSpan;
+use serde_json::json;
+
+async fn create_user(span: RequestSpan<Transmission>) -> HttpResponse {
+    {
+        let validation = span.child("validate_input");
+        validation.add_field("app.fields", json!(3));
+        // ...
+    }
+    HttpResponse::Created().finish()
+}
+```
+
+Calls to downstream services made with `awc`, actix's HTTP client, join the trace when sent
+through `RequestSpan::send_request`, which times them in a child span and adds the trace
+header:
+
+```rust
+use actix_web::{client::Client as HttpClient, HttpResponse};
+use beeline::Transmission;
+use beeline_actix_web::RequestSpan;
+
+async fn profile(span: RequestSpan<Transmission>) -> HttpResponse {
+    let request = HttpClient::default().get("http://users.internal/users/42");
+    match span.send_request(request).await {
+        Ok(response) => HttpResponse::build(response.status()).finish(),
+        Err(_) => HttpResponse::BadGateway().finish(),
+    }
+}
+```
+
+`RequestSpan::send_request_with_retries` retries failed calls, with a child span per
+attempt recording `retry.attempt` and `retry.reason`.
+
+# Ordering with other middlewares
+
+actix-web runs the middleware registered last first, so register `BeelineMiddleware` with
+the first `.wrap()` call, before `NormalizePath`, `Compress` or `Cors`:
+
+```rust
+use actix_web::middleware::{Compress, NormalizePath};
+use actix_web::{web, App, HttpResponse};
+use beeline::{init, Config};
+use beeline_actix_web::BeelineMiddleware;
+
+# if false {
+let beeline = BeelineMiddleware::new(init(Config::default()));
+let app = App::new()
+    .wrap(beeline)
+    .wrap(NormalizePath::default())
+    .wrap(Compress::default())
+    .service(web::resource("/health").to(HttpResponse::Ok));
+# }
+```
+
+This way:
+ - `request.path` and `request.route` are those of the normalized path. `request.path` is
+   read once the response is ready, so it is normalized in any order.
+ - `response.body.size` is the size of the body before compression. When the middleware
+   wraps `Compress` instead, the size is that of the compressed body, and
+   `response.body.compressed` is set to tell them apart.
+ - requests answered by an outer middleware without calling the app, such as CORS
+   preflight requests, aren't traced.
+
+Events are sent in batches in the background, so the ones from the last seconds before the
+server stops would be lost. Take a shutdown handle before starting the server and shut it
+down once the server has stopped:
+
+```rust
+use actix_web::{web, App, HttpResponse, HttpServer};
+use beeline::{init, Config};
+use beeline_actix_web::BeelineMiddleware;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    # if false {
+    let beeline = BeelineMiddleware::new(init(Config::default()));
+    let shutdown = beeline.shutdown_handle();
+    HttpServer::new(move || {
+        App::new()
+            .wrap(beeline.clone())
+            .service(web::resource("/health").to(HttpResponse::Ok))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await?;
+    shutdown.shutdown().expect("unable to flush events");
+    # }
+    Ok(())
+}
+```
+
+The instrumentation of an application can be unit-tested with the helpers of the `test`
+module, which capture the events in memory instead of sending them to Honeycomb. It is
+only available with the `testutil` feature, meant for `dev-dependencies`.
+
  */
 
 #![deny(missing_docs)]
 
+mod client;
+#[cfg(any(test, feature = "testutil"))]
+pub mod test;
+
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use actix_service::{Service, Transform};
 use actix_web::{
-    dev::{BodySize, MessageBody, ResponseBody, ServiceRequest, ServiceResponse},
-    http::{Method, StatusCode},
+    dev::{BodySize, MessageBody, Payload, ResponseBody, ServiceRequest, ServiceResponse},
+    error::ErrorInternalServerError,
+    http::{
+        header::{CONTENT_ENCODING, COOKIE},
+        HeaderMap, HeaderName, Method, StatusCode,
+    },
     web::Bytes,
-    Error,
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use beeline::trace::SafeSpan;
+use beeline::{
+    keys, route_latency, BeelineError, Client, CookieRecorder, Instrumentation, ResponseClassifier,
+    RouteLatency, SafeTrace, Sender, SpanGuard, UserExtractor, UserInfo,
 };
-use beeline::{Client, SafeTrace, Sender};
 use futures::{
-    future::{ok, Ready},
+    future::{err, ok, Ready},
     task::{Context, Poll},
     Future,
 };
 use pin_project::{pin_project, pinned_drop};
 use serde_json::json;
 
+/// Stamped on the spans created by the middleware
+const INSTRUMENTATION: Instrumentation = beeline::instrumentation!();
+
+/// header carrying the trace context, read from incoming requests and added to the ones
+/// sent to downstream services
+const TRACE_HEADER: &str = "X-Honeycomb-Trace";
+
 #[derive(Debug, Clone)]
 #[must_use = "must be set up as middleware for actix-web"]
-/// By default XXX: talk about the trace that gets sent
+/// `BeelineMiddleware` starts a trace for every request, whose root span is sent once the
+/// response has been sent
 pub struct BeelineMiddleware<T>
 where
     T: Sender + Clone,
 {
     client: Client<T>,
-    trace: SafeTrace,
+    user_extractor: Option<UserExtractor<HeaderMap>>,
+    response_classifier: Option<ResponseClassifier<HeaderMap>>,
+    error_body_limit: Option<usize>,
+    error_code_header: Option<HeaderName>,
+    cookie_recorder: Option<CookieRecorder>,
+    route_latency: Option<RouteLatency<T>>,
 }
 
 impl<T: Sender + Clone> BeelineMiddleware<T> {
     /// Build with already started client
     pub fn new(client: Client<T>) -> Self {
-        let trace = client.new_trace(None);
-        Self { client, trace }
+        Self {
+            client,
+            user_extractor: None,
+            response_classifier: None,
+            error_body_limit: None,
+            error_code_header: None,
+            cookie_recorder: None,
+            route_latency: None,
+        }
+    }
+
+    /// `with_user_extractor` sets the function called with the headers of every request
+    /// to find out who made it
+    pub fn with_user_extractor<F>(mut self, extractor: F) -> Self
+    where
+        F: Fn(&HeaderMap) -> Option<UserInfo> + Send + Sync + 'static,
+    {
+        self.user_extractor = Some(UserExtractor::new(extractor));
+        self
+    }
+
+    /// `with_response_classifier` sets the function called with the status and headers of
+    /// every response to find out whether the request failed, returning the reason it did
+    pub fn with_response_classifier<F>(mut self, classifier: F) -> Self
+    where
+        F: Fn(u16, &HeaderMap) -> Option<String> + Send + Sync + 'static,
+    {
+        self.response_classifier = Some(ResponseClassifier::new(classifier));
+        self
+    }
+
+    /// `with_error_body_summary` records the first `max_bytes` of the body of responses
+    /// with a status of 400 or more as `response.error.summary`. Keep it small: the body
+    /// may hold anything the handler put there.
+    pub fn with_error_body_summary(mut self, max_bytes: usize) -> Self {
+        self.error_body_limit = Some(max_bytes);
+        self
+    }
+
+    /// `with_error_code_header` records the value of the response header `name`, e.g. an
+    /// application error code, as `response.error.summary` of responses with a status of
+    /// 400 or more. When set, it takes precedence over the body summary.
+    pub fn with_error_code_header(mut self, name: HeaderName) -> Self {
+        self.error_code_header = Some(name);
+        self
     }
 
-    fn send(
+    /// `with_cookie_recorder` records the cookies allowlisted by `recorder` as
+    /// `request.cookie.<name>`, instead of the raw `Cookie` header
+    pub fn with_cookie_recorder(mut self, recorder: CookieRecorder) -> Self {
+        self.cookie_recorder = Some(recorder);
+        self
+    }
+
+    /// `with_route_latency` records the latency and status of every request, sampled or
+    /// not, in `latency`, which emits a rollup per route and interval. Requests that
+    /// didn't match any route are rolled up under `<unmatched>`.
+    pub fn with_route_latency(mut self, latency: RouteLatency<T>) -> Self {
+        self.route_latency = Some(latency);
+        self
+    }
+
+    /// `shutdown_handle` returns a handle that flushes all outstanding traces and closes
+    /// the client when the HTTP server stops
+    pub fn shutdown_handle(&self) -> ShutdownHandle<T> {
+        ShutdownHandle {
+            client: self.client.clone(),
+        }
+    }
+
+    /// `send_unanswered` sends the span of the request traced by `trace` whose handler
+    /// failed with `error`, or was dropped before it returned a response
+    fn send_unanswered(
         &self,
-        path: &str,
+        trace: &SafeTrace,
         method: &Method,
-        status: StatusCode,
+        path: &str,
         clock: SystemTime,
-        size: usize,
+        deadline: Option<Duration>,
+        error: Option<&Error>,
     ) {
-        let trace = self.trace.clone();
+        let rs = trace.lock().get_root_span();
+        let mut guard = rs.lock();
+        guard.add_field(keys::META_TYPE, json!("http_request"));
+        INSTRUMENTATION.add_to(&mut guard);
+        guard.add_field(keys::REQUEST_METHOD, json!(method.to_string()));
+        guard.add_field(keys::REQUEST_PATH, json!(path));
+        if let Ok(elapsed) = clock.elapsed() {
+            guard.add_field(keys::DURATION_MS, json!(duration_ms(elapsed)));
+            if let Some(deadline) = deadline {
+                beeline::deadline::record(&mut guard, deadline, elapsed);
+            }
+        }
+        match error {
+            Some(error) => guard.add_field(keys::ERROR, json!(error.to_string())),
+            None => guard.add_field(keys::REQUEST_CANCELLED, json!(true)),
+        }
+        let mut span_client = self.client.clone();
+        guard.send(&mut span_client)
+    }
+
+    fn send<B>(&self, log: &StreamLog<B, T>) {
+        let trace = &log.trace;
+        // the trace of this request is the exemplar of the latency of its route
+        let exemplar = {
+            let trace = trace.lock();
+            if trace.is_sampled_out() {
+                None
+            } else {
+                Some(trace.trace_id.clone())
+            }
+        };
         let rs = trace.lock().get_root_span();
         {
             let mut guard = rs.lock();
             {
-                guard.add_field("meta.type", json!("http_request"));
-                guard.add_field("request.method", json!(method.to_string()));
-                guard.add_field("request.path", json!(path));
-                if let Ok(elapsed) = clock.elapsed() {
-                    let duration = (elapsed.as_secs() as f64)
-                        + f64::from(elapsed.subsec_nanos()) / 1_000_000_000_f64;
-                    guard.add_field("duration_ms", json!(duration));
+                guard.add_field(keys::META_TYPE, json!("http_request"));
+                INSTRUMENTATION.add_to(&mut guard);
+                guard.add_field(keys::REQUEST_METHOD, json!(log.method.to_string()));
+                guard.add_field(keys::REQUEST_PATH, json!(log.path));
+                if let Ok(elapsed) = log.clock.elapsed() {
+                    guard.add_field(keys::DURATION_MS, json!(duration_ms(elapsed)));
+                    if let Some(deadline) = log.deadline {
+                        beeline::deadline::record(&mut guard, deadline, elapsed);
+                    }
+                    if let Some(latency) = &self.route_latency {
+                        latency.record(
+                            log.route
+                                .as_deref()
+                                .unwrap_or(route_latency::UNMATCHED_ROUTE),
+                            log.status.as_u16(),
+                            elapsed,
+                            exemplar.as_deref(),
+                        );
+                    }
+                }
+                guard.add_field("response.status", json!(log.status.as_u16()));
+                guard.add_field(keys::RESPONSE_BODY_SIZE, json!(log.size));
+                if log.compressed {
+                    guard.add_field("response.body.compressed", json!(true));
+                }
+                if let Some(summary) = log.error_summary() {
+                    guard.add_field(keys::RESPONSE_ERROR_SUMMARY, json!(summary));
+                }
+                if !log.complete {
+                    guard.add_field(keys::REQUEST_CANCELLED, json!(true));
                 }
-                guard.add_field("response.status", json!(status.as_u16()));
-                guard.add_field("response.body.size", json!(size));
             }
             let mut span_client = self.client.clone();
             guard.send(&mut span_client)
@@ -118,12 +461,100 @@ impl<T: Sender + Clone> BeelineMiddleware<T> {
     }
 }
 
+/// `duration_ms` returns the value of `duration_ms` for a request that took `elapsed`
+fn duration_ms(elapsed: Duration) -> f64 {
+    (elapsed.as_secs() as f64) + f64::from(elapsed.subsec_nanos()) / 1_000_000_000_f64
+}
+
+/// `request_deadline` returns the deadline set by the caller of a request, if any
+fn request_deadline(headers: &HeaderMap) -> Option<Duration> {
+    beeline::deadline::HEADERS.iter().find_map(|name| {
+        let value = headers.get(*name)?.to_str().ok()?;
+        beeline::deadline::from_header(name, value)
+    })
+}
+
+/// `ShutdownHandle` closes the client of a `BeelineMiddleware` once the server is done
+#[derive(Debug)]
+#[must_use = "events still queued are lost unless `shutdown` is called"]
+pub struct ShutdownHandle<T: Sender> {
+    client: Client<T>,
+}
+
+impl<T: Sender> ShutdownHandle<T> {
+    /// `shutdown` sends the traces still open and blocks until every event has been
+    /// transmitted. Events sent afterwards are dropped.
+    pub fn shutdown(self) -> Result<(), BeelineError> {
+        self.client.close()
+    }
+}
+
+/// `TlsInfo` describes the TLS connection a request came over. Add it to the connection
+/// extensions in `HttpServer::on_connect` to record it on the requests' spans.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsInfo {
+    /// TLS version, e.g. "TLSv1.3"
+    pub version: String,
+    /// negotiated cipher suite, e.g. "TLS13_AES_128_GCM_SHA256"
+    pub cipher: String,
+}
+
+/// `RequestSpan` is the span of the request being handled, extracted from the request by
+/// handlers behind a `BeelineMiddleware`
+#[derive(Debug)]
+pub struct RequestSpan<T: Sender> {
+    client: Client<T>,
+    span: SafeSpan,
+}
+
+impl<T: Sender> Clone for RequestSpan<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: Client(self.client.0.clone()),
+            span: self.span.clone(),
+        }
+    }
+}
+
+impl<T: Sender> RequestSpan<T> {
+    /// `span` returns the span of the request
+    pub fn span(&self) -> &SafeSpan {
+        &self.span
+    }
+
+    /// `add_field` adds a field to the span of the request
+    pub fn add_field(&self, key: &str, value: serde_json::Value) {
+        self.span.lock().add_field(key, value);
+    }
+
+    /// `child` creates a child of the span of the request named `name`. It is timed and
+    /// sent when the returned guard is dropped.
+    pub fn child(&self, name: &str) -> SpanGuard<T> {
+        SpanGuard::child(&self.client, &self.span, name)
+    }
+}
+
+impl<T: Sender + 'static> FromRequest for RequestSpan<T> {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Self>() {
+            Some(span) => ok(span.clone()),
+            None => err(ErrorInternalServerError(
+                "RequestSpan requires the BeelineMiddleware",
+            )),
+        }
+    }
+}
+
 impl<S, B, T> Transform<S> for BeelineMiddleware<T>
 where
     B: MessageBody,
     S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
-    T: Sender + Clone,
+    T: Sender + Clone + 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<StreamLog<B, T>>;
@@ -151,7 +582,7 @@ impl<T, S, B> Service for BeelineService<S, T>
 where
     S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     B: MessageBody,
-    T: Sender + Clone,
+    T: Sender + Clone + 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<StreamLog<B, T>>;
@@ -163,17 +594,43 @@ where
     }
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let header = req
+            .headers()
+            .get(TRACE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let (trace, propagation_error) = match self.inner.client.try_new_trace(header) {
+            Ok(trace) => (trace, None),
+            Err(e) => (self.inner.client.new_trace(None), Some(e.to_string())),
+        };
+        let span = trace.lock().get_root_span();
+        if let Some(error) = propagation_error {
+            span.lock()
+                .add_field(keys::META_PROPAGATION_ERROR, json!(error));
+        }
+        req.extensions_mut().insert(RequestSpan {
+            client: self.inner.client.clone(),
+            span,
+        });
+        let method = req.method().clone();
+        let path = req.path().to_string();
+        let deadline = request_deadline(req.headers());
         BeelineServiceResponse {
             fut: self.service.call(req),
             clock: SystemTime::now(),
             inner: self.inner.clone(),
+            trace,
+            method,
+            path,
+            deadline,
+            done: false,
             _t: PhantomData,
         }
     }
 }
 
 #[doc(hidden)]
-#[pin_project::pin_project]
+#[pin_project(PinnedDrop)]
 pub struct BeelineServiceResponse<S, B, T>
 where
     B: MessageBody,
@@ -184,9 +641,38 @@ where
     fut: S::Future,
     clock: SystemTime,
     inner: Arc<BeelineMiddleware<T>>,
+    // the trace of the request, whose root span is the span of the request
+    trace: SafeTrace,
+    // the method and path of the request, for when the handler is dropped before
+    // returning a response, e.g. because the client went away
+    method: Method,
+    path: String,
+    deadline: Option<Duration>,
+    done: bool,
     _t: PhantomData<(B,)>,
 }
 
+#[pinned_drop]
+impl<S, B, T> PinnedDrop for BeelineServiceResponse<S, B, T>
+where
+    B: MessageBody,
+    S: Service,
+    T: Sender + Clone,
+{
+    fn drop(self: Pin<&mut Self>) {
+        if !self.done {
+            self.inner.send_unanswered(
+                &self.trace,
+                &self.method,
+                &self.path,
+                self.clock,
+                self.deadline,
+                None,
+            );
+        }
+    }
+}
+
 impl<S, B, T> Future for BeelineServiceResponse<S, B, T>
 where
     B: MessageBody,
@@ -198,9 +684,21 @@ where
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
 
-        let res = match futures::ready!(this.fut.poll(cx)) {
+        let res = futures::ready!(this.fut.poll(cx));
+        *this.done = true;
+        let res = match res {
             Ok(res) => res,
-            Err(e) => return Poll::Ready(Err(e)),
+            Err(e) => {
+                this.inner.send_unanswered(
+                    this.trace,
+                    this.method,
+                    this.path,
+                    *this.clock,
+                    *this.deadline,
+                    Some(&e),
+                );
+                return Poll::Ready(Err(e));
+            }
         };
 
         let req = res.request();
@@ -208,35 +706,97 @@ where
         let method = req.method().clone();
         let path = req.path().to_string();
         let headers = req.headers();
+        // the router has resolved the handler by now, so the span can be named after it
+        let route = req.match_pattern();
+        let name = req.match_name().map(String::from).or_else(|| route.clone());
+        let http_version = format!("{:?}", req.version());
+        let tls = req.extensions().get::<TlsInfo>().cloned();
         let time = *this.clock;
-        let trace = inner.trace.clone();
+        let deadline = *this.deadline;
+        let trace = this.trace.clone();
         let rs = trace.lock().get_root_span();
         {
             let mut guard = rs.lock();
             {
+                if let Some(route) = &route {
+                    guard.add_field(keys::REQUEST_ROUTE, json!(route));
+                }
+                guard.add_field(keys::REQUEST_HTTP_VERSION, json!(http_version));
+                if let Some(tls) = tls {
+                    guard.add_field(keys::TLS_VERSION, json!(tls.version));
+                    guard.add_field(keys::TLS_CIPHER, json!(tls.cipher));
+                }
+                if let Some(name) = name {
+                    guard.add_field(keys::NAME, json!(name));
+                }
                 for (name, value) in headers.iter() {
+                    if name == COOKIE && inner.cookie_recorder.is_some() {
+                        continue;
+                    }
                     guard.add_field(
-                        &format!(
-                            "request.header.{}",
-                            name.as_str().to_lowercase().replace("-", "_")
-                        ),
+                        &keys::header_key(name.as_str()),
                         match value.to_str() {
                             Ok(v) => json!(v),
                             _ => json!("<error converting to str>"),
                         },
                     );
                 }
+                if let Some(recorder) = &inner.cookie_recorder {
+                    for cookies in headers.get_all(COOKIE) {
+                        if let Ok(cookies) = cookies.to_str() {
+                            recorder.record(&mut guard, cookies);
+                        }
+                    }
+                }
+                if let Some(user) = inner
+                    .user_extractor
+                    .as_ref()
+                    .and_then(|extractor| extractor.extract(headers))
+                {
+                    user.add_to(&mut guard);
+                }
+                if let Some(classifier) = &inner.response_classifier {
+                    classifier.record(&mut guard, res.status().as_u16(), res.headers());
+                }
             }
         }
 
         Poll::Ready(Ok(res.map_body(move |head, body| {
+            // an inner `Compress` has already encoded the body
+            let compressed = head
+                .headers()
+                .get(CONTENT_ENCODING)
+                .is_some_and(|encoding| encoding != "identity");
+            let (error_code, error_body) = if head.status.as_u16() >= 400 {
+                let error_code = inner
+                    .error_code_header
+                    .as_ref()
+                    .and_then(|name| head.headers().get(name))
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+                let error_body = match error_code {
+                    None => inner.error_body_limit.map(Vec::with_capacity),
+                    Some(_) => None,
+                };
+                (error_code, error_body)
+            } else {
+                (None, None)
+            };
+            let complete = is_complete(body.size(), 0);
             ResponseBody::Body(StreamLog {
                 body,
                 size: 0,
+                complete,
+                compressed,
+                error_code,
+                error_body,
                 clock: time,
+                deadline,
                 inner,
+                trace: trace.clone(),
                 status: head.status,
                 path: path.clone(),
+                route,
                 method,
             })
         })))
@@ -249,18 +809,51 @@ pub struct StreamLog<B, T: Sender + Clone> {
     #[pin]
     body: ResponseBody<B>,
     size: usize,
+    // whether the body was sent to the end, or failed
+    complete: bool,
+    compressed: bool,
+    error_code: Option<String>,
+    // prefix of the body of an error response, up to its capacity
+    error_body: Option<Vec<u8>>,
     clock: SystemTime,
+    deadline: Option<Duration>,
     inner: Arc<BeelineMiddleware<T>>,
+    trace: SafeTrace,
     status: StatusCode,
     path: String,
+    route: Option<String>,
     method: Method,
 }
 
 #[pinned_drop]
 impl<B, T: Sender + Clone> PinnedDrop for StreamLog<B, T> {
     fn drop(self: Pin<&mut Self>) {
-        self.inner
-            .send(&self.path, &self.method, self.status, self.clock, self.size);
+        self.inner.send(&self);
+    }
+}
+
+impl<B, T: Sender + Clone> StreamLog<B, T> {
+    /// `error_summary` returns the error code header or the prefix of the body of an
+    /// error response, if either was captured
+    fn error_summary(&self) -> Option<String> {
+        let summary = self.error_code.clone().or_else(|| {
+            let prefix = self.error_body.as_ref()?;
+            // the prefix may end in the middle of a character
+            let summary = String::from_utf8_lossy(prefix);
+            Some(summary.trim_end_matches('\u{FFFD}').trim().to_string())
+        });
+        summary.filter(|summary| !summary.is_empty())
+    }
+}
+
+/// `is_complete` returns whether a body of size `size` is sent to the end once `sent`
+/// bytes of it are
+fn is_complete(size: BodySize, sent: usize) -> bool {
+    match size {
+        BodySize::None | BodySize::Empty => true,
+        BodySize::Sized(size) => sent >= size,
+        BodySize::Sized64(size) => sent as u64 >= size,
+        BodySize::Stream => false,
     }
 }
 
@@ -271,22 +864,37 @@ impl<B: MessageBody, T: Sender + Clone> MessageBody for StreamLog<B, T> {
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
         let this = self.project();
-        match MessageBody::poll_next(this.body, cx) {
+        match MessageBody::poll_next(this.body.as_mut(), cx) {
             Poll::Ready(Some(Ok(chunk))) => {
                 *this.size += chunk.len();
+                *this.complete = is_complete(this.body.size(), *this.size);
+                if let Some(prefix) = this.error_body {
+                    let missing = prefix.capacity() - prefix.len();
+                    prefix.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+                }
                 Poll::Ready(Some(Ok(chunk)))
             }
-            val => val,
+            Poll::Ready(val) => {
+                *this.complete = true;
+                Poll::Ready(val)
+            }
+            Poll::Pending => Poll::Pending,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use actix_web::dev::Body;
+    use actix_web::middleware::{normalize::TrailingSlash, Compress, NormalizePath};
     use actix_web::rt as actix_rt;
     use actix_web::test::{call_service, init_service, read_body, TestRequest};
     use actix_web::{web, App, HttpResponse};
     use beeline::{Client, Config};
+    use futures::future::Either;
+    use futures::TryFutureExt;
     use libhoney::mock::TransmissionMock;
 
     use super::*;
@@ -334,6 +942,37 @@ mod tests {
         assert_eq!(events.len(), 1);
     }
 
+    #[actix_rt::test]
+    async fn middleware_propagation() {
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for header in &["1;trace_id=abc,parent_id=def", "1;parent_id=def"] {
+            let req = TestRequest::with_uri("/")
+                .header(TRACE_HEADER, *header)
+                .to_request();
+            drop(call_service(&mut app, req).await);
+        }
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let (joined, malformed) = (events[0].fields(), events[1].fields());
+        assert_eq!(joined[keys::TRACE_TRACE_ID], json!("abc"));
+        assert_eq!(joined[keys::TRACE_PARENT_ID], json!("def"));
+        assert!(!joined.contains_key(keys::META_PROPAGATION_ERROR));
+        // a malformed header starts a fresh trace, telling why
+        assert_ne!(malformed[keys::TRACE_TRACE_ID], json!("abc"));
+        assert!(!malformed.contains_key(keys::TRACE_PARENT_ID));
+        assert!(malformed[keys::META_PROPAGATION_ERROR]
+            .as_str()
+            .unwrap()
+            .contains("missing trace id"));
+    }
+
     #[actix_rt::test]
     async fn middleware_basic_failure() {
         let middleware = BeelineMiddleware::new(new_client());
@@ -351,4 +990,636 @@ mod tests {
         let events = middleware.client.0.write().client.transmission.events();
         assert_eq!(events.len(), 1);
     }
+
+    #[actix_rt::test]
+    async fn request_span_send_request() {
+        let client = new_client();
+        let trace = client.new_trace(None);
+        let span = RequestSpan {
+            client: client.clone(),
+            span: trace.lock().get_root_span(),
+        };
+        let server = actix_web::test::start(|| {
+            App::new().service(web::resource("/users/{id}").to(|request: HttpRequest| {
+                let traced = request.headers().contains_key("X-Honeycomb-Trace");
+                HttpResponse::Ok().json(traced)
+            }))
+        });
+
+        let mut response = span.send_request(server.get("/users/42")).await.unwrap();
+        assert!(response.json::<bool>().await.unwrap());
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        let fields = events[0].fields();
+        assert_eq!(fields[keys::META_TYPE], json!("http_client"));
+        assert_eq!(fields["request.method"], json!("GET"));
+        assert_eq!(fields["response.status_code"], json!(200));
+        assert_eq!(fields["trace.trace_id"], json!(trace.lock().trace_id));
+    }
+
+    #[actix_rt::test]
+    async fn request_span_send_request_with_retries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let client = new_client();
+        let trace = client.new_trace(None);
+        let span = RequestSpan {
+            client: client.clone(),
+            span: trace.lock().get_root_span(),
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server_calls = calls.clone();
+        let server = actix_web::test::start(move || {
+            let calls = server_calls.clone();
+            App::new().service(web::resource("/flaky").to(move || {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    HttpResponse::ServiceUnavailable().finish()
+                } else {
+                    HttpResponse::Ok().finish()
+                }
+            }))
+        });
+
+        let response = span
+            .send_request_with_retries(|| server.get("/flaky"), 3)
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        let (first, second, call) = (events[0].fields(), events[1].fields(), events[2].fields());
+        assert_eq!(first["retry.attempt"], json!(1));
+        assert_eq!(first["response.status_code"], json!(503));
+        assert_eq!(second["retry.attempt"], json!(2));
+        assert_eq!(second["retry.reason"], json!("status 503"));
+        assert_eq!(second["trace.parent_id"], call["trace.span_id"]);
+        assert_eq!(call[keys::META_TYPE], json!("http_client"));
+        assert_eq!(call["response.status_code"], json!(200));
+    }
+
+    #[actix_rt::test]
+    async fn test_helpers() {
+        let middleware = BeelineMiddleware::new(crate::test::client(Config::default()));
+        let mut app = init_service(crate::test::app(&middleware, |config| {
+            config.service(web::resource("/users/{id}").to(HttpResponse::Ok));
+        }))
+        .await;
+
+        {
+            let res = call_service(&mut app, TestRequest::with_uri("/users/42").to_request()).await;
+            assert!(res.status().is_success());
+        }
+        let events = crate::test::events(&middleware);
+        assert_eq!(events.len(), 1);
+        let request =
+            crate::test::find_event(&events, keys::REQUEST_ROUTE, &json!("/users/{id}")).unwrap();
+        crate::test::assert_field(request, "response.status", &json!(200));
+        crate::test::assert_field(request, keys::REQUEST_PATH, &json!("/users/42"));
+        assert!(crate::test::find_event(&events, keys::REQUEST_ROUTE, &json!("/")).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_route_latency() {
+        let client = new_client();
+        let latency = client.start_route_latency(Duration::from_secs(3600));
+        let middleware = BeelineMiddleware::new(client).with_route_latency(latency.clone());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/users/{id}").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for uri in &["/users/42", "/users/43", "/missing"] {
+            let res = call_service(&mut app, TestRequest::with_uri(uri).to_request()).await;
+            read_body(res).await;
+        }
+        latency.emit();
+
+        let events = middleware.client.0.write().client.transmission.events();
+        let rollups: Vec<_> = events
+            .iter()
+            .map(|event| event.fields())
+            .filter(|fields| fields[keys::META_TYPE] == json!(route_latency::EVENT_TYPE))
+            .collect();
+        assert_eq!(rollups.len(), 2);
+        let users = rollups
+            .iter()
+            .find(|fields| fields[keys::REQUEST_ROUTE] == json!("/users/{id}"))
+            .unwrap();
+        assert_eq!(users["count"], json!(2));
+        assert_eq!(users["response.status_class.2xx"], json!(2));
+        // the exemplars are the traces of the requests themselves
+        let mut exemplars: Vec<_> = users["exemplar.trace_ids"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| id.as_str().unwrap().to_string())
+            .collect();
+        let mut requests: Vec<_> = events
+            .iter()
+            .map(|event| event.fields())
+            .filter(|fields| fields.get(keys::REQUEST_ROUTE) == Some(&json!("/users/{id}")))
+            .filter(|fields| fields[keys::META_TYPE] == json!("http_request"))
+            .map(|fields| fields[keys::TRACE_TRACE_ID].as_str().unwrap().to_string())
+            .collect();
+        exemplars.sort();
+        requests.sort();
+        assert_eq!(requests.len(), 2);
+        assert_ne!(requests[0], requests[1]);
+        assert_eq!(exemplars, requests);
+        let unmatched = rollups
+            .iter()
+            .find(|fields| fields[keys::REQUEST_ROUTE] == json!(route_latency::UNMATCHED_ROUTE))
+            .unwrap();
+        assert_eq!(unmatched["response.status_class.4xx"], json!(1));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_route_name() {
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(
+                    web::resource("/users/{id}")
+                        .name("user_detail")
+                        .to(HttpResponse::Ok),
+                )
+                .service(web::resource("/teams/{id}").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        // every request is named on a span of its own
+        let uris = ["/users/42", "/teams/7", "/users/43"];
+        for uri in &uris {
+            let res = call_service(&mut app, TestRequest::with_uri(uri).to_request()).await;
+            assert!(res.status().is_success());
+        }
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        for (event, (route, name)) in events.iter().zip(&[
+            ("/users/{id}", "user_detail"),
+            ("/teams/{id}", "/teams/{id}"),
+            ("/users/{id}", "user_detail"),
+        ]) {
+            assert_eq!(event.fields()["request.route"], json!(route));
+            assert_eq!(event.fields()["name"], json!(name));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_request_span() {
+        async fn handler(span: RequestSpan<TransmissionMock>) -> HttpResponse {
+            span.add_field("app.handler", json!("create_user"));
+            let validation = span.child("validate_input");
+            validation.add_field("app.valid", json!(true));
+            HttpResponse::Ok().finish()
+        }
+
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/").to(handler)),
+        )
+        .await;
+
+        let res = call_service(&mut app, TestRequest::with_uri("/").to_request()).await;
+        assert!(res.status().is_success());
+        drop(res);
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].fields()["name"], json!("validate_input"));
+        assert_eq!(events[0].fields()["app.valid"], json!(true));
+        assert_eq!(events[1].fields()["app.handler"], json!("create_user"));
+        assert_eq!(
+            events[0].fields()["trace.parent_id"],
+            events[1].fields()["trace.span_id"]
+        );
+
+        // outside of the middleware, the extractor fails
+        let mut app = init_service(App::new().service(web::resource("/").to(handler))).await;
+        let res = call_service(&mut app, TestRequest::with_uri("/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_rt::test]
+    async fn middleware_normalize_path() {
+        for outer in &[false, true] {
+            let middleware = BeelineMiddleware::new(new_client());
+            let normalize = NormalizePath::new(TrailingSlash::Trim);
+            let resource = web::resource("/users/{id}").to(HttpResponse::Ok);
+            let uri = "//users///42/";
+            let res = if *outer {
+                let mut app = init_service(
+                    App::new()
+                        .wrap(normalize)
+                        .wrap(middleware.clone())
+                        .service(resource),
+                )
+                .await;
+                call_service(&mut app, TestRequest::with_uri(uri).to_request()).await
+            } else {
+                let mut app = init_service(
+                    App::new()
+                        .wrap(middleware.clone())
+                        .wrap(normalize)
+                        .service(resource),
+                )
+                .await;
+                call_service(&mut app, TestRequest::with_uri(uri).to_request()).await
+            };
+            assert!(res.status().is_success());
+            drop(res);
+            let events = middleware.client.0.write().client.transmission.events();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].fields()["request.path"], json!("/users/42"));
+            if !outer {
+                assert_eq!(events[0].fields()["request.route"], json!("/users/{id}"));
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_compress() {
+        let body = "x".repeat(1024);
+        for inner in &[true, false] {
+            let middleware = BeelineMiddleware::new(new_client());
+            let body = body.clone();
+            let resource = web::resource("/").to(move || HttpResponse::Ok().body(body.clone()));
+            let req = || {
+                TestRequest::with_uri("/")
+                    .header("accept-encoding", "gzip")
+                    .to_request()
+            };
+            let (encoding, compressed) = if *inner {
+                let mut app = init_service(
+                    App::new()
+                        .wrap(middleware.clone())
+                        .wrap(Compress::default())
+                        .service(resource),
+                )
+                .await;
+                let res = call_service(&mut app, req()).await;
+                (
+                    res.headers().get(CONTENT_ENCODING).cloned(),
+                    read_body(res).await,
+                )
+            } else {
+                let mut app = init_service(
+                    App::new()
+                        .wrap(Compress::default())
+                        .wrap(middleware.clone())
+                        .service(resource),
+                )
+                .await;
+                let res = call_service(&mut app, req()).await;
+                (
+                    res.headers().get(CONTENT_ENCODING).cloned(),
+                    read_body(res).await,
+                )
+            };
+            assert_eq!(encoding.unwrap(), "gzip");
+            assert!(compressed.len() < 1024);
+
+            let events = middleware.client.0.write().client.transmission.events();
+            assert_eq!(events.len(), 1);
+            if *inner {
+                assert_eq!(events[0].fields()["response.body.size"], json!(1024));
+                assert!(!events[0].fields().contains_key("response.body.compressed"));
+            } else {
+                assert_eq!(
+                    events[0].fields()["response.body.size"],
+                    json!(compressed.len())
+                );
+                assert_eq!(events[0].fields()["response.body.compressed"], json!(true));
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_short_circuited() {
+        // stands in for `Cors`, answering preflight requests without calling the app
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .wrap_fn(|req, srv| {
+                    if req.method() == Method::OPTIONS {
+                        let res = req.into_response(HttpResponse::NoContent().finish());
+                        Either::Left(ok(res))
+                    } else {
+                        Either::Right(srv.call(req).map_ok(|res| {
+                            res.map_body(|_, body| ResponseBody::Other(Body::from_message(body)))
+                        }))
+                    }
+                })
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let preflight = TestRequest::with_uri("/")
+            .method(Method::OPTIONS)
+            .to_request();
+        let res = call_service(&mut app, preflight).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        drop(res);
+        assert!(middleware
+            .client
+            .0
+            .write()
+            .client
+            .transmission
+            .events()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_error_summary() {
+        let cases = [
+            (StatusCode::OK, None, None),
+            (StatusCode::BAD_REQUEST, None, Some("invalid email")),
+            (
+                StatusCode::CONFLICT,
+                Some("E_DUPLICATE"),
+                Some("E_DUPLICATE"),
+            ),
+        ];
+        for (status, code, summary) in &cases {
+            let middleware = BeelineMiddleware::new(new_client())
+                .with_error_body_summary(13)
+                .with_error_code_header(HeaderName::from_static("x-error-code"));
+            let (status, code) = (*status, *code);
+            let mut app = init_service(App::new().wrap(middleware.clone()).service(
+                web::resource("/").to(move || {
+                    let mut res = HttpResponse::build(status);
+                    if let Some(code) = code {
+                        res.header("x-error-code", code);
+                    }
+                    res.body("invalid email address: jane@")
+                }),
+            ))
+            .await;
+
+            let res = call_service(&mut app, TestRequest::with_uri("/").to_request()).await;
+            assert_eq!(res.status(), status);
+            read_body(res).await;
+            let events = middleware.client.0.write().client.transmission.events();
+            assert_eq!(events.len(), 1);
+            assert_eq!(
+                events[0].fields().get("response.error.summary"),
+                summary.map(|summary| json!(summary)).as_ref()
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_connection_metadata() {
+        for tls in &[None, Some("TLSv1.3")] {
+            let middleware = BeelineMiddleware::new(new_client());
+            let mut app = init_service(
+                App::new()
+                    .wrap(middleware.clone())
+                    .service(web::resource("/").to(HttpResponse::Ok)),
+            )
+            .await;
+
+            let req = TestRequest::with_uri("/")
+                .version(actix_web::http::Version::HTTP_2)
+                .to_request();
+            if let Some(version) = tls {
+                // what `HttpServer::on_connect` would have added
+                req.extensions_mut().insert(TlsInfo {
+                    version: version.to_string(),
+                    cipher: "TLS13_AES_128_GCM_SHA256".to_string(),
+                });
+            }
+            let res = call_service(&mut app, req).await;
+            assert!(res.status().is_success());
+            drop(res);
+            let events = middleware.client.0.write().client.transmission.events();
+            assert_eq!(events.len(), 1);
+            let fields = events[0].fields();
+            assert_eq!(fields["request.http_version"], json!("HTTP/2.0"));
+            assert_eq!(fields.get("tls.version"), tls.map(|v| json!(v)).as_ref());
+            assert_eq!(fields.contains_key("tls.cipher"), tls.is_some());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_cookie_recorder() {
+        let recorder = beeline::CookieRecorder::new()
+            .with_cookie("session_id", beeline::CookieMode::Hash)
+            .with_cookie("beta", beeline::CookieMode::Presence);
+        let middleware = BeelineMiddleware::new(new_client()).with_cookie_recorder(recorder);
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = TestRequest::with_uri("/")
+            .header("cookie", "session_id=s3cr3t; beta=1; tracking=xyz")
+            .to_request();
+        let res = call_service(&mut app, req).await;
+        assert!(res.status().is_success());
+        drop(res);
+        let events = middleware.client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert!(!fields.contains_key("request.header.cookie"));
+        assert_eq!(fields["request.cookie.beta"], json!(true));
+        assert!(!fields.contains_key("request.cookie.tracking"));
+        let session = fields["request.cookie.session_id"].as_str().unwrap();
+        assert!(!session.contains("s3cr3t"));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_user_extractor() {
+        let middleware = BeelineMiddleware::new(new_client()).with_user_extractor(|headers| {
+            let id = headers.get("x-user-id")?.to_str().ok()?;
+            Some(UserInfo {
+                id: Some(id.to_string()),
+                ..UserInfo::default()
+            })
+        });
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = call_service(
+            &mut app,
+            TestRequest::with_uri("/")
+                .header("x-user-id", "42")
+                .to_request(),
+        )
+        .await;
+        assert!(res.status().is_success());
+        drop(res);
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields()["user.id"], json!("42"));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_response_classifier() {
+        let middleware =
+            BeelineMiddleware::new(new_client()).with_response_classifier(|_, headers| {
+                let errors = headers.get("x-graphql-errors")?.to_str().ok()?;
+                Some(format!("{} graphql errors", errors))
+            });
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/ok").to(HttpResponse::Ok))
+                .service(
+                    web::resource("/graphql")
+                        .to(|| HttpResponse::Ok().header("x-graphql-errors", "2").finish()),
+                ),
+        )
+        .await;
+
+        for uri in &["/ok", "/graphql"] {
+            let res = call_service(&mut app, TestRequest::with_uri(uri).to_request()).await;
+            assert!(res.status().is_success());
+        }
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let ok = events[0].fields();
+        assert!(!ok.contains_key(keys::ERROR));
+        assert!(!ok.contains_key(keys::APP_ERROR));
+        let failed = events[1].fields();
+        assert_eq!(failed["response.status"], json!(200));
+        assert_eq!(failed[keys::ERROR], json!("2 graphql errors"));
+        assert_eq!(failed[keys::SPAN_STATUS], json!("error"));
+        assert_eq!(failed[keys::APP_ERROR], json!(true));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_shutdown() {
+        let middleware = BeelineMiddleware::new(new_client());
+        let shutdown = middleware.shutdown_handle();
+
+        // a trace still open when the server stops, e.g. from a background job
+        let trace = middleware.client.new_trace(None);
+        trace
+            .lock()
+            .get_root_span()
+            .lock()
+            .add_field("job", json!("cleanup"));
+
+        shutdown.shutdown().unwrap();
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .any(|event| event.fields().contains_key("job")));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_request_cancelled() {
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/slow").to(futures::future::pending::<HttpResponse>)),
+        )
+        .await;
+
+        let mut res = Box::pin(app.call(TestRequest::with_uri("/slow").to_request()));
+        assert!(futures::poll!(res.as_mut()).is_pending());
+        // the client goes away before the handler returns
+        drop(res);
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        let fields = events[0].fields();
+        assert_eq!(fields[keys::REQUEST_CANCELLED], json!(true));
+        assert_eq!(fields[keys::REQUEST_PATH], json!("/slow"));
+        assert!(fields.contains_key(keys::DURATION_MS));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_concurrent_requests() {
+        async fn slow(span: RequestSpan<TransmissionMock>) -> HttpResponse {
+            span.add_field("app.handler", json!("slow"));
+            futures::future::pending::<HttpResponse>().await
+        }
+        async fn fast(span: RequestSpan<TransmissionMock>) -> HttpResponse {
+            span.add_field("app.handler", json!("fast"));
+            HttpResponse::Ok().finish()
+        }
+
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/slow").to(slow))
+                .service(web::resource("/fast").to(fast)),
+        )
+        .await;
+
+        let mut cancelled = Box::pin(app.call(TestRequest::with_uri("/slow").to_request()));
+        assert!(futures::poll!(cancelled.as_mut()).is_pending());
+        // another request is handled while the first one is pending, which is then
+        // cancelled
+        let res = call_service(&mut app, TestRequest::with_uri("/fast").to_request()).await;
+        assert!(res.status().is_success());
+        drop(res);
+        drop(cancelled);
+
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let (fast, slow) = (events[0].fields(), events[1].fields());
+        assert_eq!(fast["app.handler"], json!("fast"));
+        assert!(!fast.contains_key(keys::REQUEST_CANCELLED));
+        assert_eq!(slow["app.handler"], json!("slow"));
+        assert_eq!(slow[keys::REQUEST_CANCELLED], json!(true));
+        assert_ne!(fast[keys::TRACE_TRACE_ID], slow[keys::TRACE_TRACE_ID]);
+    }
+
+    #[actix_rt::test]
+    async fn middleware_response_cancelled() {
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(App::new().wrap(middleware.clone()).service(
+            web::resource("/stream").to(|| {
+                HttpResponse::Ok().streaming(futures::stream::pending::<Result<Bytes, Error>>())
+            }),
+        ))
+        .await;
+
+        let res = call_service(&mut app, TestRequest::with_uri("/stream").to_request()).await;
+        assert!(res.status().is_success());
+        // the client goes away before the body ends
+        drop(res);
+        let events = middleware.client.0.write().client.transmission.events();
+        assert_eq!(events[0].fields()[keys::REQUEST_CANCELLED], json!(true));
+        assert_eq!(events[0].fields()["response.status"], json!(200));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_deadline() {
+        let middleware = BeelineMiddleware::new(new_client());
+        let mut app = init_service(
+            App::new()
+                .wrap(middleware.clone())
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = TestRequest::with_uri("/")
+            .header("grpc-timeout", "0n")
+            .to_request();
+        drop(call_service(&mut app, req).await);
+        let events = middleware.client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert_eq!(fields[keys::REQUEST_DEADLINE_MS], json!(0f64));
+        assert_eq!(fields[keys::META_DEADLINE_EXCEEDED], json!(true));
+    }
 }
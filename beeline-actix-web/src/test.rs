@@ -0,0 +1,71 @@
+/*! Helpers to unit-test the instrumentation of an actix-web application.
+
+The client returned by `client` keeps the events in memory instead of sending them to
+Honeycomb, `app` wraps the handlers under test with the middleware and `events` returns
+what the middleware has sent so far:
+
+```rust
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::{web, HttpResponse};
+use beeline::Config;
+use beeline_actix_web::{test, BeelineMiddleware};
+use serde_json::json;
+
+# async fn example() {
+let middleware = BeelineMiddleware::new(test::client(Config::default()));
+let mut app = init_service(test::app(&middleware, |config| {
+    config.service(web::resource("/users/{id}").to(HttpResponse::Ok));
+}))
+.await;
+call_service(&mut app, TestRequest::with_uri("/users/42").to_request()).await;
+
+let events = test::events(&middleware);
+let request = test::find_event(&events, "request.route", &json!("/users/{id}")).unwrap();
+test::assert_field(request, "response.status", &json!(200));
+# }
+```
+
+The request span is sent once the response body has been consumed or dropped, so drop the
+response before looking at the events.
+ */
+
+use actix_service::ServiceFactory;
+use actix_web::dev::{Body, ServiceRequest, ServiceResponse};
+use actix_web::{web, App, Error};
+use beeline::{Client, Config};
+
+use crate::{BeelineMiddleware, StreamLog};
+
+pub use beeline::test::{assert_field, find_event, Fields, TransmissionMock};
+
+/// `client` returns a client keeping the events it sends in memory
+pub fn client(config: Config) -> Client<TransmissionMock> {
+    beeline::test::init(config)
+}
+
+/// `app` returns an `App` wrapped with `middleware`, with the services registered by
+/// `configure`. Pass it to `actix_web::test::init_service`.
+pub fn app<F>(
+    middleware: &BeelineMiddleware<TransmissionMock>,
+    configure: F,
+) -> App<
+    impl ServiceFactory<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse<StreamLog<Body, TransmissionMock>>,
+        Error = Error,
+        InitError = (),
+    >,
+    StreamLog<Body, TransmissionMock>,
+>
+where
+    F: FnOnce(&mut web::ServiceConfig),
+{
+    App::new().wrap(middleware.clone()).configure(configure)
+}
+
+/// `events` returns the fields of the events sent through `middleware` so far, in the
+/// order they were sent
+pub fn events(middleware: &BeelineMiddleware<TransmissionMock>) -> Vec<Fields> {
+    beeline::test::events(&middleware.client)
+}
@@ -0,0 +1,373 @@
+/*! Honeycomb support for async-graphql.
+
+A span is created for every GraphQL operation with the following fields:
+ - `meta.type` (always "graphql_operation")
+ - `name` (always "graphql.operation")
+ - `graphql.operation_name`
+ - `graphql.operation_type` ("query", "mutation" or "subscription")
+ - `graphql.error_count` and `error` (the message of the first error), when the
+   operation fails
+
+Each resolver gets a child span (of its parent resolver, or of the operation span) with:
+ - `meta.type` (always "graphql_resolver")
+ - `name` (always "graphql.resolve")
+ - `graphql.field_path`
+ - `graphql.parent_type`
+ - `graphql.return_type`
+
+Both kinds of spans carry `meta.instrumentation` (always "beeline-async-graphql") and
+`meta.instrumentation_version` (the version of this crate).
+
+If the request data contains a `SafeSpan` (e.g. the span of the surrounding HTTP request),
+the operation span is created as a child of it so the GraphQL spans join the HTTP trace.
+Otherwise a new trace is started for every operation.
+
+# Usage
+
+First add `beeline_async_graphql` to your `Cargo.toml`:
+
+```toml
+[dependencies]
+beeline_async_graphql = "0.1"
+```
+
+You then instantiate the extension and register it with the schema:
+
+```rust
+use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+use beeline::{init, Config};
+use beeline_async_graphql::BeelineExtension;
+
+struct Query;
+
+#[Object]
+impl Query {
+    async fn value(&self) -> i32 {
+        10
+    }
+}
+
+fn main() {
+    # if false {
+    let client = init(Config::default());
+    let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+        .extension(BeelineExtension::new(client))
+        .finish();
+    # }
+}
+```
+
+ */
+
+#![deny(missing_docs)]
+
+use std::collections::HashMap;
+
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, ResolveInfo};
+use async_graphql::{ServerError, Variables};
+use beeline::trace::{SafeSpan, SafeTrace, TraceSender};
+use beeline::{keys, Client, Instrumentation, Sender};
+use serde_json::json;
+
+/// Stamped on the spans created by the extension
+const INSTRUMENTATION: Instrumentation = beeline::instrumentation!();
+
+#[derive(Debug, Clone)]
+/// `BeelineExtension` creates spans for GraphQL operations and their resolvers
+pub struct BeelineExtension<S: Sender + Send + Sync + Clone + 'static> {
+    client: Client<S>,
+}
+
+impl<S> BeelineExtension<S>
+where
+    S: Sender + Send + Sync + Clone + 'static,
+{
+    /// Build with already started client
+    pub fn new(client: Client<S>) -> Self {
+        Self { client }
+    }
+}
+
+impl<S> ExtensionFactory for BeelineExtension<S>
+where
+    S: Sender + Send + Sync + Clone + 'static,
+{
+    fn create(&self) -> Box<dyn Extension> {
+        Box::new(BeelineOperation {
+            client: self.client.clone(),
+            trace: None,
+            span: None,
+            resolvers: HashMap::new(),
+            error_count: 0,
+        })
+    }
+}
+
+/// `BeelineOperation` holds the spans of a single GraphQL operation
+struct BeelineOperation<S: Sender + Send + Sync + Clone + 'static> {
+    client: Client<S>,
+    /// trace started by the extension, when there was no span to attach to
+    trace: Option<SafeTrace>,
+    span: Option<SafeSpan>,
+    resolvers: HashMap<usize, SafeSpan>,
+    error_count: usize,
+}
+
+impl<S> Extension for BeelineOperation<S>
+where
+    S: Sender + Send + Sync + Clone + 'static,
+{
+    fn name(&self) -> Option<&'static str> {
+        Some("beeline")
+    }
+
+    fn parse_start(
+        &mut self,
+        ctx: &ExtensionContext<'_>,
+        query_source: &str,
+        _variables: &Variables,
+    ) {
+        self.start(ctx);
+        if let Some(span) = &self.span {
+            let (operation_type, operation_name) = operation(query_source);
+            let mut guard = span.lock();
+            guard.add_field("graphql.operation_type", json!(operation_type));
+            if let Some(name) = operation_name {
+                guard.add_field("graphql.operation_name", json!(name));
+            }
+        }
+    }
+
+    fn resolve_start(&mut self, _ctx: &ExtensionContext<'_>, info: &ResolveInfo<'_>) {
+        // top level fields are resolved as children of the root resolution (id 0), which
+        // maps to the operation span
+        let parent = match info.resolve_id.parent {
+            Some(parent) if parent > 0 => self.resolvers.get(&parent).cloned(),
+            _ => self.span.clone(),
+        };
+        let child = parent.and_then(|parent| parent.lock().create_child(&mut self.client));
+        if let Some(span) = child {
+            {
+                let mut guard = span.lock();
+                guard.add_field(keys::META_TYPE, json!("graphql_resolver"));
+                INSTRUMENTATION.add_to(&mut guard);
+                guard.add_field(keys::NAME, json!("graphql.resolve"));
+                guard.add_field("graphql.field_path", json!(info.path_node.to_string()));
+                guard.add_field("graphql.parent_type", json!(info.parent_type));
+                guard.add_field("graphql.return_type", json!(info.return_type));
+            }
+            self.resolvers.insert(info.resolve_id.current, span);
+        }
+    }
+
+    fn resolve_end(&mut self, _ctx: &ExtensionContext<'_>, info: &ResolveInfo<'_>) {
+        if let Some(span) = self.resolvers.remove(&info.resolve_id.current) {
+            span.lock().send(&mut self.client);
+        }
+    }
+
+    fn error(&mut self, _ctx: &ExtensionContext<'_>, err: &ServerError) {
+        self.error_count += 1;
+        if let Some(span) = &self.span {
+            let mut guard = span.lock();
+            if self.error_count == 1 {
+                guard.add_field(keys::ERROR, json!(err.message));
+            }
+            guard.add_field("graphql.error_count", json!(self.error_count));
+        }
+    }
+}
+
+impl<S> BeelineOperation<S>
+where
+    S: Sender + Send + Sync + Clone + 'static,
+{
+    /// `start` creates the operation span, as a child of the span found in the request
+    /// data or as the root of a brand new trace
+    fn start(&mut self, ctx: &ExtensionContext<'_>) {
+        self.span = match ctx.data_opt::<SafeSpan>() {
+            Some(parent) => parent.lock().create_child(&mut self.client),
+            None => {
+                let trace = self.client.new_trace(None);
+                let root_span = trace.lock().get_root_span();
+                self.trace = Some(trace);
+                Some(root_span)
+            }
+        };
+        if let Some(span) = &self.span {
+            let mut guard = span.lock();
+            guard.add_field(keys::META_TYPE, json!("graphql_operation"));
+            INSTRUMENTATION.add_to(&mut guard);
+            guard.add_field(keys::NAME, json!("graphql.operation"));
+        }
+    }
+}
+
+/// The extension is dropped once the operation has been executed or has failed to parse or
+/// validate, which makes it the one place where the operation span is always sent.
+impl<S> Drop for BeelineOperation<S>
+where
+    S: Sender + Send + Sync + Clone + 'static,
+{
+    fn drop(&mut self) {
+        match self.trace.take() {
+            Some(trace) => trace.send(&mut self.client),
+            None => {
+                if let Some(span) = self.span.take() {
+                    span.lock().send(&mut self.client);
+                }
+            }
+        }
+    }
+}
+
+/// `operation` extracts the type and name of the first operation in a GraphQL document
+/// (e.g. `query GetUser($id: ID!) { ... }` yields `("query", Some("GetUser"))`). The
+/// query shorthand (`{ ... }`) is an anonymous query.
+fn operation(query_source: &str) -> (&'static str, Option<String>) {
+    let mut tokens = query_source
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| {
+            line.split(|c: char| c.is_whitespace() || "{}()$:@,".contains(c))
+                .filter(|token| !token.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        });
+
+    let operation_type = match tokens.next().as_deref() {
+        Some("mutation") => "mutation",
+        Some("subscription") => "subscription",
+        Some("query") => "query",
+        _ => return ("query", None),
+    };
+
+    let name = query_source
+        .trim_start()
+        .trim_start_matches(operation_type)
+        .trim_start();
+    let name = if name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        tokens.next()
+    } else {
+        None
+    };
+    (operation_type, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Request, Schema};
+    use beeline::test::TransmissionMock;
+    use beeline::Config;
+
+    fn new_client() -> Client<TransmissionMock> {
+        let api_host = &mockito::server_url();
+        let _m = mockito::mock(
+            "POST",
+            mockito::Matcher::Regex(r"/1/batch/(.*)$".to_string()),
+        )
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body("[{ \"status\": 202 }]")
+        .create();
+
+        let mut config = Config::default();
+        config.client_config.options.api_host = api_host.to_string();
+        config.client_config.options.api_key = "key".to_string();
+        config.service_name = Some("beeline-async-graphql-test".to_string());
+
+        beeline::test::init(config)
+    }
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn value(&self) -> i32 {
+            10
+        }
+    }
+
+    #[test]
+    fn test_operation() {
+        assert_eq!(operation("{ value }"), ("query", None));
+        assert_eq!(
+            operation("query GetValue { value }"),
+            ("query", Some("GetValue".to_string()))
+        );
+        assert_eq!(
+            operation("  mutation SetValue($v: Int!) { set(v: $v) }"),
+            ("mutation", Some("SetValue".to_string()))
+        );
+        assert_eq!(operation("subscription { values }"), ("subscription", None));
+    }
+
+    #[test]
+    fn test_extension() {
+        let client = new_client();
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(BeelineExtension::new(client.clone()))
+            .finish();
+
+        let response = futures::executor::block_on(schema.execute("query GetValue { value }"));
+        assert!(response.is_ok());
+
+        let events = client.0.write().client.transmission.events();
+        // the operation span plus the resolver for `value`
+        assert_eq!(events.len(), 2);
+        let operation = events
+            .iter()
+            .map(|event| event.fields())
+            .find(|fields| fields["meta.type"] == json!("graphql_operation"))
+            .unwrap();
+        assert_eq!(operation["graphql.operation_name"], json!("GetValue"));
+        assert_eq!(operation["graphql.operation_type"], json!("query"));
+        assert!(events
+            .iter()
+            .all(|event| event.fields()[keys::META_INSTRUMENTATION]
+                == json!("beeline-async-graphql")));
+    }
+
+    #[test]
+    fn test_extension_parse_error() {
+        let client = new_client();
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(BeelineExtension::new(client.clone()))
+            .finish();
+
+        let response = futures::executor::block_on(schema.execute("query {"));
+        assert!(response.is_err());
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        let fields = events[0].fields();
+        assert_eq!(fields["graphql.error_count"], json!(1));
+        assert!(fields.contains_key("error"));
+    }
+
+    #[test]
+    fn test_extension_within_span() {
+        let mut client = new_client();
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(BeelineExtension::new(client.clone()))
+            .finish();
+
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        let request = Request::new("{ value }").data(root_span);
+        let response = futures::executor::block_on(schema.execute(request));
+        assert!(response.is_ok());
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        // the http-like root span, the operation span and the resolver span
+        assert_eq!(events.len(), 3);
+        let trace_ids: Vec<_> = events
+            .iter()
+            .map(|event| event.fields()["trace.trace_id"].clone())
+            .collect();
+        assert!(trace_ids.iter().all(|id| *id == trace_ids[0]));
+    }
+}
@@ -0,0 +1,9 @@
+
+[![docs.rs](https://docs.rs/beeline-rust/badge.svg)](https://docs.rs/beeline-rust)
+[![crates.io](https://img.shields.io/crates/v/beeline-hyper.svg)](https://crates.io/crates/beeline-async-graphql)
+[![MIT licensed](https://img.shields.io/badge/license-MIT-blue.svg)](https://github.com/nlopes/beeline-rust/blob/master/beeline-hyper/LICENSE)
+{{badges}}
+
+# {{crate}}
+
+{{readme}}
@@ -0,0 +1,728 @@
+/*! Honeycomb support for hyper clients.
+
+`BeelineLayer` is a tower `Layer` for `hyper::Client`, or any other service of
+`http::Request`s, creating a span for every request made in a trace. The span is a child of
+the `SafeSpan` found in the extensions of the request, and is created with
+`beeline::semconv::http_client`, so it has the following fields:
+ - `meta.type` (always "http_client")
+ - `name` (the method of the request)
+ - `request.method`
+ - `request.url`
+ - `response.status_code`, or `error` when the request fails
+ - `response.first_byte_ms` (the time until the response headers arrived)
+ - `peer.service`, when `Config::peer_services` names the service behind the host
+ - `meta.instrumentation` (always "beeline-hyper") and `meta.instrumentation_version` (the
+   version of this crate)
+
+What counts as a failed response is up to you: with a classifier set through
+`BeelineLayer::with_response_classifier`, the responses it fails (e.g. a gRPC error in a
+200 response) get `error` and `span.status` set, and the root span of their trace
+`app.error`.
+
+Requests without a `SafeSpan` are not traced. The trace context is sent in the
+`X-Honeycomb-Trace` header, so the downstream service joins the trace, unless
+`Config::propagation_hosts` keeps it from the host of the request.
+
+When the client connects through a `TimedConnector`, the span also describes the connection
+the request went over:
+ - `connection.reused` (false for the first request made over a connection)
+ - `connection.dns_ms`, `connection.connect_ms` and `connection.tls_ms` (only on the request
+   that opened the connection, `connection.dns_ms` only when the host was resolved by a
+   `TimedResolver` and `connection.tls_ms` only when the TCP connection under the TLS
+   connector was made by a `TimedTcpConnector`)
+
+The time spent opening the connection is then left out of `response.first_byte_ms`, so a
+slow downstream service can be told apart from a slow network.
+
+For HTTPS, the TLS connector of your choice goes between the two connectors, e.g. with
+`hyper-tls`:
+
+```rust,ignore
+let https = HttpsConnector::from((TimedTcpConnector::http(), TlsConnector::new()?.into()));
+let client = hyper::Client::builder().build::<_, Body>(TimedConnector::new(https));
+```
+
+# Usage
+
+First add `beeline_hyper` to your `Cargo.toml`:
+
+```toml
+[dependencies]
+beeline_hyper = "0.1"
+```
+
+You then wrap the client in the layer, and add the span of the request to its extensions:
+
+```rust
+use beeline::{init, Config};
+use beeline_hyper::{BeelineLayer, TimedConnector};
+use hyper::{Body, Request};
+use tower::{Layer, Service};
+
+# async fn run() {
+let beeline = init(Config::default());
+let client = hyper::Client::builder().build::<_, Body>(TimedConnector::http());
+let mut client = BeelineLayer::new(beeline.clone()).layer(client);
+
+let trace = beeline.new_trace(None);
+let mut request = Request::get("http://localhost:8080/users/42")
+    .body(Body::empty())
+    .unwrap();
+request
+    .extensions_mut()
+    .insert(trace.lock().get_root_span());
+let response = client.call(request).await;
+# }
+```
+
+ */
+
+#![deny(missing_docs)]
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use beeline::semconv::http_client;
+use beeline::trace::SafeSpan;
+use beeline::{keys, Client, Instrumentation, ResponseClassifier, Sender};
+use http::header::{HeaderMap, HeaderValue};
+use http::uri::Scheme;
+use http::{Request, Response, Uri};
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::client::connect::{Connected, Connection, HttpConnector};
+use pin_project::pin_project;
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tower::{Layer, Service};
+
+/// Stamped on the spans created by the layer
+const INSTRUMENTATION: Instrumentation = beeline::instrumentation!();
+
+/// header carrying the trace context to the downstream service
+const TRACE_HEADER: &str = "X-Honeycomb-Trace";
+
+tokio::task_local! {
+    /// phases of the connection being established by a `TimedConnector`, timed by the
+    /// `TimedResolver` and `TimedTcpConnector` it wraps
+    static PHASES: Arc<Mutex<Phases>>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Phases {
+    dns: Option<Duration>,
+    // includes the name resolution
+    tcp: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+/// `BeelineLayer` wraps services of `http::Request`s in a `BeelineService`
+pub struct BeelineLayer<T: Sender + Clone> {
+    client: Client<T>,
+    classifier: Option<ResponseClassifier<HeaderMap>>,
+}
+
+impl<T: Sender + Clone> BeelineLayer<T> {
+    /// `new` returns a layer sending the spans of requests through `client`
+    pub fn new(client: Client<T>) -> Self {
+        Self {
+            client,
+            classifier: None,
+        }
+    }
+
+    /// `with_response_classifier` sets the function called with the status and headers of
+    /// every response to find out whether it failed, returning the reason it did
+    pub fn with_response_classifier<F>(mut self, classifier: F) -> Self
+    where
+        F: Fn(u16, &HeaderMap) -> Option<String> + Send + Sync + 'static,
+    {
+        self.classifier = Some(ResponseClassifier::new(classifier));
+        self
+    }
+}
+
+impl<S, T: Sender + Clone> Layer<S> for BeelineLayer<T> {
+    type Service = BeelineService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BeelineService {
+            inner,
+            client: self.client.clone(),
+            classifier: self.classifier.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// `BeelineService` creates a span for every request made through the service it wraps
+pub struct BeelineService<S, T: Sender + Clone> {
+    inner: S,
+    client: Client<T>,
+    classifier: Option<ResponseClassifier<HeaderMap>>,
+}
+
+impl<S, T, B, R> Service<Request<B>> for BeelineService<S, T>
+where
+    S: Service<Request<B>, Response = Response<R>>,
+    S::Error: fmt::Display,
+    T: Sender + Clone,
+{
+    type Response = Response<R>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, T>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let mut client = self.client.clone();
+        let span = request
+            .extensions()
+            .get::<SafeSpan>()
+            .cloned()
+            .and_then(|parent| {
+                let peer_service = request
+                    .uri()
+                    .host()
+                    .and_then(|host| client.peer_service(host));
+                http_client::http_client_span(
+                    &mut client,
+                    &parent,
+                    request.method().as_str(),
+                    &request.uri().to_string(),
+                    peer_service.as_deref(),
+                )
+            });
+        if let Some(span) = &span {
+            let mut guard = span.lock();
+            INSTRUMENTATION.add_to(&mut guard);
+            if client.propagates_to(request.uri().host().unwrap_or_default()) {
+                let headers = guard.serialize_headers();
+                if let (false, Ok(value)) = (headers.is_empty(), HeaderValue::from_str(&headers)) {
+                    request.headers_mut().insert(TRACE_HEADER, value);
+                }
+            }
+        }
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            client,
+            span,
+            classifier: self.classifier.clone(),
+            started: Instant::now(),
+        }
+    }
+}
+
+#[pin_project]
+/// `ResponseFuture` is the response of a `BeelineService`, which sends the span of the
+/// request once the response headers arrive
+pub struct ResponseFuture<F, T: Sender> {
+    #[pin]
+    inner: F,
+    client: Client<T>,
+    span: Option<SafeSpan>,
+    classifier: Option<ResponseClassifier<HeaderMap>>,
+    started: Instant,
+}
+
+impl<F, T, R, E> Future for ResponseFuture<F, T>
+where
+    F: Future<Output = Result<Response<R>, E>>,
+    E: fmt::Display,
+    T: Sender,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = match this.inner.poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        if let Some(span) = this.span.take() {
+            match &result {
+                Ok(response) => {
+                    http_client::record_response(&span, response.status().as_u16());
+                    let mut first_byte = this.started.elapsed();
+                    if let Some(timing) = response.extensions().get::<ConnectionTiming>() {
+                        first_byte = timing.add_to(&span, first_byte);
+                    }
+                    let mut guard = span.lock();
+                    guard.add_field(http_client::FIRST_BYTE_MS, json!(millis(first_byte)));
+                    if let Some(classifier) = this.classifier {
+                        classifier.record(
+                            &mut guard,
+                            response.status().as_u16(),
+                            response.headers(),
+                        );
+                    }
+                }
+                Err(e) => span.lock().add_field(keys::ERROR, json!(e.to_string())),
+            }
+            span.lock().send(this.client);
+        }
+        Poll::Ready(result)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// `ConnectionTiming` is set by a `TimedConnector` on the responses received over the
+/// connections it establishes
+pub struct ConnectionTiming {
+    dns: Option<Duration>,
+    connect: Duration,
+    tls: Option<Duration>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl ConnectionTiming {
+    /// `add_to` adds the connection fields of a request to `span`, the timings only being
+    /// added for the first request of the connection. It returns the time the request
+    /// waited for its response once connected, out of `elapsed`.
+    fn add_to(&self, span: &SafeSpan, elapsed: Duration) -> Duration {
+        let mut span = span.lock();
+        let reused = self.requests.fetch_add(1, Ordering::Relaxed) > 0;
+        span.add_field(http_client::CONNECTION_REUSED, json!(reused));
+        if reused {
+            return elapsed;
+        }
+        let mut established = self.connect;
+        if let Some(dns) = self.dns {
+            span.add_field(http_client::DNS_MS, json!(millis(dns)));
+            established += dns;
+        }
+        span.add_field(http_client::CONNECT_MS, json!(millis(self.connect)));
+        if let Some(tls) = self.tls {
+            span.add_field(http_client::TLS_MS, json!(millis(tls)));
+            established += tls;
+        }
+        elapsed.saturating_sub(established)
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1_000_000f64
+}
+
+#[derive(Debug, Clone)]
+/// `TimedConnector` wraps the connector of a hyper client to time the connections it
+/// establishes
+pub struct TimedConnector<C> {
+    inner: C,
+}
+
+impl<C> TimedConnector<C> {
+    /// `new` returns a connector timing the connections established by `inner`
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+}
+
+impl TimedConnector<HttpConnector<TimedResolver<GaiResolver>>> {
+    /// `http` returns a connector timing both the name resolution and the establishment of
+    /// plain HTTP connections
+    pub fn http() -> Self {
+        Self::new(HttpConnector::new_with_resolver(TimedResolver::new(
+            GaiResolver::new(),
+        )))
+    }
+}
+
+impl<C> Service<Uri> for TimedConnector<C>
+where
+    C: Service<Uri>,
+    C::Response: Connection + Send + 'static,
+    C::Error: Send + 'static,
+    C::Future: Send + 'static,
+{
+    type Response = TimedConnection<C::Response>;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let secure = uri.scheme() == Some(&Scheme::HTTPS);
+        let phases = Arc::new(Mutex::new(Phases::default()));
+        let connecting = PHASES.scope(phases.clone(), self.inner.call(uri));
+        let started = Instant::now();
+        Box::pin(async move {
+            let io = connecting.await?;
+            let elapsed = started.elapsed();
+            let Phases { dns, tcp } = *phases.lock().unwrap();
+            // the handshake is only told apart when the TCP connection was timed on its own
+            let (established, tls) = match tcp {
+                Some(tcp) if secure => (tcp, Some(elapsed.saturating_sub(tcp))),
+                _ => (elapsed, None),
+            };
+            let timing = ConnectionTiming {
+                dns,
+                connect: established.saturating_sub(dns.unwrap_or_default()),
+                tls,
+                requests: Arc::new(AtomicUsize::new(0)),
+            };
+            Ok(TimedConnection { io, timing })
+        })
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+/// `TimedConnection` is a connection established by a `TimedConnector`
+pub struct TimedConnection<IO> {
+    #[pin]
+    io: IO,
+    timing: ConnectionTiming,
+}
+
+impl<IO: Connection> Connection for TimedConnection<IO> {
+    fn connected(&self) -> Connected {
+        self.io.connected().extra(self.timing.clone())
+    }
+}
+
+impl<IO: AsyncRead> AsyncRead for TimedConnection<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().io.poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite> AsyncWrite for TimedConnection<IO> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().io.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().io.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().io.poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// `TimedTcpConnector` wraps the TCP connector under the TLS connector of a hyper client,
+/// so the `TimedConnector` wrapping the TLS connector tells the TLS handshake apart
+pub struct TimedTcpConnector<C> {
+    inner: C,
+}
+
+impl<C> TimedTcpConnector<C> {
+    /// `new` returns a connector timing the TCP connections established by `inner`
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+}
+
+impl TimedTcpConnector<HttpConnector<TimedResolver<GaiResolver>>> {
+    /// `http` returns a connector timing both the name resolution and the establishment of
+    /// TCP connections, for a TLS connector to wrap
+    pub fn http() -> Self {
+        let mut http = HttpConnector::new_with_resolver(TimedResolver::new(GaiResolver::new()));
+        http.enforce_http(false);
+        Self::new(http)
+    }
+}
+
+impl<C> Service<Uri> for TimedTcpConnector<C>
+where
+    C: Service<Uri>,
+    C::Response: Send + 'static,
+    C::Error: Send + 'static,
+    C::Future: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.inner.call(uri);
+        let started = Instant::now();
+        Box::pin(async move {
+            let io = connecting.await?;
+            // connections made outside of a `TimedConnector` aren't recorded
+            let _ = PHASES.try_with(|phases| phases.lock().unwrap().tcp = Some(started.elapsed()));
+            Ok(io)
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// `TimedResolver` wraps the resolver of an `HttpConnector` to time name resolutions, for
+/// the `TimedConnector` wrapping the `HttpConnector`
+pub struct TimedResolver<R> {
+    inner: R,
+}
+
+impl<R> TimedResolver<R> {
+    /// `new` returns a resolver timing the name resolutions of `inner`
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+}
+
+impl<R> Service<Name> for TimedResolver<R>
+where
+    R: Service<Name>,
+    R::Response: Send + 'static,
+    R::Error: Send + 'static,
+    R::Future: Send + 'static,
+{
+    type Response = R::Response;
+    type Error = R::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolving = self.inner.call(name);
+        let started = Instant::now();
+        Box::pin(async move {
+            let addrs = resolving.await?;
+            // resolutions made outside of a `TimedConnector` aren't recorded
+            let _ = PHASES.try_with(|phases| phases.lock().unwrap().dns = Some(started.elapsed()));
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::Infallible;
+
+    use beeline::test::{assert_field, events, TransmissionMock};
+    use beeline::Config;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Server};
+    use tower::ServiceExt;
+
+    fn new_client() -> Client<TransmissionMock> {
+        new_client_with(Config::default())
+    }
+
+    fn new_client_with(mut config: Config) -> Client<TransmissionMock> {
+        let api_host = &mockito::server_url();
+        let _m = mockito::mock(
+            "POST",
+            mockito::Matcher::Regex(r"/1/batch/(.*)$".to_string()),
+        )
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body("[{ \"status\": 202 }]")
+        .create();
+
+        config.client_config.options.api_host = api_host.to_string();
+        config.client_config.options.api_key = "key".to_string();
+        config.service_name = Some("beeline-hyper-test".to_string());
+
+        beeline::test::init(config)
+    }
+
+    #[tokio::test]
+    async fn test_layer() {
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let traced = request.headers().contains_key(TRACE_HEADER);
+                Ok::<_, Infallible>(Response::new(Body::from(traced.to_string())))
+            }))
+        }));
+        let url = format!("http://localhost:{}/users/42", server.local_addr().port());
+        tokio::spawn(server);
+
+        let beeline = new_client();
+        let client = hyper::Client::builder().build::<_, Body>(TimedConnector::http());
+        let mut client = BeelineLayer::new(beeline.clone()).layer(client);
+
+        let trace = beeline.new_trace(None);
+        for _ in 0..2 {
+            let mut request = Request::get(&url).body(Body::empty()).unwrap();
+            request
+                .extensions_mut()
+                .insert(trace.lock().get_root_span());
+            let response = client.ready().await.unwrap().call(request).await.unwrap();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(&body[..], b"true");
+            // give the connection the time to go back to the pool
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // requests without a span aren't traced
+        let request = Request::get(&url).body(Body::empty()).unwrap();
+        let response = client.ready().await.unwrap().call(request).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"false");
+
+        let events = events(&beeline);
+        assert_eq!(events.len(), 2);
+        for event in &events {
+            assert_field(event, keys::META_TYPE, &json!("http_client"));
+            assert_field(event, http_client::URL, &json!(url));
+            assert_field(event, http_client::STATUS_CODE, &json!(200));
+            assert_field(event, keys::META_INSTRUMENTATION, &json!("beeline-hyper"));
+        }
+        assert_field(&events[0], http_client::CONNECTION_REUSED, &json!(false));
+        assert!(events[0].contains_key(http_client::DNS_MS));
+        assert!(events[0].contains_key(http_client::CONNECT_MS));
+        assert!(!events[0].contains_key(http_client::TLS_MS));
+        assert_field(&events[1], http_client::CONNECTION_REUSED, &json!(true));
+        assert!(!events[1].contains_key(http_client::CONNECT_MS));
+        assert!(events
+            .iter()
+            .all(|event| event.contains_key(http_client::FIRST_BYTE_MS)));
+    }
+
+    #[tokio::test]
+    async fn test_response_classifier() {
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let grpc_status = if request.uri().path() == "/unavailable" {
+                    "14"
+                } else {
+                    "0"
+                };
+                let response = Response::builder()
+                    .header("grpc-status", grpc_status)
+                    .body(Body::empty())
+                    .unwrap();
+                Ok::<_, Infallible>(response)
+            }))
+        }));
+        let url = format!("http://localhost:{}", server.local_addr().port());
+        tokio::spawn(server);
+
+        let beeline = new_client();
+        let mut client = BeelineLayer::new(beeline.clone())
+            .with_response_classifier(|_, headers| {
+                let grpc_status = headers.get("grpc-status")?.to_str().ok()?;
+                match grpc_status {
+                    "0" => None,
+                    code => Some(format!("grpc-status {}", code)),
+                }
+            })
+            .layer(hyper::Client::new());
+
+        let trace = beeline.new_trace(None);
+        for path in &["/ok", "/unavailable"] {
+            let mut request = Request::get(format!("{}{}", url, path))
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(trace.lock().get_root_span());
+            let response = client.ready().await.unwrap().call(request).await.unwrap();
+            assert_eq!(response.status(), 200);
+        }
+
+        let events = events(&beeline);
+        assert_eq!(events.len(), 2);
+        assert!(!events[0].contains_key(keys::ERROR));
+        assert_field(&events[1], http_client::STATUS_CODE, &json!(200));
+        assert_field(&events[1], keys::ERROR, &json!("grpc-status 14"));
+        assert_field(&events[1], keys::SPAN_STATUS, &json!("error"));
+    }
+
+    #[test]
+    fn test_connection_timing() {
+        let mut beeline = new_client();
+        let trace = beeline.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        let timing = ConnectionTiming {
+            dns: Some(Duration::from_millis(5)),
+            connect: Duration::from_millis(10),
+            tls: Some(Duration::from_millis(20)),
+            requests: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let span = root_span.lock().create_child(&mut beeline).unwrap();
+        let first_byte = timing.add_to(&span, Duration::from_millis(100));
+        assert_eq!(first_byte, Duration::from_millis(65));
+        span.lock().send(&mut beeline);
+        let span = root_span.lock().create_child(&mut beeline).unwrap();
+        let first_byte = timing.add_to(&span, Duration::from_millis(100));
+        assert_eq!(first_byte, Duration::from_millis(100));
+        span.lock().send(&mut beeline);
+
+        let events = events(&beeline);
+        assert_field(&events[0], http_client::DNS_MS, &json!(5f64));
+        assert_field(&events[0], http_client::CONNECT_MS, &json!(10f64));
+        assert_field(&events[0], http_client::TLS_MS, &json!(20f64));
+        assert!(!events[1].contains_key(http_client::TLS_MS));
+    }
+
+    #[tokio::test]
+    async fn test_propagation_hosts() {
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let traced = request.headers().contains_key(TRACE_HEADER);
+                Ok::<_, Infallible>(Response::new(Body::from(traced.to_string())))
+            }))
+        }));
+        let url = format!("http://localhost:{}/charges", server.local_addr().port());
+        tokio::spawn(server);
+
+        let mut config = Config::default();
+        config.propagation_hosts = Some(beeline::PropagationHosts::new().deny("localhost"));
+        let beeline = new_client_with(config);
+        let client = hyper::Client::new();
+        let mut client = BeelineLayer::new(beeline.clone()).layer(client);
+
+        let trace = beeline.new_trace(None);
+        let mut request = Request::post(&url).body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(trace.lock().get_root_span());
+        let response = client.ready().await.unwrap().call(request).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"false");
+        // the request is traced all the same
+        assert_eq!(events(&beeline).len(), 1);
+    }
+    #[tokio::test]
+    async fn test_layer_error() {
+        let beeline = new_client();
+        let client = hyper::Client::builder().build::<_, Body>(TimedConnector::http());
+        let mut client = BeelineLayer::new(beeline.clone()).layer(client);
+
+        let trace = beeline.new_trace(None);
+        // nothing listens on the discard port
+        let mut request = Request::get("http://127.0.0.1:9/")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(trace.lock().get_root_span());
+        assert!(client.ready().await.unwrap().call(request).await.is_err());
+
+        let events = events(&beeline);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].contains_key(keys::ERROR));
+        assert!(!events[0].contains_key(http_client::STATUS_CODE));
+    }
+}
@@ -0,0 +1,251 @@
+/*! Honeycomb support for the MongoDB driver.
+
+`BeelineCommandHandler` listens to the commands the driver sends to the server, and creates
+a span for each of them as a child of the current span (see `beeline::context`), with the
+following fields:
+ - `meta.type` (always "db")
+ - `name` (always "mongodb")
+ - `db.system` (always "mongodb")
+ - `db.statement` (the name of the command, e.g. "find")
+ - `db.name`
+ - `db.mongodb.collection`, for the commands run against a collection
+ - `db.rows`, for the writes reporting the number of documents they affected
+ - `error`, `db.mongodb.error_code` and `db.mongodb.error_code_name`, when the command fails
+ - `meta.instrumentation` (always "beeline-mongodb") and `meta.instrumentation_version` (the
+   version of this crate)
+
+Commands run without a current span are not traced. The span is timed from the moment the
+command is sent until its reply, or failure, arrives.
+
+# Usage
+
+First add `beeline_mongodb` to your `Cargo.toml`:
+
+```toml
+[dependencies]
+beeline_mongodb = "0.1"
+```
+
+You then register the handler in the options of the client:
+
+```rust
+use std::sync::Arc;
+
+use beeline::{init, Config, Context};
+use beeline_mongodb::BeelineCommandHandler;
+use mongodb::bson::{doc, Document};
+use mongodb::options::ClientOptions;
+
+# async fn run() -> mongodb::error::Result<()> {
+let beeline = init(Config::default());
+let mut options = ClientOptions::parse("mongodb://localhost:27017").await?;
+options.command_event_handler = Some(Arc::new(BeelineCommandHandler::new(beeline.clone())));
+let mongo = mongodb::Client::with_options(options)?;
+
+let trace = beeline.new_trace(None);
+let _guard = Context::from_span(&trace.lock().get_root_span()).attach();
+let users = mongo.database("app").collection::<Document>("users");
+users.find_one(doc! { "name": "ada" }, None).await?;
+# Ok(())
+# }
+```
+
+ */
+
+#![deny(missing_docs)]
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use beeline::semconv::db;
+use beeline::trace::SafeSpan;
+use beeline::{keys, Client, Instrumentation, Sender};
+use mongodb::bson::Document;
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+use mongodb::event::command::{
+    CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent,
+};
+use serde_json::json;
+
+/// Stamped on the spans created by the handler
+const INSTRUMENTATION: Instrumentation = beeline::instrumentation!();
+
+/// database system of the spans, sent as `db.system`
+const SYSTEM: &str = "mongodb";
+/// name of the database the command runs against
+pub const DATABASE: &str = "db.name";
+/// collection the command runs against
+pub const COLLECTION: &str = "db.mongodb.collection";
+/// code of the error returned by the server
+pub const ERROR_CODE: &str = "db.mongodb.error_code";
+/// name of the code of the error returned by the server
+pub const ERROR_CODE_NAME: &str = "db.mongodb.error_code_name";
+
+/// `BeelineCommandHandler` creates a span for every command sent by the driver
+pub struct BeelineCommandHandler<T: Sender + Clone> {
+    client: Client<T>,
+    // spans of the commands waiting for a reply, by request id
+    in_flight: Mutex<HashMap<i32, SafeSpan>>,
+}
+
+impl<T: Sender + Clone> BeelineCommandHandler<T> {
+    /// `new` returns a handler sending the spans of commands through `client`
+    pub fn new(client: Client<T>) -> Self {
+        Self {
+            client,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn start(&self, parent: &SafeSpan, request_id: i32, database: &str, command: &Document) {
+        let command_name = match command.keys().next() {
+            Some(command_name) => command_name,
+            None => return,
+        };
+        let mut client = self.client.clone();
+        let span = match db::db_span(&mut client, parent, SYSTEM, command_name) {
+            Some(span) => span,
+            None => return,
+        };
+        {
+            let mut guard = span.lock();
+            INSTRUMENTATION.add_to(&mut guard);
+            guard.add_field(DATABASE, json!(database));
+            // the first value of a command names the collection it runs against, when
+            // there's one
+            if let Ok(collection) = command.get_str(command_name) {
+                guard.add_field(COLLECTION, json!(collection));
+            }
+        }
+        self.in_flight.lock().unwrap().insert(request_id, span);
+    }
+
+    fn finish(&self, request_id: i32, record: impl FnOnce(&SafeSpan)) {
+        let span = self.in_flight.lock().unwrap().remove(&request_id);
+        if let Some(span) = span {
+            record(&span);
+            span.lock().send(&mut self.client.clone());
+        }
+    }
+}
+
+impl<T: Sender + Clone> std::fmt::Debug for BeelineCommandHandler<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BeelineCommandHandler")
+            .field("in_flight", &self.in_flight.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<T> CommandEventHandler for BeelineCommandHandler<T>
+where
+    T: Sender + Clone + Send + Sync + 'static,
+{
+    fn handle_command_started_event(&self, event: CommandStartedEvent) {
+        if let Some(parent) = beeline::context::current_span() {
+            self.start(&parent, event.request_id, &event.db, &event.command);
+        }
+    }
+
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        self.finish(event.request_id, |span| record_reply(span, &event.reply));
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        self.finish(event.request_id, |span| {
+            record_failure(span, &event.failure)
+        });
+    }
+}
+
+/// `record_reply` adds the number of documents affected by a write to `span`
+fn record_reply(span: &SafeSpan, reply: &Document) {
+    if let Ok(rows) = reply.get_i32("n") {
+        db::record_rows(span, rows as u64);
+    }
+}
+
+/// `record_failure` adds the error of a failed command to `span`, along with the code the
+/// server returned it with, if any
+fn record_failure(span: &SafeSpan, failure: &Error) {
+    let mut span = span.lock();
+    span.add_field(keys::ERROR, json!(failure.to_string()));
+    let (code, code_name) = match &*failure.kind {
+        ErrorKind::Command(error) => (error.code, Some(error.code_name.clone())),
+        ErrorKind::Write(WriteFailure::WriteError(error)) => (error.code, error.code_name.clone()),
+        ErrorKind::Write(WriteFailure::WriteConcernError(error)) => {
+            (error.code, Some(error.code_name.clone()))
+        }
+        _ => return,
+    };
+    span.add_field(ERROR_CODE, json!(code));
+    if let Some(code_name) = code_name.filter(|code_name| !code_name.is_empty()) {
+        span.add_field(ERROR_CODE_NAME, json!(code_name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use beeline::test::{assert_field, events, TransmissionMock};
+    use beeline::Config;
+    use mongodb::bson::doc;
+
+    fn new_client() -> Client<TransmissionMock> {
+        let api_host = &mockito::server_url();
+        let _m = mockito::mock(
+            "POST",
+            mockito::Matcher::Regex(r"/1/batch/(.*)$".to_string()),
+        )
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body("[{ \"status\": 202 }]")
+        .create();
+
+        let mut config = Config::default();
+        config.client_config.options.api_host = api_host.to_string();
+        config.client_config.options.api_key = "key".to_string();
+        config.service_name = Some("beeline-mongodb-test".to_string());
+
+        beeline::test::init(config)
+    }
+
+    #[test]
+    fn test_command_spans() {
+        let beeline = new_client();
+        let handler = BeelineCommandHandler::new(beeline.clone());
+        let trace = beeline.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        handler.start(
+            &root_span,
+            1,
+            "app",
+            &doc! { "insert": "users", "ordered": true },
+        );
+        handler.start(&root_span, 2, "admin", &doc! { "ping": 1 });
+        handler.finish(2, |_| {});
+        handler.finish(1, |span| record_reply(span, &doc! { "n": 3, "ok": 1.0 }));
+        // replies to commands that weren't traced are ignored
+        handler.finish(3, |_| panic!("command 3 wasn't started"));
+        assert_eq!(handler.in_flight.lock().unwrap().len(), 0);
+
+        let events = events(&beeline);
+        assert_eq!(events.len(), 2);
+        assert_field(&events[0], db::STATEMENT, &json!("ping"));
+        assert_field(&events[0], DATABASE, &json!("admin"));
+        assert!(!events[0].contains_key(COLLECTION));
+        assert_field(&events[1], keys::META_TYPE, &json!("db"));
+        assert_field(&events[1], db::SYSTEM, &json!("mongodb"));
+        assert_field(&events[1], db::STATEMENT, &json!("insert"));
+        assert_field(&events[1], COLLECTION, &json!("users"));
+        assert_field(&events[1], db::ROWS, &json!(3));
+        assert_field(
+            &events[1],
+            keys::META_INSTRUMENTATION,
+            &json!("beeline-mongodb"),
+        );
+        assert!(events[1].contains_key(keys::TRACE_PARENT_ID));
+    }
+}
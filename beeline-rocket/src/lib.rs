@@ -2,13 +2,27 @@
 
 By default, the following fields are added to the trace:
  - `meta.type` (always "http_request")
+ - `meta.instrumentation` and `meta.instrumentation_version` (always "beeline-rocket" and
+   the version of this crate)
  - `request.method`
  - `request.path`
+ - `request.route` (the URI of the route the request matched, e.g. `/users/<id>`)
  - `request.header.<name>` (name is the same as the original header name but with dashes replaced with underscores)
    - example: `request.header.content_type`
  - `response.status`
  - `response.body.size`
 
+With a user extractor set through `BeelineMiddleware::with_user_extractor`, the caller of
+the request is added as well:
+ - `user.id`
+ - `user.tenant`
+ - `auth.scheme`
+
+Traces can be sampled heavily and still give cheap SLO metrics: with a `RouteLatency` set
+through `BeelineMiddleware::with_route_latency`, every request is rolled up in a
+`route_latency` event per route and interval, with latency percentiles and counts by status
+class.
+
 # Usage
 
 First add `beeline_rocket` to your `Cargo.toml`:
@@ -46,19 +60,74 @@ fn main() {
 }
 ```
 
+To find out who made each request, pass a function reading the request headers:
+
+```rust
+use beeline::{init, Config, UserInfo};
+use beeline_rocket::BeelineMiddleware;
+use rocket::http::HeaderMap;
+
+fn user(headers: &HeaderMap<'static>) -> Option<UserInfo> {
+    let authorization = headers.get_one("Authorization")?;
+    Some(UserInfo::from_authorization(authorization, "sub", "org_id"))
+}
+
+# if false {
+let client = init(Config::default());
+let middleware = BeelineMiddleware::new(client).with_user_extractor(user);
+# }
+```
+
+Rocket 0.4 has no shutdown hook, and events are sent in batches in the background. Keep a
+clone of the middleware around and flush it when the application stops, so the events from
+the last seconds aren't lost:
+
+```rust
+use beeline::{init, Config};
+use beeline_rocket::BeelineMiddleware;
+
+# if false {
+let middleware = BeelineMiddleware::new(init(Config::default()));
+let error = rocket::ignite().attach(middleware.clone()).launch();
+middleware.flush().expect("unable to flush events");
+panic!("{}", error);
+# }
+```
+
+The instrumentation of an application can be unit-tested with the helpers of the `test`
+module, which capture the events in memory instead of sending them to Honeycomb. It is
+only available with the `testutil` feature, meant for `dev-dependencies`.
+
  */
 
 #![feature(proc_macro_hygiene, decl_macro)]
 
+#[cfg(any(test, feature = "testutil"))]
+pub mod test;
+
+use std::time::Instant;
+
 use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::HeaderMap;
 use rocket::{Data, Request, Response, Rocket};
 use serde_json::{json, Value};
 
-use beeline::{trace::SafeSpan, trace::SafeTrace, trace::TraceSender, Client, Sender};
+use beeline::{
+    keys, route_latency, trace::SafeSpan, trace::SafeTrace, trace::TraceSender, BeelineError,
+    Client, Instrumentation, RouteLatency, Sender, UserExtractor, UserInfo,
+};
+
+/// Stamped on the spans created by the fairing
+const INSTRUMENTATION: Instrumentation = beeline::instrumentation!();
+
+/// header carrying the trace context of the upstream service
+const TRACE_HEADER: &str = "X-Honeycomb-Trace";
 
 #[derive(Debug, Clone)]
 pub struct BeelineMiddleware<S: Sender + Send + Sync + Clone> {
     client: Client<S>,
+    user_extractor: Option<UserExtractor<HeaderMap<'static>>>,
+    route_latency: Option<RouteLatency<S>>,
 }
 
 impl<S> BeelineMiddleware<S>
@@ -66,7 +135,35 @@ where
     S: Sender + Send + Sync + Clone,
 {
     pub fn new(client: Client<S>) -> Self {
-        Self { client }
+        Self {
+            client,
+            user_extractor: None,
+            route_latency: None,
+        }
+    }
+
+    /// `with_user_extractor` sets the function called with the headers of every request
+    /// to find out who made it
+    pub fn with_user_extractor<F>(mut self, extractor: F) -> Self
+    where
+        F: Fn(&HeaderMap<'static>) -> Option<UserInfo> + Send + Sync + 'static,
+    {
+        self.user_extractor = Some(UserExtractor::new(extractor));
+        self
+    }
+
+    /// `with_route_latency` records the latency and status of every request, sampled or
+    /// not, in `latency`, which emits a rollup per route and interval. Requests that
+    /// didn't match any route are rolled up under `<unmatched>`.
+    pub fn with_route_latency(mut self, latency: RouteLatency<S>) -> Self {
+        self.route_latency = Some(latency);
+        self
+    }
+
+    /// `flush` sends the traces still open and blocks until every queued event has been
+    /// transmitted. Call it during graceful shutdown.
+    pub fn flush(&self) -> Result<(), BeelineError> {
+        self.client.flush()
     }
 }
 
@@ -74,6 +171,7 @@ where
 struct InternalTrace {
     trace: Option<SafeTrace>,
     span: Option<SafeSpan>,
+    started: Option<Instant>,
 }
 
 impl<S> Fairing for BeelineMiddleware<S>
@@ -94,27 +192,45 @@ where
 
     fn on_request(&self, request: &mut Request, _: &Data) {
         let mut client = self.client.clone();
-        let trace = client.new_trace(None);
+        let header = request.headers().get_one(TRACE_HEADER).map(String::from);
+        let (trace, propagation_error) = match client.try_new_trace(header) {
+            Ok(trace) => (trace, None),
+            Err(e) => (client.new_trace(None), Some(e.to_string())),
+        };
         let rs = trace.lock().get_root_span();
+        if let Some(error) = propagation_error {
+            rs.lock()
+                .add_field(keys::META_PROPAGATION_ERROR, json!(error));
+        }
         let child = rs.lock().create_child(&mut client);
         if let Some(span) = child.clone() {
             let mut span_guard = span.lock();
             for header in request.headers().iter() {
                 span_guard.add_field(
-                    &format!(
-                        "request.header.{}",
-                        header.name.as_str().to_lowercase().replace("-", "_")
-                    ),
+                    &keys::header_key(header.name.as_str()),
                     json!(header.value()),
                 );
             }
-            span_guard.add_field("meta.type", json!("http_request"));
-            span_guard.add_field("request.method", json!(request.method().as_str()));
-            span_guard.add_field("request.path", json!(request.uri().path()));
+            span_guard.add_field(keys::META_TYPE, json!("http_request"));
+            INSTRUMENTATION.add_to(&mut span_guard);
+            span_guard.add_field(keys::REQUEST_METHOD, json!(request.method().as_str()));
+            span_guard.add_field(keys::REQUEST_PATH, json!(request.uri().path()));
+            if let Some(extractor) = &self.user_extractor {
+                // the extractor outlives the request, so it gets an owned copy of the
+                // headers
+                let mut headers = HeaderMap::new();
+                for header in request.headers().iter() {
+                    headers.add_raw(header.name.as_str().to_string(), header.value().to_string());
+                }
+                if let Some(user) = extractor.extract(&headers) {
+                    user.add_to(&mut span_guard);
+                }
+            }
         }
         request.local_cache(|| InternalTrace {
             trace: Some(trace.clone()),
             span: child.clone(),
+            started: Some(Instant::now()),
         });
     }
 
@@ -123,16 +239,37 @@ where
         let internal_trace: &InternalTrace = request.local_cache(|| InternalTrace {
             trace: None,
             span: None,
+            started: None,
         });
+        let route = request.route().map(|route| route.uri.to_string());
+        if let (Some(latency), Some(started)) = (&self.route_latency, internal_trace.started) {
+            let exemplar = internal_trace.trace.as_ref().and_then(|trace| {
+                let trace = trace.lock();
+                if trace.is_sampled_out() {
+                    None
+                } else {
+                    Some(trace.trace_id.clone())
+                }
+            });
+            latency.record(
+                route.as_deref().unwrap_or(route_latency::UNMATCHED_ROUTE),
+                response.status().code,
+                started.elapsed(),
+                exemplar.as_deref(),
+            );
+        }
         if let Some(span) = &internal_trace.span {
             let mut span_guard = span.lock();
-            span_guard.add_field("response.status_code", json!(response.status().code));
+            span_guard.add_field(keys::RESPONSE_STATUS_CODE, json!(response.status().code));
+            if let Some(route) = &route {
+                span_guard.add_field(keys::REQUEST_ROUTE, json!(route));
+            }
             if let Some(b) = response.body() {
                 let size = match b {
                     rocket::response::Body::Sized(_, size) => size,
                     rocket::response::Body::Chunked(_, size) => size,
                 };
-                span_guard.add_field("response.body.size", json!(size));
+                span_guard.add_field(keys::RESPONSE_BODY_SIZE, json!(size));
             }
         }
         if let Some(trace) = &internal_trace.trace {
@@ -144,6 +281,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
 
     use beeline::test::TransmissionMock;
     use beeline::Config;
@@ -195,4 +333,120 @@ mod tests {
         let events = beeline_client.0.write().client.transmission.events();
         assert_eq!(events.len(), 4);
     }
+
+    #[test]
+    fn test_propagation() {
+        let beeline_client = new_client();
+        let client = RocketClient::new(setup(beeline_client.clone())).unwrap();
+        for header in &["1;trace_id=abc,parent_id=def", "1;parent_id=def"] {
+            let _ = client
+                .get("/")
+                .header(rocket::http::Header::new(TRACE_HEADER, *header))
+                .dispatch();
+        }
+
+        let events = beeline_client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        let roots: Vec<_> = events
+            .iter()
+            .map(|event| event.fields())
+            .filter(|fields| !fields.contains_key("request.path"))
+            .collect();
+        assert_eq!(roots[0][keys::TRACE_TRACE_ID], json!("abc"));
+        assert_eq!(roots[0][keys::TRACE_PARENT_ID], json!("def"));
+        assert!(!roots[0].contains_key(keys::META_PROPAGATION_ERROR));
+        // a malformed header starts a fresh trace, telling why
+        assert_ne!(roots[1][keys::TRACE_TRACE_ID], json!("abc"));
+        assert!(roots[1][keys::META_PROPAGATION_ERROR]
+            .as_str()
+            .unwrap()
+            .contains("missing trace id"));
+    }
+
+    #[test]
+    fn test_user_extractor() {
+        let beeline_client = new_client();
+        let middleware =
+            BeelineMiddleware::new(beeline_client.clone()).with_user_extractor(|headers| {
+                Some(UserInfo {
+                    id: headers.get_one("X-User-Id").map(String::from),
+                    ..UserInfo::default()
+                })
+            });
+        let rocket = rocket::ignite()
+            .attach(middleware)
+            .mount("/", rocket::routes![index]);
+        let client = RocketClient::new(rocket).unwrap();
+        let _ = client
+            .get("/")
+            .header(rocket::http::Header::new("X-User-Id", "42"))
+            .dispatch();
+
+        let events = beeline_client.0.write().client.transmission.events();
+        let request = events
+            .iter()
+            .find(|event| event.fields().contains_key("request.path"))
+            .unwrap();
+        assert_eq!(request.fields()["user.id"], json!("42"));
+    }
+
+    #[test]
+    fn test_route_latency() {
+        let beeline_client = new_client();
+        let latency = beeline_client.start_route_latency(Duration::from_secs(3600));
+        let middleware =
+            BeelineMiddleware::new(beeline_client.clone()).with_route_latency(latency.clone());
+        let rocket = rocket::ignite()
+            .attach(middleware)
+            .mount("/", rocket::routes![index]);
+        let client = RocketClient::new(rocket).unwrap();
+        let _ = client.get("/").dispatch();
+        let _ = client.get("/missing").dispatch();
+        latency.emit();
+
+        let events = beeline_client.0.write().client.transmission.events();
+        let rollups: Vec<_> = events
+            .iter()
+            .map(|event| event.fields())
+            .filter(|fields| fields[keys::META_TYPE] == json!(route_latency::EVENT_TYPE))
+            .collect();
+        assert_eq!(rollups.len(), 2);
+        let index = rollups
+            .iter()
+            .find(|fields| fields[keys::REQUEST_ROUTE] == json!("/"))
+            .unwrap();
+        assert_eq!(index["count"], json!(1));
+        assert_eq!(index["response.status_class.2xx"], json!(1));
+    }
+
+    #[test]
+    fn test_helpers() {
+        let middleware = BeelineMiddleware::new(crate::test::client(Config::default()));
+        let client =
+            RocketClient::new(crate::test::rocket(&middleware).mount("/", rocket::routes![index]))
+                .unwrap();
+        let _ = client.get("/").dispatch();
+
+        let events = crate::test::events(&middleware);
+        let request = crate::test::find_event(&events, keys::REQUEST_ROUTE, &json!("/")).unwrap();
+        crate::test::assert_field(request, keys::RESPONSE_STATUS_CODE, &json!(200));
+        crate::test::assert_field(request, keys::META_TYPE, &json!("http_request"));
+    }
+
+    #[test]
+    fn test_flush() {
+        let beeline_client = new_client();
+        let middleware = BeelineMiddleware::new(beeline_client.clone());
+        let trace = beeline_client.new_trace(None);
+        trace
+            .lock()
+            .get_root_span()
+            .lock()
+            .add_field("job", json!("cleanup"));
+
+        middleware.flush().unwrap();
+        let events = beeline_client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields()["job"], json!("cleanup"));
+    }
 }
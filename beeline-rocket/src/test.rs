@@ -0,0 +1,57 @@
+/*! Helpers to unit-test the instrumentation of a Rocket application.
+
+The client returned by `client` keeps the events in memory instead of sending them to
+Honeycomb, `rocket` attaches the middleware to a new `Rocket` to mount the routes under
+test on and `events` returns what the middleware has sent so far:
+
+```rust
+#![feature(proc_macro_hygiene, decl_macro)]
+
+#[macro_use]
+extern crate rocket;
+
+use beeline::Config;
+use beeline_rocket::{test, BeelineMiddleware};
+use rocket::local::Client;
+use serde_json::json;
+
+#[get("/users/<id>")]
+fn user(id: u32) -> String {
+    id.to_string()
+}
+
+fn main() {
+    let middleware = BeelineMiddleware::new(test::client(Config::default()));
+    let client = Client::new(test::rocket(&middleware).mount("/", routes![user])).unwrap();
+    let _ = client.get("/users/42").dispatch();
+
+    let events = test::events(&middleware);
+    let request = test::find_event(&events, "request.route", &json!("/users/<id>")).unwrap();
+    test::assert_field(request, "request.path", &json!("/users/42"));
+}
+```
+ */
+
+use beeline::{Client, Config};
+use rocket::Rocket;
+
+use crate::BeelineMiddleware;
+
+pub use beeline::test::{assert_field, find_event, Fields, TransmissionMock};
+
+/// `client` returns a client keeping the events it sends in memory
+pub fn client(config: Config) -> Client<TransmissionMock> {
+    beeline::test::init(config)
+}
+
+/// `rocket` returns a new `Rocket` with `middleware` attached, to mount the routes under
+/// test on and pass to `rocket::local::Client::new`
+pub fn rocket(middleware: &BeelineMiddleware<TransmissionMock>) -> Rocket {
+    rocket::ignite().attach(middleware.clone())
+}
+
+/// `events` returns the fields of the events sent through `middleware` so far, in the
+/// order they were sent
+pub fn events(middleware: &BeelineMiddleware<TransmissionMock>) -> Vec<Fields> {
+    beeline::test::events(&middleware.client)
+}
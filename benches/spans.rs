@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use beeline::bench;
+use beeline::trace::TraceSender;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serde_json::json;
+
+fn bench_spans(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spans");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("create_child", |b| {
+        let mut client = bench::client();
+        b.iter_custom(|iters| {
+            // the children are tracked by the trace, which `measure` would forget
+            bench::reset(&client);
+            let trace = client.new_trace(None);
+            let root_span = trace.lock().get_root_span();
+            let start = Instant::now();
+            for _ in 0..iters {
+                let child = root_span.lock().create_child(&mut client).unwrap();
+                child.lock().send(&mut client);
+            }
+            let elapsed = start.elapsed();
+            trace.send(&mut client);
+            elapsed
+        });
+    });
+    group.bench_function("add_field", |b| {
+        let mut client = bench::client();
+        b.iter_custom(|iters| {
+            let trace = client.new_trace(None);
+            let root_span = trace.lock().get_root_span();
+            bench::measure(&mut client, iters, |_| {
+                root_span.lock().add_field("app.user_id", json!(42));
+            })
+        });
+    });
+    group.finish();
+}
+
+fn bench_propagation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("propagation");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("marshal", |b| {
+        let mut client = bench::client();
+        b.iter_custom(|iters| {
+            let trace = client.new_trace(Some(bench::HEADERS.to_string()));
+            let root_span = trace.lock().get_root_span();
+            bench::measure(&mut client, iters, |_| {
+                root_span.lock().serialize_headers();
+            })
+        });
+    });
+    group.bench_function("unmarshal", |b| {
+        let mut client = bench::client();
+        b.iter_custom(|iters| {
+            bench::measure(&mut client, iters, |_| {
+                bench::parse_headers(bench::HEADERS).unwrap();
+            })
+        });
+    });
+    group.finish();
+}
+
+fn bench_middleware_request(c: &mut Criterion) {
+    let mut group = c.benchmark_group("middleware");
+    // a service handling 10k requests per second has a budget of 100µs per request, of
+    // which instrumentation should only use a small fraction
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("request", |b| {
+        let mut client = bench::client();
+        b.iter_custom(|iters| bench::measure(&mut client, iters, bench::request));
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(5));
+    targets = bench_spans, bench_propagation, bench_middleware_request
+}
+criterion_main!(benches);
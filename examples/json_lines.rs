@@ -0,0 +1,60 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+use beeline::sink::{self, EventSink, SerializedEvent};
+use beeline::trace::TraceSender;
+use beeline::Config;
+use serde_json::json;
+
+/// `JsonLines` appends every event to a file, one JSON object per line, ready to be loaded
+/// into ClickHouse or uploaded to S3 by a separate job
+struct JsonLines {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl JsonLines {
+    fn open(path: &str) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+impl EventSink for JsonLines {
+    fn emit(&self, event: SerializedEvent) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writeln!(writer, "{}", event.to_json()) {
+            eprintln!("unable to write event: {}", e);
+        }
+    }
+
+    fn flush(&self) {
+        if let Err(e) = self.writer.lock().unwrap().flush() {
+            eprintln!("unable to flush events: {}", e);
+        }
+    }
+}
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "events.jsonl".to_string());
+    let sink = JsonLines::open(&path).expect("unable to open the events file");
+
+    let mut config = Config::default();
+    config.service_name = Some("beeline-json-lines".to_string());
+    let mut client = sink::init(config, sink);
+
+    let trace = client.new_trace(None);
+    let root_span = trace.lock().get_root_span();
+    root_span.lock().add_field("name", json!("import"));
+    if let Some(child) = root_span.lock().create_child(&mut client) {
+        child.lock().add_field("name", json!("parse"));
+    }
+    trace.send(&mut client);
+
+    client.close().expect("unable to close the client");
+    println!("events written to {}", path);
+}
@@ -0,0 +1,169 @@
+/*! Sending spans off the caller's critical path.
+
+`Span::send_async` and `TraceSender::send_async` finish the span bookkeeping (duration,
+ids, trace level fields) on the calling thread and hand the resulting events over to a
+per-client sender thread, which runs the sampler and presend hooks and transmits them. The
+returned `SendFuture` resolves once the sender thread has dealt with every event queued
+before it, so callers may await it or simply drop it.
+
+*/
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use libhoney::{Event, Sender};
+use parking_lot::Mutex;
+
+use crate::trace;
+use crate::Client;
+
+#[derive(Debug)]
+pub(crate) enum Job {
+    Send(Box<Event>),
+    Notify(SendFuture),
+}
+
+#[derive(Debug, Default)]
+struct Completion {
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// `SendFuture` resolves once the events queued by `send_async` have been sent (or
+/// dropped by the sampler)
+#[derive(Debug, Clone, Default)]
+#[must_use = "futures do nothing unless polled, but the events are sent regardless"]
+pub struct SendFuture(Arc<Mutex<Completion>>);
+
+impl SendFuture {
+    fn complete(&self) {
+        let mut completion = self.0.lock();
+        completion.done = true;
+        if let Some(waker) = completion.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// `wait` blocks the calling thread until the future resolves
+    pub(crate) fn wait(mut self) {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        while Pin::new(&mut self).poll(&mut cx).is_pending() {
+            thread::park();
+        }
+    }
+}
+
+/// `Unpark` wakes a thread blocked in `SendFuture::wait`
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl Future for SendFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut completion = self.0.lock();
+        if completion.done {
+            Poll::Ready(())
+        } else {
+            completion.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// `start` spawns the sender thread of `client`, unless it is already running. The thread
+/// only holds a weak reference to the client and stops once the client is dropped.
+pub(crate) fn start<T>(client: &Client<T>)
+where
+    T: Sender + Send + Sync + 'static,
+{
+    let mut guard = client.0.write();
+    if guard.async_queue.is_some() {
+        return;
+    }
+
+    let (queue, jobs) = mpsc::channel::<Job>();
+    let weak = Arc::downgrade(&client.0);
+    thread::Builder::new()
+        .name("beeline-sender".to_string())
+        .spawn(move || {
+            for job in jobs {
+                match job {
+                    Job::Send(mut ev) => {
+                        if let Some(inner) = weak.upgrade() {
+                            trace::dispatch(&mut Client(inner), &mut ev);
+                        }
+                    }
+                    Job::Notify(future) => future.complete(),
+                }
+            }
+        })
+        .expect("failed to spawn beeline sender thread");
+    guard.async_queue = Some(queue);
+}
+
+/// `enqueue` hands `job` over to the sender thread, giving it back if the thread is not
+/// running
+pub(crate) fn enqueue<T: Sender>(client: &Client<T>, job: Job) -> Result<(), Job> {
+    match &client.0.read().async_queue {
+        Some(queue) => queue.send(job).map_err(|e| e.0),
+        None => Err(job),
+    }
+}
+
+/// `notify` returns a future resolving once everything queued so far has been handled
+pub(crate) fn notify<T: Sender>(client: &Client<T>) -> SendFuture {
+    let future = SendFuture::default();
+    if let Err(Job::Notify(future)) = enqueue(client, Job::Notify(future.clone())) {
+        future.complete();
+    }
+    future
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    #[test]
+    fn test_send_async() {
+        let config = Config {
+            sampler_hook: Arc::new(|fields| (!fields.contains_key("drop"), 1).into()),
+            ..Default::default()
+        };
+        let mut client = new_client(config);
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        let child = root_span.lock().create_child(&mut client).unwrap();
+        let dropped = root_span.lock().create_child(&mut client).unwrap();
+        dropped.lock().add_field("drop", serde_json::json!(true));
+        child.lock().add_field("key", serde_json::json!("value"));
+
+        trace.send_async(&mut client).wait();
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].fields()["key"], serde_json::json!("value"));
+        assert_eq!(client.stats().total_sampled_out(), 1);
+
+        // sending again is a no-op that still resolves
+        trace.send_async(&mut client).wait();
+        assert_eq!(client.0.write().client.transmission.events().len(), 2);
+    }
+
+    #[test]
+    fn test_notify_without_sender_thread() {
+        let client = new_client(Config::default());
+        notify(&client).wait();
+    }
+}
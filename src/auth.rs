@@ -0,0 +1,164 @@
+/*! API key validation.
+
+With `Config::validate_api_key` set, `init` asks the Honeycomb auth endpoint about the
+configured API key before sending anything, instead of queueing events that would be
+rejected. A valid key tells which team (and, for Environments & Services keys, which
+environment) the events go to, recorded on every event as `meta.team` and
+`meta.environment`.
+
+*/
+use reqwest::blocking::Client as HttpClient;
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::errors::{BeelineError, ConfigErrorKind, Result};
+use crate::markers::HONEYCOMB_TEAM_HEADER;
+
+const AUTH_ENDPOINT: &str = "/1/auth";
+
+/// `ApiKeyType` is the kind of Honeycomb team an API key belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyType {
+    /// key of a Honeycomb Classic team, whose datasets aren't grouped in environments
+    Classic,
+    /// key of an environment of an Environments & Services team
+    Environment,
+}
+
+/// `ApiKeyInfo` is what the Honeycomb auth endpoint knows about an API key
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKeyInfo {
+    /// kind of team the key belongs to
+    pub key_type: ApiKeyType,
+    /// slug of the team
+    pub team: String,
+    /// slug of the environment, for Environments & Services keys
+    pub environment: Option<String>,
+}
+
+impl ApiKeyInfo {
+    fn from_response(body: &Value) -> Result<Self> {
+        let slug = |name: &str| {
+            body.get(name)
+                .and_then(|object| object.get("slug"))
+                .and_then(Value::as_str)
+                .filter(|slug| !slug.is_empty())
+                .map(String::from)
+        };
+        let team =
+            slug("team").ok_or_else(|| BeelineError::send("auth response is missing the team"))?;
+        // classic keys come with an empty environment
+        let environment = slug("environment");
+        Ok(Self {
+            key_type: match environment {
+                Some(_) => ApiKeyType::Environment,
+                None => ApiKeyType::Classic,
+            },
+            team,
+            environment,
+        })
+    }
+}
+
+/// `validate` asks the auth endpoint of `api_host` about `api_key`. A key the API
+/// rejects is a `BeelineError::ConfigError` of kind `ConfigErrorKind::InvalidApiKey`; failing to reach the API, or an unexpected
+/// response, is a `BeelineError::SendError`, as it says nothing about the key.
+pub(crate) fn validate(http: &HttpClient, api_host: &str, api_key: &str) -> Result<ApiKeyInfo> {
+    let response = http
+        .get(&format!(
+            "{}{}",
+            api_host.trim_end_matches('/'),
+            AUTH_ENDPOINT
+        ))
+        .header(HONEYCOMB_TEAM_HEADER, api_key)
+        .send()
+        .map_err(|e| BeelineError::send_caused_by("unable to validate API key", e))?;
+
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err(BeelineError::config(
+                ConfigErrorKind::InvalidApiKey,
+                format!("API key rejected by {}", api_host),
+            ))
+        }
+        status if !status.is_success() => {
+            return Err(BeelineError::send(format!(
+                "auth API responded with {}",
+                status
+            )))
+        }
+        _ => {}
+    }
+
+    let body: Value = response
+        .json()
+        .map_err(|e| BeelineError::send_caused_by("unable to parse auth response", e))?;
+    ApiKeyInfo::from_response(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let _m = mockito::mock("GET", "/1/auth")
+            .match_header(HONEYCOMB_TEAM_HEADER, "env-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"api_key_access":{"events":true},"environment":{"name":"Production","slug":"production"},"team":{"name":"Acme","slug":"acme"}}"#,
+            )
+            .create();
+        let info = validate(&HttpClient::new(), &mockito::server_url(), "env-key").unwrap();
+        assert_eq!(
+            info,
+            ApiKeyInfo {
+                key_type: ApiKeyType::Environment,
+                team: "acme".to_string(),
+                environment: Some("production".to_string()),
+            }
+        );
+
+        let _m = mockito::mock("GET", "/1/auth")
+            .match_header(HONEYCOMB_TEAM_HEADER, "classic-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"environment":{"name":"","slug":""},"team":{"name":"Acme","slug":"acme"}}"#,
+            )
+            .create();
+        let info = validate(&HttpClient::new(), &mockito::server_url(), "classic-key").unwrap();
+        assert_eq!(info.key_type, ApiKeyType::Classic);
+        assert_eq!(info.environment, None);
+    }
+
+    #[test]
+    fn test_validate_failure() {
+        let _m = mockito::mock("GET", "/1/auth")
+            .match_header(HONEYCOMB_TEAM_HEADER, "bad-key")
+            .with_status(401)
+            .create();
+        let err = validate(&HttpClient::new(), &mockito::server_url(), "bad-key").unwrap_err();
+        assert!(matches!(
+            err,
+            BeelineError::ConfigError {
+                kind: ConfigErrorKind::InvalidApiKey,
+                ..
+            }
+        ));
+
+        // a response without a team says nothing about the key
+        let _m = mockito::mock("GET", "/1/auth")
+            .match_header(HONEYCOMB_TEAM_HEADER, "teamless-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body("{}")
+            .create();
+        let err = validate(&HttpClient::new(), &mockito::server_url(), "teamless-key").unwrap_err();
+        assert!(matches!(err, BeelineError::SendError { .. }));
+
+        let err = validate(&HttpClient::new(), "http://127.0.0.1:1", "key").unwrap_err();
+        assert!(matches!(err, BeelineError::SendError { .. }));
+    }
+}
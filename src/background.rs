@@ -0,0 +1,77 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// `BackgroundTask` is a handle to a thread periodically emitting events on behalf of
+/// the client. Dropping the handle asks the thread to stop; `stop` additionally waits for
+/// it to finish.
+#[derive(Debug)]
+pub struct BackgroundTask {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BackgroundTask {
+    /// `spawn` runs `tick` on a new thread every `interval` until the returned handle is
+    /// stopped or dropped
+    pub(crate) fn spawn<F>(name: &str, interval: Duration, mut tick: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                // anything but a timeout (an explicit stop or the handle being dropped)
+                // ends the loop
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    tick();
+                }
+            })
+            .expect("failed to spawn background thread");
+
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// `stop` signals the background thread to stop and waits for it to finish
+    pub fn stop(mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for BackgroundTask {
+    fn drop(&mut self) {
+        // dropping the sender disconnects the channel, which the thread treats as a
+        // request to stop
+        self.stop.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_spawn_and_stop() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let inner = ticks.clone();
+        let task = BackgroundTask::spawn("beeline-test", Duration::from_millis(5), move || {
+            inner.fetch_add(1, Ordering::SeqCst);
+        });
+        thread::sleep(Duration::from_millis(50));
+        task.stop();
+
+        let seen = ticks.load(Ordering::SeqCst);
+        assert!(seen > 0);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(ticks.load(Ordering::SeqCst), seen);
+    }
+}
@@ -0,0 +1,101 @@
+/*! Harness for benchmarks of the beeline and of its integrations.
+
+Only available with the `bench` feature. It holds the workloads of the benchmarks in
+`benches/`, so the integrations, and applications wanting to check the overhead of the
+beeline on their own hardware, measure the same thing:
+
+```rust
+use std::time::Duration;
+use beeline::bench;
+
+let mut client = bench::client();
+let elapsed: Duration = bench::measure(&mut client, 1000, bench::request);
+println!("{:?} per request", elapsed / 1000);
+```
+
+`measure` fits criterion's `Bencher::iter_custom`:
+
+```rust,ignore
+group.bench_function("request", |b| {
+    let mut client = bench::client();
+    b.iter_custom(|iters| bench::measure(&mut client, iters, bench::request));
+});
+```
+ */
+
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::errors::Result;
+use crate::propagation::Propagation;
+use crate::test::{self, TransmissionMock};
+use crate::trace::TraceSender;
+use crate::{Client, Config};
+
+/// trace headers of a request coming from an instrumented service
+pub const HEADERS: &str = "1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=eyJ1c2VySUQiOjF9";
+
+/// `client` returns a client keeping its events in memory, as the benchmarks shouldn't
+/// measure the network
+pub fn client() -> Client<TransmissionMock> {
+    test::init(Config {
+        service_name: Some("bench".to_string()),
+        ..Config::default()
+    })
+}
+
+/// `reset` forgets the events and traces `client` holds. The mock transmission keeps every
+/// event and the client every trace, so the memory they use would otherwise skew the
+/// results of long runs.
+pub fn reset(client: &Client<TransmissionMock>) {
+    let transmission = self::client().0.read().client.transmission.clone();
+    let mut inner = client.0.write();
+    inner.client.transmission = transmission;
+    inner.traces.lock().clear();
+}
+
+/// `measure` resets `client` and returns how long running `work` with it `iters` times
+/// takes
+pub fn measure<F>(client: &mut Client<TransmissionMock>, iters: u64, mut work: F) -> Duration
+where
+    F: FnMut(&mut Client<TransmissionMock>),
+{
+    reset(client);
+    let start = Instant::now();
+    for _ in 0..iters {
+        work(client);
+    }
+    start.elapsed()
+}
+
+/// `request` does what an HTTP middleware does for a single request: continue the upstream
+/// trace, describe the request on the root span, create a couple of children for the work
+/// done by the handler and send everything.
+pub fn request(client: &mut Client<TransmissionMock>) {
+    let trace = client.new_trace(Some(HEADERS.to_string()));
+    let root_span = trace.lock().get_root_span();
+    {
+        let mut span = root_span.lock();
+        span.add_field("meta.type", json!("http_request"));
+        span.add_field("request.method", json!("GET"));
+        span.add_field("request.path", json!("/users/42"));
+        span.add_field("request.header.user_agent", json!("curl/7.64.1"));
+        span.add_field("request.header.accept", json!("*/*"));
+    }
+    for name in &["db.query", "cache.get"] {
+        let child = root_span.lock().create_child(client).unwrap();
+        child.lock().add_field("name", json!(name));
+        child.lock().send(client);
+    }
+    root_span
+        .lock()
+        .add_field("response.status_code", json!(200));
+    trace.send(client);
+}
+
+/// `parse_headers` parses trace headers like `Client::new_trace` does, without starting a
+/// trace
+pub fn parse_headers(headers: &str) -> Result<()> {
+    Propagation::unmarshal_trace_context(headers).map(|_| ())
+}
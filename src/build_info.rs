@@ -0,0 +1,72 @@
+use libhoney::Value;
+
+/// `BuildInfo` describes the build of the instrumented application. When set in
+/// `Config`, it is added as `meta.*` fields to every event so regressions can be
+/// correlated with releases.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BuildInfo {
+    /// git commit the application was built from, sent as `meta.git_sha`
+    pub git_sha: Option<String>,
+    /// version of the application, sent as `meta.version`
+    pub version: Option<String>,
+    /// time the application was built at, sent as `meta.build_time`
+    pub build_time: Option<String>,
+    /// version of rustc used to build the application, sent as `meta.rustc_version`
+    pub rustc: Option<String>,
+}
+
+impl BuildInfo {
+    pub(crate) fn fields(&self) -> Vec<(&'static str, Value)> {
+        [
+            ("meta.version", &self.version),
+            ("meta.git_sha", &self.git_sha),
+            ("meta.build_time", &self.build_time),
+            ("meta.rustc_version", &self.rustc),
+        ]
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .as_ref()
+                .map(|value| (*name, Value::String(value.clone())))
+        })
+        .collect()
+    }
+}
+
+/// `build_info!` builds a `BuildInfo` from the `VERGEN_*` environment variables set when
+/// compiling the calling crate. The beeline doesn't depend on `vergen`: the calling crate
+/// runs it (or sets the variables itself) in its own `build.rs`; fields whose variable isn't
+/// set are left unset.
+#[cfg(feature = "build-info-macro")]
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::BuildInfo {
+            git_sha: option_env!("VERGEN_GIT_SHA").map(String::from),
+            version: option_env!("CARGO_PKG_VERSION").map(String::from),
+            build_time: option_env!("VERGEN_BUILD_TIMESTAMP").map(String::from),
+            rustc: option_env!("VERGEN_RUSTC_SEMVER").map(String::from),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let info = BuildInfo {
+            git_sha: Some("9fceb02".to_string()),
+            version: Some("1.2.3".to_string()),
+            ..BuildInfo::default()
+        };
+        assert_eq!(
+            info.fields(),
+            vec![
+                ("meta.version", Value::String("1.2.3".to_string())),
+                ("meta.git_sha", Value::String("9fceb02".to_string())),
+            ]
+        );
+    }
+}
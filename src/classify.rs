@@ -0,0 +1,100 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::trace::Span;
+
+type ClassifierFn<H> = dyn Fn(u16, &H) -> Option<String> + Send + Sync;
+
+/// `ResponseClassifier` wraps the hook an HTTP integration calls with the status and
+/// headers of every response (`H` being the header map type of the library) to find out
+/// whether it is a failure, in the manner of tower-http's `ClassifyResponse`. It lets
+/// responses the status code alone doesn't tell apart, e.g. a gRPC error or a GraphQL
+/// response with errors, count as failed.
+pub struct ResponseClassifier<H>(Arc<ClassifierFn<H>>);
+
+impl<H> ResponseClassifier<H> {
+    /// `new` wraps `classifier`, which returns the reason a response failed, or `None`
+    /// for a success
+    pub fn new<F>(classifier: F) -> Self
+    where
+        F: Fn(u16, &H) -> Option<String> + Send + Sync + 'static,
+    {
+        Self(Arc::new(classifier))
+    }
+
+    /// `classify` runs the hook
+    pub fn classify(&self, status: u16, headers: &H) -> Option<String> {
+        (self.0)(status, headers)
+    }
+
+    /// `record` runs the hook and, for a failed response, records the reason on `span`
+    /// with `Span::record_error`, so the root span of its trace gets `app.error` set. It
+    /// returns whether the response failed.
+    pub fn record(&self, span: &mut Span, status: u16, headers: &H) -> bool {
+        match self.classify(status, headers) {
+            Some(reason) => {
+                span.record_error(&reason);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<H> Clone for ResponseClassifier<H> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<H> fmt::Debug for ResponseClassifier<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ResponseClassifier(Fn())")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys;
+    use crate::tests::new_client;
+    use crate::Config;
+    use libhoney::json;
+
+    #[test]
+    fn test_response_classifier() {
+        let classifier = ResponseClassifier::new(|status, headers: &Vec<(&str, &str)>| {
+            let grpc_status = headers
+                .iter()
+                .find(|(name, _)| *name == "grpc-status")
+                .map(|(_, value)| *value);
+            match grpc_status {
+                Some(code) if code != "0" => Some(format!("grpc-status {}", code)),
+                _ if status >= 500 => Some(format!("status {}", status)),
+                _ => None,
+            }
+        });
+        assert_eq!(classifier.classify(200, &vec![]), None);
+        assert_eq!(
+            classifier.classify(200, &vec![("grpc-status", "14")]),
+            Some("grpc-status 14".to_string())
+        );
+        assert_eq!(
+            classifier.clone().classify(503, &vec![]),
+            Some("status 503".to_string())
+        );
+
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        assert!(!classifier.record(&mut root_span.lock(), 200, &vec![]));
+        assert!(classifier.record(&mut root_span.lock(), 200, &vec![("grpc-status", "14")]));
+        root_span.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert_eq!(fields[keys::ERROR], json!("grpc-status 14"));
+        assert_eq!(fields[keys::SPAN_STATUS], json!("error"));
+        assert_eq!(fields[keys::APP_ERROR], json!(true));
+    }
+}
@@ -0,0 +1,143 @@
+/*! Carrying the current span over to other threads.
+
+Work offloaded to another thread (a rayon pool, a blocking thread) has no idea which span
+it is running under. `Context::capture` takes a cheap, `Send` handle on the current span
+of the calling thread, and `Context::attach` makes it the current span of the thread the
+work runs on, until the returned guard is dropped:
+
+```rust
+use beeline::{init, Config, Context};
+
+# if false {
+let mut client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+let _guard = Context::from_span(&root_span).attach();
+
+let context = Context::capture();
+let mut thread_client = client.clone();
+std::thread::spawn(move || {
+    let _guard = context.attach();
+    if let Some(span) = beeline::context::current_span() {
+        let child = span.lock().create_child(&mut thread_client);
+        // ...
+    }
+});
+# }
+```
+
+*/
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+use crate::trace::SafeSpan;
+
+thread_local! {
+    static CURRENT: RefCell<Option<SafeSpan>> = const { RefCell::new(None) };
+}
+
+/// `current_span` returns the span attached to the calling thread or, with the `tokio`
+/// feature, the span of the task started with `spawn` this is called from
+pub fn current_span() -> Option<SafeSpan> {
+    let span = CURRENT.with(|current| current.borrow().clone());
+    #[cfg(feature = "tokio")]
+    let span = span.or_else(crate::spawn::current_span);
+    span
+}
+
+/// `Context` is a handle on a span that can be sent to another thread and attached there
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    span: Option<SafeSpan>,
+}
+
+impl Context {
+    /// `capture` returns the context of the calling thread, see `current_span`
+    pub fn capture() -> Self {
+        Self {
+            span: current_span(),
+        }
+    }
+
+    /// `from_span` returns a context whose current span is `span`
+    pub fn from_span(span: &SafeSpan) -> Self {
+        Self {
+            span: Some(span.clone()),
+        }
+    }
+
+    /// `span` returns the span of the context, if any
+    pub fn span(&self) -> Option<SafeSpan> {
+        self.span.clone()
+    }
+
+    /// `attach` makes the span of this context the current span of the calling thread.
+    /// The previous one is restored when the guard is dropped.
+    pub fn attach(&self) -> ContextGuard {
+        let previous = CURRENT.with(|current| current.replace(self.span.clone()));
+        ContextGuard {
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// `ContextGuard` restores the previous context of the thread when dropped
+#[derive(Debug)]
+#[must_use = "the context is detached as soon as the guard is dropped"]
+pub struct ContextGuard {
+    previous: Option<SafeSpan>,
+    // the guard must be dropped on the thread it was created on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::{Client, Config};
+    use serde_json::json;
+    use std::thread;
+
+    #[test]
+    fn test_capture_and_attach() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        assert!(Context::capture().span().is_none());
+
+        let context = {
+            let _guard = Context::from_span(&root_span).attach();
+            Context::capture()
+        };
+        assert!(current_span().is_none());
+
+        let mut thread_client = Client(client.0.clone());
+        thread::spawn(move || {
+            let _guard = context.attach();
+            let span = current_span().unwrap();
+            let child = span.lock().create_child(&mut thread_client).unwrap();
+            child.lock().add_field("name", json!("offloaded"));
+            child.lock().send(&mut thread_client);
+        })
+        .join()
+        .unwrap();
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].fields()["name"], json!("offloaded"));
+        assert_eq!(
+            events[0].fields()["trace.parent_id"],
+            events[1].fields()["trace.span_id"]
+        );
+    }
+}
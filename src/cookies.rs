@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::trace::Span;
+
+const FIELD_PREFIX: &str = "request.cookie.";
+/// hex digits of the SHA-256 of a value kept by `CookieMode::Hash`
+const HASH_LENGTH: usize = 16;
+
+/// `CookieMode` is how a cookie is recorded, as `request.cookie.<name>`. Values are never
+/// recorded as is, as cookies often hold session tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieMode {
+    /// only record that the cookie was sent, as `true`
+    Presence,
+    /// record the start of the SHA-256 of the value, enough to tell whether two requests
+    /// carried the same session without storing the session token
+    Hash,
+}
+
+/// `CookieRecorder` records the allowlisted cookies of requests on their span. The HTTP
+/// middlewares hand it the `Cookie` headers of every request.
+#[derive(Debug, Clone, Default)]
+pub struct CookieRecorder {
+    cookies: HashMap<String, CookieMode>,
+}
+
+impl CookieRecorder {
+    /// `new` returns a recorder with an empty allowlist
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `with_cookie` adds the cookie `name` to the allowlist, recorded as per `mode`
+    pub fn with_cookie(mut self, name: &str, mode: CookieMode) -> Self {
+        self.cookies.insert(name.to_string(), mode);
+        self
+    }
+
+    /// `record` adds the allowlisted cookies found in the `Cookie` header value `header`
+    /// (e.g. "session=abc; theme=dark") to `span`
+    pub fn record(&self, span: &mut Span, header: &str) {
+        for (name, value) in parse(header) {
+            let value = match self.cookies.get(name) {
+                Some(CookieMode::Presence) => json!(true),
+                Some(CookieMode::Hash) => json!(hash(value)),
+                None => continue,
+            };
+            span.add_field(&format!("{}{}", FIELD_PREFIX, name), value);
+        }
+    }
+}
+
+/// `parse` splits a `Cookie` header into the names and values of its cookies
+fn parse(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        let name = parts.next()?.trim();
+        let value = parts.next()?.trim().trim_matches('"');
+        Some((name, value)).filter(|(name, _)| !name.is_empty())
+    })
+}
+
+fn hash(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    let mut hex = digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    hex.truncate(HASH_LENGTH);
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    #[test]
+    fn test_record() {
+        let mut client = new_client(Config::default());
+        let recorder = CookieRecorder::new()
+            .with_cookie("session", CookieMode::Hash)
+            .with_cookie("csrf", CookieMode::Presence)
+            .with_cookie("missing", CookieMode::Presence);
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        recorder.record(
+            &mut root_span.lock(),
+            "session=\"s3cr3t-token\"; csrf=abc123; theme=dark; broken",
+        );
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        let session = fields["request.cookie.session"].as_str().unwrap();
+        assert_eq!(session, &hash("s3cr3t-token"));
+        assert_eq!(session.len(), HASH_LENGTH);
+        assert!(!session.contains("s3cr3t"));
+        assert_eq!(fields["request.cookie.csrf"], json!(true));
+        assert!(!fields.contains_key("request.cookie.theme"));
+        assert!(!fields.contains_key("request.cookie.missing"));
+    }
+}
@@ -0,0 +1,100 @@
+/*! Dataset names Honeycomb accepts.
+
+Events are sent to a URL ending with the name of their dataset, and Honeycomb rejects, with
+a 4xx response, the batches of datasets whose names it can't turn into a slug. Rather than
+queueing such events, the beeline checks dataset names where they come in:
+ - `try_init` returns an error when the configured dataset is invalid
+ - a dataset propagated by the trace header of an upstream service is sanitized, and
+   ignored (the client dataset being used instead) when nothing is left of it
+
+Valid names are made of ASCII letters, digits, spaces, `-`, `_` and `.`, with at least one
+letter or digit.
+
+```rust
+use beeline::dataset;
+
+assert!(dataset::validate("checkout-api").is_ok());
+assert!(dataset::validate("team/checkout").is_err());
+assert_eq!(dataset::sanitize("team/checkout"), Some("team-checkout".to_string()));
+assert_eq!(dataset::sanitize("///"), None);
+```
+
+*/
+use crate::errors::{BeelineError, ConfigErrorKind, Result};
+
+/// `validate` returns a `BeelineError::ConfigError` naming the first character of
+/// `dataset` Honeycomb doesn't accept, or telling it has no letter or digit
+pub fn validate(dataset: &str) -> Result<()> {
+    if let Some(c) = dataset.chars().find(|c| !is_valid(*c)) {
+        return Err(BeelineError::config(
+            ConfigErrorKind::InvalidDataset,
+            format!(
+                "dataset {:?} contains {:?}, only ASCII letters, digits, spaces, '-', '_' and '.' are allowed",
+                dataset, c
+            ),
+        ));
+    }
+    if !dataset.chars().any(|c| c.is_ascii_alphanumeric()) {
+        return Err(BeelineError::config(
+            ConfigErrorKind::InvalidDataset,
+            format!("dataset {:?} has no letter or digit", dataset),
+        ));
+    }
+    Ok(())
+}
+
+/// `sanitize` returns `dataset`, trimmed, with every character Honeycomb doesn't accept
+/// replaced with `-`, or None when it has no letter or digit left
+pub fn sanitize(dataset: &str) -> Option<String> {
+    let sanitized: String = dataset
+        .trim()
+        .chars()
+        .map(|c| if is_valid(c) { c } else { '-' })
+        .collect();
+    if sanitized.chars().any(|c| c.is_ascii_alphanumeric()) {
+        Some(sanitized)
+    } else {
+        None
+    }
+}
+
+fn is_valid(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("beeline-rust").is_ok());
+        assert!(validate("Checkout API_v2.1").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate(" - ").is_err());
+        assert!(validate("team/checkout").is_err());
+        assert!(validate("données").is_err());
+        assert!(validate("checkout\n").is_err());
+
+        let err = validate("a/b").unwrap_err();
+        assert!(matches!(
+            err,
+            BeelineError::ConfigError {
+                kind: ConfigErrorKind::InvalidDataset,
+                ..
+            }
+        ));
+        assert!(err.to_string().contains("'/'"));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("beeline-rust"), Some("beeline-rust".into()));
+        assert_eq!(sanitize(" team/checkout "), Some("team-checkout".into()));
+        assert_eq!(sanitize("données"), Some("donn-es".into()));
+        assert_eq!(sanitize("a\\b?c#d"), Some("a-b-c-d".into()));
+        assert_eq!(sanitize("日本"), None);
+        assert_eq!(sanitize("//"), None);
+        assert_eq!(sanitize(""), None);
+    }
+}
@@ -0,0 +1,109 @@
+/*! Request deadlines set by callers.
+
+Callers often tell how long they are willing to wait for a response, and give up past that
+point. A handler finishing after the deadline did its work for nothing, and the caller's own
+callers may be timing out in turn. The HTTP middlewares read the deadline from the
+`X-Request-Timeout` and `grpc-timeout` headers, and record on the span of the request:
+ - `request.deadline_ms`, the deadline in milliseconds
+ - `meta.deadline_exceeded`, whether the request took longer than that
+
+`X-Request-Timeout` holds milliseconds (`250`), or a number of milliseconds or seconds with a
+unit (`250ms`, `1.5s`). `grpc-timeout` follows the gRPC format: at most 8 digits and a unit
+among `H`, `M`, `S`, `m`, `u` and `n`.
+
+*/
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::keys;
+use crate::trace::Span;
+
+/// header holding the deadline of HTTP requests
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+/// header holding the deadline of gRPC requests
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+/// headers a deadline is read from, in order of precedence
+pub const HEADERS: &[&str] = &[REQUEST_TIMEOUT_HEADER, GRPC_TIMEOUT_HEADER];
+
+/// `from_header` returns the deadline held by `value`, the value of the header `name`,
+/// which is one of `HEADERS`
+pub fn from_header(name: &str, value: &str) -> Option<Duration> {
+    if name.eq_ignore_ascii_case(GRPC_TIMEOUT_HEADER) {
+        parse_grpc_timeout(value)
+    } else if name.eq_ignore_ascii_case(REQUEST_TIMEOUT_HEADER) {
+        parse_request_timeout(value)
+    } else {
+        None
+    }
+}
+
+/// `parse_request_timeout` parses the value of a `X-Request-Timeout` header
+pub fn parse_request_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, seconds) = if let Some(number) = value.strip_suffix("ms") {
+        (number, false)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, true)
+    } else {
+        (value, false)
+    };
+    let number = number.trim().parse::<f64>().ok()?;
+    let seconds = if seconds { number } else { number / 1000f64 };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// `parse_grpc_timeout` parses the value of a `grpc-timeout` header
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    // the unit is split off by byte
+    if !value.is_ascii() || value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let amount = digits.parse::<u64>().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// `record` adds the deadline of a request that took `elapsed` to its span
+pub fn record(span: &mut Span, deadline: Duration, elapsed: Duration) {
+    span.add_field(
+        keys::REQUEST_DEADLINE_MS,
+        json!(deadline.as_secs_f64() * 1000f64),
+    );
+    span.add_field(keys::META_DEADLINE_EXCEEDED, json!(elapsed > deadline));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_header() {
+        let ms = Duration::from_millis;
+        assert_eq!(from_header("X-Request-Timeout", "250"), Some(ms(250)));
+        assert_eq!(from_header("x-request-timeout", "250ms"), Some(ms(250)));
+        assert_eq!(from_header("x-request-timeout", "1.5s"), Some(ms(1500)));
+        assert_eq!(from_header("x-request-timeout", "soon"), None);
+        assert_eq!(from_header("x-request-timeout", "-1"), None);
+        assert_eq!(from_header("grpc-timeout", "100m"), Some(ms(100)));
+        assert_eq!(from_header("grpc-timeout", "2S"), Some(ms(2000)));
+        assert_eq!(from_header("grpc-timeout", "1H"), Some(ms(3_600_000)));
+        assert_eq!(from_header("grpc-timeout", "123456789m"), None);
+        assert_eq!(from_header("grpc-timeout", "10x"), None);
+        assert_eq!(from_header("grpc-timeout", "+1S"), None);
+        assert_eq!(from_header("grpc-timeout", "1é"), None);
+        assert_eq!(from_header("timeout", "100"), None);
+    }
+}
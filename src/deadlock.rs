@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use parking_lot::deadlock;
+
+use crate::background::BackgroundTask;
+use crate::trace::SafeSpan;
+use crate::{Client, Sender};
+
+/// `DeadlockedThread` is one of the threads stuck in a deadlock
+#[derive(Debug, Clone)]
+pub struct DeadlockedThread {
+    /// id of the thread, as reported by parking_lot
+    pub thread_id: usize,
+    /// backtrace of the thread at the time it blocked
+    pub backtrace: String,
+}
+
+/// `Deadlock` is what the detector found on a check: the threads waiting on each other
+/// and the traces of the client that were locked at the time
+#[derive(Debug, Clone)]
+pub struct Deadlock {
+    /// the threads involved in the deadlock
+    pub threads: Vec<DeadlockedThread>,
+    /// ids of the traces whose lock, or the lock of one of their spans, was held. Spans
+    /// in use by healthy threads are locked too, so this is a list of suspects.
+    pub locked_traces: Vec<String>,
+}
+
+impl<T> Client<T>
+where
+    T: Sender + Send + Sync + 'static,
+{
+    /// `start_deadlock_detection` runs parking_lot's deadlock detector every `interval`
+    /// and hands `callback` each deadlock it finds, typically caused by `SafeSpan` or
+    /// `SafeTrace` locks taken in a different order by two threads. The detector runs
+    /// until the returned handle is stopped or dropped.
+    pub fn start_deadlock_detection<F>(&self, interval: Duration, callback: F) -> BackgroundTask
+    where
+        F: Fn(&Deadlock) + Send + 'static,
+    {
+        let client = Client(self.0.clone());
+        BackgroundTask::spawn("beeline-deadlock-detection", interval, move || {
+            let deadlocks = deadlock::check_deadlock();
+            if deadlocks.is_empty() {
+                return;
+            }
+
+            let locked_traces = client.locked_traces();
+            for threads in deadlocks {
+                callback(&Deadlock {
+                    threads: threads
+                        .iter()
+                        .map(|thread| DeadlockedThread {
+                            thread_id: thread.thread_id(),
+                            backtrace: format!("{:?}", thread.backtrace()),
+                        })
+                        .collect(),
+                    locked_traces: locked_traces.clone(),
+                });
+            }
+        })
+    }
+
+    /// `locked_traces` returns the ids of the traces with a lock held on them or on one
+    /// of their spans. It never blocks, as the client itself may be part of the deadlock.
+    fn locked_traces(&self) -> Vec<String> {
+        let traces = match self.0.try_read() {
+            Some(client) => match client.traces.try_lock() {
+                Some(traces) => traces.clone(),
+                None => return Vec::new(),
+            },
+            None => return Vec::new(),
+        };
+
+        let mut locked = traces
+            .into_iter()
+            .filter(|(_, trace)| match trace.try_lock() {
+                Some(mut trace) => is_locked(&trace.get_root_span()),
+                None => true,
+            })
+            .map(|(trace_id, _)| trace_id)
+            .collect::<Vec<_>>();
+        locked.sort();
+        locked
+    }
+}
+
+/// `is_locked` returns whether the span or one of its descendants is locked
+fn is_locked(span: &SafeSpan) -> bool {
+    match span.try_lock() {
+        Some(span) => span.get_children().iter().any(is_locked),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc, Barrier};
+    use std::thread;
+
+    use crate::tests::new_client;
+    use crate::Config;
+
+    #[test]
+    fn test_deadlock_detection() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        let first = root_span.lock().create_child(&mut client).unwrap();
+        let second = root_span.lock().create_child(&mut client).unwrap();
+
+        let (found, deadlocks) = mpsc::channel();
+        let _detector = client.start_deadlock_detection(Duration::from_millis(10), move |d| {
+            let _ = found.send(d.clone());
+        });
+
+        // lock the two spans in a different order on two threads
+        let barrier = Arc::new(Barrier::new(2));
+        for (a, b) in [(first.clone(), second.clone()), (second, first)] {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let _a = a.lock();
+                barrier.wait();
+                let _b = b.lock();
+            });
+        }
+
+        let deadlock = deadlocks.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(deadlock.threads.len(), 2);
+        assert_eq!(deadlock.locked_traces, vec![trace.lock().trace_id.clone()]);
+    }
+}
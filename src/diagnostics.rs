@@ -0,0 +1,69 @@
+/*! Self-diagnostics.
+
+The beeline reports what goes wrong on its own side (hooks panicking, invalid trace
+headers, events that could not be queued or sent) through the `log` crate, under the
+`beeline` target. `Config::diagnostics_level` sets how verbose it is, independently of the
+level the application logs at.
+
+*/
+use std::fmt;
+
+use log::{Level, LevelFilter};
+
+/// Target of the records logged by the beeline
+pub const TARGET: &str = "beeline";
+
+/// `log` logs `args` at `level`, unless it is more verbose than `max`
+pub(crate) fn log(max: LevelFilter, level: Level, args: fmt::Arguments<'_>) {
+    if level <= max {
+        log::log!(target: TARGET, level, "{}", args);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Metadata, Record};
+    use parking_lot::Mutex;
+
+    static RECORDS: Mutex<Vec<String>> = parking_lot::const_mutex(Vec::new());
+
+    struct Recorder;
+
+    impl log::Log for Recorder {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target() == TARGET
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            if self.enabled(record.metadata()) {
+                RECORDS
+                    .lock()
+                    .push(format!("{} {}", record.level(), record.args()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_log() {
+        let _ = log::set_logger(&Recorder);
+        log::set_max_level(LevelFilter::Trace);
+
+        log(
+            LevelFilter::Warn,
+            Level::Warn,
+            format_args!("queue {}", "full"),
+        );
+        log(LevelFilter::Warn, Level::Debug, format_args!("too verbose"));
+        log(LevelFilter::Off, Level::Error, format_args!("muted"));
+
+        // other tests may log concurrently
+        let records = RECORDS.lock();
+        assert!(records.contains(&"WARN queue full".to_string()));
+        assert!(!records
+            .iter()
+            .any(|r| r.ends_with("too verbose") || r.ends_with("muted")));
+    }
+}
@@ -1,10 +1,239 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::Duration;
+
 use thiserror::Error;
 
 /// Result shorthand for a `std::result::Result` wrapping our own `Error`
 pub type Result<T> = std::result::Result<T, BeelineError>;
 
+/// Underlying cause of a `BeelineError`, returned by `Error::source`
+pub type Source = Box<dyn StdError + Send + Sync + 'static>;
+
+/// `ConfigErrorKind` is the part of the configuration a `BeelineError::ConfigError` is
+/// about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigErrorKind {
+    /// the dataset isn't one Honeycomb accepts, see `dataset::validate`
+    InvalidDataset,
+    /// Honeycomb rejects the API key, see `Config::validate_api_key`
+    InvalidApiKey,
+    /// a transmission setting is zero, see `Config::validate`
+    InvalidTransmissionSettings,
+    /// the proxy or CA bundle can't be used, see `Config::http_proxy` and
+    /// `Config::ca_bundle`
+    InvalidHttpSettings,
+}
+
+impl fmt::Display for ConfigErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidDataset => "invalid dataset",
+            Self::InvalidApiKey => "invalid API key",
+            Self::InvalidTransmissionSettings => "invalid transmission settings",
+            Self::InvalidHttpSettings => "invalid HTTP settings",
+        })
+    }
+}
+
+/// `PropagationErrorKind` is the reason a trace header couldn't be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropagationErrorKind {
+    /// the header is of a version this beeline doesn't know about
+    UnsupportedVersion,
+    /// the header has a parent id but no trace id
+    MissingTraceId,
+    /// the baggage isn't a base64 encoded JSON object of strings
+    InvalidBaggage,
+    /// the trace context isn't a base64 encoded JSON value
+    InvalidTraceContext,
+    /// the binary header is truncated or malformed, see `Propagation::unmarshal_binary`
+    InvalidBinary,
+    /// a clause of the text header has no `=`, a key is repeated or the trace id is empty,
+    /// see `PropagationParsing::Strict`
+    MalformedHeader,
+}
+
+impl fmt::Display for PropagationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UnsupportedVersion => "unsupported version",
+            Self::MissingTraceId => "missing trace id",
+            Self::InvalidBaggage => "invalid baggage",
+            Self::InvalidTraceContext => "invalid trace context",
+            Self::InvalidBinary => "invalid binary header",
+            Self::MalformedHeader => "malformed header",
+        })
+    }
+}
+
+/// `BeelineError` is every error the beeline returns. The message describes what failed;
+/// the underlying error, when there is one, is available through `Error::source`.
 #[derive(Error, Debug)]
 pub enum BeelineError {
-    #[error("")]
-    PropagationError(String),
+    /// the configuration is invalid, or a resource it points to can't be used, e.g. the
+    /// dataset isn't one Honeycomb accepts or Honeycomb rejects the API key, told apart
+    /// by its kind
+    #[error("{message}")]
+    ConfigError {
+        kind: ConfigErrorKind,
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// the client couldn't be started
+    #[error("unable to start the beeline: {message}")]
+    InitError {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// an incoming trace header couldn't be parsed
+    #[error("{kind}: {message}")]
+    PropagationError {
+        kind: PropagationErrorKind,
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// events couldn't be handed to, or sent by, the transmission, or a request to a
+    /// Honeycomb API (markers, auth) failed or got an unexpected response
+    #[error("{message}")]
+    SendError {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// an operation didn't complete within the given time
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+}
+
+impl BeelineError {
+    pub(crate) fn config(kind: ConfigErrorKind, message: impl Into<String>) -> Self {
+        Self::ConfigError {
+            kind,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub(crate) fn config_caused_by(
+        kind: ConfigErrorKind,
+        message: impl Into<String>,
+        source: impl Into<Source>,
+    ) -> Self {
+        Self::ConfigError {
+            kind,
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub(crate) fn init_caused_by(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        Self::InitError {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub(crate) fn propagation(kind: PropagationErrorKind, message: impl Into<String>) -> Self {
+        Self::PropagationError {
+            kind,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub(crate) fn propagation_caused_by(
+        kind: PropagationErrorKind,
+        message: impl Into<String>,
+        source: impl Into<Source>,
+    ) -> Self {
+        Self::PropagationError {
+            kind,
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub(crate) fn send(message: impl Into<String>) -> Self {
+        Self::SendError {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub(crate) fn send_caused_by(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        Self::SendError {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// `chain` displays the error followed by each of its sources, separated by `: `,
+    /// e.g. to log it
+    pub fn chain(&self) -> Chain<'_> {
+        Chain(self)
+    }
+}
+
+/// `Chain` displays an error along with its sources, see `BeelineError::chain`
+#[derive(Debug)]
+pub struct Chain<'a>(&'a BeelineError);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(e) = source {
+            write!(f, ": {}", e)?;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain() {
+        let err = BeelineError::init_caused_by(
+            "invalid HTTP settings",
+            BeelineError::config_caused_by(
+                ConfigErrorKind::InvalidHttpSettings,
+                "unable to read ca_bundle /missing.pem",
+                std::io::Error::new(std::io::ErrorKind::NotFound, "no such file"),
+            ),
+        );
+        assert_eq!(
+            err.to_string(),
+            "unable to start the beeline: invalid HTTP settings"
+        );
+        let source = err.source().unwrap();
+        assert!(matches!(
+            source.downcast_ref::<BeelineError>(),
+            Some(BeelineError::ConfigError {
+                kind: ConfigErrorKind::InvalidHttpSettings,
+                ..
+            })
+        ));
+        assert!(source.source().unwrap().is::<std::io::Error>());
+        assert_eq!(
+            err.chain().to_string(),
+            "unable to start the beeline: invalid HTTP settings: unable to read ca_bundle /missing.pem: no such file"
+        );
+
+        let err = BeelineError::propagation(
+            PropagationErrorKind::MissingTraceId,
+            "parent_id without trace_id",
+        );
+        assert_eq!(
+            err.to_string(),
+            "missing trace id: parent_id without trace_id"
+        );
+        assert!(err.source().is_none());
+    }
 }
@@ -0,0 +1,153 @@
+/*! Reshaping events right before they are exported.
+
+`Config::export_hook` sees the events as they are handed to the transmission, with every
+field the beeline adds. `rename_fields` makes a hook matching a company-wide schema, or the
+OpenTelemetry semantic conventions:
+
+```rust
+use std::sync::Arc;
+
+use beeline::{export, Config};
+
+let mut config = Config::default();
+config.export_hook = Some(Arc::new(export::rename_fields(&[
+    ("trace.trace_id", "trace_id"),
+    ("trace.span_id", "span_id"),
+    ("request.method", "http.method"),
+])));
+```
+
+Setting `Config::otel_field_names` renames the fields of the beeline and of its integrations
+to their OpenTelemetry counterparts, listed in `OTEL_FIELD_NAMES`, before the export hook
+runs. This eases sharing a dataset with services instrumented with OpenTelemetry, and
+moving to it.
+
+*/
+use std::collections::HashMap;
+
+use libhoney::Value;
+
+/// fields of the beeline and of its integrations, and the name OpenTelemetry's semantic
+/// conventions give them
+pub const OTEL_FIELD_NAMES: &[(&str, &str)] = &[
+    ("request.method", "http.request.method"),
+    ("request.path", "url.path"),
+    ("request.url", "url.full"),
+    ("request.route", "http.route"),
+    ("request.http_version", "network.protocol.version"),
+    ("response.status_code", "http.response.status_code"),
+    // the name the actix-web middleware uses
+    ("response.status", "http.response.status_code"),
+    ("response.body.size", "http.response.body.size"),
+    ("tls.version", "tls.protocol.version"),
+    ("db.statement", "db.query.text"),
+    ("db.name", "db.namespace"),
+    ("db.rows", "db.response.returned_rows"),
+    ("messaging.destination", "messaging.destination.name"),
+    ("messaging.operation", "messaging.operation.type"),
+    ("messaging.message_id", "messaging.message.id"),
+];
+
+/// `to_otel_field_names` renames the fields of an event following `OTEL_FIELD_NAMES`
+pub(crate) fn to_otel_field_names(fields: &mut HashMap<String, Value>) {
+    for (from, to) in OTEL_FIELD_NAMES {
+        if let Some(value) = fields.remove(*from) {
+            fields.insert(to.to_string(), value);
+        }
+    }
+}
+
+/// `rename_fields` returns an export hook renaming the fields of every event, from the
+/// first name of each pair to the second. A renamed field replaces any field already
+/// named like it.
+pub fn rename_fields(
+    renames: &[(&str, &str)],
+) -> impl Fn(&mut HashMap<String, Value>) + 'static + Send + Sync {
+    let renames = renames
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect::<Vec<_>>();
+    move |fields| {
+        for (from, to) in &renames {
+            if let Some(value) = fields.remove(from) {
+                fields.insert(to.clone(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::{keys, Config};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_export_hook() {
+        let mut config = Config::default();
+        config.export_hook = Some(Arc::new(rename_fields(&[
+            (keys::TRACE_TRACE_ID, "trace_id"),
+            ("app.user", "enduser.id"),
+        ])));
+        let mut client = new_client(config);
+        let trace = client.new_trace(None);
+        let trace_id = trace.lock().trace_id.clone();
+        trace
+            .lock()
+            .get_root_span()
+            .lock()
+            .add_field("app.user", json!("ada"));
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert_eq!(fields["trace_id"], json!(trace_id));
+        assert!(!fields.contains_key(keys::TRACE_TRACE_ID));
+        assert_eq!(fields["enduser.id"], json!("ada"));
+        // the hook runs after the beeline added its own fields
+        assert!(fields.contains_key(keys::META_SAMPLE_RATE_WEIGHT));
+    }
+
+    #[test]
+    fn test_otel_field_names() {
+        let mut config = Config::default();
+        config.otel_field_names = true;
+        config.export_hook = Some(Arc::new(|fields: &mut HashMap<String, Value>| {
+            assert!(fields.contains_key("http.request.method"));
+        }));
+        let mut client = new_client(config);
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        root_span
+            .lock()
+            .add_field(keys::REQUEST_METHOD, json!("GET"));
+        root_span
+            .lock()
+            .add_field(keys::REQUEST_PATH, json!("/users/42"));
+        root_span
+            .lock()
+            .add_field(keys::RESPONSE_STATUS_CODE, json!(200));
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert_eq!(fields["http.request.method"], json!("GET"));
+        assert_eq!(fields["url.path"], json!("/users/42"));
+        assert_eq!(fields["http.response.status_code"], json!(200));
+        assert!(!fields.contains_key(keys::REQUEST_METHOD));
+        assert!(fields.contains_key(keys::TRACE_TRACE_ID));
+    }
+
+    #[test]
+    fn test_export_hook_panic() {
+        let mut config = Config::default();
+        config.export_hook = Some(Arc::new(|_: &mut HashMap<String, Value>| panic!("oops")));
+        let mut client = new_client(config);
+        client.new_trace(None).send(&mut client);
+
+        assert!(client.0.write().client.transmission.events().is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+/*! Dropping and routing events at the sink.
+
+An `EventFilter` set as `Config::event_filter` sees every event the client is about to
+transmit, once sampled and through the presend hook, whatever its sender. Its rules are
+tried in order: the first whose predicate matches the fields of the event either drops it
+or routes it to an `EventSink` instead of the transmission. Events matching no rule are
+transmitted as usual.
+
+```rust
+use std::sync::Mutex;
+
+use beeline::filter::EventFilter;
+use beeline::sink::{EventSink, SerializedEvent};
+use beeline::Config;
+use serde_json::json;
+
+struct AuditLog(Mutex<Vec<SerializedEvent>>);
+
+impl EventSink for AuditLog {
+    fn emit(&self, event: SerializedEvent) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+let mut config = Config::default();
+config.event_filter = Some(
+    EventFilter::new()
+        .drop_if(|event| event.get("request.path") == Some(&json!("/metrics")))
+        .route_if(
+            |event| event.get("meta.type") == Some(&json!("audit")),
+            AuditLog(Mutex::new(Vec::new())),
+        ),
+);
+```
+
+Dropped events are counted in `Stats::filtered_out`, and routed ones in `Stats::routed`.
+Routed events are never retried.
+
+*/
+use std::fmt;
+use std::sync::Arc;
+
+use libhoney::Event;
+
+use crate::sink::{EventSink, SerializedEvent};
+
+type PredicateFn = dyn Fn(&SerializedEvent) -> bool + 'static + Send + Sync;
+
+#[derive(Clone)]
+struct Rule {
+    predicate: Arc<PredicateFn>,
+    // None drops the events
+    sink: Option<Arc<dyn EventSink>>,
+}
+
+/// `Outcome` is what a filter did with an event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Outcome {
+    Transmit,
+    Dropped,
+    Routed,
+}
+
+/// `EventFilter` drops or routes the events matching its rules, see the module docs
+#[derive(Clone, Default)]
+pub struct EventFilter {
+    rules: Vec<Rule>,
+}
+
+impl EventFilter {
+    /// `new` returns a filter without rules, letting every event through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `drop_if` adds a rule dropping the events `predicate` matches
+    pub fn drop_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&SerializedEvent) -> bool + 'static + Send + Sync,
+    {
+        self.rules.push(Rule {
+            predicate: Arc::new(predicate),
+            sink: None,
+        });
+        self
+    }
+
+    /// `route_if` adds a rule handing the events `predicate` matches to `sink`
+    pub fn route_if<F, S>(mut self, predicate: F, sink: S) -> Self
+    where
+        F: Fn(&SerializedEvent) -> bool + 'static + Send + Sync,
+        S: EventSink,
+    {
+        self.rules.push(Rule {
+            predicate: Arc::new(predicate),
+            sink: Some(Arc::new(sink)),
+        });
+        self
+    }
+
+    /// `apply` runs the rules on `ev`, handing it to the sink of the first matching rule
+    pub(crate) fn apply(&self, ev: &Event) -> Outcome {
+        if self.rules.is_empty() {
+            return Outcome::Transmit;
+        }
+        let event = SerializedEvent {
+            fields: ev.fields(),
+        };
+        let rule = match self.rules.iter().find(|rule| (rule.predicate)(&event)) {
+            Some(rule) => rule,
+            None => return Outcome::Transmit,
+        };
+        match &rule.sink {
+            Some(sink) => {
+                sink.emit(event);
+                Outcome::Routed
+            }
+            None => Outcome::Dropped,
+        }
+    }
+
+    /// `flush` flushes the sinks events are routed to
+    pub(crate) fn flush(&self) {
+        for sink in self.rules.iter().filter_map(|rule| rule.sink.as_ref()) {
+            sink.flush();
+        }
+    }
+}
+
+impl fmt::Debug for EventFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventFilter")
+            .field("rules", &self.rules.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+    use parking_lot::Mutex;
+    use serde_json::json;
+
+    #[derive(Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<SerializedEvent>>>);
+
+    impl EventSink for Collector {
+        fn emit(&self, event: SerializedEvent) {
+            self.0.lock().push(event);
+        }
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let audit = Collector::default();
+        let mut config = Config::default();
+        config.event_filter = Some(
+            EventFilter::new()
+                .drop_if(|event| event.get("request.path") == Some(&json!("/metrics")))
+                .route_if(
+                    |event| event.get("meta.type") == Some(&json!("audit")),
+                    audit.clone(),
+                ),
+        );
+        let mut client = new_client(config);
+
+        for (key, value) in &[
+            ("request.path", "/metrics"),
+            ("meta.type", "audit"),
+            ("request.path", "/users"),
+        ] {
+            let trace = client.new_trace(None);
+            trace
+                .lock()
+                .get_root_span()
+                .lock()
+                .add_field(key, json!(value));
+            trace.send(&mut client);
+        }
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields()["request.path"], json!("/users"));
+        let audited = audit.0.lock();
+        assert_eq!(audited.len(), 1);
+        assert_eq!(audited[0].get("meta.type"), Some(&json!("audit")));
+        let stats = client.stats();
+        assert_eq!((stats.filtered_out, stats.routed), (1, 1));
+    }
+}
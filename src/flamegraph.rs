@@ -0,0 +1,107 @@
+/*! Folded stacks of traces, for flamegraph tooling.
+
+With `Config::flamegraph` set, every trace whose root span is sent is followed by an event
+of `meta.type` "flamegraph", carrying the trace id, the name and duration of the root span,
+and the span tree in `flamegraph.folded`. This is the folded stack format of
+`flamegraph.pl` and `inferno`: a line per path from the root to a span, made of the span
+names separated by `;`, then a space and the time spent in the last span but not in its
+children, in microseconds:
+
+```text
+GET /users;db.query 1520
+GET /users;render 310
+GET /users 95
+```
+
+Query the events of slow traces, save their `flamegraph.folded` and feed it to
+`inferno-flamegraph` (or `flamegraph.pl`) to see where the time went. Only the spans sent
+before the root span are included, and overlapping asynchronous spans are charged in full,
+so their parents can show less self time than they had.
+
+*/
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// `Frame` is a span of a trace, as sent
+#[derive(Debug, Clone)]
+pub(crate) struct Frame {
+    pub(crate) span_id: Arc<str>,
+    pub(crate) parent_id: Arc<str>,
+    pub(crate) name: String,
+    pub(crate) duration_ms: f64,
+}
+
+/// `fold` returns the folded stacks of `frames`, a line per path, sorted
+pub(crate) fn fold(frames: &[Frame]) -> String {
+    let by_id: HashMap<&str, &Frame> = frames
+        .iter()
+        .map(|frame| (&*frame.span_id, frame))
+        .collect();
+    let mut children_ms: HashMap<&str, f64> = HashMap::new();
+    for frame in frames {
+        *children_ms.entry(&*frame.parent_id).or_insert(0f64) += frame.duration_ms;
+    }
+
+    let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+    for frame in frames {
+        let self_ms = frame.duration_ms - children_ms.get(&*frame.span_id).unwrap_or(&0f64);
+        let self_us = (self_ms.max(0f64) * 1000f64).round() as u64;
+        if self_us == 0 {
+            continue;
+        }
+        *stacks.entry(stack(frame, &by_id)).or_insert(0) += self_us;
+    }
+    stacks
+        .iter()
+        .map(|(stack, us)| format!("{} {}", stack, us))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `stack` returns the names of the spans from the root to `frame`, separated by `;`
+fn stack(frame: &Frame, by_id: &HashMap<&str, &Frame>) -> String {
+    let mut names = vec![escape(&frame.name)];
+    let mut parent_id = &*frame.parent_id;
+    // a bound on the depth, in case of a cycle between ids
+    while let Some(parent) = by_id.get(parent_id).filter(|_| names.len() <= by_id.len()) {
+        names.push(escape(&parent.name));
+        parent_id = &parent.parent_id;
+    }
+    names.reverse();
+    names.join(";")
+}
+
+/// `escape` replaces the characters separating the parts of a folded stack line
+fn escape(name: &str) -> String {
+    name.replace(|c| c == ';' || c == '\n', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(span_id: &str, parent_id: &str, name: &str, duration_ms: f64) -> Frame {
+        Frame {
+            span_id: Arc::from(span_id),
+            parent_id: Arc::from(parent_id),
+            name: name.to_string(),
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn test_fold() {
+        let frames = vec![
+            frame("c", "b", "db;query", 2.0),
+            frame("b", "a", "load", 5.0),
+            frame("d", "a", "load", 1.0),
+            frame("e", "a", "render", 1.5),
+            frame("a", "upstream", "GET /users", 10.0),
+        ];
+        assert_eq!(
+            fold(&frames),
+            "GET /users 2500\nGET /users;load 4000\nGET /users;load;db_query 2000\nGET /users;render 1500"
+        );
+        assert_eq!(fold(&[]), "");
+    }
+}
@@ -0,0 +1,134 @@
+/*! Trace propagation through gRPC metadata.
+
+gRPC metadata keys ending in `-bin` carry binary values, base64 encoded on the wire. The
+trace context is sent in `x-honeycomb-trace-bin`, in the compact binary encoding of
+`Propagation::marshal_binary`, which keeps the metadata of every call small. `inject` adds
+it to the metadata of an outgoing call and `extract` reads it back, falling back to the text
+`x-honeycomb-trace` sent by callers that don't use the binary form.
+
+Metadata is handled as an `http::HeaderMap`, which the metadata maps of gRPC stacks such as
+tonic convert to and from:
+
+```rust
+use beeline::{grpc, init, Config};
+use http::HeaderMap;
+
+# if false {
+let client = init(Config::default());
+let trace = client.new_trace(None);
+let mut metadata = HeaderMap::new();
+grpc::inject(&trace.lock().get_root_span(), &mut metadata);
+
+// on the server
+let server_trace = client.try_new_trace(grpc::extract(&metadata).unwrap_or(None));
+# }
+```
+
+*/
+use http::header::HeaderValue;
+use http::HeaderMap;
+
+use crate::errors::{BeelineError, PropagationErrorKind, Result};
+use crate::propagation::Propagation;
+use crate::trace::SafeSpan;
+
+/// name of the metadata carrying the trace context as text
+pub const HEADER_NAME: &str = "x-honeycomb-trace";
+/// name of the metadata carrying the binary trace context
+pub const BINARY_HEADER_NAME: &str = "x-honeycomb-trace-bin";
+
+/// `inject` adds the trace context of `span` to the metadata of an outgoing call, in
+/// binary form
+pub fn inject(span: &SafeSpan, metadata: &mut HeaderMap) {
+    let serialized = span.lock().serialize_headers();
+    if serialized.is_empty() {
+        return;
+    }
+    let propagation = match Propagation::unmarshal_trace_context(&serialized) {
+        Ok(propagation) => propagation,
+        Err(_) => return,
+    };
+    // gRPC implementations emit unpadded base64, and accept both
+    let encoded = base64::encode_config(propagation.marshal_binary(), base64::STANDARD_NO_PAD);
+    if let Ok(value) = HeaderValue::from_str(&encoded) {
+        metadata.insert(BINARY_HEADER_NAME, value);
+    }
+}
+
+/// `extract` returns the serialized trace context found in the metadata of an incoming
+/// call, to hand to `Client::try_new_trace`. It fails when the binary trace context can't
+/// be decoded.
+pub fn extract(metadata: &HeaderMap) -> Result<Option<String>> {
+    if let Some(value) = metadata.get(BINARY_HEADER_NAME) {
+        let encoded = value.to_str().map_err(|e| {
+            BeelineError::propagation_caused_by(
+                PropagationErrorKind::InvalidBinary,
+                "binary trace header isn't base64",
+                e,
+            )
+        })?;
+        let unpadded = encoded.trim_end_matches('=');
+        let bytes = base64::decode_config(unpadded, base64::STANDARD_NO_PAD).map_err(|e| {
+            BeelineError::propagation_caused_by(
+                PropagationErrorKind::InvalidBinary,
+                "unable to decode base64 binary trace header",
+                e,
+            )
+        })?;
+        let propagation = Propagation::unmarshal_binary(&bytes)?;
+        return Ok(Some(propagation.marshal_trace_context()));
+    }
+    Ok(metadata
+        .get(HEADER_NAME)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::Config;
+
+    #[test]
+    fn test_inject_extract() {
+        let client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        trace.lock().add_field("tenant", serde_json::json!("acme"));
+        let root_span = trace.lock().get_root_span();
+        let mut metadata = HeaderMap::new();
+        inject(&root_span, &mut metadata);
+        assert!(metadata.contains_key(BINARY_HEADER_NAME));
+        assert!(!metadata[BINARY_HEADER_NAME]
+            .to_str()
+            .unwrap()
+            .ends_with('='));
+
+        let server_trace = client.try_new_trace(extract(&metadata).unwrap()).unwrap();
+        let server_trace = server_trace.lock();
+        assert_eq!(server_trace.trace_id, trace.lock().trace_id);
+        assert_eq!(
+            server_trace.trace_level_fields()["tenant"],
+            serde_json::json!("acme")
+        );
+    }
+
+    #[test]
+    fn test_extract() {
+        let mut metadata = HeaderMap::new();
+        assert_eq!(extract(&metadata).unwrap(), None);
+
+        let header = "1;trace_id=a,parent_id=b,context=e30=";
+        metadata.insert(HEADER_NAME, HeaderValue::from_static(header));
+        assert_eq!(extract(&metadata).unwrap(), Some(header.to_string()));
+
+        metadata.insert(BINARY_HEADER_NAME, HeaderValue::from_static("!!"));
+        assert!(matches!(
+            extract(&metadata),
+            Err(BeelineError::PropagationError {
+                kind: PropagationErrorKind::InvalidBinary,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+/// `Health` is a snapshot of how well events are making it to Honeycomb, as returned by
+/// `Client::health`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    /// whether the last batch reached the API. True until a response says otherwise.
+    pub reachable: bool,
+    /// time since a batch was last accepted by the API, if one ever was
+    pub since_last_success: Option<Duration>,
+    /// the error of the last failed batch, if any batch failed
+    pub last_error: Option<String>,
+    /// number of events handed over for transmission that have not been responded to yet
+    pub queue_depth: u64,
+}
+
+/// `Tracker` keeps track of the events sent and of the transmission responses
+#[derive(Debug, Clone)]
+pub(crate) struct Tracker {
+    sent: u64,
+    responded: u64,
+    reachable: bool,
+    last_success: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self {
+            sent: 0,
+            responded: 0,
+            reachable: true,
+            last_success: None,
+            last_error: None,
+        }
+    }
+}
+
+impl Tracker {
+    pub(crate) fn record_sent(&mut self) {
+        self.sent += 1;
+    }
+
+    /// `record_response` records the response to one event. A response without a status
+    /// means the request never made it to the API.
+    pub(crate) fn record_response(&mut self, status: Option<u16>, error: Option<String>) {
+        self.responded += 1;
+        self.reachable = status.is_some();
+        match (status, error) {
+            (Some(status), None) if (200..300).contains(&status) => {
+                self.last_success = Some(Instant::now());
+            }
+            (status, error) => {
+                self.last_error = Some(error.unwrap_or_else(|| {
+                    format!("unexpected status {}", status.unwrap_or_default())
+                }));
+            }
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Health {
+        Health {
+            reachable: self.reachable,
+            since_last_success: self.last_success.map(|at| at.elapsed()),
+            last_error: self.last_error.clone(),
+            queue_depth: self.sent.saturating_sub(self.responded),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = Tracker::default();
+        for _ in 0..3 {
+            tracker.record_sent();
+        }
+        let health = tracker.snapshot();
+        assert!(health.reachable);
+        assert_eq!(health.since_last_success, None);
+        assert_eq!(health.queue_depth, 3);
+
+        tracker.record_response(Some(202), None);
+        tracker.record_response(Some(401), None);
+        let health = tracker.snapshot();
+        assert!(health.reachable);
+        assert!(health.since_last_success.is_some());
+        assert_eq!(health.last_error, Some("unexpected status 401".to_string()));
+        assert_eq!(health.queue_depth, 1);
+
+        tracker.record_response(None, Some("connection refused".to_string()));
+        let health = tracker.snapshot();
+        assert!(!health.reachable);
+        assert_eq!(health.last_error, Some("connection refused".to_string()));
+        assert_eq!(health.queue_depth, 0);
+    }
+}
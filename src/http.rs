@@ -0,0 +1,104 @@
+/*! HTTP settings for egress proxies and private CAs.
+
+`Config::http_proxy`, `Config::ca_bundle` and `Config::http_client` apply to the requests
+the beeline makes itself, such as creating markers.
+
+The transmission is different: libhoney builds a new HTTP client for every batch, which
+can't be configured from here. It uses the proxy set in the `HTTPS_PROXY` (or
+`HTTP_PROXY`) environment variable and the system CA store, which OpenSSL lets you replace
+with the `SSL_CERT_FILE` environment variable. The client warns at startup when a proxy is
+configured but not set in the environment.
+
+*/
+use std::env;
+use std::fs;
+
+use reqwest::blocking::Client as HttpClient;
+use reqwest::{Certificate, Proxy};
+
+use crate::errors::{BeelineError, ConfigErrorKind, Result};
+use crate::Config;
+
+/// `client` returns the HTTP client to use for the requests of the beeline: the one set
+/// in `Config::http_client` or, if unset, one going through the configured proxy and
+/// trusting the configured CA bundle
+pub(crate) fn client(config: &Config) -> Result<HttpClient> {
+    if let Some(client) = &config.http_client {
+        return Ok(client.clone());
+    }
+
+    let mut builder = HttpClient::builder();
+    if let Some(proxy) = &config.http_proxy {
+        let proxy = Proxy::all(proxy.as_str()).map_err(|e| {
+            BeelineError::config_caused_by(
+                ConfigErrorKind::InvalidHttpSettings,
+                format!("invalid http_proxy {}", proxy),
+                e,
+            )
+        })?;
+        builder = builder.proxy(proxy);
+    }
+    if let Some(ca_bundle) = &config.ca_bundle {
+        let pem = fs::read(ca_bundle).map_err(|e| {
+            BeelineError::config_caused_by(
+                ConfigErrorKind::InvalidHttpSettings,
+                format!("unable to read ca_bundle {}", ca_bundle.display()),
+                e,
+            )
+        })?;
+        let certificate = Certificate::from_pem(&pem).map_err(|e| {
+            BeelineError::config_caused_by(
+                ConfigErrorKind::InvalidHttpSettings,
+                format!("invalid ca_bundle {}", ca_bundle.display()),
+                e,
+            )
+        })?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    builder.build().map_err(|e| {
+        BeelineError::config_caused_by(
+            ConfigErrorKind::InvalidHttpSettings,
+            "unable to build HTTP client",
+            e,
+        )
+    })
+}
+
+/// `transmission_ignores_proxy` returns whether a proxy is configured that the
+/// transmission won't go through, as it isn't set in the environment
+pub(crate) fn transmission_ignores_proxy(config: &Config) -> bool {
+    config.http_proxy.is_some()
+        && ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]
+            .iter()
+            .all(|name| env::var_os(name).is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_client() {
+        assert!(client(&Config::default()).is_ok());
+
+        let config = Config {
+            http_proxy: Some("http://proxy.internal:3128".to_string()),
+            ..Default::default()
+        };
+        assert!(client(&config).is_ok());
+
+        let config = Config {
+            http_proxy: Some("not a url".to_string()),
+            ..Default::default()
+        };
+        assert!(client(&config).is_err());
+
+        let config = Config {
+            ca_bundle: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..Default::default()
+        };
+        let err = client(&config).unwrap_err().to_string();
+        assert!(err.starts_with("unable to read ca_bundle /nonexistent/ca.pem"));
+    }
+}
@@ -0,0 +1,60 @@
+use serde_json::json;
+
+use crate::keys;
+use crate::trace::Span;
+
+/// `Instrumentation` names the integration that created a span. Integrations stamp it on
+/// the spans they create, as `meta.instrumentation` and `meta.instrumentation_version`, so
+/// schema issues can be traced back to the integration (and release) that produced them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instrumentation {
+    /// name of the integration, e.g. `beeline-actix-web`
+    pub name: &'static str,
+    /// version of the integration
+    pub version: &'static str,
+}
+
+impl Instrumentation {
+    /// `add_to` adds the name and version of the integration to `span`
+    pub fn add_to(&self, span: &mut Span) {
+        span.add_field(keys::META_INSTRUMENTATION, json!(self.name));
+        span.add_field(keys::META_INSTRUMENTATION_VERSION, json!(self.version));
+    }
+}
+
+/// `instrumentation!` builds the `Instrumentation` of the calling crate, from its package
+/// name and version
+#[macro_export]
+macro_rules! instrumentation {
+    () => {
+        $crate::Instrumentation {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    #[test]
+    fn test_add_to() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        crate::instrumentation!().add_to(&mut root_span.lock());
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert_eq!(fields[keys::META_INSTRUMENTATION], json!("beeline-rust"));
+        assert_eq!(
+            fields[keys::META_INSTRUMENTATION_VERSION],
+            json!(env!("CARGO_PKG_VERSION"))
+        );
+    }
+}
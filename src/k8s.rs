@@ -0,0 +1,165 @@
+/*! Kubernetes/container metadata enrichment.
+
+When enabled through `Config::k8s_metadata`, the pod, namespace, node and container the
+application runs in are detected once at init and added as `meta.k8s.*` fields to every
+event.
+
+Detection looks at, in order:
+ - the `POD_NAME`, `POD_NAMESPACE` and `NODE_NAME` environment variables, which are
+   usually populated through the downward API
+ - the service account namespace file and `HOSTNAME` (which defaults to the pod name)
+ - `/proc/self/cgroup` for the container id
+
+*/
+use std::env;
+use std::fs;
+
+use libhoney::Value;
+
+const SERVICE_ACCOUNT_NAMESPACE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+const CGROUP_FILE: &str = "/proc/self/cgroup";
+const CONTAINER_ID_LEN: usize = 64;
+
+/// `K8sMetadata` holds whatever could be detected about the pod the application runs in
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct K8sMetadata {
+    /// name of the pod, sent as `meta.k8s.pod_name`
+    pub pod_name: Option<String>,
+    /// namespace of the pod, sent as `meta.k8s.namespace`
+    pub namespace: Option<String>,
+    /// node the pod is scheduled on, sent as `meta.k8s.node_name`
+    pub node_name: Option<String>,
+    /// id of the container, sent as `meta.k8s.container_id`
+    pub container_id: Option<String>,
+}
+
+impl K8sMetadata {
+    /// `detect` reads the metadata from the environment and the filesystem
+    pub fn detect() -> Self {
+        Self::from_sources(
+            |name| env::var(name).ok(),
+            |path| fs::read_to_string(path).ok(),
+        )
+    }
+
+    fn from_sources<E, F>(env: E, read: F) -> Self
+    where
+        E: Fn(&str) -> Option<String>,
+        F: Fn(&str) -> Option<String>,
+    {
+        let in_cluster = env("KUBERNETES_SERVICE_HOST").is_some();
+        let non_empty = |value: String| {
+            let value = value.trim().to_string();
+            if value.is_empty() {
+                None
+            } else {
+                Some(value)
+            }
+        };
+
+        Self {
+            pod_name: env("POD_NAME")
+                .or_else(|| if in_cluster { env("HOSTNAME") } else { None })
+                .and_then(non_empty),
+            namespace: env("POD_NAMESPACE")
+                .or_else(|| read(SERVICE_ACCOUNT_NAMESPACE))
+                .and_then(non_empty),
+            node_name: env("NODE_NAME").and_then(non_empty),
+            container_id: read(CGROUP_FILE).and_then(|cgroup| container_id(&cgroup)),
+        }
+    }
+
+    pub(crate) fn fields(&self) -> Vec<(&'static str, Value)> {
+        [
+            ("meta.k8s.pod_name", &self.pod_name),
+            ("meta.k8s.namespace", &self.namespace),
+            ("meta.k8s.node_name", &self.node_name),
+            ("meta.k8s.container_id", &self.container_id),
+        ]
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .as_ref()
+                .map(|value| (*name, Value::String(value.clone())))
+        })
+        .collect()
+    }
+}
+
+/// `container_id` extracts the container id from the contents of a cgroup file. It
+/// understands both cgroup v1 (`/kubepods/.../<id>`, `/docker/<id>`) and the systemd
+/// style used with cgroup v2 (`.../cri-containerd-<id>.scope`).
+fn container_id(cgroup: &str) -> Option<String> {
+    cgroup.lines().find_map(|line| {
+        let segment = line.rsplit('/').next()?;
+        let segment = segment.trim_end_matches(".scope");
+        let id = segment.rsplit('-').next()?;
+        if id.len() == CONTAINER_ID_LEN && id.chars().all(|c| c.is_ascii_hexdigit()) {
+            Some(id.to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f1ae2e5a4c7b5d7f6e8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0";
+
+    #[test]
+    fn test_container_id() {
+        assert_eq!(
+            container_id(&format!("12:memory:/kubepods/burstable/pod1234/{}\n", ID)),
+            Some(ID.to_string())
+        );
+        assert_eq!(
+            container_id(&format!(
+                "0::/kubepods.slice/kubepods-pod1234.slice/cri-containerd-{}.scope\n",
+                ID
+            )),
+            Some(ID.to_string())
+        );
+        assert_eq!(container_id("0::/user.slice/session-2.scope\n"), None);
+    }
+
+    #[test]
+    fn test_from_sources() {
+        let metadata = K8sMetadata::from_sources(
+            |name| match name {
+                "KUBERNETES_SERVICE_HOST" => Some("10.0.0.1".to_string()),
+                "HOSTNAME" => Some("web-5d9c7b-xk2lp".to_string()),
+                "NODE_NAME" => Some("node-1".to_string()),
+                _ => None,
+            },
+            |path| match path {
+                SERVICE_ACCOUNT_NAMESPACE => Some("payments\n".to_string()),
+                CGROUP_FILE => Some(format!("0::/docker/{}\n", ID)),
+                _ => None,
+            },
+        );
+        assert_eq!(
+            metadata,
+            K8sMetadata {
+                pod_name: Some("web-5d9c7b-xk2lp".to_string()),
+                namespace: Some("payments".to_string()),
+                node_name: Some("node-1".to_string()),
+                container_id: Some(ID.to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_outside_cluster() {
+        let metadata = K8sMetadata::from_sources(
+            |name| match name {
+                "HOSTNAME" => Some("laptop".to_string()),
+                _ => None,
+            },
+            |_| None,
+        );
+        assert_eq!(metadata, K8sMetadata::default());
+        assert!(metadata.fields().is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+/*! Field names.
+
+Standard field names are `&'static str` constants, so that every integration spells them
+the same way and none of them has to be built at runtime. Names derived from request data,
+such as `request.header.<name>`, go through `header_key`, which keeps the names it has
+already built so each distinct header is only formatted once.
+
+*/
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use parking_lot::RwLock;
+
+/// type of event or span, e.g. "http_request"
+pub const META_TYPE: &str = "meta.type";
+/// position of the span in the trace: "root", "subroot", "mid", "leaf" or "async"
+pub const META_SPAN_TYPE: &str = "meta.span_type";
+/// set on spans sent because their parent was sent
+pub const META_SENT_BY_PARENT: &str = "meta.sent_by_parent";
+/// number of asynchronous spans of the trace not sent yet when its root span was sent
+pub const META_ASYNC_SPANS_PENDING: &str = "meta.async_spans_pending";
+/// set on the spans sent after their trace was closed, e.g. by `Client::flush`. Their
+/// trace level fields are the ones the trace had when they were created.
+pub const META_TRACE_CLOSED: &str = "meta.trace_closed";
+/// set on the asynchronous spans sent after the root span of their trace
+pub const META_SENT_AFTER_ROOT: &str = "meta.sent_after_root";
+/// how long after the root span of its trace an asynchronous span was sent, in
+/// milliseconds
+pub const META_SENT_AFTER_ROOT_MS: &str = "meta.sent_after_root_ms";
+/// reason the sampler gave for keeping the event
+pub const META_SAMPLE_REASON: &str = "meta.sample.reason";
+/// sample rate the event was kept at, for re-weighting counts outside Honeycomb
+pub const META_SAMPLE_RATE_WEIGHT: &str = "meta.sample_rate_weight";
+/// name of the service, from `Config::service_name`
+pub const META_SERVICE_NAME: &str = "meta.service_name";
+/// version of the beeline
+pub const META_BEELINE_VERSION: &str = "meta.beeline_version";
+/// host the service runs on
+pub const META_LOCAL_HOSTNAME: &str = "meta.local_hostname";
+/// Refinery the events are sent to, from `Config::refinery`
+pub const META_REFINERY_TARGET: &str = "meta.refinery_target";
+/// slug of the Honeycomb team the events are sent to, see `Config::validate_api_key`
+pub const META_TEAM: &str = "meta.team";
+/// slug of the Honeycomb environment the events are sent to, see
+/// `Config::validate_api_key`
+pub const META_ENVIRONMENT: &str = "meta.environment";
+/// set on spans whose trace header was cut down to `Config::max_propagation_header_size`
+pub const META_PROPAGATION_TRUNCATED: &str = "meta.propagation_truncated";
+/// set on the root span of a trace started afresh because the incoming trace header was
+/// malformed, with the reason, see `Client::try_new_trace`
+pub const META_PROPAGATION_ERROR: &str = "meta.propagation_error";
+/// set on the root span of a trace continued from an incoming trace header in the text
+/// form, with the version of the header
+pub const META_PROPAGATION_VERSION: &str = "meta.propagation_version";
+/// folded stacks of the span tree of a trace, on the events sent with `Config::flamegraph`
+pub const FLAMEGRAPH_FOLDED: &str = "flamegraph.folded";
+/// set on spans slower than their `Config::slow_thresholds`, which are never sampled out
+pub const META_SLOW: &str = "meta.slow";
+/// sample rate chosen for the whole trace, by an upstream service or when the trace
+/// headers were first serialized, see `Config::propagate_sample_rate`
+pub const META_TRACE_SAMPLE_RATE: &str = "meta.trace_sample_rate";
+/// integration that created the span, see `Instrumentation`
+pub const META_INSTRUMENTATION: &str = "meta.instrumentation";
+/// version of the integration that created the span
+pub const META_INSTRUMENTATION_VERSION: &str = "meta.instrumentation_version";
+/// id of the trace the span belongs to
+pub const TRACE_TRACE_ID: &str = "trace.trace_id";
+/// id of the span
+pub const TRACE_SPAN_ID: &str = "trace.span_id";
+/// id of the parent of the span
+pub const TRACE_PARENT_ID: &str = "trace.parent_id";
+/// name of the span
+pub const NAME: &str = "name";
+/// duration of the span, in milliseconds
+pub const DURATION_MS: &str = "duration_ms";
+/// error message
+pub const ERROR: &str = "error";
+/// number of spans of the trace that recorded an error, sent as `rollup.error_count` on
+/// the root span
+pub const ERROR_COUNT: &str = "error_count";
+/// number of spans of the trace sent before its root, itself aside, sent as
+/// `rollup.spans.total` on the root span. Spans are also counted by `meta.type`, as
+/// `rollup.spans.<meta.type>`.
+pub const SPANS_TOTAL: &str = "spans.total";
+/// number of asynchronous spans of the trace sent before its root, sent as
+/// `rollup.spans.async` on the root span
+pub const SPANS_ASYNC: &str = "spans.async";
+/// set on the root span when any span of the trace recorded an error
+pub const APP_ERROR: &str = "app.error";
+/// status of the span, "ok" or "error", see `Span::set_status`
+pub const SPAN_STATUS: &str = "span.status";
+/// method of an incoming request
+pub const REQUEST_METHOD: &str = "request.method";
+/// path of an incoming request
+pub const REQUEST_PATH: &str = "request.path";
+/// HTTP version of an incoming request, e.g. "HTTP/2.0"
+pub const REQUEST_HTTP_VERSION: &str = "request.http_version";
+/// route pattern an incoming request was routed to, e.g. "/users/{id}"
+pub const REQUEST_ROUTE: &str = "request.route";
+/// set when the client of an incoming request went away before the response was fully
+/// sent
+pub const REQUEST_CANCELLED: &str = "request.cancelled";
+/// deadline the caller of an incoming request set, in milliseconds, see the `deadline`
+/// module
+pub const REQUEST_DEADLINE_MS: &str = "request.deadline_ms";
+/// whether an incoming request took longer than `request.deadline_ms`
+pub const META_DEADLINE_EXCEEDED: &str = "meta.deadline_exceeded";
+/// TLS version of the connection an incoming request came over
+pub const TLS_VERSION: &str = "tls.version";
+/// TLS cipher suite of the connection an incoming request came over
+pub const TLS_CIPHER: &str = "tls.cipher";
+/// status code of the response to an incoming request
+pub const RESPONSE_STATUS_CODE: &str = "response.status_code";
+/// short description of the error an incoming request failed with, taken from the
+/// response
+pub const RESPONSE_ERROR_SUMMARY: &str = "response.error.summary";
+/// size of the body of the response to an incoming request
+pub const RESPONSE_BODY_SIZE: &str = "response.body.size";
+
+const HEADER_PREFIX: &str = "request.header.";
+// header names come from clients, so only so many are kept
+const MAX_INTERNED_HEADERS: usize = 1024;
+
+fn header_keys() -> &'static RwLock<HashMap<String, &'static str>> {
+    static HEADER_KEYS: OnceLock<RwLock<HashMap<String, &'static str>>> = OnceLock::new();
+    HEADER_KEYS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// `header_key` returns the field name for the request header `name`:
+/// `request.header.<name>`, lowercased and with dashes replaced by underscores (e.g.
+/// `Content-Type` is recorded as `request.header.content_type`).
+pub fn header_key(name: &str) -> Cow<'static, str> {
+    if let Some(key) = header_keys().read().get(name) {
+        return Cow::Borrowed(key);
+    }
+
+    let key = format!("{}{}", HEADER_PREFIX, name.to_lowercase().replace('-', "_"));
+    let mut keys = header_keys().write();
+    if keys.len() >= MAX_INTERNED_HEADERS {
+        return Cow::Owned(key);
+    }
+    let key: &'static str = keys
+        .entry(name.to_string())
+        .or_insert_with(|| Box::leak(key.into_boxed_str()));
+    Cow::Borrowed(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_key() {
+        let key = header_key("X-Request-Id");
+        assert_eq!(key, "request.header.x_request_id");
+        assert!(matches!(key, Cow::Borrowed(_)));
+        // interned keys are handed out again as is
+        match (header_key("X-Request-Id"), key) {
+            (Cow::Borrowed(a), Cow::Borrowed(b)) => assert!(std::ptr::eq(a, b)),
+            _ => panic!("expected interned keys"),
+        }
+    }
+}
@@ -7,48 +7,320 @@ Currently there are two different web frameworks supported:
   - Actix Web
   - Rocket
 
+GraphQL servers built with async-graphql are supported as well, and so are the requests
+made with hyper clients and the commands sent by the MongoDB driver.
+
 You can find more information on their respective READMEs at:
   - [beeline-actix-web](https://github.com/nlopes/beeline-rust/tree/master/beeline-actix-web)
   - [beeline-rocket](https://github.com/nlopes/beeline-rust/tree/master/beeline-rocket)
+  - [beeline-async-graphql](https://github.com/nlopes/beeline-rust/tree/master/beeline-async-graphql)
+  - [beeline-hyper](https://github.com/nlopes/beeline-rust/tree/master/beeline-hyper)
+  - [beeline-mongodb](https://github.com/nlopes/beeline-rust/tree/master/beeline-mongodb)
 
 */
 use std::collections::HashMap;
 use std::fmt;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
+use log::Level;
 use parking_lot::{Mutex, RwLock};
 
+use libhoney::FieldHolder;
+
+mod async_send;
+mod auth;
+mod background;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod build_info;
+mod classify;
+pub mod context;
+mod cookies;
+pub mod dataset;
+pub mod deadline;
+#[cfg(feature = "deadlock-detection")]
+mod deadlock;
+pub mod diagnostics;
 mod errors;
+pub mod export;
+pub mod filter;
+pub mod flamegraph;
+pub mod grpc;
+mod health;
+mod http;
+mod instrumentation;
+pub mod k8s;
+pub mod keys;
+mod markers;
+#[cfg(feature = "metrics")]
+mod metrics_bridge;
+pub mod nats;
+mod process_stats;
 mod propagation;
+#[cfg(feature = "rayon")]
+pub mod rayon;
+mod registry;
+mod retry;
+pub mod route_latency;
+pub mod sampler;
+pub mod sanitize;
+pub mod schema;
+mod secret;
+pub mod semconv;
+pub mod sink;
+#[cfg(feature = "tokio")]
+pub mod spawn;
+pub mod sqs;
+mod stats;
+#[cfg(any(test, feature = "testutil"))]
+pub mod test;
 mod timer;
 pub mod trace;
+pub mod tracestate;
+mod user;
 pub use libhoney::client::Options as ClientOptions;
 pub use libhoney::transmission::Options as TransmissionOptions;
 pub use libhoney::Config as ClientConfig;
 pub use libhoney::{transmission::Transmission, Sender};
+pub use reqwest::blocking::Client as HttpClient;
 
-pub use trace::{SafeTrace, Trace};
+pub use async_send::SendFuture;
+pub use auth::{ApiKeyInfo, ApiKeyType};
+pub use background::BackgroundTask;
+pub use build_info::BuildInfo;
+pub use classify::ResponseClassifier;
+pub use context::Context;
+pub use cookies::{CookieMode, CookieRecorder};
+#[cfg(feature = "deadlock-detection")]
+pub use deadlock::{Deadlock, DeadlockedThread};
+pub use errors::{BeelineError, Chain, ConfigErrorKind, PropagationErrorKind};
+pub use filter::EventFilter;
+pub use health::Health;
+pub use instrumentation::Instrumentation;
+pub use k8s::K8sMetadata;
+pub use log::LevelFilter as DiagnosticsLevel;
+pub use markers::Marker;
+#[cfg(feature = "metrics")]
+pub use metrics_bridge::HoneycombRecorder;
+pub use propagation::{Propagation, PropagationHeaders, PropagationHosts, PropagationParsing};
+pub use registry::{ClientRegistry, Destination};
+pub use retry::RetryPolicy;
+pub use route_latency::RouteLatency;
+pub use sampler::{PerKeySampler, SamplerDecision, SlowThresholds};
+pub use schema::FieldSchema;
+pub use semconv::http_client::PeerServices;
+#[cfg(feature = "tokio")]
+pub use spawn::{spawn, SpawnExt};
+pub use stats::Stats;
+use trace::TraceSender;
+pub use trace::{
+    SafeTrace, SpanGuard, SpanStatus, TimerGuard, Trace, TraceSnapshot, UnsentChildPolicy,
+};
+pub use tracestate::TraceState;
+pub use user::{UserExtractor, UserInfo};
+
+/// Default `Config::max_propagation_header_size`, well within the limits of common proxies
+const DEFAULT_MAX_PROPAGATION_HEADER_SIZE: usize = 4096;
 
 type SamplerHookFn =
-    dyn Fn(HashMap<String, libhoney::Value>) -> (bool, usize) + 'static + Send + Sync;
+    dyn Fn(HashMap<String, libhoney::Value>) -> SamplerDecision + 'static + Send + Sync;
+
+type HeadSamplerHookFn = dyn Fn(&str) -> bool + 'static + Send + Sync;
+
+type PropagationFilterFn = dyn Fn(&str, &libhoney::Value) -> bool + 'static + Send + Sync;
 
 type PresendHookFn = dyn FnMut(&mut HashMap<String, libhoney::Value>) + 'static + Send + Sync;
 
+type IdGeneratorFn = dyn Fn() -> String + 'static + Send + Sync;
+
+type ExportHookFn = dyn Fn(&mut HashMap<String, libhoney::Value>) + 'static + Send + Sync;
+
+type SlowTraceHookFn = dyn Fn(&TraceSnapshot) + 'static + Send + Sync;
+
+type PropagationParserFn = dyn Fn(&str) -> Option<Propagation> + 'static + Send + Sync;
+
 #[derive(Clone)]
 pub struct Config {
     pub client_config: ClientConfig,
     pub service_name: Option<String>,
+    pub build_info: Option<BuildInfo>,
+    pub k8s_metadata: bool,
+    /// `sampler_hook` decides whether every span is kept, see the `sampler` module. Hooks
+    /// written for earlier versions, returning a `bool` or a `(bool, usize)`, can be set
+    /// with `Config::with_sampler_hook`.
     pub sampler_hook: Arc<SamplerHookFn>,
+    /// `head_sampler_hook` is handed the id of every new trace and returns whether it
+    /// should be kept. Traces it drops are made of no-op spans that are never sent, which
+    /// avoids building events only for `sampler_hook` to discard them.
+    pub head_sampler_hook: Option<Arc<HeadSamplerHookFn>>,
+    /// `diagnostics_level` is the most verbose level the beeline logs its own problems
+    /// at, see the `diagnostics` module
+    pub diagnostics_level: DiagnosticsLevel,
     pub presend_hook: Arc<Mutex<PresendHookFn>>,
+    /// `propagation_filter` is handed the name and value of every trace level field when
+    /// serializing the trace headers, and returns whether it can be sent to downstream
+    /// services. Fields it rejects are still added to the spans of this service. See
+    /// `sanitize::is_sensitive_field`.
+    pub propagation_filter: Option<Arc<PropagationFilterFn>>,
+    /// `propagation_hosts` restricts the hosts the HTTP client integrations send the trace
+    /// headers to, see `PropagationHosts`. All of them by default.
+    pub propagation_hosts: Option<PropagationHosts>,
+    /// `peer_services` names the services behind the hosts the HTTP client integrations
+    /// send requests to, recorded as `peer.service`
+    pub peer_services: Option<PeerServices>,
+    /// `max_propagation_header_size` caps the length of the serialized trace headers, which
+    /// proxies reject past some size. The trace level fields, and then the baggage, are
+    /// left out of longer headers and the span gets `meta.propagation_truncated`. 4096 bytes
+    /// by default, None lifts the cap.
+    pub max_propagation_header_size: Option<usize>,
+    /// `propagation_parsers` parses the payload of incoming trace headers, keyed by the
+    /// version of the header, returning None for the ones it rejects. Headers of other
+    /// versions than 1 without a parser are parsed as version 1 ones, when possible.
+    pub propagation_parsers: HashMap<String, Arc<PropagationParserFn>>,
+    /// `propagation_parsing` is whether incoming trace headers that don't follow the
+    /// format are read as far as possible, the default, or rejected
+    pub propagation_parsing: PropagationParsing,
+    /// `validate_api_key` makes `init` check the API key with Honeycomb before sending
+    /// anything, failing on a key Honeycomb rejects, and record the team and environment
+    /// of the key as `meta.team` and `meta.environment`
+    pub validate_api_key: bool,
+    /// `batch_timeout` overrides how long the transmission waits for a batch to fill up
+    /// before sending it anyway
+    pub batch_timeout: Option<Duration>,
+    /// `max_batch_size` overrides the number of events sent in a single batch
+    pub max_batch_size: Option<usize>,
+    /// `pending_work_capacity` overrides how many events can wait for transmission
+    pub pending_work_capacity: Option<usize>,
+    /// `flush_interval` forces a flush of the transmission every interval, even when
+    /// batches aren't full
+    pub flush_interval: Option<Duration>,
+    /// `retry_policy` enables sending again the events of failed batches
+    pub retry_policy: Option<RetryPolicy>,
+    /// `http_proxy` is the proxy the requests of the beeline go through, see the `http`
+    /// module for the transmission
+    pub http_proxy: Option<String>,
+    /// `ca_bundle` is a PEM file with an extra CA to trust
+    pub ca_bundle: Option<PathBuf>,
+    /// `http_client` replaces the HTTP client built from `http_proxy` and `ca_bundle`
+    pub http_client: Option<HttpClient>,
+    /// `refinery_target` is the Refinery the events are sent to, recorded on every event
+    /// as `meta.refinery_target`. Set by `Config::refinery`.
+    pub refinery_target: Option<String>,
+    /// `id_generator` replaces the random UUIDs used as the ids of new traces and spans,
+    /// e.g. with `test::sequential_ids` to compare serialized traces across test runs. The
+    /// ids must be unique, and shouldn't contain `,`, which the trace headers use as a
+    /// separator.
+    pub id_generator: Option<Arc<IdGeneratorFn>>,
+    /// `event_filter` drops or routes elsewhere the events matching its rules, right
+    /// before they are transmitted, see the `filter` module
+    pub event_filter: Option<EventFilter>,
+    /// `export_hook` is the last to see every event, once sampled and through the presend
+    /// hook, with all the `meta.` and `trace.` fields set. It is meant for changes to the
+    /// whole schema, such as the renames of `export::rename_fields`. It runs before
+    /// `event_filter`, whose rules see the changed fields. Events it panics on are dropped.
+    pub export_hook: Option<Arc<ExportHookFn>>,
+    /// `otel_field_names` renames the fields of the events to their names in the
+    /// OpenTelemetry semantic conventions (e.g. `http.request.method`), see the `export`
+    /// module
+    pub otel_field_names: bool,
+    /// `field_schema` is the expected type of some fields, checked on every event in debug
+    /// builds, see the `schema` module
+    pub field_schema: Option<FieldSchema>,
+    /// `flamegraph` sends, after the root span of every trace, an event with the folded
+    /// stacks of its span tree for flamegraph tooling, see the `flamegraph` module
+    pub flamegraph: bool,
+    /// `slow_trace_threshold` is the duration past which the root span of a trace makes it
+    /// slow, see `slow_trace_hook`
+    pub slow_trace_threshold: Option<Duration>,
+    /// `slow_trace_hook` is handed a snapshot of every trace whose root span lasted longer
+    /// than `slow_trace_threshold`, before the root span is sent, e.g. to capture a CPU
+    /// profile or extra diagnostics of pathological requests. It runs on the thread
+    /// sending the root span, so it should hand long work over to another thread.
+    pub slow_trace_hook: Option<Arc<SlowTraceHookFn>>,
+    /// `slow_thresholds` flags the spans lasting longer than the threshold of their name or
+    /// `meta.type` with `meta.slow`, and keeps them with a sample rate of 1 without asking
+    /// `sampler_hook`, so the worst offenders are always captured. Spans of traces dropped
+    /// by `head_sampler_hook` are never built, so they can't be kept.
+    pub slow_thresholds: Option<SlowThresholds>,
+    /// `always_keep` lists fields and values, e.g. `("user.tier", json!("enterprise"))`,
+    /// whose events are kept with a sample rate of 1 without asking `sampler_hook`, so
+    /// business-critical flows are never sampled out. Add them as trace level fields to
+    /// keep whole traces.
+    pub always_keep: Vec<(String, libhoney::Value)>,
+    /// `propagate_sample_rate` samples every trace at a single rate, sent to downstream
+    /// services in the trace headers so they reuse it rather than deciding again, which
+    /// keeps whole traces consistently weighted. The rate comes from the upstream service,
+    /// or else from `sampler_hook`, handed the trace level fields and the trace id when the
+    /// headers are first serialized. The spans of a trace with a rate are then kept or
+    /// dropped on their trace id at that rate, like `PerKeySampler` does, without asking
+    /// `sampler_hook`, and get `meta.trace_sample_rate`.
+    pub propagate_sample_rate: bool,
+    /// `unsent_child_policy` is what becomes of the synchronous spans not sent when their
+    /// parent is. By default they are sent along with it.
+    pub unsent_child_policy: UnsentChildPolicy,
 }
 
 impl fmt::Debug for Config {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Config {{\n  client_config: {:?},\n  service_name: {:?},\n  sampler_hook: Fn(),\n}}",
-            self.client_config, self.service_name
-        )
+        // the API key is the only secret of the configuration
+        let mut client_config = self.client_config.clone();
+        client_config.options.api_key = "<redacted>".to_string();
+        f.debug_struct("Config")
+            .field("client_config", &client_config)
+            .field("service_name", &self.service_name)
+            .field("build_info", &self.build_info)
+            .field("k8s_metadata", &self.k8s_metadata)
+            .field("sampler_hook", &"<fn>")
+            .field(
+                "head_sampler_hook",
+                &self.head_sampler_hook.as_ref().map(|_| "<fn>"),
+            )
+            .field("diagnostics_level", &self.diagnostics_level)
+            .field("presend_hook", &"<fn>")
+            .field(
+                "propagation_filter",
+                &self.propagation_filter.as_ref().map(|_| "<fn>"),
+            )
+            .field("propagation_hosts", &self.propagation_hosts)
+            .field("peer_services", &self.peer_services)
+            .field(
+                "max_propagation_header_size",
+                &self.max_propagation_header_size,
+            )
+            .field(
+                "propagation_parsers",
+                &self.propagation_parsers.keys().collect::<Vec<_>>(),
+            )
+            .field("propagation_parsing", &self.propagation_parsing)
+            .field("validate_api_key", &self.validate_api_key)
+            .field("batch_timeout", &self.batch_timeout)
+            .field("max_batch_size", &self.max_batch_size)
+            .field("pending_work_capacity", &self.pending_work_capacity)
+            .field("flush_interval", &self.flush_interval)
+            .field("retry_policy", &self.retry_policy)
+            .field("http_proxy", &self.http_proxy)
+            .field("ca_bundle", &self.ca_bundle)
+            .field("http_client", &self.http_client)
+            .field("refinery_target", &self.refinery_target)
+            .field("id_generator", &self.id_generator.as_ref().map(|_| "<fn>"))
+            .field("event_filter", &self.event_filter)
+            .field("export_hook", &self.export_hook.as_ref().map(|_| "<fn>"))
+            .field("otel_field_names", &self.otel_field_names)
+            .field("field_schema", &self.field_schema)
+            .field("flamegraph", &self.flamegraph)
+            .field("slow_trace_threshold", &self.slow_trace_threshold)
+            .field(
+                "slow_trace_hook",
+                &self.slow_trace_hook.as_ref().map(|_| "<fn>"),
+            )
+            .field("slow_thresholds", &self.slow_thresholds)
+            .field("always_keep", &self.always_keep)
+            .field("propagate_sample_rate", &self.propagate_sample_rate)
+            .field("unsent_child_policy", &self.unsent_child_policy)
+            .finish()
     }
 }
 
@@ -67,12 +339,156 @@ impl Default for Config {
                 transmission_options: libhoney::transmission::Options::default(),
             },
             service_name: None,
-            sampler_hook: Arc::new(|_| (true, 1)),
+            build_info: None,
+            k8s_metadata: false,
+            sampler_hook: Arc::new(|_| SamplerDecision::keep(1)),
+            head_sampler_hook: None,
+            diagnostics_level: DiagnosticsLevel::Error,
             presend_hook: Arc::new(Mutex::new(default_presend_hook)),
+            propagation_filter: None,
+            propagation_hosts: None,
+            peer_services: None,
+            max_propagation_header_size: Some(DEFAULT_MAX_PROPAGATION_HEADER_SIZE),
+            propagation_parsers: HashMap::new(),
+            propagation_parsing: PropagationParsing::default(),
+            validate_api_key: false,
+            batch_timeout: None,
+            max_batch_size: None,
+            pending_work_capacity: None,
+            flush_interval: None,
+            retry_policy: None,
+            http_proxy: None,
+            ca_bundle: None,
+            http_client: None,
+            refinery_target: None,
+            id_generator: None,
+            event_filter: None,
+            export_hook: None,
+            otel_field_names: false,
+            field_schema: None,
+            flamegraph: false,
+            slow_trace_threshold: None,
+            slow_trace_hook: None,
+            slow_thresholds: None,
+            always_keep: Vec::new(),
+            propagate_sample_rate: false,
+            unsent_child_policy: UnsentChildPolicy::default(),
         }
     }
 }
 
+impl Config {
+    /// `refinery` returns a configuration sending events to the Refinery (Honeycomb's
+    /// sampling proxy) at `api_host`. Sampling is left to Refinery: every span is kept
+    /// with a sample rate of 1, and Refinery can honor the rates set by a custom
+    /// `sampler_hook`. Refinery samples whole traces, grouping spans by `trace.trace_id`,
+    /// which every span carries:
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "testutil")] {
+    /// use beeline::{trace::TraceSender, Config};
+    ///
+    /// let mut client = beeline::test::init(Config::refinery("http://localhost:8080"));
+    /// let trace = client.new_trace(None);
+    /// let root_span = trace.lock().get_root_span();
+    /// let child = root_span.lock().create_child(&mut client).unwrap();
+    /// let async_child = child.lock().create_async_child(&mut client).unwrap();
+    /// async_child.lock().send(&mut client);
+    /// trace.send(&mut client);
+    ///
+    /// let events = client.0.write().client.transmission.events();
+    /// assert_eq!(events.len(), 3);
+    /// for event in events {
+    ///     let fields = event.fields();
+    ///     assert_eq!(fields["trace.trace_id"], trace.lock().trace_id.as_str());
+    ///     assert_eq!(fields["meta.refinery_target"], "http://localhost:8080");
+    /// }
+    /// # }
+    /// ```
+    pub fn refinery(api_host: &str) -> Self {
+        let mut config = Self::default();
+        config.client_config.options.api_host = api_host.to_string();
+        config.refinery_target = Some(api_host.to_string());
+        config
+    }
+
+    /// `local_collector` returns a configuration sending events over plain HTTP to a
+    /// node-local collector listening on `address`, which forwards them to Honeycomb.
+    ///
+    /// Unix domain sockets and keep-alive tuning are not supported: the transmission
+    /// opens a new HTTP connection for every batch.
+    pub fn local_collector(address: SocketAddr) -> Self {
+        let mut config = Self::default();
+        config.client_config.options.api_host = format!("http://{}", address);
+        config
+    }
+
+    /// `with_sampler_hook` sets `sampler_hook` to `hook`, which may return anything turning
+    /// into a `SamplerDecision`, such as the `(bool, usize)` of a keep flag and a sample
+    /// rate, or a bare `bool`
+    ///
+    /// ```rust
+    /// use beeline::Config;
+    ///
+    /// let config = Config::default().with_sampler_hook(|fields| (fields.contains_key("error"), 1));
+    /// ```
+    pub fn with_sampler_hook<F, D>(mut self, hook: F) -> Self
+    where
+        F: Fn(HashMap<String, libhoney::Value>) -> D + 'static + Send + Sync,
+        D: Into<SamplerDecision>,
+    {
+        self.sampler_hook = Arc::new(move |fields| hook(fields).into());
+        self
+    }
+
+    /// `validate` checks the transmission settings, which must all be greater than zero
+    pub fn validate(&self) -> errors::Result<()> {
+        let zero = Some(Duration::from_secs(0));
+        let settings = [
+            ("batch_timeout", self.batch_timeout == zero),
+            ("max_batch_size", self.max_batch_size == Some(0)),
+            (
+                "pending_work_capacity",
+                self.pending_work_capacity == Some(0),
+            ),
+            ("flush_interval", self.flush_interval == zero),
+        ];
+        match settings.iter().find(|(_, is_zero)| *is_zero) {
+            Some((name, _)) => Err(BeelineError::config(
+                ConfigErrorKind::InvalidTransmissionSettings,
+                format!("{} must be greater than zero", name),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// `libhoney_config` returns the libhoney configuration with the transmission
+    /// settings applied. Invalid settings are all ignored.
+    fn libhoney_config(&self) -> ClientConfig {
+        let mut client_config = self.client_config.clone();
+        if let Err(e) = self.validate() {
+            diagnostics::log(
+                self.diagnostics_level,
+                Level::Error,
+                format_args!("ignoring the transmission settings: {}", e),
+            );
+            return client_config;
+        }
+
+        let options = &mut client_config.transmission_options;
+        if let Some(batch_timeout) = self.batch_timeout {
+            options.batch_timeout = batch_timeout;
+        }
+        if let Some(max_batch_size) = self.max_batch_size {
+            options.max_batch_size = max_batch_size;
+        }
+        if let Some(pending_work_capacity) = self.pending_work_capacity {
+            options.pending_work_capacity = pending_work_capacity;
+        }
+        client_config
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Client<T: Sender>(pub Arc<RwLock<BeelineClient<T>>>);
 
@@ -81,6 +497,14 @@ pub struct BeelineClient<T: Sender> {
     pub config: Config,
     pub client: libhoney::Client<T>,
     pub traces: Arc<Mutex<HashMap<String, SafeTrace>>>,
+    pub stats: Stats,
+    started: Instant,
+    async_queue: Option<mpsc::Sender<async_send::Job>>,
+    health: health::Tracker,
+    flusher: Option<Arc<BackgroundTask>>,
+    // held for the whole of a flush, so flushes don't swap the transmission concurrently
+    flushing: Arc<Mutex<()>>,
+    retries: retry::Pending,
 }
 
 impl<T> Client<T>
@@ -88,21 +512,115 @@ where
     T: Sender,
 {
     pub fn get_trace(&self, trace_id: String) -> Option<SafeTrace> {
-        let traces = &self.0.write().traces.clone();
-        let guard = traces.lock();
-        match guard.get(&trace_id) {
-            Some(trace) => Some(trace.clone()),
-            None => None,
-        }
+        self.trace(&trace_id)
+    }
+
+    pub(crate) fn trace(&self, trace_id: &str) -> Option<SafeTrace> {
+        self.0.read().traces.lock().get(trace_id).cloned()
     }
 
     pub fn remove_child_span_from_trace(&self, trace_id: String, span_id: String) {
-        let traces = &self.0.write().traces;
-        let guard = traces.lock();
-        if let Some(trace) = guard.get(&trace_id) {
-            let mut trace = trace.lock();
-            trace.remove_child_span(span_id);
+        if let Some(trace) = self.trace(&trace_id) {
+            trace.lock().remove_child_span(&span_id);
+        }
+    }
+
+    /// `stats` returns a snapshot of the client counters, including how many events the
+    /// sampler dropped and why
+    pub fn stats(&self) -> Stats {
+        self.0.read().stats.clone()
+    }
+
+    /// `health` reports whether events are making it to Honeycomb, e.g. for readiness
+    /// probes. It consumes the pending transmission responses, so it shouldn't be combined
+    /// with reading them from the libhoney client directly.
+    pub fn health(&self) -> Health {
+        let responses = self.0.read().client.responses();
+        for response in responses.try_iter() {
+            self.handle_response(
+                response.status_code.map(|status| status.as_u16()),
+                response.error,
+                response.metadata,
+            );
+        }
+        self.0.read().health.snapshot()
+    }
+
+    /// `flush` sends every trace that is still open, waits for the events queued by
+    /// `send_async` and blocks until the transmission has sent everything it was handed.
+    /// It is meant to be called when the application shuts down, or before it gets
+    /// frozen (e.g. in AWS Lambda).
+    pub fn flush(&self) -> errors::Result<()>
+    where
+        T: Clone,
+    {
+        self.send_open_traces();
+        self.flush_event_filter();
+        flush_transmission(&self.0)
+            .map_err(|e| BeelineError::send_caused_by("unable to flush events", e))
+    }
+
+    /// `close` is like `flush`, but stops the transmission once it is done: events sent
+    /// afterwards are never transmitted
+    pub fn close(&self) -> errors::Result<()> {
+        self.send_open_traces();
+        self.flush_event_filter();
+        self.0
+            .write()
+            .client
+            .transmission
+            .stop()
+            .map_err(|e| BeelineError::send_caused_by("unable to close client", e))
+    }
+
+    fn flush_event_filter(&self) {
+        let event_filter = self.0.read().config.event_filter.clone();
+        if let Some(event_filter) = event_filter {
+            event_filter.flush();
+        }
+    }
+
+    fn send_open_traces(&self) {
+        let traces = self.0.read().traces.lock().clone();
+        let mut client = Client(self.0.clone());
+        for trace in traces.values() {
+            trace.send(&mut client);
         }
+        // the traces are only forgotten once sent, as their spans look them up for the
+        // trace level fields
+        let open = self.0.read().traces.clone();
+        open.lock()
+            .retain(|trace_id, _| !traces.contains_key(trace_id));
+
+        async_send::notify(self).wait();
+    }
+
+    /// `create_marker` creates a Honeycomb marker (e.g. a deploy) on the configured
+    /// dataset, using the configured API key and host.
+    pub fn create_marker(
+        &self,
+        message: &str,
+        marker_type: &str,
+        url: Option<&str>,
+    ) -> errors::Result<Marker> {
+        let config = self.0.read().config.clone();
+        let options = &config.client_config.options;
+        markers::create(
+            &http::client(&config)?,
+            &options.api_host,
+            &options.api_key,
+            &options.dataset,
+            message,
+            marker_type,
+            url,
+        )
+    }
+
+    /// `diagnostic` logs a problem of the beeline itself, if `level` is within the
+    /// configured `diagnostics_level`. The client must not be locked by the caller.
+    pub(crate) fn diagnostic(&self, level: Level, args: fmt::Arguments<'_>) {
+        let max = self.0.read().config.diagnostics_level;
+        diagnostics::log(max, level, args);
     }
 
     pub fn new_builder(&self) -> libhoney::Builder {
@@ -113,6 +631,22 @@ where
         self.0.write().client.add_field(name, value)
     }
 
+    /// `peer_service` returns the name of the service behind `host`, following
+    /// `Config::peer_services`
+    pub fn peer_service(&self, host: &str) -> Option<String> {
+        let peer_services = self.0.read().config.peer_services.clone()?;
+        peer_services.service_for(host)
+    }
+
+    /// `propagates_to` returns whether outgoing requests to `host` can carry the trace
+    /// headers, following `Config::propagation_hosts`
+    pub fn propagates_to(&self, host: &str) -> bool {
+        match &self.0.read().config.propagation_hosts {
+            Some(hosts) => hosts.allows(host),
+            None => true,
+        }
+    }
+
     pub fn new_trace(&self, serialized_headers: Option<String>) -> SafeTrace {
         let trace = Trace::new(self, serialized_headers);
         self.0
@@ -122,34 +656,256 @@ where
             .insert(trace.lock().trace_id.clone(), trace.clone());
         trace
     }
+
+    /// `try_new_trace` starts a trace like `new_trace`, but returns a
+    /// `BeelineError::PropagationError` when `serialized_headers` can't be parsed, where
+    /// `new_trace` starts a fresh trace. Integrations use it to record
+    /// `meta.propagation_error` on the fresh trace they start instead.
+    pub fn try_new_trace(&self, serialized_headers: Option<String>) -> errors::Result<SafeTrace> {
+        let trace = Trace::try_new(self, serialized_headers)?;
+        self.0
+            .write()
+            .traces
+            .lock()
+            .insert(trace.lock().trace_id.clone(), trace.clone());
+        Ok(trace)
+    }
+
+    /// `send_event` sends a standalone event, outside of any trace, made of the client
+    /// level fields plus the given ones
+    pub(crate) fn send_event<I>(&self, fields: I)
+    where
+        I: IntoIterator<Item = (String, libhoney::Value)>,
+    {
+        let mut ev = self.new_builder().new_event();
+        for (name, value) in fields {
+            ev.add_field(&name, value);
+        }
+        self.transmit(&mut ev);
+    }
 }
 
+impl<T> Client<T>
+where
+    T: Sender + Send + Sync + 'static,
+{
+    /// `flush_timeout` is like `flush`, but gives up after `timeout` with a
+    /// `BeelineError::Timeout`. The flush carries on in the background without holding the
+    /// client, so spans keep being sent and it is safe to exit the process even when the
+    /// transmission is stuck, e.g. on an unreachable API. A later `flush` waits for it to
+    /// be done.
+    pub fn flush_timeout(&self, timeout: Duration) -> errors::Result<()>
+    where
+        T: Clone,
+    {
+        let client = Client(self.0.clone());
+        let (done, flushed) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("beeline-flush".to_string())
+            .spawn(move || {
+                let _ = done.send(client.flush());
+            })
+            .map_err(|e| BeelineError::send_caused_by("unable to spawn flush thread", e))?;
+        match flushed.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(BeelineError::Timeout(timeout)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(BeelineError::SendError {
+                message: "flush thread panicked".to_string(),
+                source: None,
+            }),
+        }
+    }
+
+    /// `start_process_stats` starts emitting a `process_stats` event (RSS, CPU, open file
+    /// descriptors and, with the `tokio` feature, runtime metrics) every `interval`. The
+    /// emitter runs until the returned handle is stopped or dropped.
+    pub fn start_process_stats(&self, interval: Duration) -> BackgroundTask {
+        let client = Client(self.0.clone());
+        let mut stats = process_stats::ProcessStats::new();
+        BackgroundTask::spawn("beeline-process-stats", interval, move || {
+            client.send_event(stats.sample())
+        })
+    }
+
+    /// `start_heartbeat` starts emitting a small `heartbeat` event carrying the client
+    /// uptime, the service metadata and the given fields every `interval`, so triggers
+    /// can alert on a service that is running but no longer serving traffic. The
+    /// heartbeat runs until the returned handle is stopped or dropped.
+    pub fn start_heartbeat(
+        &self,
+        interval: Duration,
+        fields: HashMap<String, libhoney::Value>,
+    ) -> BackgroundTask {
+        let client = Client(self.0.clone());
+        BackgroundTask::spawn("beeline-heartbeat", interval, move || {
+            let uptime = client.0.read().started.elapsed();
+            let mut heartbeat = fields.clone();
+            heartbeat.insert(
+                keys::META_TYPE.to_string(),
+                libhoney::Value::String("heartbeat".to_string()),
+            );
+            heartbeat.insert(
+                "uptime_ms".to_string(),
+                libhoney::json!(uptime.as_nanos() as f64 / 1_000_000f64),
+            );
+            client.send_event(heartbeat)
+        })
+    }
+}
+
+/// `init` starts a client sending events with `config`.
+///
+/// # Panics
+///
+/// If the dataset isn't one Honeycomb accepts, or, with `Config::validate_api_key` set, if
+/// Honeycomb rejects the API key. Use `try_init` to handle these cases.
 pub fn init(config: Config) -> Client<Transmission> {
-    let cfg = config.clone();
-    let mut client: libhoney::client::Client<Transmission> = libhoney::init(cfg.client_config);
+    match try_init(config) {
+        Ok(client) => client,
+        Err(e) => panic!("{}", e.chain()),
+    }
+}
+
+/// `try_init` starts a client like `init`, but returns a `BeelineError::InitError` when
+/// the dataset isn't one Honeycomb accepts (see `dataset::validate`), or when
+/// `Config::validate_api_key` is set and the key can't be validated. Its source is the
+/// `BeelineError::ConfigError` when the dataset or the HTTP settings are invalid, or when
+/// Honeycomb rejects the API key, its kind telling which (e.g.
+/// `ConfigErrorKind::InvalidApiKey`). When Honeycomb is unreachable, the client starts all
+/// the same.
+pub fn try_init(config: Config) -> errors::Result<Client<Transmission>> {
+    dataset::validate(&config.client_config.options.dataset)
+        .map_err(|e| BeelineError::init_caused_by("invalid dataset", e))?;
+    let key_info = if config.validate_api_key {
+        let options = &config.client_config.options;
+        let http = http::client(&config)
+            .map_err(|e| BeelineError::init_caused_by("invalid HTTP settings", e))?;
+        match auth::validate(&http, &options.api_host, &options.api_key) {
+            Ok(info) => Some(info),
+            Err(
+                e @ BeelineError::ConfigError {
+                    kind: ConfigErrorKind::InvalidApiKey,
+                    ..
+                },
+            ) => return Err(BeelineError::init_caused_by("API key rejected", e)),
+            Err(e) => {
+                diagnostics::log(
+                    config.diagnostics_level,
+                    Level::Warn,
+                    format_args!("starting without validating the API key: {}", e.chain()),
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let client: libhoney::client::Client<Transmission> = libhoney::init(config.libhoney_config());
+    let mut client = new_client(config, client);
+    if let Some(info) = key_info {
+        client.add_field(keys::META_TEAM, libhoney::Value::String(info.team));
+        if let Some(environment) = info.environment {
+            client.add_field(keys::META_ENVIRONMENT, libhoney::Value::String(environment));
+        }
+    }
+    Ok(client)
+}
 
-    internal_config::<Transmission>(config.clone(), &mut client);
+fn new_client<T>(config: Config, mut client: libhoney::Client<T>) -> Client<T>
+where
+    T: Sender + Clone + Send + Sync + 'static,
+{
+    internal_config::<T>(config.clone(), &mut client);
 
-    Client(Arc::new(RwLock::new(BeelineClient {
+    let flush_interval = config.flush_interval.filter(|_| config.validate().is_ok());
+    let client = Client(Arc::new(RwLock::new(BeelineClient {
         config,
         client,
         traces: Arc::new(Mutex::new(HashMap::new())),
-    })))
+        stats: Stats::default(),
+        started: Instant::now(),
+        async_queue: None,
+        health: health::Tracker::default(),
+        flusher: None,
+        flushing: Arc::default(),
+        retries: retry::Pending::default(),
+    })));
+    if let Some(interval) = flush_interval {
+        // the flusher is owned by the client, so it must not keep it alive
+        let weak = Arc::downgrade(&client.0);
+        let flusher = BackgroundTask::spawn("beeline-flusher", interval, move || {
+            if let Some(inner) = weak.upgrade() {
+                let flushed = flush_transmission(&inner);
+                if let Err(e) = flushed {
+                    Client(inner)
+                        .diagnostic(Level::Warn, format_args!("unable to flush events: {}", e));
+                }
+            }
+        });
+        client.0.write().flusher = Some(Arc::new(flusher));
+    }
+    if client.0.read().config.retry_policy.is_some() {
+        retry::start(&client);
+    }
+    if http::transmission_ignores_proxy(&client.0.read().config) {
+        client.diagnostic(
+            Level::Warn,
+            format_args!("http_proxy is only used by the transmission if set in HTTPS_PROXY"),
+        );
+    }
+    client
+}
+
+/// `flush_transmission` stops and restarts the transmission of `inner`, which blocks until
+/// it has sent everything it was handed. The client lock isn't held meanwhile, so spans
+/// keep being sent: a clone of the transmission, sharing its queue as libhoney's does,
+/// stands in for it until it is back.
+fn flush_transmission<T>(inner: &RwLock<BeelineClient<T>>) -> libhoney::Result<()>
+where
+    T: Sender + Clone,
+{
+    let flushing = inner.read().flushing.clone();
+    let _flushing = flushing.lock();
+    let mut transmission = {
+        let mut inner = inner.write();
+        let stand_in = inner.client.transmission.clone();
+        std::mem::replace(&mut inner.client.transmission, stand_in)
+    };
+    let flushed = transmission.stop().map(|()| transmission.start());
+    inner.write().client.transmission = transmission;
+    flushed
 }
 
 fn internal_config<T: Sender>(config: Config, client: &mut libhoney::Client<T>) {
     client.add_field(
-        "meta.beeline_version",
+        keys::META_BEELINE_VERSION,
         libhoney::Value::String(env!("CARGO_PKG_VERSION").to_string()),
     );
 
+    if let Some(target) = config.refinery_target {
+        client.add_field(keys::META_REFINERY_TARGET, libhoney::Value::String(target));
+    }
+
     if let Some(svc) = config.service_name {
-        client.add_field("meta.service_name", libhoney::Value::String(svc));
+        client.add_field(keys::META_SERVICE_NAME, libhoney::Value::String(svc));
+    }
+
+    if let Some(build_info) = config.build_info {
+        for (name, value) in build_info.fields() {
+            client.add_field(name, value);
+        }
+    }
+
+    if config.k8s_metadata {
+        for (name, value) in K8sMetadata::detect().fields() {
+            client.add_field(name, value);
+        }
     }
 
     if let Ok(hostname) = hostname::get() {
         client.add_field(
-            "meta.local_hostname",
+            keys::META_LOCAL_HOSTNAME,
             libhoney::Value::String(
                 hostname
                     .into_string()
@@ -159,33 +915,11 @@ fn internal_config<T: Sender>(config: Config, client: &mut libhoney::Client<T>)
     }
 }
 
-pub mod test {
-    pub use libhoney::mock::TransmissionMock;
-
-    use crate::{Client, Config};
-
-    use super::*;
-
-    pub fn init(config: Config) -> Client<TransmissionMock> {
-        let cfg = config.clone();
-        let mut client = libhoney::test::init(cfg.client_config);
-
-        internal_config::<TransmissionMock>(config.clone(), &mut client);
-
-        Client(Arc::new(RwLock::new(BeelineClient {
-            config,
-            client,
-            traces: Arc::new(Mutex::new(HashMap::new())),
-        })))
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use libhoney::mock::TransmissionMock;
 
     use super::*;
-    use crate::trace::TraceSender;
 
     pub fn new_client(config: Config) -> Client<TransmissionMock> {
         let api_host = &mockito::server_url();
@@ -204,6 +938,240 @@ mod tests {
         crate::test::init(config)
     }
 
+    #[test]
+    fn test_health() {
+        let mut client = new_client(Config::default());
+        for _ in 0..2 {
+            client.new_trace(None).send(&mut client);
+        }
+        let health = client.health();
+        assert!(health.reachable);
+        assert_eq!(health.last_error, None);
+        // the mock transmission never responds
+        assert_eq!(health.queue_depth, 2);
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut client = new_client(Config::default());
+        let sent = client.new_trace(None);
+        sent.send(&mut client);
+        let open = client.new_trace(None);
+        open.lock().add_field("open", libhoney::json!(true));
+        let root_span = open.lock().get_root_span();
+        root_span.lock().create_child(&mut client).unwrap();
+
+        client.flush().unwrap();
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        assert!(events[1..]
+            .iter()
+            .all(|event| event.fields()["open"] == libhoney::json!(true)));
+        assert!(client.0.read().traces.lock().is_empty());
+
+        client.flush_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_transmission_settings() {
+        let config = Config {
+            batch_timeout: Some(Duration::from_millis(10)),
+            max_batch_size: Some(5),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        let options = config.libhoney_config().transmission_options;
+        assert_eq!(options.batch_timeout, Duration::from_millis(10));
+        assert_eq!(options.max_batch_size, 5);
+
+        let config = Config {
+            max_batch_size: Some(5),
+            pending_work_capacity: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "pending_work_capacity must be greater than zero"
+        );
+        let options = config.libhoney_config().transmission_options;
+        assert_eq!(
+            options.max_batch_size,
+            TransmissionOptions::default().max_batch_size
+        );
+    }
+
+    #[test]
+    fn test_local_collector() {
+        let config = Config::local_collector(([127, 0, 0, 1], 9000).into());
+        assert_eq!(
+            config.client_config.options.api_host,
+            "http://127.0.0.1:9000"
+        );
+        let config = Config::local_collector("[::1]:9000".parse().unwrap());
+        assert_eq!(config.client_config.options.api_host, "http://[::1]:9000");
+    }
+
+    #[test]
+    fn test_try_init() {
+        let _m = mockito::mock("GET", "/1/auth")
+            .match_header("X-Honeycomb-Team", "rejected-key")
+            .with_status(401)
+            .create();
+        let _m = mockito::mock("GET", "/1/auth")
+            .match_header("X-Honeycomb-Team", "teamless-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body("{}")
+            .create();
+        let _m = mockito::mock("GET", "/1/auth")
+            .match_header("X-Honeycomb-Team", "env-key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"environment":{"slug":"production"},"team":{"slug":"acme"}}"#)
+            .create();
+        let config = |api_host: &str, api_key: &str| {
+            let mut config = Config {
+                validate_api_key: true,
+                ..Default::default()
+            };
+            config.client_config.options.api_host = api_host.to_string();
+            config.client_config.options.api_key = api_key.to_string();
+            config
+        };
+
+        let err = try_init(config(&mockito::server_url(), "rejected-key")).unwrap_err();
+        assert!(matches!(err, BeelineError::InitError { .. }));
+        assert!(matches!(
+            std::error::Error::source(&err).and_then(|e| e.downcast_ref::<BeelineError>()),
+            Some(BeelineError::ConfigError {
+                kind: ConfigErrorKind::InvalidApiKey,
+                ..
+            })
+        ));
+
+        let client = try_init(config(&mockito::server_url(), "env-key")).unwrap();
+        let fields = client.new_builder().new_event().fields();
+        assert_eq!(fields[keys::META_TEAM], libhoney::json!("acme"));
+        assert_eq!(
+            fields[keys::META_ENVIRONMENT],
+            libhoney::json!("production")
+        );
+        client.close().unwrap();
+
+        // an unreachable API doesn't prevent the client from starting
+        let client = try_init(config("http://127.0.0.1:1", "env-key")).unwrap();
+        let fields = client.new_builder().new_event().fields();
+        assert!(!fields.contains_key(keys::META_TEAM));
+        client.close().unwrap();
+
+        // neither does an unexpected response
+        let client = try_init(config(&mockito::server_url(), "teamless-key")).unwrap();
+        let fields = client.new_builder().new_event().fields();
+        assert!(!fields.contains_key(keys::META_TEAM));
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn test_config_debug() {
+        let mut config = Config {
+            always_keep: vec![("user.tier".to_string(), libhoney::json!("enterprise"))],
+            export_hook: Some(Arc::new(|_| {})),
+            ..Config::default()
+        };
+        config.client_config.options.api_key = "secret-key".to_string();
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("secret-key"));
+        assert!(debug.contains("<redacted>"));
+        assert!(debug.contains(r#"export_hook: Some("<fn>")"#));
+        assert!(debug.contains("id_generator: None"));
+        assert!(debug.contains("always_keep: [(\"user.tier\", String(\"enterprise\"))]"));
+        assert!(debug.contains("retry_policy: None"));
+    }
+
+    #[test]
+    fn test_try_init_invalid_dataset() {
+        for dataset in &["team/checkout", "données", ""] {
+            let mut config = Config::default();
+            config.client_config.options.dataset = dataset.to_string();
+            let err = try_init(config).unwrap_err();
+            assert!(matches!(err, BeelineError::InitError { .. }));
+            assert!(matches!(
+                std::error::Error::source(&err).and_then(|e| e.downcast_ref::<BeelineError>()),
+                Some(BeelineError::ConfigError {
+                    kind: ConfigErrorKind::InvalidDataset,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_flush_interval() {
+        let client = new_client(Config {
+            flush_interval: Some(Duration::from_millis(5)),
+            ..Default::default()
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        // a flush stops and restarts the transmission
+        let debug = format!("{:?}", client.0.read().client.transmission);
+        assert!(!debug.contains("stopped: 0"));
+
+        // the flusher doesn't keep the client alive
+        let weak = Arc::downgrade(&client.0);
+        drop(client);
+        // a tick may be holding on to the client for a moment
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(weak.upgrade().is_none());
+    }
+
+    /// `StuckTransmission` is a transmission whose `stop` blocks until it is released, like
+    /// one stuck on an unreachable API
+    #[derive(Debug, Clone)]
+    struct StuckTransmission {
+        release: Arc<std::sync::Mutex<mpsc::Receiver<()>>>,
+        responses: crossbeam_channel::Receiver<libhoney::response::Response>,
+    }
+
+    impl Sender for StuckTransmission {
+        fn send(&mut self, _: libhoney::Event) {}
+
+        fn start(&mut self) {}
+
+        fn stop(&mut self) -> libhoney::Result<()> {
+            let _ = self.release.lock().unwrap().recv();
+            Ok(())
+        }
+
+        fn responses(&self) -> crossbeam_channel::Receiver<libhoney::response::Response> {
+            self.responses.clone()
+        }
+    }
+
+    #[test]
+    fn test_flush_unlocked() {
+        let (release, released) = mpsc::channel();
+        let transmission = StuckTransmission {
+            release: Arc::new(std::sync::Mutex::new(released)),
+            responses: crossbeam_channel::unbounded().1,
+        };
+        let config = Config::default();
+        let options = config.client_config.options.clone();
+        let client = super::new_client(config, libhoney::Client::new(options, transmission));
+
+        let timeout = Duration::from_millis(20);
+        assert!(matches!(
+            client.flush_timeout(timeout),
+            Err(BeelineError::Timeout(t)) if t == timeout
+        ));
+        // the stuck flush doesn't keep spans from being sent
+        assert!(client.0.try_write().is_some());
+
+        // the next flush waits for the stuck one to be done
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        client.flush().unwrap();
+    }
+
     #[test]
     fn test_multiple_threads_with_span() {
         let client = new_client(Config::default());
@@ -245,6 +1213,63 @@ mod tests {
         assert_eq!(events.len(), 3);
     }
 
+    #[test]
+    fn test_build_info() {
+        let mut client = new_client(Config {
+            build_info: Some(BuildInfo {
+                git_sha: Some("9fceb02".to_string()),
+                version: Some("1.2.3".to_string()),
+                ..BuildInfo::default()
+            }),
+            ..Config::default()
+        });
+        let trace = client.new_trace(None);
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        let fields = events[0].fields();
+        assert_eq!(fields["meta.git_sha"], serde_json::json!("9fceb02"));
+        assert_eq!(fields["meta.version"], serde_json::json!("1.2.3"));
+        assert!(!fields.contains_key("meta.build_time"));
+    }
+
+    #[test]
+    fn test_process_stats() {
+        let client = new_client(Config::default());
+        let task = client.start_process_stats(Duration::from_millis(5));
+        std::thread::sleep(Duration::from_millis(50));
+        task.stop();
+
+        let events = client.0.write().client.transmission.events();
+        assert!(!events.is_empty());
+        assert_eq!(
+            events[0].fields()["meta.type"],
+            serde_json::json!("process_stats")
+        );
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let client = new_client(Config::default());
+        let mut fields = HashMap::new();
+        fields.insert("region".to_string(), serde_json::json!("eu-west-1"));
+        let task = client.start_heartbeat(Duration::from_millis(5), fields);
+        std::thread::sleep(Duration::from_millis(50));
+        task.stop();
+
+        let events = client.0.write().client.transmission.events();
+        assert!(!events.is_empty());
+        let fields = events[0].fields();
+        assert_eq!(fields["meta.type"], serde_json::json!("heartbeat"));
+        assert_eq!(fields["region"], serde_json::json!("eu-west-1"));
+        assert_eq!(
+            fields["meta.service_name"],
+            serde_json::json!("beeline-rust-test")
+        );
+        assert!(fields["uptime_ms"].as_f64().unwrap() > 0f64);
+    }
+
     #[test]
     fn test_multiple_threads() {
         let client = new_client(Config::default());
@@ -0,0 +1,141 @@
+/*! Honeycomb markers.
+
+Markers annotate graphs in Honeycomb with points in time, such as deploys or feature flag
+flips. They are created through the markers API using the API key, host and dataset the
+client was configured with.
+
+*/
+use reqwest::blocking::Client as HttpClient;
+use serde_json::{json, Value};
+
+use crate::errors::{BeelineError, Result};
+
+const MARKERS_ENDPOINT: &str = "/1/markers/";
+pub(crate) const HONEYCOMB_TEAM_HEADER: &str = "X-Honeycomb-Team";
+
+/// `Marker` is a marker as returned by the Honeycomb markers API
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    /// id assigned to the marker by Honeycomb
+    pub id: String,
+    /// message shown alongside the marker
+    pub message: String,
+    /// type of the marker, used to group markers of the same kind (e.g. "deploy")
+    pub marker_type: String,
+    /// optional url the marker links to
+    pub url: Option<String>,
+}
+
+impl Marker {
+    fn from_response(body: &Value) -> Result<Self> {
+        let field = |name: &str| body.get(name).and_then(Value::as_str).map(String::from);
+        Ok(Self {
+            id: field("id")
+                .ok_or_else(|| BeelineError::send("marker response is missing an id"))?,
+            message: field("message").unwrap_or_default(),
+            marker_type: field("type").unwrap_or_default(),
+            url: field("url"),
+        })
+    }
+}
+
+pub(crate) fn create(
+    http: &HttpClient,
+    api_host: &str,
+    api_key: &str,
+    dataset: &str,
+    message: &str,
+    marker_type: &str,
+    url: Option<&str>,
+) -> Result<Marker> {
+    let mut body = json!({
+        "message": message,
+        "type": marker_type,
+    });
+    if let Some(url) = url {
+        body["url"] = json!(url);
+    }
+
+    let response = http
+        .post(&format!(
+            "{}{}{}",
+            api_host.trim_end_matches('/'),
+            MARKERS_ENDPOINT,
+            dataset
+        ))
+        .header(HONEYCOMB_TEAM_HEADER, api_key)
+        .json(&body)
+        .send()
+        .map_err(|e| BeelineError::send_caused_by("unable to create marker", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(BeelineError::send(format!(
+            "markers API responded with {}",
+            status
+        )));
+    }
+
+    let body: Value = response
+        .json()
+        .map_err(|e| BeelineError::send_caused_by("unable to parse marker", e))?;
+    Marker::from_response(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create() {
+        let _m = mockito::mock("POST", "/1/markers/beeline-rust")
+            .match_header(HONEYCOMB_TEAM_HEADER, "key")
+            .match_body(mockito::Matcher::Json(json!({
+                "message": "deploy 1234",
+                "type": "deploy",
+                "url": "https://example.com/deploys/1234",
+            })))
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"id":"2zYkVr","message":"deploy 1234","type":"deploy","url":"https://example.com/deploys/1234"}"#,
+            )
+            .create();
+
+        let marker = create(
+            &HttpClient::new(),
+            &mockito::server_url(),
+            "key",
+            "beeline-rust",
+            "deploy 1234",
+            "deploy",
+            Some("https://example.com/deploys/1234"),
+        )
+        .unwrap();
+        assert_eq!(marker.id, "2zYkVr");
+        assert_eq!(marker.marker_type, "deploy");
+        assert_eq!(
+            marker.url,
+            Some("https://example.com/deploys/1234".to_string())
+        );
+    }
+
+    #[test]
+    fn test_create_failure() {
+        let _m = mockito::mock("POST", "/1/markers/unauthorized")
+            .with_status(401)
+            .create();
+
+        let err = create(
+            &HttpClient::new(),
+            &mockito::server_url(),
+            "bad-key",
+            "unauthorized",
+            "deploy",
+            "deploy",
+            None,
+        )
+        .unwrap_err();
+        assert!(matches!(err, BeelineError::SendError { .. }));
+    }
+}
@@ -0,0 +1,252 @@
+/*! Bridge from the `metrics` facade to Honeycomb.
+
+`HoneycombRecorder` implements `metrics::Recorder`. Counters, gauges and histograms
+recorded through the facade are aggregated in memory and, once started with
+`HoneycombRecorder::start`, emitted every interval as `meta.type = "metrics"` events: one
+event per distinct set of labels, with the labels added as fields.
+
+ - counters are sent as the increase over the interval
+ - gauges are sent as their latest value
+ - histograms are sent as `<name>.count`, `<name>.sum`, `<name>.min`, `<name>.max`,
+   `<name>.avg`, `<name>.p50`, `<name>.p95` and `<name>.p99` over the interval
+
+```rust,no_run
+use std::time::Duration;
+use beeline::{init, Config, HoneycombRecorder};
+
+let client = init(Config::default());
+let recorder = HoneycombRecorder::new();
+let _task = recorder.start(&client, Duration::from_secs(10));
+metrics::set_global_recorder(recorder).expect("recorder already installed");
+```
+
+*/
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use libhoney::{Sender, Value};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use parking_lot::Mutex;
+use serde_json::json;
+
+use crate::background::BackgroundTask;
+use crate::keys;
+use crate::Client;
+
+#[derive(Debug, Default)]
+struct AtomicCounter(AtomicU64);
+
+impl CounterFn for AtomicCounter {
+    fn increment(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.0.fetch_max(value, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default)]
+struct LastValueGauge(Mutex<f64>);
+
+impl GaugeFn for LastValueGauge {
+    fn increment(&self, value: f64) {
+        *self.0.lock() += value;
+    }
+
+    fn decrement(&self, value: f64) {
+        *self.0.lock() -= value;
+    }
+
+    fn set(&self, value: f64) {
+        *self.0.lock() = value;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Samples(Mutex<Vec<f64>>);
+
+impl HistogramFn for Samples {
+    fn record(&self, value: f64) {
+        self.0.lock().push(value);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: HashMap<Key, (Arc<AtomicCounter>, u64)>,
+    gauges: HashMap<Key, Arc<LastValueGauge>>,
+    histograms: HashMap<Key, Arc<Samples>>,
+}
+
+/// `HoneycombRecorder` is a `metrics::Recorder` aggregating metrics into periodic
+/// Honeycomb events
+#[derive(Debug, Default, Clone)]
+pub struct HoneycombRecorder {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl HoneycombRecorder {
+    /// `new` creates a recorder with no metrics registered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `start` emits the aggregated metrics through `client` every `interval`, until the
+    /// returned handle is stopped or dropped
+    pub fn start<T>(&self, client: &Client<T>, interval: Duration) -> BackgroundTask
+    where
+        T: Sender + Send + Sync + 'static,
+    {
+        let recorder = self.clone();
+        let client = Client(client.0.clone());
+        BackgroundTask::spawn("beeline-metrics", interval, move || {
+            for event in recorder.drain() {
+                client.send_event(event);
+            }
+        })
+    }
+
+    /// `drain` returns the fields of the events for the interval that just ended, resetting
+    /// counters and histograms
+    fn drain(&self) -> Vec<HashMap<String, Value>> {
+        let mut events = BTreeMap::new();
+        let mut registry = self.registry.lock();
+        for (key, (counter, last)) in registry.counters.iter_mut() {
+            let current = counter.0.load(Ordering::Relaxed);
+            event_for(&mut events, key)
+                .insert(key.name().to_string(), json!(current.saturating_sub(*last)));
+            *last = current;
+        }
+        for (key, gauge) in registry.gauges.iter() {
+            event_for(&mut events, key).insert(key.name().to_string(), json!(*gauge.0.lock()));
+        }
+        for (key, histogram) in registry.histograms.iter() {
+            let mut samples = std::mem::take(&mut *histogram.0.lock());
+            if samples.is_empty() {
+                continue;
+            }
+            samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            let sum: f64 = samples.iter().sum();
+            let percentile = |p: f64| {
+                let rank = ((samples.len() as f64 * p).ceil() as usize).max(1);
+                samples[rank.min(samples.len()) - 1]
+            };
+            let fields = event_for(&mut events, key);
+            let name = key.name();
+            fields.insert(format!("{}.count", name), json!(samples.len()));
+            fields.insert(format!("{}.sum", name), json!(sum));
+            fields.insert(format!("{}.min", name), json!(samples[0]));
+            fields.insert(format!("{}.max", name), json!(samples[samples.len() - 1]));
+            fields.insert(format!("{}.avg", name), json!(sum / samples.len() as f64));
+            fields.insert(format!("{}.p50", name), json!(percentile(0.5)));
+            fields.insert(format!("{}.p95", name), json!(percentile(0.95)));
+            fields.insert(format!("{}.p99", name), json!(percentile(0.99)));
+        }
+
+        events.into_values().collect()
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+/// `event_for` returns the fields of the event for the label set of `key`, creating it
+/// if needed
+fn event_for<'a>(
+    events: &'a mut BTreeMap<Labels, HashMap<String, Value>>,
+    key: &Key,
+) -> &'a mut HashMap<String, Value> {
+    let labels: Labels = key
+        .labels()
+        .map(|label| (label.key().to_string(), label.value().to_string()))
+        .collect();
+    events.entry(labels.clone()).or_insert_with(|| {
+        let mut fields: HashMap<String, Value> = labels
+            .into_iter()
+            .map(|(name, value)| (name, Value::String(value)))
+            .collect();
+        fields.insert(keys::META_TYPE.to_string(), json!("metrics"));
+        fields
+    })
+}
+
+impl Recorder for HoneycombRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        let mut registry = self.registry.lock();
+        let (counter, _) = registry
+            .counters
+            .entry(key.clone())
+            .or_insert_with(|| (Arc::new(AtomicCounter::default()), 0));
+        Counter::from_arc(counter.clone())
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        let mut registry = self.registry.lock();
+        let gauge = registry
+            .gauges
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(LastValueGauge::default()));
+        Gauge::from_arc(gauge.clone())
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        let mut registry = self.registry.lock();
+        let histogram = registry
+            .histograms
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(Samples::default()));
+        Histogram::from_arc(histogram.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::Label;
+
+    const METADATA: Metadata<'static> =
+        Metadata::new(module_path!(), metrics::Level::INFO, Some(module_path!()));
+
+    #[test]
+    fn test_drain() {
+        let recorder = HoneycombRecorder::new();
+        let requests = Key::from_parts("requests", vec![Label::new("route", "/")]);
+        recorder.register_counter(&requests, &METADATA).increment(3);
+        recorder
+            .register_gauge(&Key::from_name("connections"), &METADATA)
+            .set(12f64);
+        let latency = recorder.register_histogram(&Key::from_name("latency_ms"), &METADATA);
+        for value in 1..=100 {
+            latency.record(f64::from(value));
+        }
+
+        let events = recorder.drain();
+        assert_eq!(events.len(), 2);
+        let unlabelled = &events[0];
+        assert_eq!(unlabelled["meta.type"], json!("metrics"));
+        assert_eq!(unlabelled["connections"], json!(12f64));
+        assert_eq!(unlabelled["latency_ms.count"], json!(100));
+        assert_eq!(unlabelled["latency_ms.p50"], json!(50f64));
+        assert_eq!(unlabelled["latency_ms.p99"], json!(99f64));
+        let labelled = &events[1];
+        assert_eq!(labelled["route"], json!("/"));
+        assert_eq!(labelled["requests"], json!(3));
+
+        // counters report the increase since the last drain, histograms are reset
+        recorder.register_counter(&requests, &METADATA).increment(2);
+        let events = recorder.drain();
+        assert!(!events[0].contains_key("latency_ms.count"));
+        assert_eq!(events[1]["requests"], json!(2));
+    }
+}
@@ -0,0 +1,216 @@
+/*! Trace propagation through NATS message headers.
+
+The serialized trace context is carried in the `X-Honeycomb-Trace` header, as it is over
+HTTP. `inject` adds it to the headers of a message about to be published and `extract`
+reads it back.
+
+`TracedSubscription` wraps the iterator of messages delivered by a subscription and starts
+a span per message, continuing the trace of the publisher when the message carries one.
+The span of a message is sent when the next message is requested or when the subscription
+is dropped. To use it, implement `NatsMessage` for the message type of your NATS client.
+
+```rust,no_run
+use beeline::nats::{NatsMessage, TracedSubscription};
+use beeline::{init, Config};
+
+struct Message {
+    subject: String,
+    trace_header: Option<String>,
+}
+
+impl NatsMessage for Message {
+    fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        if name == beeline::nats::HEADER_NAME {
+            self.trace_header.as_deref()
+        } else {
+            None
+        }
+    }
+}
+
+let client = init(Config::default());
+let messages: Vec<Message> = vec![];
+for (message, trace) in TracedSubscription::new(client.clone(), messages) {
+    // handle the message, creating children of `trace.lock().get_root_span()`
+}
+```
+
+*/
+use std::collections::HashMap;
+
+use libhoney::Sender;
+use serde_json::json;
+
+use crate::keys;
+use crate::semconv::messaging;
+use crate::trace::{SafeSpan, SafeTrace, TraceSender};
+use crate::Client;
+
+/// name of the header carrying the trace context
+pub const HEADER_NAME: &str = "X-Honeycomb-Trace";
+
+/// `NatsMessage` gives access to the parts of a delivered message needed for tracing
+pub trait NatsMessage {
+    /// subject the message was delivered on
+    fn subject(&self) -> &str;
+    /// value of the header `name`, if present
+    fn header(&self, name: &str) -> Option<&str>;
+}
+
+/// `inject` adds the trace context of `span` to the headers of a message about to be
+/// published
+pub fn inject(span: &SafeSpan, headers: &mut HashMap<String, String>) {
+    let serialized = span.lock().serialize_headers();
+    if !serialized.is_empty() {
+        headers.insert(HEADER_NAME.to_string(), serialized);
+    }
+}
+
+/// `extract` returns the serialized trace context found in the headers of a delivered
+/// message
+pub fn extract<M: NatsMessage>(message: &M) -> Option<String> {
+    message.header(HEADER_NAME).map(String::from)
+}
+
+/// `TracedSubscription` yields every delivered message along with the trace opened for it
+pub struct TracedSubscription<T: Sender, I> {
+    client: Client<T>,
+    messages: I,
+    current: Option<SafeTrace>,
+}
+
+impl<T, I, M> TracedSubscription<T, I>
+where
+    T: Sender,
+    I: Iterator<Item = M>,
+    M: NatsMessage,
+{
+    /// `new` wraps the messages delivered by a subscription
+    pub fn new<S: IntoIterator<IntoIter = I>>(client: Client<T>, messages: S) -> Self {
+        Self {
+            client,
+            messages: messages.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl<T: Sender, I> TracedSubscription<T, I> {
+    /// `finish_current` sends the trace of the message handled last
+    fn finish_current(&mut self) {
+        if let Some(trace) = self.current.take() {
+            trace.send(&mut self.client);
+        }
+    }
+}
+
+impl<T, I, M> Iterator for TracedSubscription<T, I>
+where
+    T: Sender,
+    I: Iterator<Item = M>,
+    M: NatsMessage,
+{
+    type Item = (M, SafeTrace);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.finish_current();
+        let message = self.messages.next()?;
+
+        let (trace, propagation_error) = match self.client.try_new_trace(extract(&message)) {
+            Ok(trace) => (trace, None),
+            Err(e) => (self.client.new_trace(None), Some(e.to_string())),
+        };
+        let root_span = trace.lock().get_root_span();
+        {
+            let mut span = root_span.lock();
+            if let Some(error) = propagation_error {
+                span.add_field(keys::META_PROPAGATION_ERROR, json!(error));
+            }
+            span.add_field(keys::META_TYPE, json!(messaging::SPAN_TYPE));
+            span.add_field(keys::NAME, json!(format!("{} consume", message.subject())));
+            span.add_field(messaging::SYSTEM, json!("nats"));
+            span.add_field(messaging::DESTINATION, json!(message.subject()));
+            span.add_field(messaging::OPERATION, json!("consume"));
+        }
+        self.current = Some(trace.clone());
+        Some((message, trace))
+    }
+}
+
+impl<T: Sender, I> Drop for TracedSubscription<T, I> {
+    fn drop(&mut self) {
+        self.finish_current();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::Config;
+
+    struct Message {
+        subject: String,
+        headers: HashMap<String, String>,
+    }
+
+    impl NatsMessage for Message {
+        fn subject(&self) -> &str {
+            &self.subject
+        }
+
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.get(name).map(String::as_str)
+        }
+    }
+
+    #[test]
+    fn test_traced_subscription() {
+        let client = new_client(Config::default());
+        let publisher = client.new_trace(None);
+        let publisher_span = publisher.lock().get_root_span();
+        let mut headers = HashMap::new();
+        inject(&publisher_span, &mut headers);
+
+        let messages = vec![
+            Message {
+                subject: "orders.created".to_string(),
+                headers,
+            },
+            Message {
+                subject: "orders.deleted".to_string(),
+                headers: HashMap::new(),
+            },
+            Message {
+                subject: "orders.updated".to_string(),
+                headers: vec![(HEADER_NAME.to_string(), "2;trace_id=abc".to_string())]
+                    .into_iter()
+                    .collect(),
+            },
+        ];
+        let traces: Vec<String> = TracedSubscription::new(client.clone(), messages)
+            .map(|(_, trace)| trace.lock().trace_id.clone())
+            .collect();
+        assert_eq!(traces[0], publisher.lock().trace_id);
+        assert_ne!(traces[1], publisher.lock().trace_id);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        let fields = events[0].fields();
+        assert_eq!(fields["name"], json!("orders.created consume"));
+        assert_eq!(fields[messaging::SYSTEM], json!("nats"));
+        assert_eq!(fields["meta.span_type"], json!("subroot"));
+        assert_eq!(events[1].fields()["meta.span_type"], json!("root"));
+        assert!(!events[1]
+            .fields()
+            .contains_key(keys::META_PROPAGATION_ERROR));
+        assert_eq!(
+            events[2].fields()[keys::META_PROPAGATION_ERROR],
+            json!("unsupported version: unrecognized version for trace header 2")
+        );
+    }
+}
@@ -0,0 +1,139 @@
+/*! Process statistics.
+
+`Client::start_process_stats` spawns a background thread emitting a
+`meta.type = "process_stats"` event every interval with:
+ - `process.rss_bytes`
+ - `process.cpu_time_ms` (user + system, since the process started)
+ - `process.cpu_percent` (over the last interval)
+ - `process.open_fds`
+
+With the `tokio` feature enabled and a tokio runtime running when the emitter is started,
+`tokio.workers`, `tokio.alive_tasks` and `tokio.global_queue_depth` are added as well.
+
+Process statistics are read from `/proc`, so they are only available on Linux.
+
+*/
+use std::fs;
+use std::time::Instant;
+
+use libhoney::Value;
+use serde_json::json;
+
+use crate::keys;
+
+// USER_HZ is part of the userspace ABI and is 100 on every architecture we care about
+const CLOCK_TICKS_PER_SEC: f64 = 100f64;
+
+/// `ProcessStats` keeps the state needed to compute rates between two samples
+#[derive(Debug)]
+pub(crate) struct ProcessStats {
+    last_sample: Option<(Instant, f64)>,
+    #[cfg(feature = "tokio")]
+    runtime: Option<tokio::runtime::Handle>,
+}
+
+impl ProcessStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            last_sample: None,
+            #[cfg(feature = "tokio")]
+            runtime: tokio::runtime::Handle::try_current().ok(),
+        }
+    }
+
+    /// `sample` returns the fields of a `process_stats` event
+    pub(crate) fn sample(&mut self) -> Vec<(String, Value)> {
+        let mut fields = vec![(keys::META_TYPE.to_string(), json!("process_stats"))];
+
+        if let Some(rss) = fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| rss_bytes(&status))
+        {
+            fields.push(("process.rss_bytes".to_string(), json!(rss)));
+        }
+
+        if let Some(cpu_time_ms) = fs::read_to_string("/proc/self/stat")
+            .ok()
+            .and_then(|stat| cpu_time_ms(&stat))
+        {
+            fields.push(("process.cpu_time_ms".to_string(), json!(cpu_time_ms)));
+            let now = Instant::now();
+            if let Some((then, last_cpu_time_ms)) = self.last_sample {
+                let elapsed_ms = now.duration_since(then).as_nanos() as f64 / 1_000_000f64;
+                if elapsed_ms > 0f64 {
+                    fields.push((
+                        "process.cpu_percent".to_string(),
+                        json!((cpu_time_ms - last_cpu_time_ms) / elapsed_ms * 100f64),
+                    ));
+                }
+            }
+            self.last_sample = Some((now, cpu_time_ms));
+        }
+
+        if let Ok(fds) = fs::read_dir("/proc/self/fd") {
+            fields.push(("process.open_fds".to_string(), json!(fds.count())));
+        }
+
+        #[cfg(feature = "tokio")]
+        {
+            if let Some(runtime) = &self.runtime {
+                let metrics = runtime.metrics();
+                fields.push(("tokio.workers".to_string(), json!(metrics.num_workers())));
+                fields.push((
+                    "tokio.alive_tasks".to_string(),
+                    json!(metrics.num_alive_tasks()),
+                ));
+                fields.push((
+                    "tokio.global_queue_depth".to_string(),
+                    json!(metrics.global_queue_depth()),
+                ));
+            }
+        }
+
+        fields
+    }
+}
+
+/// `rss_bytes` parses the `VmRSS` line of `/proc/self/status`
+fn rss_bytes(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// `cpu_time_ms` parses `utime` and `stime` out of `/proc/self/stat`
+fn cpu_time_ms(stat: &str) -> Option<f64> {
+    // the command name is wrapped in parentheses and may contain spaces, so only split
+    // what comes after it. utime and stime are the 14th and 15th fields overall.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let utime: f64 = fields.get(11)?.parse().ok()?;
+    let stime: f64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) / CLOCK_TICKS_PER_SEC * 1000f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rss_bytes() {
+        let status = "Name:\tbeeline\nVmPeak:\t   10000 kB\nVmRSS:\t    2048 kB\n";
+        assert_eq!(rss_bytes(status), Some(2048 * 1024));
+        assert_eq!(rss_bytes("Name:\tbeeline\n"), None);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_cpu_time_ms() {
+        let stat = "4242 (my (weird) app) S 1 4242 4242 0 -1 4194304 1000 0 0 0 150 50 0 0 20 0 8 0 100 0 0";
+        assert_eq!(cpu_time_ms(stat), Some(2000f64));
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut stats = ProcessStats::new();
+        let fields = stats.sample();
+        assert_eq!(fields[0], ("meta.type".to_string(), json!("process_stats")));
+    }
+}
@@ -11,104 +11,609 @@
 ///  parent_id=${spanId}    - spanId is an opaque ascii string which shall not include ','
 ///  dataset=${datasetId}   - datasetId is the slug for the honeycomb dataset to which downstream spans should be sent; shall not include ','
 ///  context=${contextBlob} - contextBlob is a base64 encoded json object.
+///  baggage=${baggageBlob} - baggageBlob is a base64 encoded json object of string values.
+///  sample_rate=${rate}    - rate is the sample rate chosen for the trace, see `Config::propagate_sample_rate`
 ///
 /// ex: X-Honeycomb-Trace: 1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=SGVsbG8gV29ybGQ=
-use crate::errors::{BeelineError, Result};
+///
+/// OTHER VERSIONS
+/// ==============
+/// Headers of a version registered in `Config::propagation_parsers` are handed to its
+/// parser. The payload of other versions is parsed as a version 1 one, on the assumption
+/// that later versions only add keys, so services can be upgraded one at a time; the header
+/// is rejected when that fails. The version of the incoming header is recorded on the root
+/// span as `meta.propagation_version`.
+///
+/// MALFORMED HEADERS
+/// =================
+/// By default, clauses without `=` are skipped, the last of repeated keys wins and a header
+/// without a trace id starts a new trace. `PropagationParsing::Strict` rejects such headers
+/// instead.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::errors::{BeelineError, PropagationErrorKind, Result};
+use crate::PropagationParserFn;
 use libhoney::Value;
+use uuid::Uuid;
 
-// TODO(nlopes): once we add http propagation we should remove this allow
-#[allow(dead_code)]
 const PROPAGATION_HTTP_HEADER: &str = "X-Honeycomb-Trace";
 const PROPAGATION_VERSION: usize = 1;
+/// first byte of the binary encoding
+const BINARY_VERSION: u8 = 1;
+/// first byte of the binary encoding followed by the sample rate of the trace, only used
+/// when there is one so decoders knowing of version 1 only still read the others
+const BINARY_VERSION_SAMPLE_RATE: u8 = 2;
+/// tags of the ids in the binary encoding
+const ID_STRING: u8 = 0;
+const ID_UUID: u8 = 1;
+
+/// `PropagationHeaders` is the trace header to attach to an outgoing request or message,
+/// see `Trace::propagation_for`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropagationHeaders {
+    /// name of the header, `X-Honeycomb-Trace`
+    pub name: &'static str,
+    /// serialized trace context
+    pub value: String,
+    /// whether part of the context was left out to fit
+    /// `Config::max_propagation_header_size`
+    pub truncated: bool,
+}
+
+impl PropagationHeaders {
+    pub(crate) fn new(value: String, truncated: bool) -> Self {
+        Self {
+            name: PROPAGATION_HTTP_HEADER,
+            value,
+            truncated,
+        }
+    }
+}
+
+/// `PropagationParsing` is how incoming text trace headers that don't follow the format
+/// are handled, see `Config::propagation_parsing`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PropagationParsing {
+    /// skip clauses without `=`, keep the last value of repeated keys, and start a new
+    /// trace when the trace id is missing or empty
+    #[default]
+    Lenient,
+    /// reject the headers with a clause without `=`, a repeated key or an empty trace id
+    Strict,
+}
+
+/// `PropagationHosts` decides which hosts the outgoing requests made through the HTTP client
+/// integrations carry the trace headers to, so the trace context never reaches third-party
+/// APIs. Patterns are host names, or `*.` followed by a domain to match all its subdomains,
+/// compared case-insensitively. Denied hosts are never sent the headers; when hosts are
+/// allowed, only they are.
+///
+/// ```rust
+/// use beeline::PropagationHosts;
+///
+/// let hosts = PropagationHosts::new()
+///     .allow("*.internal")
+///     .deny("legacy.internal");
+/// assert!(hosts.allows("users.internal"));
+/// assert!(!hosts.allows("legacy.internal"));
+/// assert!(!hosts.allows("api.stripe.com"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PropagationHosts {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl PropagationHosts {
+    /// `new` returns a filter letting the headers go to every host
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `allow` adds `pattern` to the hosts the headers are sent to
+    pub fn allow(mut self, pattern: &str) -> Self {
+        self.allow.push(pattern.to_ascii_lowercase());
+        self
+    }
+
+    /// `deny` adds `pattern` to the hosts the headers are never sent to
+    pub fn deny(mut self, pattern: &str) -> Self {
+        self.deny.push(pattern.to_ascii_lowercase());
+        self
+    }
+
+    /// `allows` returns whether requests to `host` can carry the trace headers
+    pub fn allows(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        if self.deny.iter().any(|pattern| host_matches(pattern, &host)) {
+            return false;
+        }
+        self.allow.is_empty()
+            || self
+                .allow
+                .iter()
+                .any(|pattern| host_matches(pattern, &host))
+    }
+}
+
+/// `host_matches` returns whether the lowercase `host` matches `pattern`
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => pattern == host,
+    }
+}
 
 /// Propagation contains all the information about a payload header
 ///  trace_id=${traceId}    - traceId is an opaque ascii string which shall not include ','
 ///  parent_id=${spanId}    - spanId is an opaque ascii string which shall not include ','
 ///  dataset=${datasetId}   - datasetId is the slug for the honeycomb dataset to which downstream spans should be sent; shall not include ','
 ///  context=${contextBlob} - contextBlob is a base64 encoded json object.
+///  baggage=${baggageBlob} - baggageBlob is a base64 encoded json object of string values.
+///  sample_rate=${rate}    - rate is the sample rate chosen for the trace, see `Config::propagate_sample_rate`
 ///
 /// ex: X-Honeycomb-Trace: 1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=SGVsbG8gV29ybGQ=
 #[derive(Debug, PartialEq)]
 pub struct Propagation {
+    /// id of the trace, empty to start a new one
     pub trace_id: String,
+    /// id of the upstream span, parent of the root span of this service
     pub parent_id: String,
+    /// dataset the spans of this service are sent to, the client one when empty
     pub dataset: String,
+    /// trace level fields, a JSON object
     pub trace_context: Value,
+    /// baggage of the trace, see `Trace::set_baggage`
+    pub baggage: HashMap<String, String>,
+    /// sample rate chosen for the trace, see `Config::propagate_sample_rate`
+    pub sample_rate: Option<usize>,
 }
 
 impl Propagation {
+    /// `unmarshal_trace_context` parses a header in either the text form or the compact
+    /// form of `marshal_compact`, telling them apart by the `;` after the version of the
+    /// text form, which base64 doesn't use.
     pub fn unmarshal_trace_context(header: &str) -> Result<Self> {
+        Propagation::unmarshal_trace_context_with(
+            header,
+            &HashMap::new(),
+            PropagationParsing::Lenient,
+        )
+    }
+
+    /// `unmarshal_trace_context_with` parses a header like `unmarshal_trace_context`,
+    /// handing the payload of the text form to the parser `parsers` holds for its version,
+    /// if any, and else following `parsing`. A parser returning None rejects the header.
+    pub fn unmarshal_trace_context_with(
+        header: &str,
+        parsers: &HashMap<String, Arc<PropagationParserFn>>,
+        parsing: PropagationParsing,
+    ) -> Result<Self> {
+        if !header.contains(';') {
+            return Propagation::unmarshal_compact(header);
+        }
         let ver: Vec<&str> = header.splitn(2, ';').collect();
+        if let Some(parser) = parsers.get(ver[0]) {
+            return parser(ver[1]).ok_or_else(|| {
+                BeelineError::propagation(
+                    PropagationErrorKind::UnsupportedVersion,
+                    format!("the parser of version {} rejected the trace header", ver[0]),
+                )
+            });
+        }
         if ver[0] == "1" {
-            return Propagation::unmarshal_trace_context_v1(ver[1]);
+            return Propagation::unmarshal_trace_context_v1(ver[1], parsing);
         }
 
-        Err(BeelineError::PropagationError(format!(
-            "unrecognized version for trace header {}",
-            ver[0]
-        )))
+        Propagation::unmarshal_trace_context_v1(ver[1], parsing).map_err(|e| {
+            BeelineError::propagation_caused_by(
+                PropagationErrorKind::UnsupportedVersion,
+                format!("unrecognized version for trace header {}", ver[0]),
+                e,
+            )
+        })
+    }
+
+    /// `header_version` returns the version of a header in the text form, or None for the
+    /// compact form
+    pub fn header_version(header: &str) -> Option<&str> {
+        header.find(';').map(|end| &header[..end])
     }
 
-    fn unmarshal_trace_context_v1(header: &str) -> Result<Self> {
-        let clauses: Vec<&str> = header.split(',').collect();
-        let (mut trace_id, mut parent_id, mut dataset, mut context) = (
+    fn unmarshal_trace_context_v1(header: &str, parsing: PropagationParsing) -> Result<Self> {
+        let strict = parsing == PropagationParsing::Strict;
+        let (mut trace_id, mut parent_id, mut dataset, mut context, mut baggage) = (
+            "".to_string(),
             "".to_string(),
             "".to_string(),
             "".to_string(),
             "".to_string(),
         );
+        let mut sample_rate = None;
 
-        for clause in clauses.iter() {
-            let kv: Vec<&str> = clause.splitn(2, '=').collect();
-            match kv[0] {
-                "trace_id" => trace_id = kv[1].to_string(),
-                "parent_id" => parent_id = kv[1].to_string(),
-                "dataset" => dataset = kv[1].to_string(),
-                "context" => context = kv[1].to_string(),
+        let mut keys = Vec::new();
+        for clause in header.split(',') {
+            let (key, value) = match clause.split_once('=') {
+                Some(kv) => kv,
+                None if strict => {
+                    return Err(malformed(format!("clause {:?} has no '='", clause)));
+                }
+                None => continue,
+            };
+            if strict && keys.contains(&key) {
+                return Err(malformed(format!("key {:?} is repeated", key)));
+            }
+            keys.push(key);
+            match key {
+                "trace_id" => trace_id = value.to_string(),
+                "parent_id" => parent_id = value.to_string(),
+                "dataset" => dataset = value.to_string(),
+                "context" => context = value.to_string(),
+                "baggage" => baggage = value.to_string(),
+                "sample_rate" => match value.parse::<usize>() {
+                    Ok(rate) if rate > 0 => sample_rate = Some(rate),
+                    _ if strict => {
+                        return Err(malformed(format!("invalid sample rate {:?}", value)));
+                    }
+                    _ => (),
+                },
                 _ => (),
             };
         }
 
+        if strict && trace_id.is_empty() {
+            return Err(malformed("the trace id is empty"));
+        }
+
         if trace_id.is_empty() && !parent_id.is_empty() {
-            return Err(BeelineError::PropagationError(String::from(
+            return Err(BeelineError::propagation(
+                PropagationErrorKind::MissingTraceId,
                 "parent_id without trace_id",
-            )));
+            ));
         }
 
+        let baggage = if baggage.is_empty() {
+            HashMap::new()
+        } else {
+            serde_json::from_slice(&base64::decode(&baggage).map_err(|e| {
+                BeelineError::propagation_caused_by(
+                    PropagationErrorKind::InvalidBaggage,
+                    "unable to decode base64 baggage",
+                    e,
+                )
+            })?)
+            .map_err(|e| {
+                BeelineError::propagation_caused_by(
+                    PropagationErrorKind::InvalidBaggage,
+                    "unable to unmarshal baggage",
+                    e,
+                )
+            })?
+        };
+
         Ok(Propagation {
             trace_id,
             parent_id,
             dataset,
+            baggage,
+            sample_rate,
             trace_context: serde_json::from_slice(&base64::decode(&context).map_err(|e| {
-                BeelineError::PropagationError(format!(
-                    "unable to decode base64 trace context: {}",
-                    e
-                ))
+                BeelineError::propagation_caused_by(
+                    PropagationErrorKind::InvalidTraceContext,
+                    "unable to decode base64 trace context",
+                    e,
+                )
             })?)
             .map_err(|e| {
-                BeelineError::PropagationError(format!("unable to unmarshal trace context: {}", e))
+                BeelineError::propagation_caused_by(
+                    PropagationErrorKind::InvalidTraceContext,
+                    "unable to unmarshal trace context",
+                    e,
+                )
             })?,
         })
     }
 
     pub fn marshal_trace_context(&self) -> String {
+        self.marshal(true, true)
+    }
+
+    /// `marshal_trace_context_within` marshals the trace context like
+    /// `marshal_trace_context` but, when the header would be longer than `max_size`, leaves
+    /// out the trace context and then the baggage. The trace and parent ids are always
+    /// kept. The second value is whether anything was left out.
+    pub fn marshal_trace_context_within(&self, max_size: usize) -> (String, bool) {
+        within(max_size, |with_context, with_baggage| {
+            self.marshal(with_context, with_baggage)
+        })
+    }
+
+    /// `marshal_compact` marshals the trace context in the binary encoding of
+    /// `marshal_binary`, base64 encoded without padding, for size-constrained transports
+    /// that only take text, such as message queue attributes. `unmarshal_trace_context`
+    /// reads both forms.
+    pub fn marshal_compact(&self) -> String {
+        encode_compact(&self.binary(true, true))
+    }
+
+    /// `marshal_compact_within` is to `marshal_compact` what `marshal_trace_context_within`
+    /// is to `marshal_trace_context`
+    pub fn marshal_compact_within(&self, max_size: usize) -> (String, bool) {
+        within(max_size, |with_context, with_baggage| {
+            encode_compact(&self.binary(with_context, with_baggage))
+        })
+    }
+
+    fn unmarshal_compact(header: &str) -> Result<Self> {
+        let unpadded = header.trim_end_matches('=');
+        let bytes = base64::decode_config(unpadded, base64::STANDARD_NO_PAD).map_err(|e| {
+            BeelineError::propagation_caused_by(
+                PropagationErrorKind::InvalidBinary,
+                "unable to decode base64 compact trace header",
+                e,
+            )
+        })?;
+        Propagation::unmarshal_binary(&bytes)
+    }
+
+    /// `marshal_binary` encodes the trace context in a compact binary form, for transports
+    /// with binary headers such as the `-bin` gRPC metadata. It is made of a version byte
+    /// (1, or 2 when the trace has a sample rate) followed by:
+    ///  - the trace and parent ids, each a tag byte then either the 16 bytes of the id when
+    ///    it is a UUID (tag 1), or its length and bytes (tag 0)
+    ///  - the length and bytes of the dataset, of the JSON trace context (nothing for an
+    ///    empty object) and of the JSON baggage (nothing when there is none)
+    ///  - in version 2 only, the sample rate of the trace
+    ///
+    /// Lengths are unsigned LEB128 varints. A pair of UUID ids and no context takes 38
+    /// bytes, against about 100 for the text header.
+    pub fn marshal_binary(&self) -> Vec<u8> {
+        self.binary(true, true)
+    }
+
+    fn binary(&self, with_context: bool, with_baggage: bool) -> Vec<u8> {
+        let version = match self.sample_rate {
+            Some(_) => BINARY_VERSION_SAMPLE_RATE,
+            None => BINARY_VERSION,
+        };
+        let mut bytes = vec![version];
+        write_id(&mut bytes, &self.trace_id);
+        write_id(&mut bytes, &self.parent_id);
+        write_bytes(&mut bytes, self.dataset.as_bytes());
+        let context = match &self.trace_context {
+            Value::Object(fields) if fields.is_empty() => Vec::new(),
+            _ if !with_context => Vec::new(),
+            context => context.to_string().into_bytes(),
+        };
+        write_bytes(&mut bytes, &context);
+        let baggage = if !with_baggage || self.baggage.is_empty() {
+            Vec::new()
+        } else {
+            serde_json::to_vec(&self.baggage).unwrap_or_default()
+        };
+        write_bytes(&mut bytes, &baggage);
+        if let Some(sample_rate) = self.sample_rate {
+            write_len(&mut bytes, sample_rate);
+        }
+        bytes
+    }
+
+    /// `unmarshal_binary` decodes a trace context encoded by `marshal_binary`
+    pub fn unmarshal_binary(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        let version = reader.byte()?;
+        if version != BINARY_VERSION && version != BINARY_VERSION_SAMPLE_RATE {
+            return Err(BeelineError::propagation(
+                PropagationErrorKind::UnsupportedVersion,
+                format!("unrecognized version for binary trace header {}", version),
+            ));
+        }
+        let trace_id = reader.id()?;
+        let parent_id = reader.id()?;
+        let dataset = reader.string()?;
+        let context = reader.bytes()?;
+        let baggage = reader.bytes()?;
+        let sample_rate = if version == BINARY_VERSION_SAMPLE_RATE {
+            Some(reader.varint()?).filter(|rate| *rate > 0)
+        } else {
+            None
+        };
+        if !reader.0.is_empty() {
+            return Err(invalid_binary("trailing bytes"));
+        }
+
+        if trace_id.is_empty() && !parent_id.is_empty() {
+            return Err(BeelineError::propagation(
+                PropagationErrorKind::MissingTraceId,
+                "parent_id without trace_id",
+            ));
+        }
+
+        let trace_context = if context.is_empty() {
+            Value::Object(serde_json::Map::new())
+        } else {
+            serde_json::from_slice(context).map_err(|e| {
+                BeelineError::propagation_caused_by(
+                    PropagationErrorKind::InvalidTraceContext,
+                    "unable to unmarshal trace context",
+                    e,
+                )
+            })?
+        };
+        let baggage = if baggage.is_empty() {
+            HashMap::new()
+        } else {
+            serde_json::from_slice(baggage).map_err(|e| {
+                BeelineError::propagation_caused_by(
+                    PropagationErrorKind::InvalidBaggage,
+                    "unable to unmarshal baggage",
+                    e,
+                )
+            })?
+        };
+
+        Ok(Propagation {
+            trace_id,
+            parent_id,
+            dataset,
+            trace_context,
+            baggage,
+            sample_rate,
+        })
+    }
+
+    fn marshal(&self, with_context: bool, with_baggage: bool) -> String {
         let dataset = if !self.dataset.is_empty() {
             format!("dataset={},", self.dataset)
         } else {
             String::new()
         };
 
+        // other beelines ignore the keys they don't know about, so baggage can be added
+        // without a new version
+        let baggage = if with_baggage && !self.baggage.is_empty() {
+            format!(
+                ",baggage={}",
+                base64::encode(serde_json::to_string(&self.baggage).unwrap_or_default())
+            )
+        } else {
+            String::new()
+        };
+        let sample_rate = match self.sample_rate {
+            Some(rate) => format!(",sample_rate={}", rate),
+            None => String::new(),
+        };
+
+        // the context is required, an empty object stands in for a dropped one
+        let context = if with_context {
+            self.trace_context.to_string()
+        } else {
+            "{}".to_string()
+        };
+
         format!(
-            "{};trace_id={},parent_id={},{}context={}",
+            "{};trace_id={},parent_id={},{}context={}{}{}",
             PROPAGATION_VERSION,
             self.trace_id,
             self.parent_id,
             dataset,
-            base64::encode(&self.trace_context.to_string())
+            base64::encode(&context),
+            baggage,
+            sample_rate
         )
     }
 }
 
+/// `within` returns the header `marshal` makes with everything if it fits in `max_size`,
+/// or else without the trace context and then without the baggage. The second value is
+/// whether anything was left out.
+fn within<F: Fn(bool, bool) -> String>(max_size: usize, marshal: F) -> (String, bool) {
+    let header = marshal(true, true);
+    if header.len() <= max_size {
+        return (header, false);
+    }
+    let header = marshal(false, true);
+    if header.len() <= max_size {
+        return (header, true);
+    }
+    (marshal(false, false), true)
+}
+
+fn encode_compact(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::STANDARD_NO_PAD)
+}
+
+fn malformed(message: impl Into<String>) -> BeelineError {
+    BeelineError::propagation(PropagationErrorKind::MalformedHeader, message)
+}
+
+fn invalid_binary(message: &str) -> BeelineError {
+    BeelineError::propagation(PropagationErrorKind::InvalidBinary, message)
+}
+
+fn write_len(bytes: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        bytes.push((len as u8 & 0x7f) | 0x80);
+        len >>= 7;
+    }
+    bytes.push(len as u8);
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    write_len(bytes, value.len());
+    bytes.extend_from_slice(value);
+}
+
+/// `write_id` packs ids that are lowercase hyphenated UUIDs, the beeline's own, in 16
+/// bytes. Other ids are kept as they are so they are decoded unchanged.
+fn write_id(bytes: &mut Vec<u8>, id: &str) {
+    match Uuid::parse_str(id) {
+        Ok(uuid) if uuid.to_hyphenated().to_string() == id => {
+            bytes.push(ID_UUID);
+            bytes.extend_from_slice(uuid.as_bytes());
+        }
+        _ => {
+            bytes.push(ID_STRING);
+            write_bytes(bytes, id.as_bytes());
+        }
+    }
+}
+
+/// `Reader` consumes a binary trace header from the front
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid_binary("truncated header"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<usize> {
+        let mut len = 0;
+        for shift in (0..32).step_by(7) {
+            let byte = self.byte()?;
+            len |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+        }
+        Err(invalid_binary("length too large"))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.varint()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_binary("invalid UTF-8"))
+    }
+
+    fn id(&mut self) -> Result<String> {
+        match self.byte()? {
+            ID_STRING => self.string(),
+            ID_UUID => {
+                let bytes = self.take(16)?;
+                let uuid = Uuid::from_slice(bytes).map_err(|_| invalid_binary("invalid id"))?;
+                Ok(uuid.to_hyphenated().to_string())
+            }
+            tag => Err(invalid_binary(&format!("unknown id tag {}", tag))),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -125,6 +630,8 @@ mod tests {
                 "toRetry":  true,
             }),
             dataset: "".to_string(),
+            baggage: HashMap::new(),
+            sample_rate: None,
         };
         assert_eq!(
             p.marshal_trace_context(),
@@ -145,10 +652,327 @@ mod tests {
             parent_id: "owefjoweifj".to_string(),
             dataset: "dada".to_string(),
             trace_context: json!({"key": "value"}),
+            baggage: HashMap::new(),
+            sample_rate: None,
+        };
+        assert_eq!(
+            p,
+            Propagation::unmarshal_trace_context(&p.marshal_trace_context()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_baggage() {
+        let mut p = Propagation {
+            trace_id: "weofijwoeifj".to_string(),
+            parent_id: "owefjoweifj".to_string(),
+            dataset: "".to_string(),
+            trace_context: json!({}),
+            baggage: HashMap::new(),
+            sample_rate: None,
+        };
+        p.baggage.insert("tenant".to_string(), "acme".to_string());
+        let header = p.marshal_trace_context();
+        assert_eq!(
+            header,
+            "1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=e30=,baggage=eyJ0ZW5hbnQiOiJhY21lIn0="
+        );
+        assert_eq!(p, Propagation::unmarshal_trace_context(&header).unwrap());
+        assert!(matches!(
+            Propagation::unmarshal_trace_context("1;trace_id=a,context=e30=,baggage=!!"),
+            Err(BeelineError::PropagationError {
+                kind: PropagationErrorKind::InvalidBaggage,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_unmarshal_versions() {
+        // later versions are read as version 1 when they can be
+        let p =
+            Propagation::unmarshal_trace_context("2;trace_id=a,context=e30=,sampled=1").unwrap();
+        assert_eq!(p.trace_id, "a");
+        assert_eq!(Propagation::header_version("2;trace_id=a"), Some("2"));
+        assert_eq!(Propagation::header_version("AQAA"), None);
+
+        let mut parsers: HashMap<String, Arc<PropagationParserFn>> = HashMap::new();
+        parsers.insert(
+            "3".to_string(),
+            Arc::new(|payload| {
+                let (trace_id, parent_id) = payload.split_once('/')?;
+                Some(Propagation {
+                    trace_id: trace_id.to_string(),
+                    parent_id: parent_id.to_string(),
+                    dataset: String::new(),
+                    trace_context: json!({}),
+                    baggage: HashMap::new(),
+                    sample_rate: None,
+                })
+            }),
+        );
+        let p = Propagation::unmarshal_trace_context_with(
+            "3;abc/def",
+            &parsers,
+            PropagationParsing::Lenient,
+        )
+        .unwrap();
+        assert_eq!((p.trace_id.as_str(), p.parent_id.as_str()), ("abc", "def"));
+        assert!(matches!(
+            Propagation::unmarshal_trace_context_with(
+                "3;abc",
+                &parsers,
+                PropagationParsing::Lenient
+            ),
+            Err(BeelineError::PropagationError {
+                kind: PropagationErrorKind::UnsupportedVersion,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let p = Propagation {
+            trace_id: "a".to_string(),
+            parent_id: "b".to_string(),
+            dataset: "".to_string(),
+            trace_context: json!({}),
+            baggage: HashMap::new(),
+            sample_rate: Some(20),
+        };
+        let header = p.marshal_trace_context();
+        assert_eq!(
+            header,
+            "1;trace_id=a,parent_id=b,context=e30=,sample_rate=20"
+        );
+        assert_eq!(p, Propagation::unmarshal_trace_context(&header).unwrap());
+
+        let parse = |header: &str, parsing| {
+            Propagation::unmarshal_trace_context_with(header, &HashMap::new(), parsing)
+        };
+        let header = "1;trace_id=a,context=e30=,sample_rate=0";
+        assert_eq!(
+            parse(header, PropagationParsing::Lenient)
+                .unwrap()
+                .sample_rate,
+            None
+        );
+        assert!(parse(header, PropagationParsing::Strict).is_err());
+    }
+
+    #[test]
+    fn test_propagation_parsing() {
+        let parse = |header: &str, parsing| {
+            Propagation::unmarshal_trace_context_with(header, &HashMap::new(), parsing)
+        };
+        let malformed = |header: &str| {
+            matches!(
+                parse(header, PropagationParsing::Strict),
+                Err(BeelineError::PropagationError {
+                    kind: PropagationErrorKind::MalformedHeader,
+                    ..
+                })
+            )
+        };
+        let lenient = |header: &str| parse(header, PropagationParsing::Lenient).unwrap();
+
+        assert_eq!(lenient("1;trace_id=a,bogus,context=e30=").trace_id, "a");
+        assert!(malformed("1;trace_id=a,bogus,context=e30="));
+        assert_eq!(
+            lenient("1;trace_id=a,trace_id=b,context=e30=").trace_id,
+            "b"
+        );
+        assert!(malformed("1;trace_id=a,trace_id=b,context=e30="));
+        assert_eq!(lenient("1;trace_id=,context=e30=").trace_id, "");
+        assert!(malformed("1;trace_id=,context=e30="));
+        assert!(malformed("1;context=e30="));
+        assert_eq!(lenient("1;trace_id=a,context=e30=,").trace_id, "a");
+        assert!(malformed("1;trace_id=a,context=e30=,"));
+        assert!(parse("1;trace_id=a,context=e30=", PropagationParsing::Strict).is_ok());
+
+        // a clause without '=' used to panic
+        assert!(matches!(
+            parse("1;trace_id", PropagationParsing::Lenient),
+            Err(BeelineError::PropagationError {
+                kind: PropagationErrorKind::InvalidTraceContext,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_unmarshal_errors() {
+        let kind = |header: &str| match Propagation::unmarshal_trace_context(header) {
+            Err(BeelineError::PropagationError { kind, .. }) => Some(kind),
+            _ => None,
         };
+        assert_eq!(
+            kind("2;trace_id=a"),
+            Some(PropagationErrorKind::UnsupportedVersion)
+        );
+        assert_eq!(
+            kind("1;parent_id=b,context=e30="),
+            Some(PropagationErrorKind::MissingTraceId)
+        );
+        assert_eq!(
+            kind("1;trace_id=a,context=!!"),
+            Some(PropagationErrorKind::InvalidTraceContext)
+        );
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut p = Propagation {
+            trace_id: "c6a4a3c4-c1d9-4a7f-8ac7-67c3a1f0a3d1".to_string(),
+            parent_id: "0f8e2a6b-4f53-4a1e-9d1e-2d6c1b7e5a90".to_string(),
+            dataset: "".to_string(),
+            trace_context: json!({}),
+            baggage: HashMap::new(),
+            sample_rate: None,
+        };
+        let bytes = p.marshal_binary();
+        assert_eq!(bytes.len(), 38);
+        assert!(bytes.len() * 2 < p.marshal_trace_context().len());
+        assert_eq!(p, Propagation::unmarshal_binary(&bytes).unwrap());
+
+        p.parent_id = "owefjoweifj".to_string();
+        p.dataset = "dada".to_string();
+        p.trace_context = json!({"blob": "x".repeat(200)});
+        p.baggage.insert("tenant".to_string(), "acme".to_string());
+        let bytes = p.marshal_binary();
+        assert_eq!(p, Propagation::unmarshal_binary(&bytes).unwrap());
+
+        let kind = |bytes: &[u8]| match Propagation::unmarshal_binary(bytes) {
+            Err(BeelineError::PropagationError { kind, .. }) => Some(kind),
+            _ => None,
+        };
+        assert_eq!(
+            kind(&bytes[..bytes.len() - 1]),
+            Some(PropagationErrorKind::InvalidBinary)
+        );
+        assert_eq!(kind(&[3]), Some(PropagationErrorKind::UnsupportedVersion));
+        assert_eq!(kind(&[1, 7]), Some(PropagationErrorKind::InvalidBinary));
+    }
+
+    #[test]
+    fn test_binary_sample_rate() {
+        let mut p = Propagation {
+            trace_id: "c6a4a3c4-c1d9-4a7f-8ac7-67c3a1f0a3d1".to_string(),
+            parent_id: "0f8e2a6b-4f53-4a1e-9d1e-2d6c1b7e5a90".to_string(),
+            dataset: "".to_string(),
+            trace_context: json!({}),
+            baggage: HashMap::new(),
+            sample_rate: Some(300),
+        };
+        let bytes = p.marshal_binary();
+        assert_eq!(bytes[0], 2);
+        assert_eq!(bytes.len(), 40);
+        assert_eq!(p, Propagation::unmarshal_binary(&bytes).unwrap());
+        assert_eq!(
+            p,
+            Propagation::unmarshal_trace_context(&p.marshal_compact()).unwrap()
+        );
+
+        // without a sample rate, the header stays readable by version 1 decoders
+        p.sample_rate = None;
+        assert_eq!(p.marshal_binary()[0], 1);
+
+        let kind = |bytes: &[u8]| match Propagation::unmarshal_binary(bytes) {
+            Err(BeelineError::PropagationError { kind, .. }) => Some(kind),
+            _ => None,
+        };
+        assert_eq!(
+            kind(&bytes[..bytes.len() - 1]),
+            Some(PropagationErrorKind::InvalidBinary)
+        );
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut p = Propagation {
+            trace_id: "c6a4a3c4-c1d9-4a7f-8ac7-67c3a1f0a3d1".to_string(),
+            parent_id: "0f8e2a6b-4f53-4a1e-9d1e-2d6c1b7e5a90".to_string(),
+            dataset: "".to_string(),
+            trace_context: json!({"userID": 1}),
+            baggage: HashMap::new(),
+            sample_rate: None,
+        };
+        p.baggage.insert("tenant".to_string(), "acme".to_string());
+        let compact = p.marshal_compact();
+        assert!(!compact.contains(';') && !compact.ends_with('='));
+        assert!(compact.len() < p.marshal_trace_context().len());
+        // the format is detected on unmarshal
+        assert_eq!(p, Propagation::unmarshal_trace_context(&compact).unwrap());
         assert_eq!(
             p,
             Propagation::unmarshal_trace_context(&p.marshal_trace_context()).unwrap()
         );
+
+        let (header, truncated) = p.marshal_compact_within(80);
+        assert!(truncated);
+        let truncated = Propagation::unmarshal_trace_context(&header).unwrap();
+        assert_eq!(truncated.trace_context, json!({}));
+        assert_eq!(truncated.baggage, p.baggage);
+
+        assert!(matches!(
+            Propagation::unmarshal_trace_context("!!"),
+            Err(BeelineError::PropagationError {
+                kind: PropagationErrorKind::InvalidBinary,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_marshal_within() {
+        let mut p = Propagation {
+            trace_id: "weofijwoeifj".to_string(),
+            parent_id: "owefjoweifj".to_string(),
+            dataset: "".to_string(),
+            trace_context: json!({"blob": "x".repeat(100)}),
+            baggage: HashMap::new(),
+            sample_rate: None,
+        };
+        p.baggage.insert("tenant".to_string(), "acme".to_string());
+        let full = p.marshal_trace_context();
+        assert_eq!(p.marshal_trace_context_within(full.len()), (full, false));
+
+        let (header, truncated) = p.marshal_trace_context_within(100);
+        assert!(truncated);
+        assert_eq!(
+            header,
+            "1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=e30=,baggage=eyJ0ZW5hbnQiOiJhY21lIn0="
+        );
+
+        let (header, truncated) = p.marshal_trace_context_within(10);
+        assert!(truncated);
+        assert_eq!(
+            header,
+            "1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=e30="
+        );
+        assert!(Propagation::unmarshal_trace_context(&header).is_ok());
+    }
+
+    #[test]
+    fn test_propagation_hosts() {
+        let all = PropagationHosts::new();
+        assert!(all.allows("api.stripe.com"));
+
+        let hosts = PropagationHosts::new()
+            .allow("*.Internal")
+            .allow("gateway")
+            .deny("legacy.internal");
+        assert!(hosts.allows("users.internal"));
+        assert!(hosts.allows("a.b.INTERNAL"));
+        assert!(hosts.allows("gateway"));
+        assert!(!hosts.allows("internal"));
+        assert!(!hosts.allows("notinternal"));
+        assert!(!hosts.allows("legacy.internal"));
+        assert!(!hosts.allows("api.stripe.com"));
+
+        let hosts = PropagationHosts::new().deny("*.stripe.com");
+        assert!(!hosts.allows("api.stripe.com"));
+        assert!(hosts.allows("users.internal"));
     }
 }
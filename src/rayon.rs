@@ -0,0 +1,110 @@
+/*! rayon instrumentation.
+
+`TraceEach::trace_each` runs an operation over the items of a parallel iterator, each in a
+child span of the current span (see `context::current_span`), so data-parallel stages show
+up in the trace with per-item timing:
+
+```rust
+use beeline::{init, Config, Context};
+use beeline::rayon::TraceEach;
+use rayon::prelude::*;
+
+# if false {
+let mut client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+let _guard = Context::from_span(&root_span).attach();
+
+let chunks = vec![vec![1, 2], vec![3, 4]];
+chunks.par_iter().trace_each(&client, "chunk", |chunk| {
+    let _sum: i32 = chunk.iter().sum();
+});
+# }
+```
+
+The child span is the current span while the operation runs, so it can create children of
+its own.
+
+*/
+use ::rayon::iter::ParallelIterator;
+use libhoney::Sender;
+use serde_json::json;
+
+use crate::context::{self, Context};
+use crate::{keys, Client};
+
+/// `TraceEach` adds traced versions of the rayon consumers to parallel iterators
+pub trait TraceEach: ParallelIterator {
+    /// `trace_each` calls `op` on every item, in a child span of the current span named
+    /// `name`. Without a current span, it is a plain `for_each`.
+    fn trace_each<T, F>(self, client: &Client<T>, name: &str, op: F)
+    where
+        T: Sender + Send + Sync + 'static,
+        F: Fn(Self::Item) + Sync + Send,
+    {
+        let parent = match context::current_span() {
+            Some(parent) => parent,
+            None => return self.for_each(op),
+        };
+
+        self.for_each(|item| {
+            let mut client = Client(client.0.clone());
+            let child = parent.lock().create_child(&mut client);
+            match child {
+                Some(child) => {
+                    child.lock().add_field(keys::NAME, json!(name));
+                    {
+                        let _guard = Context::from_span(&child).attach();
+                        op(item);
+                    }
+                    child.lock().send(&mut client);
+                }
+                None => op(item),
+            }
+        })
+    }
+}
+
+impl<I: ParallelIterator> TraceEach for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+    use ::rayon::prelude::*;
+
+    #[test]
+    fn test_trace_each() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        {
+            let _guard = Context::from_span(&root_span).attach();
+            (0..4).into_par_iter().trace_each(&client, "chunk", |i| {
+                context::current_span()
+                    .unwrap()
+                    .lock()
+                    .add_field("chunk", json!(i));
+            });
+        }
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 5);
+        let root_span_id = &events[4].fields()["trace.span_id"];
+        let mut chunks = events[..4]
+            .iter()
+            .map(|event| {
+                assert_eq!(event.fields()["name"], json!("chunk"));
+                assert_eq!(&event.fields()["trace.parent_id"], root_span_id);
+                assert!(event.fields().contains_key("duration_ms"));
+                event.fields()["chunk"].as_u64().unwrap()
+            })
+            .collect::<Vec<_>>();
+        chunks.sort_unstable();
+        assert_eq!(chunks, vec![0, 1, 2, 3]);
+    }
+}
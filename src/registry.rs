@@ -0,0 +1,157 @@
+/*! Routing telemetry to several datasets through one client.
+
+Apps that split their telemetry (say audit, app and debug events) over several datasets,
+or Honeycomb teams, don't need a client for each: a `ClientRegistry` wraps a single client,
+and so a single transmission with its background workers, and sends the traces and events
+of each named destination to its own dataset and API key. The transmission batches events
+per dataset and API key, so the destinations don't get mixed up.
+
+```rust
+# #[cfg(feature = "testutil")] {
+use beeline::{trace::TraceSender, ClientRegistry, Config};
+
+let mut client = beeline::test::init(Config::default());
+let registry = ClientRegistry::new(client.clone())
+    .with_destination("audit", "audit-log", Some("audit-team-key"));
+
+let trace = registry.new_trace("audit", None).unwrap();
+trace.send(&mut client);
+# }
+```
+
+*/
+use std::collections::HashMap;
+
+use libhoney::{Builder, FieldHolder, Sender, Value};
+
+use crate::trace::{SafeTrace, Trace};
+use crate::Client;
+
+/// `Destination` is where the events of a destination of the registry are sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destination {
+    /// dataset the events are sent to
+    pub dataset: String,
+    /// API key of the team owning the dataset, the client one if unset
+    pub api_key: Option<String>,
+}
+
+impl Destination {
+    pub(crate) fn apply(&self, builder: &mut Builder) {
+        builder.options.dataset = self.dataset.clone();
+        if let Some(api_key) = &self.api_key {
+            builder.options.api_key = api_key.clone();
+        }
+    }
+}
+
+/// `ClientRegistry` sends traces and events to named destinations through one client
+#[derive(Debug, Clone)]
+pub struct ClientRegistry<T: Sender> {
+    client: Client<T>,
+    destinations: HashMap<String, Destination>,
+}
+
+impl<T: Sender> ClientRegistry<T> {
+    /// `new` returns a registry without destinations, sending through `client`
+    pub fn new(client: Client<T>) -> Self {
+        Self {
+            client,
+            destinations: HashMap::new(),
+        }
+    }
+
+    /// `with_destination` registers the destination `name`, sending to `dataset` with
+    /// `api_key` (or the client one)
+    pub fn with_destination(mut self, name: &str, dataset: &str, api_key: Option<&str>) -> Self {
+        self.destinations.insert(
+            name.to_string(),
+            Destination {
+                dataset: dataset.to_string(),
+                api_key: api_key.map(String::from),
+            },
+        );
+        self
+    }
+
+    /// `client` returns the client shared by all destinations
+    pub fn client(&self) -> &Client<T> {
+        &self.client
+    }
+
+    /// `destination` returns the destination registered as `name`
+    pub fn destination(&self, name: &str) -> Option<&Destination> {
+        self.destinations.get(name)
+    }
+
+    /// `new_trace` starts a trace whose spans are all sent to the destination `name`, or
+    /// returns None if there is no such destination. The trace is sent with the shared
+    /// client, like any other.
+    pub fn new_trace(&self, name: &str, serialized_headers: Option<String>) -> Option<SafeTrace> {
+        let destination = self.destinations.get(name)?;
+        let trace = self.client.new_trace(serialized_headers);
+        Trace::route(&trace, destination);
+        Some(trace)
+    }
+
+    /// `send_event` sends a standalone event made of the client level fields plus the
+    /// given ones to the destination `name`. It returns false if there is no such
+    /// destination.
+    pub fn send_event<I>(&self, name: &str, fields: I) -> bool
+    where
+        I: IntoIterator<Item = (String, Value)>,
+    {
+        let destination = match self.destinations.get(name) {
+            Some(destination) => destination,
+            None => return false,
+        };
+        let mut builder = self.client.new_builder();
+        destination.apply(&mut builder);
+        let mut ev = builder.new_event();
+        for (name, value) in fields {
+            ev.add_field(&name, value);
+        }
+        self.client.transmit(&mut ev);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+    use serde_json::json;
+
+    #[test]
+    fn test_registry() {
+        let mut client = new_client(Config::default());
+        let registry = ClientRegistry::new(client.clone())
+            .with_destination("audit", "audit-log", Some("audit-key"))
+            .with_destination("debug", "debug", None);
+        assert!(registry.new_trace("missing", None).is_none());
+
+        let trace = registry.new_trace("audit", None).unwrap();
+        let root_span = trace.lock().get_root_span();
+        root_span.lock().create_child(&mut client).unwrap();
+        trace.send(&mut client);
+        client.new_trace(None).send(&mut client);
+        assert!(registry.send_event("debug", vec![("cache".to_string(), json!("miss"))]));
+        assert!(!registry.send_event("missing", vec![]));
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        // libhoney doesn't expose the options of an event, so look at its debug output
+        let debug = events
+            .iter()
+            .map(|event| format!("{:?}", event))
+            .collect::<Vec<_>>();
+        assert!(debug[..2]
+            .iter()
+            .all(|ev| ev.contains("\"audit-log\"") && ev.contains("\"audit-key\"")));
+        assert!(debug[2].contains("\"beeline-rust\""));
+        assert!(debug[3].contains("dataset: \"debug\""));
+        assert!(debug[3].contains("api-key-placeholder"));
+    }
+}
@@ -0,0 +1,446 @@
+/*! Retrying failed batches.
+
+libhoney reports the outcome of every event it transmits, but doesn't retry anything. With
+a `RetryPolicy` in `Config`, the client keeps a copy of the events in flight and a
+`beeline-retry` thread reads the responses: events whose batch failed with a transient
+error are sent again after an exponential backoff, and counted in
+`Stats::dropped_after_retries` once they run out of attempts.
+
+The events in flight are tracked by a `beeline.retry_id` added to their metadata. It is
+merged into metadata objects, and other metadata values are wrapped in an object along with
+it, under `beeline.metadata`. Applications reading the transmission responses themselves
+get their own metadata back with `RetryPolicy::original_metadata`.
+
+*/
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libhoney::{Event, Sender, Value};
+use log::Level;
+use serde_json::json;
+
+use crate::export;
+use crate::filter::Outcome;
+use crate::Client;
+
+/// Key of the event metadata the events in flight are tracked by
+const METADATA_KEY: &str = "beeline.retry_id";
+
+/// Key of the event metadata wrapping the metadata of the application, when it isn't an
+/// object `METADATA_KEY` can be merged into
+const WRAPPED_METADATA_KEY: &str = "beeline.metadata";
+
+/// How long the retry thread waits for responses before checking on the client again
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// `RetryPolicy` decides which failed events are sent again, and when
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// number of times an event is sent, including the first one
+    pub max_attempts: u32,
+    /// wait before the first retry, doubled on each subsequent one
+    pub initial_backoff: Duration,
+    /// longest wait between two attempts
+    pub max_backoff: Duration,
+    /// whether events rejected because of rate limiting (429) are retried
+    pub retry_on_429: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retry_on_429: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// `backoff` returns how long to wait before sending an event for the `attempt`th
+    /// time (2 being the first retry)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(2));
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+
+    /// `is_retryable` returns whether an event that got the given response status should
+    /// be retried. A missing status means the request never made it to the API.
+    pub fn is_retryable(&self, status: Option<u16>) -> bool {
+        match status {
+            None => true,
+            Some(429) => self.retry_on_429,
+            Some(status) => status >= 500,
+        }
+    }
+
+    /// `original_metadata` returns the metadata the application set on an event, given the
+    /// metadata of its transmission response, without what the retries added to it
+    pub fn original_metadata(metadata: Option<Value>) -> Option<Value> {
+        match metadata {
+            Some(Value::Object(mut object)) if object.contains_key(METADATA_KEY) => {
+                object.remove(METADATA_KEY);
+                if let Some(wrapped) = object.remove(WRAPPED_METADATA_KEY) {
+                    Some(wrapped)
+                } else if object.is_empty() {
+                    None
+                } else {
+                    Some(Value::Object(object))
+                }
+            }
+            metadata => metadata,
+        }
+    }
+}
+
+/// `tag_metadata` returns `metadata` with the retry id `id` added to it, see
+/// `RetryPolicy::original_metadata`
+fn tag_metadata(metadata: Option<Value>, id: u64) -> Value {
+    match metadata {
+        Some(Value::Object(mut object)) => {
+            object.insert(METADATA_KEY.to_string(), json!(id));
+            Value::Object(object)
+        }
+        None => json!({ METADATA_KEY: id }),
+        Some(metadata) => json!({ METADATA_KEY: id, WRAPPED_METADATA_KEY: metadata }),
+    }
+}
+
+/// `Pending` holds the events in flight and the ones waiting to be retried
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Pending {
+    next_id: u64,
+    in_flight: HashMap<u64, (Event, u32)>,
+    scheduled: Vec<(Instant, Event, u32)>,
+}
+
+impl<T: Sender> Client<T> {
+    /// `transmit` hands `ev` over to the transmission, keeping a copy of it for retries
+    /// when a retry policy is set
+    pub(crate) fn transmit(&self, ev: &mut Event) {
+        #[cfg(debug_assertions)]
+        self.check_field_schema(ev);
+        let (otel_field_names, export_hook) = {
+            let client = self.0.read();
+            (
+                client.config.otel_field_names,
+                client.config.export_hook.clone(),
+            )
+        };
+        if otel_field_names {
+            export::to_otel_field_names(ev.get_fields_mut());
+        }
+        if let Some(export_hook) = export_hook {
+            let fields = ev.get_fields_mut();
+            if panic::catch_unwind(AssertUnwindSafe(|| export_hook(fields))).is_err() {
+                self.diagnostic(
+                    Level::Error,
+                    format_args!("export hook panicked, dropping the event"),
+                );
+                return;
+            }
+        }
+        let event_filter = self.0.read().config.event_filter.clone();
+        if let Some(event_filter) = event_filter {
+            match event_filter.apply(ev) {
+                Outcome::Transmit => {}
+                Outcome::Dropped => {
+                    self.0.write().stats.filtered_out += 1;
+                    return;
+                }
+                Outcome::Routed => {
+                    self.0.write().stats.routed += 1;
+                    return;
+                }
+            }
+        }
+        self.transmit_attempt(ev, 1)
+    }
+
+    /// `check_field_schema` reports the fields of `ev` whose type doesn't match
+    /// `Config::field_schema`, before any of them is renamed
+    #[cfg(debug_assertions)]
+    fn check_field_schema(&self, ev: &mut Event) {
+        let (violations, panics) = {
+            let client = self.0.read();
+            match &client.config.field_schema {
+                Some(schema) => (schema.violations(ev.get_fields_mut()), schema.panics()),
+                None => return,
+            }
+        };
+        for violation in violations {
+            if panics {
+                panic!("{}", violation);
+            }
+            self.diagnostic(Level::Error, format_args!("{}", violation));
+        }
+    }
+
+    fn transmit_attempt(&self, ev: &mut Event, attempt: u32) {
+        let mut guard = self.0.write();
+        let client = &mut *guard;
+        let mut tracked = None;
+        if client.config.retry_policy.is_some() {
+            let id = client.retries.next_id;
+            client.retries.next_id += 1;
+            // the copy kept for retries has the metadata of the application, tagged again
+            // with a new id on every attempt
+            client.retries.in_flight.insert(id, (ev.clone(), attempt));
+            ev.set_metadata(Some(tag_metadata(ev.metadata(), id)));
+            tracked = Some(id);
+        }
+
+        let sent = ev.send_presampled(&mut client.client);
+        match sent {
+            Ok(()) => client.health.record_sent(),
+            Err(e) => {
+                // no response ever comes for an event the transmission wasn't handed
+                if let Some(id) = tracked {
+                    client.retries.in_flight.remove(&id);
+                }
+                drop(guard);
+                self.diagnostic(Level::Error, format_args!("Error sending event: {}", e));
+            }
+        }
+    }
+
+    /// `handle_response` records the response to an event, scheduling the event for
+    /// another attempt if it failed and the retry policy allows it
+    pub(crate) fn handle_response(
+        &self,
+        status: Option<u16>,
+        error: Option<String>,
+        metadata: Option<Value>,
+    ) {
+        let failed = error.is_some() || !status.is_some_and(|status| (200..300).contains(&status));
+        let mut guard = self.0.write();
+        let client = &mut *guard;
+        client.health.record_response(status, error);
+
+        let tracked = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(METADATA_KEY))
+            .and_then(Value::as_u64)
+            .and_then(|id| client.retries.in_flight.remove(&id));
+        let (policy, (ev, attempt)) = match (&client.config.retry_policy, tracked) {
+            (Some(policy), Some(tracked)) if failed => (policy, tracked),
+            _ => return,
+        };
+
+        if !policy.is_retryable(status) {
+            return;
+        }
+        if attempt >= policy.max_attempts {
+            client.stats.dropped_after_retries += 1;
+            return;
+        }
+        let due = Instant::now() + policy.backoff(attempt + 1);
+        client.retries.scheduled.push((due, ev, attempt + 1));
+    }
+
+    /// `resend_due` sends again the events whose backoff is over by `now`, and returns
+    /// when the next one is due
+    pub(crate) fn resend_due(&self, now: Instant) -> Option<Instant> {
+        let due = {
+            let mut client = self.0.write();
+            let (due, waiting) = client
+                .retries
+                .scheduled
+                .drain(..)
+                .partition::<Vec<_>, _>(|(at, _, _)| *at <= now);
+            client.retries.scheduled = waiting;
+            due
+        };
+        for (_, mut ev, attempt) in due {
+            self.transmit_attempt(&mut ev, attempt);
+        }
+        self.0
+            .read()
+            .retries
+            .scheduled
+            .iter()
+            .map(|(at, _, _)| *at)
+            .min()
+    }
+}
+
+/// `start` spawns the thread reading the transmission responses of `client` and retrying
+/// failed events. It only holds a weak reference to the client and stops with it.
+pub(crate) fn start<T>(client: &Client<T>)
+where
+    T: Sender + Send + Sync + 'static,
+{
+    let responses = client.0.read().client.responses();
+    let weak = Arc::downgrade(&client.0);
+    thread::Builder::new()
+        .name("beeline-retry".to_string())
+        .spawn(move || {
+            let mut next_due: Option<Instant> = None;
+            loop {
+                let timeout = next_due.map_or(POLL_INTERVAL, |due| {
+                    due.saturating_duration_since(Instant::now())
+                        .min(POLL_INTERVAL)
+                });
+                let response = responses.recv_timeout(timeout);
+                let client = match weak.upgrade() {
+                    Some(inner) => Client(inner),
+                    None => return,
+                };
+                match response {
+                    Ok(response) => client.handle_response(
+                        response.status_code.map(|status| status.as_u16()),
+                        response.error,
+                        response.metadata,
+                    ),
+                    Err(e) if e.is_disconnected() => return,
+                    Err(_) => {}
+                }
+                next_due = client.resend_due(Instant::now());
+            }
+        })
+        .expect("failed to spawn beeline retry thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_backoff: Duration::from_millis(500),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(2), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(200));
+        assert_eq!(policy.backoff(5), Duration::from_millis(500));
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable(None));
+        assert!(policy.is_retryable(Some(503)));
+        assert!(policy.is_retryable(Some(429)));
+        assert!(!policy.is_retryable(Some(400)));
+        let policy = RetryPolicy {
+            retry_on_429: false,
+            ..policy
+        };
+        assert!(!policy.is_retryable(Some(429)));
+    }
+
+    #[test]
+    fn test_retries() {
+        let mut client = new_client(Config {
+            retry_policy: Some(RetryPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::from_secs(0),
+                ..RetryPolicy::default()
+            }),
+            ..Default::default()
+        });
+        client.new_trace(None).send(&mut client);
+        client.new_trace(None).send(&mut client);
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+
+        // the first event fails and is sent again, the second one is rejected for good
+        let error = Some("connection reset".to_string());
+        client.handle_response(None, error.clone(), events[0].metadata());
+        client.handle_response(Some(400), None, events[1].metadata());
+        assert_eq!(client.resend_due(Instant::now()), None);
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[2].fields()["trace.span_id"],
+            events[0].fields()["trace.span_id"]
+        );
+
+        // and fails again, running out of attempts
+        client.handle_response(None, error, events[2].metadata());
+        client.resend_due(Instant::now());
+        assert_eq!(client.0.write().client.transmission.events().len(), 3);
+        assert_eq!(client.stats().dropped_after_retries, 1);
+        assert!(client.0.read().retries.in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_retries_rejected_send() {
+        let mut config = Config {
+            retry_policy: Some(RetryPolicy::default()),
+            ..Default::default()
+        };
+        config.client_config.options.api_key = String::new();
+        let client = new_client(config);
+        let mut ev = client.new_builder().new_event();
+        client.transmit(&mut ev);
+
+        assert!(client.0.write().client.transmission.events().is_empty());
+        assert!(client.0.read().retries.in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_metadata() {
+        for metadata in &[
+            None,
+            Some(json!({"request_id": "r-1"})),
+            Some(json!("r-1")),
+            Some(json!({})),
+        ] {
+            let tagged = tag_metadata(metadata.clone(), 7);
+            assert_eq!(tagged[METADATA_KEY], json!(7));
+            let expected = match metadata {
+                Some(Value::Object(object)) if object.is_empty() => None,
+                metadata => metadata.clone(),
+            };
+            assert_eq!(RetryPolicy::original_metadata(Some(tagged)), expected);
+        }
+        assert_eq!(
+            tag_metadata(Some(json!({"request_id": "r-1"})), 7),
+            json!({"request_id": "r-1", METADATA_KEY: 7})
+        );
+        assert_eq!(
+            RetryPolicy::original_metadata(Some(json!(1))),
+            Some(json!(1))
+        );
+    }
+
+    #[test]
+    fn test_retries_keep_metadata() {
+        let client = new_client(Config {
+            retry_policy: Some(RetryPolicy {
+                initial_backoff: Duration::from_secs(0),
+                ..RetryPolicy::default()
+            }),
+            ..Default::default()
+        });
+        let mut ev = client.new_builder().new_event();
+        ev.set_metadata(Some(json!({"request_id": "r-1"})));
+        client.transmit(&mut ev);
+        let events = client.0.write().client.transmission.events();
+        client.handle_response(Some(503), None, events[0].metadata());
+        client.resend_due(Instant::now());
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        for event in events {
+            let metadata = event.metadata().unwrap();
+            assert!(metadata.get(METADATA_KEY).is_some());
+            assert_eq!(
+                RetryPolicy::original_metadata(Some(metadata)),
+                Some(json!({"request_id": "r-1"}))
+            );
+        }
+    }
+}
@@ -0,0 +1,274 @@
+/*! Per-route latency rollups.
+
+Traces are often heavily sampled, which makes them a poor source for SLO metrics. A
+`RouteLatency` aggregates every request the HTTP middlewares see, sampled or not, and emits
+a single `route_latency` event per route and interval with:
+ - `request.route`
+ - `count`
+ - `duration_ms.p50`, `duration_ms.p95`, `duration_ms.p99` and `duration_ms.max`
+ - `response.status_class.2xx`, `3xx`, `4xx` and `5xx` (and `1xx`), the number of
+   responses of each class
+ - `exemplar.trace_ids`, the ids of the traces of the slowest requests, slowest first, to
+   jump from the rollup to representative traces. Only traces the head sampler kept are
+   candidates, as the others never make it to Honeycomb.
+
+Durations are kept in buckets about 5% wide, so percentiles are accurate to about 5% and
+the memory used doesn't grow with the traffic.
+
+```rust,no_run
+use std::time::Duration;
+use beeline::{init, Config};
+
+let client = init(Config::default());
+let latency = client.start_route_latency(Duration::from_secs(60));
+// hand `latency` to the middleware, e.g. `BeelineMiddleware::with_route_latency`
+```
+
+*/
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use libhoney::{Sender, Value};
+use parking_lot::Mutex;
+use serde_json::json;
+
+use crate::background::BackgroundTask;
+use crate::keys;
+use crate::Client;
+
+/// type of the events emitted by `RouteLatency`, sent as `meta.type`
+pub const EVENT_TYPE: &str = "route_latency";
+/// route the requests were routed to, for requests that didn't match any
+pub const UNMATCHED_ROUTE: &str = "<unmatched>";
+
+/// relative width of the duration buckets
+const BUCKET_GROWTH: f64 = 1.05;
+/// upper bound of the first bucket, shorter durations all end up in it
+const MIN_DURATION_MS: f64 = 0.01;
+/// number of exemplar traces kept per route and interval
+const MAX_EXEMPLARS: usize = 3;
+
+/// `Window` is what has been seen of a route since the last emission
+#[derive(Debug, Default)]
+struct Window {
+    count: u64,
+    max_ms: f64,
+    buckets: BTreeMap<i32, u64>,
+    status_classes: [u64; 5],
+    /// duration and trace id of the slowest requests, slowest first
+    exemplars: Vec<(f64, String)>,
+}
+
+impl Window {
+    fn record(&mut self, status: u16, duration_ms: f64, trace_id: Option<&str>) {
+        self.count += 1;
+        self.max_ms = self.max_ms.max(duration_ms);
+        *self.buckets.entry(bucket(duration_ms)).or_insert(0) += 1;
+        if let 100..=599 = status {
+            self.status_classes[usize::from(status / 100 - 1)] += 1;
+        }
+        if let Some(trace_id) = trace_id {
+            self.add_exemplar(duration_ms, trace_id);
+        }
+    }
+
+    fn add_exemplar(&mut self, duration_ms: f64, trace_id: &str) {
+        if self.exemplars.len() == MAX_EXEMPLARS
+            && self.exemplars[MAX_EXEMPLARS - 1].0 >= duration_ms
+        {
+            return;
+        }
+        let at = self
+            .exemplars
+            .iter()
+            .position(|(slower, _)| *slower < duration_ms)
+            .unwrap_or(self.exemplars.len());
+        self.exemplars
+            .insert(at, (duration_ms, trace_id.to_string()));
+        self.exemplars.truncate(MAX_EXEMPLARS);
+    }
+
+    /// `percentile` returns the upper bound of the bucket holding the `q` quantile
+    fn percentile(&self, q: f64) -> f64 {
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return upper_bound(*bucket).min(self.max_ms);
+            }
+        }
+        self.max_ms
+    }
+
+    fn fields(&self, route: &str) -> Vec<(String, Value)> {
+        let mut fields = vec![
+            (keys::META_TYPE.to_string(), json!(EVENT_TYPE)),
+            (keys::REQUEST_ROUTE.to_string(), json!(route)),
+            ("count".to_string(), json!(self.count)),
+            ("duration_ms.p50".to_string(), json!(self.percentile(0.5))),
+            ("duration_ms.p95".to_string(), json!(self.percentile(0.95))),
+            ("duration_ms.p99".to_string(), json!(self.percentile(0.99))),
+            ("duration_ms.max".to_string(), json!(self.max_ms)),
+        ];
+        if !self.exemplars.is_empty() {
+            let trace_ids: Vec<_> = self.exemplars.iter().map(|(_, id)| id).collect();
+            fields.push(("exemplar.trace_ids".to_string(), json!(trace_ids)));
+        }
+        for (class, count) in self.status_classes.iter().enumerate() {
+            fields.push((
+                format!("response.status_class.{}xx", class + 1),
+                json!(count),
+            ));
+        }
+        fields
+    }
+}
+
+fn bucket(duration_ms: f64) -> i32 {
+    if duration_ms <= MIN_DURATION_MS {
+        return 0;
+    }
+    ((duration_ms / MIN_DURATION_MS).ln() / BUCKET_GROWTH.ln()).ceil() as i32
+}
+
+fn upper_bound(bucket: i32) -> f64 {
+    MIN_DURATION_MS * BUCKET_GROWTH.powi(bucket)
+}
+
+/// `RouteLatency` aggregates the latency and status of requests per route, see the
+/// [module documentation](index.html). Clones share the same aggregates; the emitter
+/// stops once every clone returned by `Client::start_route_latency` is dropped.
+#[derive(Debug)]
+pub struct RouteLatency<T: Sender> {
+    client: Client<T>,
+    routes: Arc<Mutex<HashMap<String, Window>>>,
+    emitter: Option<Arc<BackgroundTask>>,
+}
+
+// not derived, as that would require the sender to be `Clone`
+impl<T: Sender> Clone for RouteLatency<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: Client(self.client.0.clone()),
+            routes: self.routes.clone(),
+            emitter: self.emitter.clone(),
+        }
+    }
+}
+
+impl<T: Sender> RouteLatency<T> {
+    fn new(client: &Client<T>) -> Self {
+        Self {
+            client: Client(client.0.clone()),
+            routes: Arc::new(Mutex::new(HashMap::new())),
+            emitter: None,
+        }
+    }
+
+    /// `record` adds a request routed to `route` that got a response with `status` after
+    /// `duration`. `trace_id` is the trace of the request, if it can serve as an exemplar.
+    pub fn record(&self, route: &str, status: u16, duration: Duration, trace_id: Option<&str>) {
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+        let mut routes = self.routes.lock();
+        match routes.get_mut(route) {
+            Some(window) => window.record(status, duration_ms, trace_id),
+            None => {
+                let mut window = Window::default();
+                window.record(status, duration_ms, trace_id);
+                routes.insert(route.to_string(), window);
+            }
+        }
+    }
+
+    /// `emit` sends the event of every route seen since the last emission and starts a
+    /// new interval. It is called by the emitter every interval, and can be called on
+    /// shutdown so the last interval isn't lost.
+    pub fn emit(&self) {
+        let routes = std::mem::take(&mut *self.routes.lock());
+        for (route, window) in routes {
+            self.client.send_event(window.fields(&route));
+        }
+    }
+}
+
+impl<T> Client<T>
+where
+    T: Sender + Send + Sync + 'static,
+{
+    /// `start_route_latency` returns a `RouteLatency` that emits the latency rollup of
+    /// every route every `interval`, until all its clones are dropped
+    pub fn start_route_latency(&self, interval: Duration) -> RouteLatency<T> {
+        let latency = RouteLatency::new(self);
+        let emitter = latency.clone();
+        let task = BackgroundTask::spawn("beeline-route-latency", interval, move || emitter.emit());
+        RouteLatency {
+            emitter: Some(Arc::new(task)),
+            ..latency
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::Config;
+
+    #[test]
+    fn test_emit() {
+        let client = new_client(Config::default());
+        let latency = RouteLatency::new(&client);
+        for ms in 1..=100 {
+            let trace_id = format!("trace-{}", ms);
+            latency.record(
+                "/users/{id}",
+                200,
+                Duration::from_millis(ms),
+                Some(&trace_id),
+            );
+        }
+        latency.record("/users/{id}", 503, Duration::from_millis(250), None);
+        latency.record(UNMATCHED_ROUTE, 404, Duration::from_micros(1), None);
+        latency.emit();
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let users = events
+            .iter()
+            .map(|event| event.fields())
+            .find(|fields| fields[keys::REQUEST_ROUTE] == json!("/users/{id}"))
+            .unwrap();
+        assert_eq!(users[keys::META_TYPE], json!(EVENT_TYPE));
+        assert_eq!(users["count"], json!(101));
+        let p50 = users["duration_ms.p50"].as_f64().unwrap();
+        assert!((50.0..=51.0 * BUCKET_GROWTH).contains(&p50), "p50 {}", p50);
+        let p99 = users["duration_ms.p99"].as_f64().unwrap();
+        assert!((99.0..=100.0 * BUCKET_GROWTH).contains(&p99), "p99 {}", p99);
+        assert_eq!(users["duration_ms.max"], json!(250.0));
+        assert_eq!(users["response.status_class.2xx"], json!(100));
+        assert_eq!(users["response.status_class.5xx"], json!(1));
+        assert_eq!(
+            users["exemplar.trace_ids"],
+            json!(["trace-100", "trace-99", "trace-98"])
+        );
+
+        // the next interval starts afresh
+        latency.emit();
+        assert_eq!(client.0.write().client.transmission.events().len(), 2);
+    }
+
+    #[test]
+    fn test_start_route_latency() {
+        let client = new_client(Config::default());
+        let latency = client.start_route_latency(Duration::from_millis(5));
+        latency.record("/", 200, Duration::from_millis(1), None);
+        std::thread::sleep(Duration::from_millis(50));
+        drop(latency);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields()["count"], json!(1));
+    }
+}
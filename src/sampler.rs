@@ -0,0 +1,362 @@
+/*! Sampling decisions.
+
+The `sampler_hook` in `Config` is handed the fields of every span about to be sent and
+returns a `SamplerDecision` saying whether the span should be kept, at which sample rate,
+and (optionally) why.
+
+The sample rate of the decision is the one the event is sent with, and is also recorded as
+`meta.sample_rate_weight` on kept events so counts can be re-weighted outside Honeycomb.
+Samplers that compose others (e.g. a rule sampler falling back to a dynamic one) should
+return the rate of the sampler that made the final decision.
+
+The `head_sampler_hook` decides earlier, when a trace is created, based on its id only.
+Continued traces keep the upstream id, so a deterministic head sampler reaches the same
+decision as the upstream service did.
+
+`PerKeySampler` is a ready-made sampler sharing a budget of events fairly between the
+values of a field, e.g. `user.tenant`, so a single noisy tenant doesn't crowd out the
+traces of everyone else:
+
+```rust
+use std::sync::Arc;
+use std::time::Duration;
+use beeline::sampler::PerKeySampler;
+use beeline::Config;
+
+// about 1000 events a minute, shared between tenants
+let sampler = PerKeySampler::new("user.tenant", 1000, Duration::from_secs(60));
+let config = Config {
+    sampler_hook: Arc::new(move |fields| sampler.sample(&fields)),
+    ..Config::default()
+};
+```
+
+*/
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+use libhoney::Value;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+
+use crate::keys;
+
+/// Reason recorded when a sampler keeps or drops an event because of a rule match
+pub const REASON_RULE_PREFIX: &str = "rule:";
+/// Reason recorded when a sampler decides deterministically (e.g. based on the trace id)
+pub const REASON_DETERMINISTIC: &str = "deterministic";
+/// Reason recorded when a sampler drops an event to stay within a rate limit
+pub const REASON_RATE_LIMIT: &str = "rate_limit";
+/// Reason recorded when the head sampler drops a trace as it is created
+pub const REASON_HEAD: &str = "head";
+/// Reason recorded when the sampler hook panics and the event is dropped
+pub const REASON_HOOK_FAILURE: &str = "hook_failure";
+/// Reason recorded when `PerKeySampler` keeps or drops an event
+pub const REASON_PER_KEY: &str = "per_key";
+/// Reason recorded when a span is kept for being slower than its `SlowThresholds`
+pub const REASON_SLOW: &str = "slow";
+/// Reason recorded when an event is kept for a field in `Config::always_keep`
+pub const REASON_ALWAYS_KEEP: &str = "always_keep";
+/// Reason recorded when an event is sampled at the rate chosen for its whole trace, see
+/// `Config::propagate_sample_rate`
+pub const REASON_TRACE_RATE: &str = "trace_rate";
+
+/// `SamplerDecision` is the outcome of running the sampler hook over an event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplerDecision {
+    /// whether the event should be sent
+    pub keep: bool,
+    /// sample rate the event should be sent with
+    pub sample_rate: usize,
+    /// why the sampler reached this decision. When set, it is recorded as
+    /// `meta.sample.reason` on kept events and counted in the client `Stats` for
+    /// dropped ones.
+    pub reason: Option<String>,
+}
+
+impl SamplerDecision {
+    /// `keep` returns a decision keeping the event at the given sample rate
+    pub fn keep(sample_rate: usize) -> Self {
+        Self {
+            keep: true,
+            sample_rate,
+            reason: None,
+        }
+    }
+
+    /// `drop` returns a decision dropping the event that would have had the given
+    /// sample rate
+    pub fn drop(sample_rate: usize) -> Self {
+        Self {
+            keep: false,
+            sample_rate,
+            reason: None,
+        }
+    }
+
+    /// `with_reason` records why the decision was made
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    /// `with_rule` records that the decision was made by the named rule, producing a
+    /// reason of the form `rule:<name>`
+    pub fn with_rule(self, rule: &str) -> Self {
+        self.with_reason(&format!("{}{}", REASON_RULE_PREFIX, rule))
+    }
+}
+
+/// A bare `bool` keeps or drops the event at a sample rate of 1
+impl From<bool> for SamplerDecision {
+    fn from(keep: bool) -> Self {
+        (keep, 1).into()
+    }
+}
+
+impl From<(bool, usize)> for SamplerDecision {
+    fn from((keep, sample_rate): (bool, usize)) -> Self {
+        Self {
+            keep,
+            sample_rate,
+            reason: None,
+        }
+    }
+}
+
+/// `PerKeySampler` samples events by the value of a field (the key), giving every key a
+/// fair share of a budget of events per interval. Keys that send fewer events than their
+/// share keep them all and leave the rest of the budget to the others.
+///
+/// Sample rates are computed from the traffic of the previous interval, so every event is
+/// kept during the first one and when a key is first seen. Events with a trace id are
+/// sampled deterministically on it, which keeps or drops the spans of a trace together
+/// when they share the key: pick a field set on every span, such as a trace level field.
+#[derive(Debug)]
+pub struct PerKeySampler {
+    field: String,
+    budget: usize,
+    interval: Duration,
+    state: Mutex<PerKeyState>,
+}
+
+#[derive(Debug)]
+struct PerKeyState {
+    started: Instant,
+    /// number of events seen per key in the current interval
+    seen: HashMap<String, u64>,
+    /// sample rate per key, from the previous interval
+    rates: HashMap<String, usize>,
+}
+
+impl PerKeyState {
+    /// `rotate` computes the sample rates of the next interval: the budget is shared
+    /// evenly, and what the quietest keys don't use goes to the others
+    fn rotate(&mut self, budget: usize) {
+        let mut seen: Vec<_> = self.seen.drain().collect();
+        seen.sort_by_key(|(_, count)| *count);
+        let mut remaining = budget as f64;
+        let keys = seen.len();
+        self.rates = seen
+            .into_iter()
+            .enumerate()
+            .map(|(i, (key, count))| {
+                let share = remaining / (keys - i) as f64;
+                let kept = (count as f64).min(share).max(1.0);
+                remaining = (remaining - kept).max(0.0);
+                (key, ((count as f64 / kept).ceil() as usize).max(1))
+            })
+            .collect();
+        self.started = Instant::now();
+    }
+}
+
+impl PerKeySampler {
+    /// `new` returns a sampler keyed on `field`, keeping about `budget` events every
+    /// `interval` overall
+    pub fn new(field: &str, budget: usize, interval: Duration) -> Self {
+        Self {
+            field: field.to_string(),
+            budget,
+            interval,
+            state: Mutex::new(PerKeyState {
+                started: Instant::now(),
+                seen: HashMap::new(),
+                rates: HashMap::new(),
+            }),
+        }
+    }
+
+    /// `sample` decides on the event made of `fields`. Events without the field share
+    /// the key of the empty string.
+    pub fn sample(&self, fields: &HashMap<String, Value>) -> SamplerDecision {
+        let key = match fields.get(&self.field) {
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        };
+        let (rate, seen) = {
+            let mut state = self.state.lock();
+            if state.started.elapsed() >= self.interval {
+                state.rotate(self.budget);
+            }
+            let seen = state.seen.entry(key.clone()).or_insert(0);
+            *seen += 1;
+            let seen = *seen;
+            (state.rates.get(&key).copied().unwrap_or(1), seen)
+        };
+
+        let keep = match fields.get(keys::TRACE_TRACE_ID).and_then(Value::as_str) {
+            Some(trace_id) => keep_trace(trace_id, rate),
+            None => seen % rate as u64 == 0,
+        };
+        SamplerDecision {
+            keep,
+            sample_rate: rate,
+            reason: Some(REASON_PER_KEY.to_string()),
+        }
+    }
+}
+
+/// `SlowThresholds` are the durations past which spans are slow, by name or by `meta.type`,
+/// see `Config::slow_thresholds`. The threshold of the name of a span comes before the one
+/// of its type.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use beeline::sampler::SlowThresholds;
+///
+/// let thresholds = SlowThresholds::new()
+///     .span_type("http_request", Duration::from_secs(2))
+///     .span_type("db", Duration::from_millis(200))
+///     .name("GET /export", Duration::from_secs(30));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SlowThresholds {
+    names: HashMap<String, Duration>,
+    types: HashMap<String, Duration>,
+}
+
+impl SlowThresholds {
+    /// `new` returns thresholds without any span being slow
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `name` makes the spans named `name` slow past `threshold`
+    pub fn name(mut self, name: &str, threshold: Duration) -> Self {
+        self.names.insert(name.to_string(), threshold);
+        self
+    }
+
+    /// `span_type` makes the spans of `meta.type` `span_type` slow past `threshold`
+    pub fn span_type(mut self, span_type: &str, threshold: Duration) -> Self {
+        self.types.insert(span_type.to_string(), threshold);
+        self
+    }
+
+    /// `is_slow` returns whether the span made of `fields` lasted longer than its
+    /// threshold, if any
+    pub fn is_slow(&self, fields: &HashMap<String, Value>) -> bool {
+        let field = |key| fields.get(key).and_then(Value::as_str);
+        let threshold = field(keys::NAME)
+            .and_then(|name| self.names.get(name))
+            .or_else(|| field(keys::META_TYPE).and_then(|ty| self.types.get(ty)));
+        match (
+            threshold,
+            fields.get(keys::DURATION_MS).and_then(Value::as_f64),
+        ) {
+            (Some(threshold), Some(duration_ms)) => duration_ms > threshold.as_secs_f64() * 1000f64,
+            _ => false,
+        }
+    }
+}
+
+/// `keep_trace` decides deterministically on the trace `trace_id` at `rate`, so every
+/// service sampling it at the same rate reaches the same decision
+pub(crate) fn keep_trace(trace_id: &str, rate: usize) -> bool {
+    let digest = Sha256::digest(trace_id.as_bytes());
+    let value = u32::from_be_bytes(digest[..4].try_into().unwrap());
+    u64::from(value) <= u64::from(u32::MAX) / rate as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_tuple() {
+        let decision: SamplerDecision = (false, 10).into();
+        assert_eq!(decision, SamplerDecision::drop(10));
+        let decision: SamplerDecision = true.into();
+        assert_eq!(decision, SamplerDecision::keep(1));
+    }
+
+    #[test]
+    fn test_with_rule() {
+        let decision = SamplerDecision::keep(1).with_rule("errors");
+        assert!(decision.keep);
+        assert_eq!(decision.reason, Some("rule:errors".to_string()));
+    }
+
+    #[test]
+    fn test_slow_thresholds() {
+        let thresholds = SlowThresholds::new()
+            .span_type("db", Duration::from_millis(200))
+            .name("db.export", Duration::from_secs(5));
+        let span = |name: &str, span_type: &str, duration_ms: f64| {
+            let mut fields = HashMap::new();
+            fields.insert(keys::NAME.to_string(), json!(name));
+            fields.insert(keys::META_TYPE.to_string(), json!(span_type));
+            fields.insert(keys::DURATION_MS.to_string(), json!(duration_ms));
+            fields
+        };
+        assert!(thresholds.is_slow(&span("db.query", "db", 250.0)));
+        assert!(!thresholds.is_slow(&span("db.query", "db", 150.0)));
+        assert!(!thresholds.is_slow(&span("db.export", "db", 250.0)));
+        assert!(thresholds.is_slow(&span("db.export", "db", 6000.0)));
+        assert!(!thresholds.is_slow(&span("render", "template", 6000.0)));
+    }
+
+    #[test]
+    fn test_per_key_sampler() {
+        let sampler = PerKeySampler::new("user.tenant", 20, Duration::from_secs(3600));
+        let event = |tenant: &str, trace_id: usize| {
+            let mut fields = HashMap::new();
+            fields.insert("user.tenant".to_string(), json!(tenant));
+            fields.insert(
+                keys::TRACE_TRACE_ID.to_string(),
+                json!(trace_id.to_string()),
+            );
+            fields
+        };
+
+        // everything is kept until the rates are known
+        for i in 0..1000 {
+            assert!(sampler.sample(&event("noisy", i)).keep);
+        }
+        for i in 0..5 {
+            assert!(sampler.sample(&event("quiet", i)).keep);
+        }
+        sampler.state.lock().rotate(20);
+
+        // the quiet tenant is under its share of 10 and keeps everything, the noisy one
+        // gets the remaining 15
+        let quiet = sampler.sample(&event("quiet", 0));
+        assert!(quiet.keep);
+        assert_eq!(quiet.sample_rate, 1);
+        assert_eq!(quiet.reason.as_deref(), Some(REASON_PER_KEY));
+        let kept = (0..1000)
+            .map(|i| sampler.sample(&event("noisy", i)))
+            .inspect(|decision| assert_eq!(decision.sample_rate, 67))
+            .filter(|decision| decision.keep)
+            .count();
+        assert!((5..=30).contains(&kept), "kept {}", kept);
+
+        // the same trace gets the same decision
+        let decision = sampler.sample(&event("noisy", 42)).keep;
+        assert_eq!(sampler.sample(&event("noisy", 42)).keep, decision);
+    }
+}
@@ -0,0 +1,276 @@
+/*! Sanitization of values before they are added to spans.
+
+`sql` replaces the literal values of a statement with placeholders and collapses `IN` lists,
+so statements can go into `db.statement` without leaking personal data and with a
+cardinality close to the number of distinct queries the application runs:
+
+```rust
+assert_eq!(
+    beeline::sanitize::sql("SELECT * FROM users WHERE email = 'jane@example.com' AND id IN (1, 2, 3)"),
+    "SELECT * FROM users WHERE email = ? AND id IN (?)"
+);
+```
+
+Use a `SqlSanitizer` to change the defaults.
+
+`is_sensitive_field` flags field names that look like they hold credentials. It makes a
+ready-made `Config::propagation_filter`, keeping such trace level fields from being sent to
+downstream services:
+
+```rust
+use std::sync::Arc;
+
+let config = beeline::Config {
+    propagation_filter: Some(Arc::new(|name, _| !beeline::sanitize::is_sensitive_field(name))),
+    ..Default::default()
+};
+```
+
+*/
+
+/// `SqlSanitizer` holds the options used to sanitize SQL statements
+#[derive(Debug, Clone)]
+pub struct SqlSanitizer {
+    /// replacement for every literal value, "?" by default
+    pub placeholder: String,
+    /// replace lists made only of placeholders (e.g. `IN (?, ?, ?)`) with a single one
+    pub collapse_in_lists: bool,
+    /// remove `--` and `/* */` comments, which may contain anything
+    pub strip_comments: bool,
+    /// treat double quoted text as a string literal (MySQL) rather than an identifier
+    pub double_quoted_strings: bool,
+}
+
+impl Default for SqlSanitizer {
+    fn default() -> Self {
+        Self {
+            placeholder: "?".to_string(),
+            collapse_in_lists: true,
+            strip_comments: true,
+            double_quoted_strings: false,
+        }
+    }
+}
+
+/// `sql` sanitizes `statement` with the default options
+pub fn sql(statement: &str) -> String {
+    SqlSanitizer::default().sanitize(statement)
+}
+
+impl SqlSanitizer {
+    /// `sanitize` replaces the string and numeric literals of `statement` with the
+    /// placeholder. Identifiers and bind parameters (`$1`, `?`, `:name`) are left untouched.
+    pub fn sanitize(&self, statement: &str) -> String {
+        let chars: Vec<char> = statement.chars().collect();
+        let mut sanitized = String::with_capacity(statement.len());
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            match c {
+                '\'' => {
+                    i = skip_quoted(&chars, i);
+                    sanitized.push_str(&self.placeholder);
+                }
+                '"' if self.double_quoted_strings => {
+                    i = skip_quoted(&chars, i);
+                    sanitized.push_str(&self.placeholder);
+                }
+                '-' if self.strip_comments && next == Some('-') => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+                '/' if self.strip_comments && next == Some('*') => {
+                    i += 2;
+                    while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                        i += 1;
+                    }
+                    i += 2;
+                    // keep the tokens around the comment apart
+                    sanitized.push(' ');
+                }
+                '$' if next.is_some_and(|n| n.is_ascii_digit()) => {
+                    sanitized.push(c);
+                    i += 1;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        sanitized.push(chars[i]);
+                        i += 1;
+                    }
+                }
+                c if c.is_ascii_digit() => {
+                    i += 1;
+                    while i < chars.len()
+                        && (chars[i].is_ascii_alphanumeric()
+                            || chars[i] == '.'
+                            || ((chars[i] == '+' || chars[i] == '-')
+                                && (chars[i - 1] == 'e' || chars[i - 1] == 'E')))
+                    {
+                        i += 1;
+                    }
+                    sanitized.push_str(&self.placeholder);
+                }
+                c if is_identifier(c) => {
+                    // copied whole so that digits within identifiers (e.g. `table1`) are
+                    // not mistaken for literals
+                    while i < chars.len() && (is_identifier(chars[i]) || chars[i] == '$') {
+                        sanitized.push(chars[i]);
+                        i += 1;
+                    }
+                }
+                _ => {
+                    sanitized.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        if self.collapse_in_lists {
+            self.collapse_lists(&sanitized)
+        } else {
+            sanitized
+        }
+    }
+
+    /// `collapse_lists` replaces `IN (?, ?, ...)` with `IN (?)`
+    fn collapse_lists(&self, statement: &str) -> String {
+        // uppercasing ascii characters keeps the byte offsets identical
+        let upper = statement.to_ascii_uppercase();
+        let mut collapsed = String::with_capacity(statement.len());
+        let (mut copied, mut from) = (0, 0);
+
+        while let Some(found) = upper[from..].find("IN") {
+            let start = from + found;
+            let end = start + 2;
+            from = end;
+
+            let is_keyword = !upper[..start]
+                .chars()
+                .next_back()
+                .is_some_and(is_identifier)
+                && !upper[end..].chars().next().is_some_and(is_identifier);
+            let rest = statement[end..].trim_start();
+            if !is_keyword || !rest.starts_with('(') {
+                continue;
+            }
+            let open = statement.len() - rest.len();
+            let close = match statement[open..].find(')') {
+                Some(close) => open + close,
+                None => continue,
+            };
+            if statement[open + 1..close]
+                .split(',')
+                .all(|value| value.trim() == self.placeholder)
+            {
+                collapsed.push_str(&statement[copied..open]);
+                collapsed.push('(');
+                collapsed.push_str(&self.placeholder);
+                collapsed.push(')');
+                copied = close + 1;
+                from = close + 1;
+            }
+        }
+
+        collapsed.push_str(&statement[copied..]);
+        collapsed
+    }
+}
+
+/// Parts of field names that hint at a credential
+const SENSITIVE_FIELD_MARKERS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "api_key",
+    "apikey",
+    "authorization",
+    "cookie",
+    "session",
+    "credential",
+    "private_key",
+];
+
+/// `is_sensitive_field` returns whether the field `name` (case insensitive) looks like it
+/// holds a credential: a password, secret, token, API key, session or cookie
+pub fn is_sensitive_field(name: &str) -> bool {
+    let name = name.to_lowercase().replace(['-', '.'], "_");
+    SENSITIVE_FIELD_MARKERS
+        .iter()
+        .any(|marker| name.contains(marker))
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// `skip_quoted` returns the position right after the quoted text starting at `start`,
+/// handling both doubled quotes and backslash escapes
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == '\\' {
+            i += 2;
+        } else if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sql() {
+        assert_eq!(
+            sql("SELECT * FROM t1 WHERE name = 'O''Brien' AND age > 42.5e-1"),
+            "SELECT * FROM t1 WHERE name = ? AND age > ?"
+        );
+        assert_eq!(
+            sql("UPDATE users SET token = 'a\\'b' WHERE id = $1 -- user 1234"),
+            "UPDATE users SET token = ? WHERE id = $1 "
+        );
+        assert_eq!(
+            sql("SELECT/* admin@example.com */1 FROM dual WHERE x in (1,2, 'three')"),
+            "SELECT ? FROM dual WHERE x in (?)"
+        );
+        assert_eq!(
+            sql("SELECT id FROM login WHERE id IN (?, ?) AND pin IN (SELECT 1)"),
+            "SELECT id FROM login WHERE id IN (?) AND pin IN (SELECT ?)"
+        );
+    }
+
+    #[test]
+    fn test_sql_sanitizer_options() {
+        let sanitizer = SqlSanitizer {
+            placeholder: "<redacted>".to_string(),
+            collapse_in_lists: false,
+            strip_comments: false,
+            double_quoted_strings: true,
+        };
+        assert_eq!(
+            sanitizer.sanitize("SELECT * FROM t WHERE a IN (1, 2) AND b = \"x\" -- 3"),
+            "SELECT * FROM t WHERE a IN (<redacted>, <redacted>) AND b = <redacted> -- <redacted>"
+        );
+    }
+
+    #[test]
+    fn test_is_sensitive_field() {
+        assert!(is_sensitive_field("app.user_password"));
+        assert!(is_sensitive_field("Auth-Token"));
+        assert!(is_sensitive_field("stripe.api-key"));
+        assert!(is_sensitive_field("request.header.authorization"));
+        assert!(!is_sensitive_field("app.user_id"));
+        assert!(!is_sensitive_field("request.path"));
+    }
+}
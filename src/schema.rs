@@ -0,0 +1,202 @@
+/*! Checking the types of fields against an expected schema.
+
+Honeycomb types a column after the first values it sees: a status code sent as a string by
+one service and as a number by another ends up half unusable. A `FieldSchema` set as
+`Config::field_schema` lists the expected type of some fields, and in debug builds every
+event is checked against it before being transmitted. Mismatches are logged as
+diagnostics, or panic with `FieldSchema::panic_on_violation`, which fails the tests of the
+instrumentation. Release builds skip the check.
+
+```rust
+use beeline::schema::{FieldSchema, FieldType};
+use beeline::Config;
+
+let mut config = Config::default();
+config.field_schema = Some(
+    FieldSchema::standard()
+        .field("app.user_id", FieldType::Integer)
+        .field("app.plan", FieldType::String)
+        .panic_on_violation(),
+);
+```
+
+*/
+use std::collections::HashMap;
+use std::fmt;
+
+use libhoney::Value;
+
+use crate::keys;
+
+/// `FieldType` is the expected type of a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    /// `true` or `false`
+    Bool,
+    /// a number without a fractional part
+    Integer,
+    /// any number
+    Number,
+    /// a string
+    String,
+    /// a JSON array
+    Array,
+    /// a JSON object
+    Object,
+}
+
+impl FieldType {
+    /// `matches` returns whether `value` is of this type. Null values match every type.
+    pub fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (_, Value::Null) => true,
+            (FieldType::Bool, Value::Bool(_)) => true,
+            (FieldType::Integer, Value::Number(number)) => !number.is_f64(),
+            (FieldType::Number, Value::Number(_)) => true,
+            (FieldType::String, Value::String(_)) => true,
+            (FieldType::Array, Value::Array(_)) => true,
+            (FieldType::Object, Value::Object(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// `Violation` is a field whose value doesn't have the type the schema expects
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// name of the field
+    pub field: String,
+    /// type the schema expects
+    pub expected: FieldType,
+    /// value the field has
+    pub value: Value,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "field {} should be of type {:?}, got {}",
+            self.field, self.expected, self.value
+        )
+    }
+}
+
+/// `FieldSchema` is the expected type of fields, by name
+#[derive(Debug, Clone, Default)]
+pub struct FieldSchema {
+    fields: HashMap<String, FieldType>,
+    panic_on_violation: bool,
+}
+
+impl FieldSchema {
+    /// `new` returns an empty schema
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `standard` returns a schema with the types of the fields added by the beeline and
+    /// its integrations
+    pub fn standard() -> Self {
+        Self::new()
+            .field(keys::DURATION_MS, FieldType::Number)
+            .field(keys::NAME, FieldType::String)
+            .field(keys::TRACE_TRACE_ID, FieldType::String)
+            .field(keys::TRACE_SPAN_ID, FieldType::String)
+            .field(keys::TRACE_PARENT_ID, FieldType::String)
+            .field(keys::META_SPAN_TYPE, FieldType::String)
+            .field(keys::META_SAMPLE_RATE_WEIGHT, FieldType::Integer)
+            .field(keys::REQUEST_METHOD, FieldType::String)
+            .field(keys::REQUEST_PATH, FieldType::String)
+            .field(keys::REQUEST_ROUTE, FieldType::String)
+            .field(keys::RESPONSE_STATUS_CODE, FieldType::Integer)
+            .field(keys::RESPONSE_BODY_SIZE, FieldType::Integer)
+    }
+
+    /// `field` sets the expected type of the field `name`
+    pub fn field(mut self, name: &str, field_type: FieldType) -> Self {
+        self.fields.insert(name.to_string(), field_type);
+        self
+    }
+
+    /// `panic_on_violation` makes mismatches panic instead of being logged
+    pub fn panic_on_violation(mut self) -> Self {
+        self.panic_on_violation = true;
+        self
+    }
+
+    /// `violations` returns the fields of an event whose value doesn't have the expected
+    /// type, sorted by name
+    pub fn violations(&self, fields: &HashMap<String, Value>) -> Vec<Violation> {
+        let mut violations = fields
+            .iter()
+            .filter_map(|(field, value)| {
+                let expected = *self.fields.get(field)?;
+                if expected.matches(value) {
+                    return None;
+                }
+                Some(Violation {
+                    field: field.clone(),
+                    expected,
+                    value: value.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        violations.sort_by(|a, b| a.field.cmp(&b.field));
+        violations
+    }
+
+    #[cfg(debug_assertions)]
+    pub(crate) fn panics(&self) -> bool {
+        self.panic_on_violation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+    use serde_json::json;
+
+    #[test]
+    fn test_violations() {
+        let schema = FieldSchema::standard().field("app.tags", FieldType::Array);
+        let mut fields = HashMap::new();
+        fields.insert(keys::DURATION_MS.to_string(), json!(1.5));
+        fields.insert(keys::RESPONSE_STATUS_CODE.to_string(), json!("200"));
+        fields.insert(keys::RESPONSE_BODY_SIZE.to_string(), json!(12.5));
+        fields.insert("app.tags".to_string(), json!(null));
+        fields.insert("app.other".to_string(), json!(true));
+
+        let violations = schema.violations(&fields);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].field, keys::RESPONSE_BODY_SIZE);
+        assert_eq!(violations[1].field, keys::RESPONSE_STATUS_CODE);
+        assert_eq!(violations[1].expected, FieldType::Integer);
+        assert_eq!(
+            violations[1].to_string(),
+            "field response.status_code should be of type Integer, got \"200\""
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "field app.user_id should be of type Integer")]
+    fn test_panic_on_violation() {
+        let mut config = Config::default();
+        config.field_schema = Some(
+            FieldSchema::standard()
+                .field("app.user_id", FieldType::Integer)
+                .panic_on_violation(),
+        );
+        let mut client = new_client(config);
+        let trace = client.new_trace(None);
+        trace
+            .lock()
+            .get_root_span()
+            .lock()
+            .add_field("app.user_id", json!("42"));
+        trace.send(&mut client);
+    }
+}
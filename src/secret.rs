@@ -0,0 +1,37 @@
+/*! Wiping secrets from memory.
+
+Tenant API keys set with `Trace::set_api_key` are overwritten with zeros when the trace is
+dropped, so they don't linger in freed memory. Events handed over to the transmission carry
+their own copy of the key, which libhoney owns and doesn't wipe.
+
+*/
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+/// `zeroize` overwrites the bytes of `secret` with zeros and empties it. The writes are
+/// volatile so the compiler can't optimise them away as dead stores.
+pub(crate) fn zeroize(secret: &mut String) {
+    // zeros are valid UTF-8, the string stays a valid `String` throughout
+    let bytes = unsafe { secret.as_bytes_mut() };
+    for byte in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+    secret.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zeroize() {
+        let mut secret = String::from("tenant-api-key");
+        let capacity = secret.capacity();
+        zeroize(&mut secret);
+        assert!(secret.is_empty());
+        assert_eq!(secret.capacity(), capacity);
+        let bytes = unsafe { std::slice::from_raw_parts(secret.as_ptr(), capacity) };
+        assert!(bytes.iter().all(|byte| *byte == 0));
+    }
+}
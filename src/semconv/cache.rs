@@ -0,0 +1,153 @@
+/*! Cache lookup spans, with the hit ratio of the trace rolled up to its root span.
+
+Every lookup is a child span named "cache.hit" or "cache.miss". The hits and misses are
+counted with rollup fields, so the root span of the trace gets `rollup.cache.hits`,
+`rollup.cache.misses` and `rollup.cache.hit_ratio`:
+
+```rust,no_run
+use std::collections::HashMap;
+
+use beeline::semconv::cache;
+use beeline::{init, Config};
+
+let mut client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+
+let users: HashMap<u64, String> = HashMap::new();
+let user = cache::lookup(&mut client, &root_span, "users", || users.get(&42).cloned());
+```
+
+Lookups into asynchronous caches (e.g. moka's future cache, redis) are timed by creating
+the span with `cache_span` before the lookup, and recording its outcome with
+`record_lookup` before sending the span.
+
+*/
+use libhoney::Sender;
+use serde_json::json;
+
+use crate::keys;
+use crate::trace::SafeSpan;
+use crate::Client;
+
+/// type of the spans created by `cache_span`, sent as `meta.type`
+pub const SPAN_TYPE: &str = "cache";
+/// name of the cache being looked up
+pub const NAME: &str = "cache.name";
+/// whether the lookup found the value
+pub const HIT: &str = "cache.hit";
+/// rollup field counting the lookups that found the value
+pub const HITS: &str = "cache.hits";
+/// rollup field counting the lookups that didn't find the value
+pub const MISSES: &str = "cache.misses";
+/// share of the lookups of the trace that found the value, on its root span
+pub const HIT_RATIO: &str = "rollup.cache.hit_ratio";
+
+/// `cache_span` creates a child of `parent` for a lookup into the cache `name`
+pub fn cache_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    name: &str,
+) -> Option<SafeSpan> {
+    super::child_span(
+        client,
+        parent,
+        SPAN_TYPE,
+        "cache.lookup",
+        vec![(NAME, json!(name))],
+    )
+}
+
+/// `record_lookup` names `span` after the outcome of the lookup, counts it in the rollup
+/// fields of the trace and updates the hit ratio of its root span
+pub fn record_lookup(span: &SafeSpan, hit: bool) {
+    let trace = {
+        let mut span = span.lock();
+        let name = if hit { "cache.hit" } else { "cache.miss" };
+        span.add_field(keys::NAME, json!(name));
+        span.add_field(HIT, json!(hit));
+        span.add_rollup_field(if hit { HITS } else { MISSES }, 1f64);
+        span.trace()
+    };
+    let (root_span, hits, misses) = match trace {
+        Some(trace) => {
+            let mut trace = trace.lock();
+            (
+                trace.get_root_span(),
+                trace.rollup_field(HITS),
+                trace.rollup_field(MISSES),
+            )
+        }
+        None => return,
+    };
+    // the lock of the span was released first, as sending the root span locks its children
+    root_span
+        .lock()
+        .add_field(HIT_RATIO, json!(hits / (hits + misses)));
+}
+
+/// `lookup` runs `get`, a lookup into the cache `name`, in a child span of `parent`
+pub fn lookup<T: Sender, V>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    name: &str,
+    get: impl FnOnce() -> Option<V>,
+) -> Option<V> {
+    let span = cache_span(client, parent, name);
+    let value = get();
+    if let Some(span) = span {
+        record_lookup(&span, value.is_some());
+        span.lock().send(client);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    #[test]
+    fn test_lookup() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        assert_eq!(
+            lookup(&mut client, &root_span, "users", || Some(1)),
+            Some(1)
+        );
+        assert_eq!(
+            lookup(&mut client, &root_span, "users", || None::<u8>),
+            None
+        );
+        let span = cache_span(&mut client, &root_span, "sessions").unwrap();
+        record_lookup(&span, true);
+        span.lock().send(&mut client);
+        assert_eq!(
+            lookup(&mut client, &root_span, "users", || Some(4)),
+            Some(4)
+        );
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 5);
+        let hit = events[0].fields();
+        assert_eq!(hit["meta.type"], json!("cache"));
+        assert_eq!(hit["name"], json!("cache.hit"));
+        assert_eq!(hit[NAME], json!("users"));
+        assert_eq!(hit[HIT], json!(true));
+        assert_eq!(hit[HITS], json!(1f64));
+        let miss = events[1].fields();
+        assert_eq!(miss["name"], json!("cache.miss"));
+        assert_eq!(miss[HIT], json!(false));
+        assert_eq!(miss[MISSES], json!(1f64));
+        assert_eq!(events[2].fields()[NAME], json!("sessions"));
+        let root = events[4].fields();
+        assert_eq!(root["rollup.cache.hits"], json!(3f64));
+        assert_eq!(root["rollup.cache.misses"], json!(1f64));
+        assert_eq!(root[HIT_RATIO], json!(0.75));
+    }
+}
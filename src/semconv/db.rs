@@ -0,0 +1,83 @@
+/*! Database spans.
+
+```rust,no_run
+use beeline::semconv::db;
+use beeline::{init, Config};
+
+let mut client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+
+if let Some(span) = db::db_span(&mut client, &root_span, "postgresql", "SELECT * FROM users") {
+    // run the query
+    db::record_rows(&span, 42);
+    span.lock().send(&mut client);
+}
+```
+
+*/
+use libhoney::Sender;
+use serde_json::json;
+
+use crate::trace::SafeSpan;
+use crate::Client;
+
+/// type of the spans created by `db_span`, sent as `meta.type`
+pub const SPAN_TYPE: &str = "db";
+/// database system (e.g. "postgresql", "mysql", "redis")
+pub const SYSTEM: &str = "db.system";
+/// statement (or command) being executed
+pub const STATEMENT: &str = "db.statement";
+/// number of rows returned or affected
+pub const ROWS: &str = "db.rows";
+
+/// `db_span` creates a child of `parent` for a `statement` run against a database of
+/// type `system`. The span is named after the system.
+pub fn db_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    system: &str,
+    statement: &str,
+) -> Option<SafeSpan> {
+    super::child_span(
+        client,
+        parent,
+        SPAN_TYPE,
+        system,
+        vec![(SYSTEM, json!(system)), (STATEMENT, json!(statement))],
+    )
+}
+
+/// `record_rows` adds the number of rows returned or affected by the statement to `span`
+pub fn record_rows(span: &SafeSpan, rows: u64) {
+    span.lock().add_field(ROWS, json!(rows));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    #[test]
+    fn test_db_span() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let span = db_span(&mut client, &root_span, "postgresql", "SELECT 1").unwrap();
+        record_rows(&span, 1);
+        span.lock().send(&mut client);
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let fields = events[0].fields();
+        assert_eq!(fields["meta.type"], json!("db"));
+        assert_eq!(fields["name"], json!("postgresql"));
+        assert_eq!(fields[SYSTEM], json!("postgresql"));
+        assert_eq!(fields[STATEMENT], json!("SELECT 1"));
+        assert_eq!(fields[ROWS], json!(1));
+    }
+}
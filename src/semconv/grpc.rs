@@ -0,0 +1,176 @@
+/*! gRPC status of responses.
+
+gRPC calls nearly always get an HTTP status of 200: the outcome of the call is in the
+`grpc-status` and `grpc-message` trailers or, for responses without a body (trailers-only
+responses), in the headers. `record_status` reads them from the `http` header maps gRPC
+stacks such as tonic hand out, and adds them to the span of the call:
+
+```rust
+# #[cfg(feature = "testutil")] {
+use beeline::semconv::grpc;
+use beeline::test::init;
+use beeline::Config;
+use http::HeaderMap;
+
+let client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+
+let headers = HeaderMap::new();
+let mut trailers = HeaderMap::new();
+trailers.insert("grpc-status", "5".parse().unwrap());
+trailers.insert("grpc-message", "user%2042%20not%20found".parse().unwrap());
+grpc::record_status(&root_span, &headers, Some(&trailers));
+# }
+```
+
+*/
+use http::HeaderMap;
+use serde_json::json;
+
+use crate::keys;
+use crate::trace::SafeSpan;
+
+/// numeric gRPC status code of the response
+pub const STATUS: &str = "response.grpc_status";
+/// name of the gRPC status code of the response, e.g. "NOT_FOUND"
+pub const STATUS_NAME: &str = "response.grpc_status_name";
+
+/// `status_name` returns the name of the gRPC status `code`
+pub fn status_name(code: u32) -> &'static str {
+    match code {
+        0 => "OK",
+        1 => "CANCELLED",
+        2 => "UNKNOWN",
+        3 => "INVALID_ARGUMENT",
+        4 => "DEADLINE_EXCEEDED",
+        5 => "NOT_FOUND",
+        6 => "ALREADY_EXISTS",
+        7 => "PERMISSION_DENIED",
+        8 => "RESOURCE_EXHAUSTED",
+        9 => "FAILED_PRECONDITION",
+        10 => "ABORTED",
+        11 => "OUT_OF_RANGE",
+        12 => "UNIMPLEMENTED",
+        13 => "INTERNAL",
+        14 => "UNAVAILABLE",
+        15 => "DATA_LOSS",
+        16 => "UNAUTHENTICATED",
+        _ => "UNKNOWN",
+    }
+}
+
+/// `record_status` adds the gRPC status of a response to `span`, read from `trailers` or,
+/// when they don't have it, from `headers`. Calls that didn't succeed also get `error`,
+/// set to the `grpc-message` or, without one, to the name of the status. Nothing is added
+/// when the response carries no status.
+pub fn record_status(span: &SafeSpan, headers: &HeaderMap, trailers: Option<&HeaderMap>) {
+    let metadata = match trailers.filter(|trailers| trailers.contains_key("grpc-status")) {
+        Some(trailers) => trailers,
+        None => headers,
+    };
+    let code = match metadata
+        .get("grpc-status")
+        .and_then(|status| status.to_str().ok())
+        .and_then(|status| status.trim().parse::<u32>().ok())
+    {
+        Some(code) => code,
+        None => return,
+    };
+
+    let mut span = span.lock();
+    span.add_field(STATUS, json!(code));
+    span.add_field(STATUS_NAME, json!(status_name(code)));
+    if code != 0 {
+        let message = metadata
+            .get("grpc-message")
+            .and_then(|message| message.to_str().ok())
+            .map(percent_decode)
+            .filter(|message| !message.is_empty());
+        let error = message.unwrap_or_else(|| status_name(code).to_string());
+        span.add_field(keys::ERROR, json!(error));
+    }
+}
+
+/// `percent_decode` decodes a `grpc-message`, which is percent-encoded UTF-8
+fn percent_decode(message: &str) -> String {
+    let bytes = message.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    fn header_map(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+        entries
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_record_status() {
+        let mut client = new_client(Config::default());
+        let cases = [
+            // status in the trailers
+            (
+                header_map(&[("content-type", "application/grpc")]),
+                Some(header_map(&[
+                    ("grpc-status", "5"),
+                    ("grpc-message", "user%2042%20not%20found"),
+                ])),
+            ),
+            // trailers-only response
+            (header_map(&[("grpc-status", "14")]), Some(HeaderMap::new())),
+            (header_map(&[]), Some(header_map(&[("grpc-status", "0")]))),
+            (header_map(&[]), None),
+        ];
+        for (headers, trailers) in &cases {
+            let trace = client.new_trace(None);
+            let root_span = trace.lock().get_root_span();
+            record_status(&root_span, headers, trailers.as_ref());
+            trace.send(&mut client);
+        }
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].fields()[STATUS], json!(5));
+        assert_eq!(events[0].fields()[STATUS_NAME], json!("NOT_FOUND"));
+        assert_eq!(events[0].fields()[keys::ERROR], json!("user 42 not found"));
+        assert_eq!(events[1].fields()[STATUS], json!(14));
+        assert_eq!(events[1].fields()[keys::ERROR], json!("UNAVAILABLE"));
+        assert_eq!(events[2].fields()[STATUS], json!(0));
+        assert!(!events[2].fields().contains_key(keys::ERROR));
+        assert!(!events[3].fields().contains_key(STATUS));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("caf%C3%A9%20closed"), "café closed");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
@@ -0,0 +1,258 @@
+/*! Outgoing HTTP request spans.
+
+```rust,no_run
+use beeline::semconv::http_client;
+use beeline::{init, Config};
+
+let mut client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+
+if let Some(span) = http_client::http_client_span(
+    &mut client,
+    &root_span,
+    "GET",
+    "https://api.example.com/users/1",
+    Some("users-api"),
+) {
+    // send the request
+    http_client::record_response(&span, 200);
+    span.lock().send(&mut client);
+}
+```
+
+The HTTP client integrations name the service they call in `peer.service` when
+`Config::peer_services` knows the host of the request:
+
+```rust
+use beeline::semconv::http_client::PeerServices;
+use beeline::Config;
+
+let mut config = Config::default();
+config.peer_services = Some(
+    PeerServices::new()
+        .host("users.internal", "users")
+        .resolver(|host| host.strip_suffix(".svc.cluster.local").map(String::from)),
+);
+```
+
+A call retried by the client is a span per attempt, children of the span of the call, made
+with `attempt_span`. Each attempt records its number as `retry.attempt` and, from the
+second one, why the previous one failed as `retry.reason`, so retries don't look like a
+single slow call.
+
+*/
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use libhoney::Sender;
+use serde_json::json;
+
+use crate::trace::SafeSpan;
+use crate::Client;
+
+/// type of the spans created by `http_client_span`, sent as `meta.type`
+pub const SPAN_TYPE: &str = "http_client";
+/// full url of the request
+pub const URL: &str = "request.url";
+/// method of the request (e.g. "GET")
+pub const METHOD: &str = "request.method";
+/// status code of the response
+pub const STATUS_CODE: &str = "response.status_code";
+/// logical name of the service being called
+pub const PEER_SERVICE: &str = "peer.service";
+/// whether the request went over a connection kept from an earlier request
+pub const CONNECTION_REUSED: &str = "connection.reused";
+/// time spent resolving the name of the host, in milliseconds
+pub const DNS_MS: &str = "connection.dns_ms";
+/// time spent establishing the connection once the host is resolved, in milliseconds
+pub const CONNECT_MS: &str = "connection.connect_ms";
+/// time spent on the TLS handshake once connected, in milliseconds
+pub const TLS_MS: &str = "connection.tls_ms";
+/// time from sending the request, over an established connection, until the response
+/// headers arrive, in milliseconds
+pub const FIRST_BYTE_MS: &str = "response.first_byte_ms";
+/// type of the spans created by `attempt_span`, sent as `meta.type`
+pub const ATTEMPT_SPAN_TYPE: &str = "http_client_attempt";
+/// number of the attempt, starting at 1
+pub const RETRY_ATTEMPT: &str = "retry.attempt";
+/// why the previous attempt failed, e.g. "status 503" or the error it failed with
+pub const RETRY_REASON: &str = "retry.reason";
+
+type ResolverFn = dyn Fn(&str) -> Option<String> + 'static + Send + Sync;
+
+/// `PeerServices` names the services behind the hosts outgoing requests are sent to
+#[derive(Clone, Default)]
+pub struct PeerServices {
+    // shared, for the client to look hosts up without holding its lock
+    hosts: Arc<HashMap<String, String>>,
+    resolver: Option<Arc<ResolverFn>>,
+}
+
+impl PeerServices {
+    /// `new` returns a mapping without any host
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `host` names `service` the service behind `host`, compared case-insensitively
+    pub fn host(mut self, host: &str, service: &str) -> Self {
+        Arc::make_mut(&mut self.hosts).insert(host.to_ascii_lowercase(), service.to_string());
+        self
+    }
+
+    /// `resolver` sets the function naming the service behind the hosts not given to
+    /// `host`, if it can
+    pub fn resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + 'static + Send + Sync,
+    {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// `service_for` returns the name of the service behind `host`, if known
+    pub fn service_for(&self, host: &str) -> Option<String> {
+        let host = host.to_ascii_lowercase();
+        if let Some(service) = self.hosts.get(&host) {
+            return Some(service.clone());
+        }
+        self.resolver.as_ref().and_then(|resolver| resolver(&host))
+    }
+}
+
+impl fmt::Debug for PeerServices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerServices")
+            .field("hosts", &self.hosts)
+            .field("resolver", &self.resolver.as_ref().map(|_| "Fn()"))
+            .finish()
+    }
+}
+
+/// `http_client_span` creates a child of `parent` for an outgoing `method` request to
+/// `url`, optionally tagged with the name of the service being called. The span is named
+/// after the method.
+pub fn http_client_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    method: &str,
+    url: &str,
+    peer_service: Option<&str>,
+) -> Option<SafeSpan> {
+    let mut fields = vec![(METHOD, json!(method)), (URL, json!(url))];
+    if let Some(peer_service) = peer_service {
+        fields.push((PEER_SERVICE, json!(peer_service)));
+    }
+    super::child_span(client, parent, SPAN_TYPE, method, fields)
+}
+
+/// `attempt_span` creates a child of `call`, a span made with `http_client_span`, for its
+/// `attempt`th attempt, starting at 1. `reason` is why the previous attempt failed.
+pub fn attempt_span<T: Sender>(
+    client: &mut Client<T>,
+    call: &SafeSpan,
+    attempt: u32,
+    reason: Option<&str>,
+) -> Option<SafeSpan> {
+    let mut fields = vec![(RETRY_ATTEMPT, json!(attempt))];
+    if let Some(reason) = reason {
+        fields.push((RETRY_REASON, json!(reason)));
+    }
+    super::child_span(
+        client,
+        call,
+        ATTEMPT_SPAN_TYPE,
+        &format!("attempt {}", attempt),
+        fields,
+    )
+}
+
+/// `record_response` adds the status code of the response to `span`
+pub fn record_response(span: &SafeSpan, status_code: u16) {
+    span.lock().add_field(STATUS_CODE, json!(status_code));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::Config;
+
+    #[test]
+    fn test_http_client_span() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let url = "http://localhost/users";
+        let span = http_client_span(&mut client, &root_span, "POST", url, Some("users")).unwrap();
+        record_response(&span, 201);
+        span.lock().send(&mut client);
+
+        let span = http_client_span(&mut client, &root_span, "GET", url, None).unwrap();
+        span.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let fields = events[0].fields();
+        assert_eq!(fields["meta.type"], json!("http_client"));
+        assert_eq!(fields["name"], json!("POST"));
+        assert_eq!(fields[METHOD], json!("POST"));
+        assert_eq!(fields[URL], json!(url));
+        assert_eq!(fields[PEER_SERVICE], json!("users"));
+        assert_eq!(fields[STATUS_CODE], json!(201));
+        assert!(!events[1].fields().contains_key(PEER_SERVICE));
+    }
+
+    #[test]
+    fn test_attempt_span() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let call = http_client_span(&mut client, &root_span, "GET", "http://a/", None).unwrap();
+        let first = attempt_span(&mut client, &call, 1, None).unwrap();
+        record_response(&first, 503);
+        first.lock().send(&mut client);
+        let second = attempt_span(&mut client, &call, 2, Some("status 503")).unwrap();
+        record_response(&second, 200);
+        second.lock().send(&mut client);
+        record_response(&call, 200);
+        call.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        let (first, second) = (events[0].fields(), events[1].fields());
+        assert_eq!(first["meta.type"], json!(ATTEMPT_SPAN_TYPE));
+        assert_eq!(first[RETRY_ATTEMPT], json!(1));
+        assert!(!first.contains_key(RETRY_REASON));
+        assert_eq!(second["name"], json!("attempt 2"));
+        assert_eq!(second[RETRY_REASON], json!("status 503"));
+        assert_eq!(
+            second["trace.parent_id"],
+            events[2].fields()["trace.span_id"]
+        );
+    }
+
+    #[test]
+    fn test_peer_services() {
+        let services = PeerServices::new()
+            .host("Users.internal", "users")
+            .resolver(|host| host.strip_suffix(".svc").map(String::from));
+        assert_eq!(services.service_for("users.INTERNAL"), Some("users".into()));
+        assert_eq!(services.service_for("billing.svc"), Some("billing".into()));
+        assert_eq!(services.service_for("api.stripe.com"), None);
+
+        let client = new_client(Config {
+            peer_services: Some(services),
+            ..Config::default()
+        });
+        assert_eq!(client.peer_service("users.internal"), Some("users".into()));
+        assert_eq!(
+            new_client(Config::default()).peer_service("users.internal"),
+            None
+        );
+    }
+}
@@ -0,0 +1,118 @@
+/*! Message publishing and consumption spans.
+
+```rust,no_run
+use beeline::semconv::messaging;
+use beeline::{init, Config};
+
+let mut client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+
+if let Some(span) = messaging::publish_span(&mut client, &root_span, "kafka", "orders", None) {
+    // publish the message
+    messaging::record_message_id(&span, "orders-0-1234");
+    span.lock().send(&mut client);
+}
+```
+
+*/
+use libhoney::Sender;
+use serde_json::json;
+
+use crate::trace::SafeSpan;
+use crate::Client;
+
+/// type of the spans created by this module, sent as `meta.type`
+pub const SPAN_TYPE: &str = "messaging";
+/// messaging system (e.g. "kafka", "rabbitmq", "sqs")
+pub const SYSTEM: &str = "messaging.system";
+/// queue or topic the message is published to or consumed from
+pub const DESTINATION: &str = "messaging.destination";
+/// id of the message
+pub const MESSAGE_ID: &str = "messaging.message_id";
+/// either "publish" or "consume"
+pub const OPERATION: &str = "messaging.operation";
+
+/// `publish_span` creates a child of `parent` for a message published to `destination`
+pub fn publish_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    system: &str,
+    destination: &str,
+    message_id: Option<&str>,
+) -> Option<SafeSpan> {
+    messaging_span(client, parent, "publish", system, destination, message_id)
+}
+
+/// `consume_span` creates a child of `parent` for a message consumed from `destination`
+pub fn consume_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    system: &str,
+    destination: &str,
+    message_id: Option<&str>,
+) -> Option<SafeSpan> {
+    messaging_span(client, parent, "consume", system, destination, message_id)
+}
+
+/// `record_message_id` adds the id of the message to `span`, for systems that only assign
+/// it once the message has been published
+pub fn record_message_id(span: &SafeSpan, message_id: &str) {
+    span.lock().add_field(MESSAGE_ID, json!(message_id));
+}
+
+/// `messaging_span` creates a span named after the destination and the operation (e.g.
+/// "orders publish")
+fn messaging_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    operation: &str,
+    system: &str,
+    destination: &str,
+    message_id: Option<&str>,
+) -> Option<SafeSpan> {
+    let mut fields = vec![
+        (SYSTEM, json!(system)),
+        (DESTINATION, json!(destination)),
+        (OPERATION, json!(operation)),
+    ];
+    if let Some(message_id) = message_id {
+        fields.push((MESSAGE_ID, json!(message_id)));
+    }
+    let name = format!("{} {}", destination, operation);
+    super::child_span(client, parent, SPAN_TYPE, &name, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::Config;
+
+    #[test]
+    fn test_messaging_spans() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let span = publish_span(&mut client, &root_span, "kafka", "orders", None).unwrap();
+        record_message_id(&span, "42");
+        span.lock().send(&mut client);
+        let span = consume_span(&mut client, &root_span, "sqs", "jobs", Some("abc")).unwrap();
+        span.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let published = events[0].fields();
+        assert_eq!(published["meta.type"], json!("messaging"));
+        assert_eq!(published["name"], json!("orders publish"));
+        assert_eq!(published[SYSTEM], json!("kafka"));
+        assert_eq!(published[DESTINATION], json!("orders"));
+        assert_eq!(published[OPERATION], json!("publish"));
+        assert_eq!(published[MESSAGE_ID], json!("42"));
+        let consumed = events[1].fields();
+        assert_eq!(consumed["name"], json!("jobs consume"));
+        assert_eq!(consumed[OPERATION], json!("consume"));
+        assert_eq!(consumed[MESSAGE_ID], json!("abc"));
+    }
+}
@@ -0,0 +1,41 @@
+/*! Semantic conventions for common kinds of spans.
+
+The helpers in this module create child spans with standardized field names, so that spans
+for the same kind of operation end up with the same schema no matter which integration (or
+team) instrumented them.
+
+*/
+use libhoney::{Sender, Value};
+use serde_json::json;
+
+use crate::keys;
+use crate::trace::SafeSpan;
+use crate::Client;
+
+pub mod cache;
+pub mod db;
+pub mod grpc;
+pub mod http_client;
+pub mod messaging;
+pub mod search;
+
+/// `child_span` creates a child of `parent` of type `meta_type` named `name`, with `fields`
+/// added to it
+pub(crate) fn child_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    meta_type: &str,
+    name: &str,
+    fields: Vec<(&str, Value)>,
+) -> Option<SafeSpan> {
+    let span = parent.lock().create_child(client)?;
+    {
+        let mut guard = span.lock();
+        guard.add_field(keys::META_TYPE, json!(meta_type));
+        guard.add_field(keys::NAME, json!(name));
+        for (key, value) in fields {
+            guard.add_field(key, value);
+        }
+    }
+    Some(span)
+}
@@ -0,0 +1,155 @@
+/*! Search engine request spans, for Elasticsearch and OpenSearch.
+
+The body of the response tells how long the cluster spent on the request (`took`), which
+sets the time spent searching apart from the time spent on the network and in the client:
+
+```rust,no_run
+use beeline::semconv::search;
+use beeline::{init, Config};
+use serde_json::json;
+
+let mut client = init(Config::default());
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+
+let span = search::search_span(&mut client, &root_span, "elasticsearch", "users", "search");
+if let Some(span) = span {
+    // send the request, e.g. with elasticsearch-rs:
+    // let response = es.search(SearchParts::Index(&["users"])).body(query).send().await?;
+    // let status = response.status_code().as_u16();
+    // let body = response.json::<serde_json::Value>().await?;
+    let body = json!({ "took": 12, "hits": { "total": { "value": 3 } } });
+    search::record_response(&span, 200, &body);
+    span.lock().send(&mut client);
+}
+```
+
+*/
+use libhoney::{Sender, Value};
+use serde_json::json;
+
+use crate::keys;
+use crate::trace::SafeSpan;
+use crate::Client;
+
+use super::http_client::STATUS_CODE;
+
+/// type of the spans created by `search_span`, sent as `meta.type`
+pub const SPAN_TYPE: &str = "search";
+/// search engine (e.g. "elasticsearch", "opensearch")
+pub const SYSTEM: &str = "search.system";
+/// index, or comma separated indices, the request targets
+pub const INDEX: &str = "search.index";
+/// operation requested (e.g. "search", "index", "bulk")
+pub const OPERATION: &str = "search.operation";
+/// time the cluster spent on the request, as reported in the response
+pub const TOOK_MS: &str = "search.took_ms";
+/// total number of documents matching a search
+pub const HITS: &str = "search.hits";
+
+/// `search_span` creates a child of `parent` for an `operation` on `index` sent to a
+/// search engine of type `system`. The span is named after the index and the operation
+/// (e.g. "users search").
+pub fn search_span<T: Sender>(
+    client: &mut Client<T>,
+    parent: &SafeSpan,
+    system: &str,
+    index: &str,
+    operation: &str,
+) -> Option<SafeSpan> {
+    let name = format!("{} {}", index, operation);
+    super::child_span(
+        client,
+        parent,
+        SPAN_TYPE,
+        &name,
+        vec![
+            (SYSTEM, json!(system)),
+            (INDEX, json!(index)),
+            (OPERATION, json!(operation)),
+        ],
+    )
+}
+
+/// `record_response` adds the status code of the response to `span`, along with what its
+/// JSON `body` tells: the time the cluster took, the number of hits of a search, and the
+/// reason of the error of a failed request
+pub fn record_response(span: &SafeSpan, status_code: u16, body: &Value) {
+    let mut span = span.lock();
+    span.add_field(STATUS_CODE, json!(status_code));
+    if let Some(took) = body.get("took").and_then(Value::as_u64) {
+        span.add_field(TOOK_MS, json!(took));
+    }
+    // the total is an object since Elasticsearch 7, and a number before
+    let total = body.pointer("/hits/total");
+    if let Some(hits) = total.and_then(|total| total.get("value").unwrap_or(total).as_u64()) {
+        span.add_field(HITS, json!(hits));
+    }
+    if let Some(error) = body.get("error") {
+        let reason = error
+            .get("reason")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or_else(|| error.to_string());
+        span.add_field(keys::ERROR, json!(reason));
+    } else if body.get("errors") == Some(&json!(true)) {
+        span.add_field(
+            keys::ERROR,
+            json!("some operations of the bulk request failed"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::Config;
+
+    #[test]
+    fn test_search_span() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let body = json!({ "took": 12, "hits": { "total": { "value": 3, "relation": "eq" } } });
+        let span = search_span(&mut client, &root_span, "elasticsearch", "users", "search");
+        let span = span.unwrap();
+        record_response(&span, 200, &body);
+        span.lock().send(&mut client);
+        let body = json!({ "took": 4, "hits": { "total": 7 } });
+        let span = search_span(&mut client, &root_span, "opensearch", "logs", "search").unwrap();
+        record_response(&span, 200, &body);
+        span.lock().send(&mut client);
+        let body = json!({
+            "error": { "type": "index_not_found_exception", "reason": "no such index [orders]" },
+            "status": 404,
+        });
+        let span = search_span(&mut client, &root_span, "opensearch", "orders", "get").unwrap();
+        record_response(&span, 404, &body);
+        span.lock().send(&mut client);
+        let body = json!({ "took": 30, "errors": true, "items": [] });
+        let span = search_span(&mut client, &root_span, "opensearch", "orders", "bulk").unwrap();
+        record_response(&span, 200, &body);
+        span.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        let searched = events[0].fields();
+        assert_eq!(searched["meta.type"], json!("search"));
+        assert_eq!(searched["name"], json!("users search"));
+        assert_eq!(searched[SYSTEM], json!("elasticsearch"));
+        assert_eq!(searched[INDEX], json!("users"));
+        assert_eq!(searched[OPERATION], json!("search"));
+        assert_eq!(searched[STATUS_CODE], json!(200));
+        assert_eq!(searched[TOOK_MS], json!(12));
+        assert_eq!(searched[HITS], json!(3));
+        assert!(!searched.contains_key(keys::ERROR));
+        assert_eq!(events[1].fields()[HITS], json!(7));
+        let failed = events[2].fields();
+        assert_eq!(failed[STATUS_CODE], json!(404));
+        assert_eq!(failed[keys::ERROR], json!("no such index [orders]"));
+        assert!(!failed.contains_key(TOOK_MS));
+        assert!(events[3].fields().contains_key(keys::ERROR));
+    }
+}
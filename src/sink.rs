@@ -0,0 +1,184 @@
+/*! Sending events to your own systems.
+
+A client sends its events through a libhoney `Sender`, which batches them for Honeycomb.
+Sending them elsewhere (a file, ClickHouse, S3) doesn't need any of that: an `EventSink`
+only gets every event, once its fields are final, and `init` starts a client whose events
+go to it.
+
+```rust
+use std::io::Write;
+use std::sync::Mutex;
+
+use beeline::sink::{self, EventSink, SerializedEvent};
+use beeline::Config;
+
+struct JsonLines<W: Write + Send>(Mutex<W>);
+
+impl<W: Write + Send + 'static> EventSink for JsonLines<W> {
+    fn emit(&self, event: SerializedEvent) {
+        let _ = writeln!(self.0.lock().unwrap(), "{}", event.to_json());
+    }
+
+    fn flush(&self) {
+        let _ = self.0.lock().unwrap().flush();
+    }
+}
+
+let client = sink::init(Config::default(), JsonLines(Mutex::new(std::io::stdout())));
+let trace = client.new_trace(None);
+```
+
+See `examples/json_lines.rs` for a sink writing the events to a file.
+
+Sinks are not told whether the events they emit were delivered, so events sent through
+them are never retried, even with `Config::retry_policy` set.
+
+*/
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crossbeam_channel::Receiver;
+use libhoney::response::Response;
+use libhoney::{Event, Sender, Value};
+
+use crate::{new_client, Client, Config};
+
+/// `SerializedEvent` is an event as sent, with all the fields of the span, the trace and
+/// the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerializedEvent {
+    /// the fields of the event, by name
+    pub fields: HashMap<String, Value>,
+}
+
+impl SerializedEvent {
+    /// `get` returns the value of the field `key`, if set
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.fields.get(key)
+    }
+
+    /// `to_json` returns the fields of the event as a JSON object
+    pub fn to_json(&self) -> String {
+        Value::Object(self.fields.clone().into_iter().collect()).to_string()
+    }
+}
+
+/// `EventSink` receives the events of a client started with `init`
+pub trait EventSink: Send + Sync + 'static {
+    /// `emit` is called with every event sent by the client, on the thread sending it
+    fn emit(&self, event: SerializedEvent);
+
+    /// `flush` is called when the client is flushed or closed, to write out any events
+    /// the sink buffers
+    fn flush(&self) {}
+}
+
+/// `SinkSender` is the `Sender` handing the events of a client to an `EventSink`
+#[derive(Debug)]
+pub struct SinkSender<S> {
+    sink: Arc<S>,
+    // no response is ever sent, but the channel must stay open for the readers of the
+    // responses not to give up on it
+    responses: (crossbeam_channel::Sender<Response>, Receiver<Response>),
+}
+
+impl<S> SinkSender<S> {
+    /// `new` returns a sender handing events to `sink`
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            responses: crossbeam_channel::unbounded(),
+        }
+    }
+
+    /// `sink` returns the sink the events are handed to
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+}
+
+impl<S> Clone for SinkSender<S> {
+    fn clone(&self) -> Self {
+        Self {
+            sink: self.sink.clone(),
+            responses: self.responses.clone(),
+        }
+    }
+}
+
+impl<S: EventSink> Sender for SinkSender<S> {
+    fn send(&mut self, ev: Event) {
+        self.sink.emit(SerializedEvent {
+            fields: ev.fields(),
+        });
+    }
+
+    fn start(&mut self) {}
+
+    fn stop(&mut self) -> libhoney::Result<()> {
+        self.sink.flush();
+        Ok(())
+    }
+
+    fn responses(&self) -> Receiver<Response> {
+        self.responses.1.clone()
+    }
+}
+
+/// `init` returns a client handing its events to `sink` instead of sending them to
+/// Honeycomb. The transmission settings of `config` are ignored.
+pub fn init<S: EventSink>(config: Config, sink: S) -> Client<SinkSender<S>> {
+    let options = config.client_config.options.clone();
+    let client = libhoney::Client::new(options, SinkSender::new(sink));
+    new_client(config, client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys;
+    use crate::trace::TraceSender;
+    use parking_lot::Mutex;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct Collector {
+        events: Mutex<Vec<SerializedEvent>>,
+        flushes: Mutex<usize>,
+    }
+
+    impl EventSink for Collector {
+        fn emit(&self, event: SerializedEvent) {
+            self.events.lock().push(event);
+        }
+
+        fn flush(&self) {
+            *self.flushes.lock() += 1;
+        }
+    }
+
+    #[test]
+    fn test_init() {
+        let mut client = init(Config::default(), Collector::default());
+        let trace = client.new_trace(None);
+        trace.lock().add_field("tenant", json!("acme"));
+        let root_span = trace.lock().get_root_span();
+        let child = root_span.lock().create_child(&mut client).unwrap();
+        child.lock().add_field("name", json!("child"));
+        trace.send(&mut client);
+        client.flush().unwrap();
+
+        let inner = client.0.read();
+        let collector = inner.client.transmission.sink();
+        let events = collector.events.lock();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].get("name"), Some(&json!("child")));
+        assert!(events
+            .iter()
+            .all(|event| event.get("tenant") == Some(&json!("acme"))));
+        assert_eq!(events[1].get(keys::META_SPAN_TYPE), Some(&json!("root")));
+        let serialized: Value = serde_json::from_str(&events[0].to_json()).unwrap();
+        assert_eq!(serialized["name"], json!("child"));
+        assert!(*collector.flushes.lock() > 0);
+    }
+}
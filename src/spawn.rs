@@ -0,0 +1,166 @@
+/*! Trace continuation across `tokio::spawn`.
+
+A spawned task runs detached from the code that spawned it, so any span it creates has to
+be parented explicitly or it ends up in a trace of its own. `spawn` creates an async child
+of the given span, runs the future with that child as its current span (see
+`current_span`) and sends the child once the future completes, or once the task is
+cancelled.
+
+*/
+use std::future::Future;
+
+use libhoney::Sender;
+use tokio::task::JoinHandle;
+
+use crate::trace::{SafeSpan, SpanGuard};
+use crate::Client;
+
+tokio::task_local! {
+    static CURRENT_SPAN: SafeSpan;
+}
+
+/// `current_span` returns the span of the task started with `spawn` this is called from,
+/// if any
+pub fn current_span() -> Option<SafeSpan> {
+    CURRENT_SPAN.try_with(|span| span.clone()).ok()
+}
+
+/// `spawn` spawns `fut` on the current tokio runtime as an async child of `parent`. The
+/// child is the current span of the task and is sent when `fut` completes or the task is
+/// aborted.
+pub fn spawn<T, F>(client: &Client<T>, parent: &SafeSpan, fut: F) -> JoinHandle<F::Output>
+where
+    T: Sender + Send + Sync + 'static,
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let mut client = Client(client.0.clone());
+    let child = parent.lock().create_async_child(&mut client);
+    match child {
+        Some(child) => {
+            // the guard is dropped along with the task, even if it never completes
+            let guard = SpanGuard::new(&client, Some(child.clone()));
+            tokio::spawn(CURRENT_SPAN.scope(child, async move {
+                let _guard = guard;
+                fut.await
+            }))
+        }
+        None => tokio::spawn(fut),
+    }
+}
+
+/// `SpawnExt` spawns futures with `spawn`
+pub trait SpawnExt: Future + Sized {
+    /// `spawn_in` spawns the future as an async child of `parent`
+    fn spawn_in<T>(self, client: &Client<T>, parent: &SafeSpan) -> JoinHandle<Self::Output>
+    where
+        T: Sender + Send + Sync + 'static;
+
+    /// `spawn_traced` spawns the future as an async child of the current span, or as a
+    /// plain task outside of a task started with `spawn`
+    fn spawn_traced<T>(self, client: &Client<T>) -> JoinHandle<Self::Output>
+    where
+        T: Sender + Send + Sync + 'static;
+}
+
+impl<F> SpawnExt for F
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn spawn_in<T>(self, client: &Client<T>, parent: &SafeSpan) -> JoinHandle<Self::Output>
+    where
+        T: Sender + Send + Sync + 'static,
+    {
+        spawn(client, parent, self)
+    }
+
+    fn spawn_traced<T>(self, client: &Client<T>) -> JoinHandle<Self::Output>
+    where
+        T: Sender + Send + Sync + 'static,
+    {
+        match current_span() {
+            Some(parent) => spawn(client, &parent, self),
+            None => tokio::spawn(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+    use serde_json::json;
+
+    #[test]
+    fn test_spawn() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let task_client = Client(client.0.clone());
+        let task = async move {
+            let span = current_span().unwrap();
+            span.lock().add_field("name", json!("task"));
+            async {
+                current_span()
+                    .unwrap()
+                    .lock()
+                    .add_field("name", json!("nested"));
+            }
+            .spawn_traced(&task_client)
+            .await
+        };
+        runtime
+            .block_on(async { spawn(&client, &root_span, task).await })
+            .unwrap()
+            .unwrap();
+        assert!(current_span().is_none());
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        let root_span_id = &events[2].fields()["trace.span_id"];
+        let task = events
+            .iter()
+            .find(|event| event.fields()["name"] == json!("task"))
+            .unwrap();
+        let nested = events
+            .iter()
+            .find(|event| event.fields()["name"] == json!("nested"))
+            .unwrap();
+        assert_eq!(&task.fields()["trace.parent_id"], root_span_id);
+        assert_eq!(
+            nested.fields()["trace.parent_id"],
+            task.fields()["trace.span_id"]
+        );
+        assert_eq!(task.fields()["meta.span_type"], json!("async"));
+    }
+
+    #[test]
+    fn test_spawn_aborted() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let result = runtime.block_on(async {
+            let task = spawn(&client, &root_span, std::future::pending::<()>());
+            task.abort();
+            task.await
+        });
+        assert!(result.unwrap_err().is_cancelled());
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].fields()["meta.span_type"], json!("async"));
+    }
+}
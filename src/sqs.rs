@@ -0,0 +1,198 @@
+/*! Trace propagation through AWS SQS and SNS message attributes.
+
+The serialized trace context is carried in a `String` message attribute (named
+`x-honeycomb-trace` by default). The helpers work on the attribute name/value pairs, so they
+can be used with any AWS SDK: map them to and from attributes with a `DataType` of
+`String`.
+
+On the producing side, `inject` adds the context of the span publishing the message. On the
+consuming side, `consume` starts a new trace per message, linked to the trace that
+published it. Linking rather than continuing the upstream trace keeps a batch of messages,
+or a message processed long after it was sent, from stretching the producer's trace.
+
+```rust,no_run
+use std::collections::HashMap;
+use beeline::sqs::MessagePropagation;
+use beeline::trace::TraceSender;
+use beeline::{init, Config};
+
+let mut client = init(Config::default());
+let propagation = MessagePropagation::default();
+
+// producer
+let trace = client.new_trace(None);
+let root_span = trace.lock().get_root_span();
+let mut attributes = HashMap::new();
+propagation.inject(&root_span, &mut attributes);
+// send the message with `attributes`
+
+// consumer, for every received message
+let trace = propagation.consume(&client, "sqs", "orders", Some("message-id"), &attributes);
+// process the message
+trace.send(&mut client);
+```
+
+*/
+use std::collections::HashMap;
+
+use libhoney::{Sender, Value};
+use serde_json::json;
+
+use crate::keys;
+use crate::propagation::Propagation;
+use crate::semconv::messaging;
+use crate::trace::{SafeSpan, SafeTrace};
+use crate::Client;
+
+/// name of the message attribute carrying the trace context, unless configured otherwise
+pub const DEFAULT_ATTRIBUTE_NAME: &str = "x-honeycomb-trace";
+
+/// `MessagePropagation` injects and extracts trace context in message attributes
+#[derive(Debug, Clone)]
+pub struct MessagePropagation {
+    /// name of the `String` message attribute carrying the trace context
+    pub attribute_name: String,
+}
+
+impl Default for MessagePropagation {
+    fn default() -> Self {
+        Self {
+            attribute_name: DEFAULT_ATTRIBUTE_NAME.to_string(),
+        }
+    }
+}
+
+impl MessagePropagation {
+    /// `inject` adds the trace context of `span` to the attributes of a message about to
+    /// be published
+    pub fn inject(&self, span: &SafeSpan, attributes: &mut HashMap<String, String>) {
+        let headers = span.lock().serialize_headers();
+        if !headers.is_empty() {
+            attributes.insert(self.attribute_name.clone(), headers);
+        }
+    }
+
+    /// `extract` returns the serialized trace context found in the attributes of a
+    /// received message
+    pub fn extract<'a>(&self, attributes: &'a HashMap<String, String>) -> Option<&'a str> {
+        attributes.get(&self.attribute_name).map(String::as_str)
+    }
+
+    /// `consume` starts a new trace for a message received from `destination`. Its root
+    /// span follows the `semconv::messaging` conventions and, when the message carries a
+    /// trace context, is linked to the span that published the message.
+    pub fn consume<T: Sender>(
+        &self,
+        client: &Client<T>,
+        system: &str,
+        destination: &str,
+        message_id: Option<&str>,
+        attributes: &HashMap<String, String>,
+    ) -> SafeTrace {
+        let trace = client.new_trace(None);
+        let (trace_id, root_span) = {
+            let mut guard = trace.lock();
+            (guard.trace_id.clone(), guard.get_root_span())
+        };
+        let mut span = root_span.lock();
+        span.add_field(keys::META_TYPE, json!(messaging::SPAN_TYPE));
+        span.add_field(keys::NAME, json!(format!("{} consume", destination)));
+        span.add_field(messaging::SYSTEM, json!(system));
+        span.add_field(messaging::DESTINATION, json!(destination));
+        span.add_field(messaging::OPERATION, json!("consume"));
+        if let Some(message_id) = message_id {
+            span.add_field(messaging::MESSAGE_ID, json!(message_id));
+        }
+
+        if let Some(upstream) = self
+            .extract(attributes)
+            .and_then(|headers| Propagation::unmarshal_trace_context(headers).ok())
+        {
+            client.send_event(vec![
+                ("meta.annotation_type".to_string(), json!("link")),
+                (keys::TRACE_TRACE_ID.to_string(), json!(trace_id)),
+                (keys::TRACE_PARENT_ID.to_string(), json!(span.span_id())),
+                (
+                    "trace.link.trace_id".to_string(),
+                    Value::String(upstream.trace_id),
+                ),
+                (
+                    "trace.link.span_id".to_string(),
+                    Value::String(upstream.parent_id),
+                ),
+            ]);
+        }
+
+        trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::trace::TraceSender;
+    use crate::Config;
+
+    #[test]
+    fn test_inject_extract() {
+        let client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+
+        let propagation = MessagePropagation {
+            attribute_name: "trace".to_string(),
+        };
+        let mut attributes = HashMap::new();
+        propagation.inject(&root_span, &mut attributes);
+
+        let headers = propagation.extract(&attributes).unwrap();
+        let upstream = Propagation::unmarshal_trace_context(headers).unwrap();
+        assert_eq!(upstream.trace_id, trace.lock().trace_id);
+        assert!(MessagePropagation::default().extract(&attributes).is_none());
+    }
+
+    #[test]
+    fn test_consume() {
+        let mut client = new_client(Config::default());
+        let propagation = MessagePropagation::default();
+        let producer = client.new_trace(None);
+        let producer_span = producer.lock().get_root_span();
+        let mut attributes = HashMap::new();
+        propagation.inject(&producer_span, &mut attributes);
+
+        let consumer = propagation.consume(&client, "sqs", "orders", Some("m-1"), &attributes);
+        assert_ne!(consumer.lock().trace_id, producer.lock().trace_id);
+        consumer.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        let link = events[0].fields();
+        assert_eq!(link["meta.annotation_type"], json!("link"));
+        assert_eq!(
+            link["trace.link.trace_id"],
+            json!(producer.lock().trace_id.clone())
+        );
+        let root = events[1].fields();
+        assert_eq!(root["name"], json!("orders consume"));
+        assert_eq!(root[messaging::MESSAGE_ID], json!("m-1"));
+        assert_eq!(root["trace.span_id"], link["trace.parent_id"]);
+    }
+
+    #[test]
+    fn test_consume_without_context() {
+        let mut client = new_client(Config::default());
+        let trace = MessagePropagation::default().consume(
+            &client,
+            "sns",
+            "notifications",
+            None,
+            &HashMap::new(),
+        );
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert!(!events[0].fields().contains_key(messaging::MESSAGE_ID));
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+/// Reason under which dropped events are counted when the sampler did not give one
+pub const UNSPECIFIED_REASON: &str = "unspecified";
+
+/// `Stats` holds counters about what the client did with the events it was handed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    /// number of events kept by the sampler and handed over for transmission
+    pub sampled_in: u64,
+    /// number of events dropped by the sampler, keyed by the reason it gave
+    pub sampled_out: HashMap<String, u64>,
+    /// number of events dropped after failing to be sent as many times as the retry
+    /// policy allows
+    pub dropped_after_retries: u64,
+    /// number of events dropped by `Config::event_filter`
+    pub filtered_out: u64,
+    /// number of events `Config::event_filter` handed to a sink instead of transmitting
+    /// them
+    pub routed: u64,
+}
+
+impl Stats {
+    pub(crate) fn record_kept(&mut self) {
+        self.sampled_in += 1;
+    }
+
+    pub(crate) fn record_dropped(&mut self, reason: Option<&str>) {
+        let counter = self
+            .sampled_out
+            .entry(reason.unwrap_or(UNSPECIFIED_REASON).to_string())
+            .or_insert(0);
+        *counter += 1;
+    }
+
+    /// `total_sampled_out` returns the number of dropped events across all reasons
+    pub fn total_sampled_out(&self) -> u64 {
+        self.sampled_out.values().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_dropped() {
+        let mut stats = Stats::default();
+        stats.record_dropped(Some("rate_limit"));
+        stats.record_dropped(Some("rate_limit"));
+        stats.record_dropped(None);
+        assert_eq!(stats.sampled_out["rate_limit"], 2);
+        assert_eq!(stats.sampled_out[UNSPECIFIED_REASON], 1);
+        assert_eq!(stats.total_sampled_out(), 3);
+    }
+}
@@ -0,0 +1,99 @@
+/*! Capturing events in tests.
+
+Only available with the `testutil` feature, which is meant for the `dev-dependencies` of
+an application:
+
+```toml
+[dev-dependencies]
+beeline-rust = { version = "0.1", features = ["testutil"] }
+```
+
+A client returned by `init` sends its events to a `TransmissionMock`, which keeps them in
+memory instead of sending them to Honeycomb. `events` returns what has been sent so far,
+and `find_event` and `assert_field` help checking it:
+
+```rust
+use beeline::test;
+use beeline::Config;
+use serde_json::json;
+
+let client = test::init(Config::default());
+let trace = client.new_trace(None);
+trace
+    .lock()
+    .get_root_span()
+    .lock()
+    .add_field("app.job", json!("cleanup"));
+client.flush().unwrap();
+
+let events = test::events(&client);
+let job = test::find_event(&events, "app.job", &json!("cleanup")).unwrap();
+test::assert_field(job, "meta.span_type", &json!("root"));
+```
+
+Set `Config::id_generator` to `sequential_ids` to get the same trace and span ids on every
+run.
+
+The functions of this module and `Fields` are stable: they don't change between minor
+versions. The fields of the events are those of the beeline, see `keys`.
+ */
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{new_client, Client, Config};
+
+pub use libhoney::mock::TransmissionMock;
+
+/// `Fields` are the fields of a captured event
+pub type Fields = HashMap<String, libhoney::Value>;
+
+/// `init` returns a client whose events are kept in memory by a `TransmissionMock`
+pub fn init(config: Config) -> Client<TransmissionMock> {
+    let client = libhoney::test::init(config.libhoney_config());
+    new_client(config, client)
+}
+
+/// `sequential_ids` returns an id generator for `Config::id_generator` handing out
+/// UUID-shaped ids made of `seed` and a counter, e.g. `00000001-0000-4000-8000-000000000001`
+/// and then `...-000000000002`. With it, the same code yields the same trace and span ids
+/// on every run, so serialized traces can be compared to golden files once the timestamps
+/// and durations are left out.
+pub fn sequential_ids(seed: u32) -> impl Fn() -> String + Send + Sync + 'static {
+    let next = AtomicU64::new(1);
+    move || {
+        let id = next.fetch_add(1, Ordering::Relaxed);
+        format!("{:08x}-0000-4000-8000-{:012x}", seed, id)
+    }
+}
+
+/// `events` returns the fields of the events the client has sent so far, in the order
+/// they were sent
+pub fn events(client: &Client<TransmissionMock>) -> Vec<Fields> {
+    let events = client.0.write().client.transmission.events();
+    events.iter().map(|event| event.fields()).collect()
+}
+
+/// `find_event` returns the first of `events` whose field `key` is `value`
+pub fn find_event<'a>(
+    events: &'a [Fields],
+    key: &str,
+    value: &libhoney::Value,
+) -> Option<&'a Fields> {
+    events.iter().find(|fields| fields.get(key) == Some(value))
+}
+
+/// `assert_field` panics, listing the fields of `event`, unless its field `key` is
+/// `expected`
+#[track_caller]
+pub fn assert_field(event: &Fields, key: &str, expected: &libhoney::Value) {
+    let actual = event.get(key);
+    assert!(
+        actual == Some(expected),
+        "field `{}` is {:?}, expected {}; the event has {:?}",
+        key,
+        actual,
+        expected,
+        event
+    );
+}
@@ -1,20 +1,59 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
-use log::error;
+use log::{Level, LevelFilter};
 use parking_lot::Mutex;
 use serde_json::json;
 use uuid::Uuid;
 
-use crate::propagation::Propagation;
+use crate::async_send::{self, Job, SendFuture};
+use crate::dataset;
+use crate::diagnostics;
+use crate::errors::Result;
+use crate::flamegraph;
+use crate::keys;
+use crate::propagation::{Propagation, PropagationHeaders};
+use crate::registry::Destination;
+use crate::sampler::{self, SamplerDecision};
+use crate::secret;
 use crate::timer::{self, Timing};
-use crate::Client;
+use crate::tracestate::TraceState;
+use crate::{Client, IdGeneratorFn, PropagationFilterFn, SamplerHookFn};
 
 use libhoney::{Builder, Event, FieldHolder, Sender, Value};
 
 pub type SafeSpan = Arc<Mutex<Span>>;
 pub type SafeTrace = Arc<Mutex<Trace>>;
 
+/// Ids are shared between a span, its children and the trace, cloning them is cheap
+type Id = Arc<str>;
+
+/// `new_id` returns an id from the `Config::id_generator`, or else a random UUID
+fn new_id(generator: Option<&IdGeneratorFn>) -> Id {
+    if let Some(generator) = generator {
+        return Arc::from(generator());
+    }
+    let mut buffer = Uuid::encode_buffer();
+    Arc::from(&*Uuid::new_v4().to_hyphenated().encode_lower(&mut buffer))
+}
+
+/// `unmarshal` parses `headers` with the `Config::propagation_parsers` and
+/// `Config::propagation_parsing` of `client`
+fn unmarshal<T: Sender>(client: &Client<T>, headers: &str) -> Result<Propagation> {
+    // the parsers run without the lock of the client held
+    let (parsers, parsing) = {
+        let client = client.0.read();
+        (
+            client.config.propagation_parsers.clone(),
+            client.config.propagation_parsing,
+        )
+    };
+    Propagation::unmarshal_trace_context_with(headers, &parsers, parsing)
+}
+
 /// Trace holds some trace level state and the root of the span tree that will be the
 /// entire in-process trace. Traces are sent to Honeycomb when the root span is sent. You
 /// can send a trace manually, and that will cause all synchronous spans in the trace to be
@@ -26,13 +65,64 @@ pub struct Trace {
     parent_id: String,
     rollup_fields: HashMap<String, f64>,
     root_span: SafeSpan,
-    trace_level_fields: Value,
-    child_spans: HashMap<String, Span>,
+    trace_level_fields: HashMap<String, Value>,
+    local_fields: HashSet<String>,
+    baggage: Baggage,
+    tracestate: TraceState,
+    child_spans: HashSet<Id>,
+    sampled_out: bool,
+    has_tenant_api_key: bool,
+    propagation_settings: PropagationSettings,
+    /// spans sent so far, with `Config::flamegraph`
+    frames: Option<Vec<flamegraph::Frame>>,
+    /// sample rate of the whole trace, with `Config::propagate_sample_rate`
+    sample_rate: Arc<Mutex<Option<usize>>>,
+}
+
+/// `PropagationSettings` is what the trace needs from the configuration to serialize its
+/// headers, captured when it starts
+#[derive(Clone)]
+struct PropagationSettings {
+    filter: Option<Arc<PropagationFilterFn>>,
+    max_size: Option<usize>,
+    diagnostics_level: LevelFilter,
+    /// sampler choosing the rate of the trace, with `Config::propagate_sample_rate`
+    sampler_hook: Option<Arc<SamplerHookFn>>,
+}
+
+impl fmt::Debug for PropagationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PropagationSettings")
+            .field("filter", &self.filter.as_ref().map(|_| "Fn()"))
+            .field("max_size", &self.max_size)
+            .field("diagnostics_level", &self.diagnostics_level)
+            .field("sampler_hook", &self.sampler_hook.as_ref().map(|_| "Fn()"))
+            .finish()
+    }
+}
+
+/// Baggage is shared between a trace and all its spans
+type Baggage = Arc<Mutex<HashMap<String, String>>>;
+
+/// `AsyncSpans` tracks the asynchronous spans of a trace outliving its root span. It is
+/// shared between all the spans of the trace.
+#[derive(Debug, Default)]
+struct AsyncSpans {
+    /// number of asynchronous spans created and not sent yet
+    pending: usize,
+    /// when the root span was sent
+    root_sent_at: Option<Instant>,
 }
 
 /// Trait to be able to send the trace
 pub trait TraceSender<T: Sender> {
     fn send(&self, client: &mut Client<T>);
+
+    /// `send_async` sends the trace without transmitting on the caller's thread, see
+    /// `Span::send_async`
+    fn send_async(&self, client: &mut Client<T>) -> SendFuture
+    where
+        T: Send + Sync + 'static;
 }
 
 /// Implement send for trait TraceSender
@@ -45,6 +135,15 @@ impl<T: Sender> TraceSender<T> for SafeTrace {
             root_span.send(&mut *client);
         }
     }
+
+    fn send_async(&self, client: &mut Client<T>) -> SendFuture
+    where
+        T: Send + Sync + 'static,
+    {
+        let root_span = self.lock().root_span.clone();
+        let mut root_span = root_span.lock();
+        root_span.send_async(client)
+    }
 }
 
 impl Trace {
@@ -55,39 +154,147 @@ impl Trace {
         client: &Client<T>,
         serialized_headers: Option<String>,
     ) -> SafeTrace {
+        let upstream = serialized_headers.as_deref().and_then(|headers| {
+            unmarshal(client, headers)
+                .map_err(|e| {
+                    client.diagnostic(
+                        Level::Warn,
+                        format_args!(
+                            "starting a new trace, ignoring the trace header: {}",
+                            e.chain()
+                        ),
+                    )
+                })
+                .ok()
+        });
+        let version = upstream
+            .as_ref()
+            .and(serialized_headers.as_deref())
+            .and_then(Propagation::header_version);
+        Self::start(client, upstream, version)
+    }
+
+    // `try_new` creates a trace like `new`, but fails when the serialized headers can't be
+    // parsed instead of starting a fresh trace
+    pub(crate) fn try_new<T: Sender>(
+        client: &Client<T>,
+        serialized_headers: Option<String>,
+    ) -> Result<SafeTrace> {
+        let upstream = serialized_headers
+            .as_deref()
+            .map(|headers| unmarshal(client, headers))
+            .transpose()?;
+        let version = serialized_headers
+            .as_deref()
+            .and_then(Propagation::header_version);
+        Ok(Self::start(client, upstream, version))
+    }
+
+    fn start<T: Sender>(
+        client: &Client<T>,
+        upstream: Option<Propagation>,
+        version: Option<&str>,
+    ) -> SafeTrace {
+        let (propagation_settings, id_generator, frames) = {
+            let client = client.0.read();
+            let settings = PropagationSettings {
+                filter: client.config.propagation_filter.clone(),
+                max_size: client.config.max_propagation_header_size,
+                diagnostics_level: client.config.diagnostics_level,
+                sampler_hook: Some(client.config.sampler_hook.clone())
+                    .filter(|_| client.config.propagate_sample_rate),
+            };
+            let frames = Some(Vec::new()).filter(|_| client.config.flamegraph);
+            (settings, client.config.id_generator.clone(), frames)
+        };
         let trace = Arc::new(Mutex::new(Self {
             builder: client.new_builder(),
             trace_id: String::new(),
             parent_id: String::new(),
-            trace_level_fields: json!({}),
-            root_span: Arc::new(Mutex::new(Span::new())),
+            trace_level_fields: HashMap::new(),
+            local_fields: HashSet::new(),
+            root_span: Arc::new(Mutex::new(Span::default())),
+            baggage: Arc::new(Mutex::new(HashMap::new())),
+            tracestate: TraceState::default(),
             rollup_fields: HashMap::new(),
-            child_spans: HashMap::new(),
+            child_spans: HashSet::new(),
+            sampled_out: false,
+            has_tenant_api_key: false,
+            propagation_settings,
+            frames,
+            sample_rate: Arc::new(Mutex::new(None)),
         }));
 
         let cloned = trace.clone();
         let mut t = cloned.lock();
 
-        if let Some(headers) = serialized_headers {
-            if let Ok(prop) = Propagation::unmarshal_trace_context(&headers) {
-                t.trace_id = prop.trace_id;
-                t.parent_id = prop.parent_id;
-                t.builder.options.dataset = prop.dataset;
-                t.trace_level_fields = prop.trace_context;
+        if let Some(prop) = upstream {
+            t.trace_id = prop.trace_id;
+            t.parent_id = prop.parent_id;
+            if !prop.dataset.is_empty() {
+                match dataset::sanitize(&prop.dataset) {
+                    Some(sanitized) => {
+                        if sanitized != prop.dataset {
+                            client.diagnostic(
+                                Level::Warn,
+                                format_args!(
+                                    "propagated dataset {:?} sanitized to {:?}",
+                                    prop.dataset, sanitized
+                                ),
+                            );
+                        }
+                        t.builder.options.dataset = sanitized;
+                    }
+                    None => client.diagnostic(
+                        Level::Warn,
+                        format_args!(
+                            "ignoring the propagated dataset {:?}, which has no letter or digit",
+                            prop.dataset
+                        ),
+                    ),
+                }
+            }
+            if let Value::Object(fields) = prop.trace_context {
+                t.trace_level_fields = fields.into_iter().collect();
+            }
+            *t.baggage.lock() = prop.baggage;
+            if t.propagation_settings.sampler_hook.is_some() {
+                *t.sample_rate.lock() = prop.sample_rate;
             }
         }
 
         if t.trace_id.is_empty() {
-            t.trace_id = Uuid::new_v4().to_string();
+            t.trace_id = new_id(id_generator.as_deref()).to_string();
+        }
+
+        let head_sampler_hook = client.0.read().config.head_sampler_hook.clone();
+        if let Some(head_sampler_hook) = head_sampler_hook {
+            if !head_sampler_hook(&t.trace_id) {
+                t.sampled_out = true;
+                client
+                    .0
+                    .write()
+                    .stats
+                    .record_dropped(Some(sampler::REASON_HEAD));
+            }
         }
 
-        let mut root_span = Span::new();
+        let mut root_span = Span::new(id_generator.as_deref());
         root_span.is_root = true;
         if !t.parent_id.is_empty() {
-            root_span.parent_id = t.parent_id.clone();
+            root_span.parent_id = Arc::from(t.parent_id.as_str());
+        }
+        if t.sampled_out {
+            root_span.sampled_out = true;
+        } else {
+            root_span.ev = Some(t.builder.new_event());
         }
-        root_span.ev = Some(t.builder.new_event());
-        root_span.trace = Some(t.trace_id.clone());
+        if let Some(version) = version {
+            root_span.add_field(keys::META_PROPAGATION_VERSION, json!(version));
+        }
+        root_span.trace = Some(Arc::from(t.trace_id.as_str()));
+        root_span.trace_handle = Arc::downgrade(&trace);
+        root_span.baggage = t.baggage.clone();
         t.root_span = Arc::new(Mutex::new(root_span));
         trace
     }
@@ -97,25 +304,172 @@ impl Trace {
     /// is useful to add fields here that pertain to the entire trace, to aid in filtering
     /// spans at many different areas of the trace together.
     pub fn add_field(&mut self, key: &str, value: Value) {
-        if let Some(ref mut tlf) = self.trace_level_fields.as_object_mut() {
-            tlf.insert(key.to_string(), value);
+        self.local_fields.remove(key);
+        self.trace_level_fields.insert(key.to_string(), value);
+    }
+
+    /// `add_field_local` adds a field to every span in the trace, like `add_field`, but
+    /// never passes it along to downstream services. Use it for trace wide fields that
+    /// must not cross service boundaries, such as internal user ids.
+    pub fn add_field_local(&mut self, key: &str, value: Value) {
+        self.local_fields.insert(key.to_string());
+        self.trace_level_fields.insert(key.to_string(), value);
+    }
+
+    /// `trace_level_fields` returns the fields added to the trace with `add_field` and
+    /// `add_field_local`, including the ones received from upstream services
+    pub fn trace_level_fields(&self) -> &HashMap<String, Value> {
+        &self.trace_level_fields
+    }
+
+    /// `is_sampled_out` returns whether the head sampler dropped this trace, in which case
+    /// none of its spans will be sent
+    pub fn is_sampled_out(&self) -> bool {
+        self.sampled_out
+    }
+
+    /// `route` sends the spans of the fresh `trace` to `destination` instead of the client
+    /// dataset. Like `set_api_key`, it locks the root span once the trace is released.
+    pub(crate) fn route(trace: &SafeTrace, destination: &Destination) {
+        let (root_span, routed) = {
+            let mut trace = trace.lock();
+            destination.apply(&mut trace.builder);
+            (trace.root_span.clone(), trace.builder.new_event())
+        };
+
+        let mut root_span = root_span.lock();
+        if root_span.ev.is_some() {
+            root_span.ev = Some(routed);
         }
     }
 
-    /// `serialize_headers` returns the trace ID, given span ID as parent ID, and an
-    /// encoded form of all trace level fields. This serialized header is intended to be
-    /// put in an HTTP (or other protocol) header to transmit to downstream services so
-    /// they may start a new trace that will be connected to this trace.  The serialized
-    /// form may be passed to NewTrace() in order to create a new trace that will be
-    /// connected to this trace.
-    fn serialize_headers(&self, span_id: &str) -> String {
+    /// `set_api_key` sends the spans of `trace` with `api_key` instead of the client one,
+    /// e.g. to the Honeycomb team of the tenant the trace belongs to. It applies to the
+    /// root span and the spans created after the call, so set it as soon as the trace
+    /// starts. The key isn't propagated downstream, and the copy held by the trace is
+    /// zeroized when the trace is dropped.
+    ///
+    /// It takes the trace unlocked: the root span is only locked once the trace is
+    /// released, as sending a span locks the span and then its trace.
+    pub fn set_api_key(trace: &SafeTrace, api_key: &str) {
+        let (root_span, mut routed) = {
+            let mut trace = trace.lock();
+            if trace.has_tenant_api_key {
+                secret::zeroize(&mut trace.builder.options.api_key);
+            }
+            trace.builder.options.api_key = api_key.to_string();
+            trace.has_tenant_api_key = true;
+            (trace.root_span.clone(), trace.builder.new_event())
+        };
+
+        let mut root_span = root_span.lock();
+        if let Some(ev) = root_span.ev.as_mut() {
+            routed.get_fields_mut().extend(ev.get_fields_mut().drain());
+            root_span.ev = Some(routed);
+        }
+    }
+
+    /// `set_baggage` sets a baggage item on the trace. Unlike trace level fields, baggage
+    /// is propagated to downstream services but never added to spans automatically; read
+    /// it with `Span::baggage` and add what is relevant to the spans that need it.
+    pub fn set_baggage(&mut self, key: &str, value: &str) {
+        self.baggage
+            .lock()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// `tracestate` returns the W3C `tracestate` of the trace, including the members of
+    /// other vendors that must be forwarded downstream
+    pub fn tracestate(&self) -> &TraceState {
+        &self.tracestate
+    }
+
+    /// `tracestate_mut` gives access to the `tracestate` of the trace, e.g. to update the
+    /// beeline member with `TraceState::set_vendor_value`
+    pub fn tracestate_mut(&mut self) -> &mut TraceState {
+        &mut self.tracestate
+    }
+
+    /// `propagation_for` returns the header that connects a downstream trace to the span
+    /// `span_id` of this trace, for any transport: HTTP, RPC or job queues. It carries the
+    /// trace ID, the span ID as parent ID and the trace level fields, except the local
+    /// ones and the ones rejected by `Config::propagation_filter`. Headers longer than
+    /// `Config::max_propagation_header_size` leave out their context.
+    pub fn propagation_for(&self, span_id: &str) -> PropagationHeaders {
+        self.headers_for(span_id, false)
+    }
+
+    /// `compact_propagation_for` is like `propagation_for`, with the header in the compact
+    /// form of `Propagation::marshal_compact`, for size-constrained transports. Beelines
+    /// of this version and later read both forms.
+    pub fn compact_propagation_for(&self, span_id: &str) -> PropagationHeaders {
+        self.headers_for(span_id, true)
+    }
+
+    fn headers_for(&self, span_id: &str, compact: bool) -> PropagationHeaders {
+        let settings = &self.propagation_settings;
+        let mut propagation = self.propagation(span_id, settings.filter.as_deref());
+        if let Some(sampler_hook) = &settings.sampler_hook {
+            propagation.sample_rate = Some(self.choose_sample_rate(sampler_hook));
+        }
+        let (value, truncated) = match (settings.max_size, compact) {
+            (None, false) => (propagation.marshal_trace_context(), false),
+            (None, true) => (propagation.marshal_compact(), false),
+            (Some(max_size), false) => propagation.marshal_trace_context_within(max_size),
+            (Some(max_size), true) => propagation.marshal_compact_within(max_size),
+        };
+        if let (true, Some(max_size)) = (truncated, settings.max_size) {
+            diagnostics::log(
+                settings.diagnostics_level,
+                Level::Warn,
+                format_args!(
+                    "trace header longer than {} bytes, leaving out its context",
+                    max_size
+                ),
+            );
+        }
+        PropagationHeaders::new(value, truncated)
+    }
+
+    /// `choose_sample_rate` returns the sample rate of the trace, asking `sampler_hook` for
+    /// one, given the trace level fields and the trace id, when there is none yet
+    fn choose_sample_rate(&self, sampler_hook: &SamplerHookFn) -> usize {
+        *self.sample_rate.lock().get_or_insert_with(|| {
+            let mut fields = self.trace_level_fields.clone();
+            fields.insert(keys::TRACE_TRACE_ID.to_string(), json!(self.trace_id));
+            match panic::catch_unwind(AssertUnwindSafe(|| sampler_hook(fields))) {
+                Ok(decision) => decision.sample_rate.max(1),
+                Err(_) => {
+                    diagnostics::log(
+                        self.propagation_settings.diagnostics_level,
+                        Level::Error,
+                        format_args!("sampler hook panicked, sampling the trace at a rate of 1"),
+                    );
+                    1
+                }
+            }
+        })
+    }
+
+    /// `propagation` returns the trace ID, given span ID as parent ID, and all trace level
+    /// fields `filter` accepts but the local ones, ready to be serialized in a header for
+    /// downstream services.
+    fn propagation(&self, span_id: &str, filter: Option<&PropagationFilterFn>) -> Propagation {
         Propagation {
             trace_id: self.trace_id.clone(),
             parent_id: span_id.to_string(),
             dataset: self.builder.options.dataset.clone(),
-            trace_context: self.trace_level_fields.clone(),
+            trace_context: Value::Object(
+                self.trace_level_fields
+                    .iter()
+                    .filter(|(k, _)| !self.local_fields.contains(*k))
+                    .filter(|(k, v)| filter.is_none_or(|filter| filter(k, v)))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            ),
+            baggage: self.baggage.lock().clone(),
+            sample_rate: None,
         }
-        .marshal_trace_context()
     }
 
     /// `add_rollup_field` is here to let a span contribute a field to the trace while
@@ -125,16 +479,41 @@ impl Trace {
         *v += value;
     }
 
+    /// `rollup_field` returns the total of the rollup field `key` so far
+    pub(crate) fn rollup_field(&self, key: &str) -> f64 {
+        self.rollup_fields.get(key).copied().unwrap_or_default()
+    }
+
     pub fn get_root_span(&mut self) -> SafeSpan {
         self.root_span.clone()
     }
 
     /// `remove_child_span`
-    pub(crate) fn remove_child_span(&mut self, span_id: String) {
-        self.child_spans.remove(&span_id);
+    pub(crate) fn remove_child_span(&mut self, span_id: &str) {
+        self.child_spans.remove(span_id);
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        if self.has_tenant_api_key {
+            secret::zeroize(&mut self.builder.options.api_key);
+        }
     }
 }
 
+/// `Span` is a unit of work within a trace, sent to Honeycomb as an event. On a key
+/// present in several of them, the fields of the event are, from lowest to highest
+/// precedence:
+///  1. the client level fields, see `Client::add_field`, as they were when the span was
+///     created
+///  2. the trace level fields, see `Trace::add_field`, added when the span is sent
+///  3. the fields added to the span, see `Span::add_field`. A span field set to the
+///     value of the client level field of the same key is taken for the latter.
+///  4. the fields the beeline adds when the span is sent, such as `duration_ms` and the
+///     trace and span ids
+///
+/// `Config::presend_hook` then gets the event and can change any field.
 #[derive(Debug, Default, Clone)]
 pub struct Span {
     is_async: bool,
@@ -142,17 +521,75 @@ pub struct Span {
     is_root: bool,
     children: Vec<SafeSpan>,
     ev: Option<Event>,
-    span_id: String,
-    parent_id: String,
+    span_id: Id,
+    parent_id: Id,
     rollup_fields: Arc<Mutex<HashMap<String, f64>>>,
+    baggage: Baggage,
+    async_spans: Arc<Mutex<AsyncSpans>>,
+    /// trace level fields when the span was created, for asynchronous spans to send them
+    /// even once their trace is gone
+    trace_fields_snapshot: Option<HashMap<String, Value>>,
+    /// keys of the fields removed from the span, which trace level fields don't add back
+    removed_keys: Vec<String>,
+    /// total time of the timers started with `start_timer`, by name, in milliseconds
+    timers: Arc<Mutex<HashMap<String, f64>>>,
     timer: timer::Timer,
-    trace: Option<String>,
+    trace: Option<Id>,
+    /// handle on the trace, to serialize headers without looking it up in the client
+    trace_handle: Weak<Mutex<Trace>>,
+    sampled_out: bool,
+    /// whether the span is already counted in the `error_count` of the trace
+    has_error: bool,
+}
+
+/// `UnsentChildPolicy` is what becomes of the synchronous children a span is sent
+/// before, including the ones left when `Client::flush` or `Client::close` send the open
+/// traces
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnsentChildPolicy {
+    /// the children are sent along with their parent, with `meta.sent_by_parent` set
+    #[default]
+    SendWithFlag,
+    /// the children are dropped without a word
+    Discard,
+    /// the children are dropped, and logged as a diagnostic
+    LogOnly,
+}
+
+/// `TraceSnapshot` is a slow trace handed to `Config::slow_trace_hook`
+#[derive(Debug, Clone)]
+pub struct TraceSnapshot {
+    /// id of the trace
+    pub trace_id: String,
+    /// how long the root span lasted
+    pub duration: Duration,
+    /// fields of the root span as it is about to be sent, including the trace level and
+    /// rollup fields
+    pub fields: HashMap<String, Value>,
+}
+
+/// `SpanStatus` is the outcome of the work a span covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanStatus {
+    /// the work succeeded
+    Ok,
+    /// the work failed
+    Error,
+}
+
+impl SpanStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            SpanStatus::Ok => "ok",
+            SpanStatus::Error => "error",
+        }
+    }
 }
 
 impl Span {
-    fn new() -> Span {
+    fn new(id_generator: Option<&IdGeneratorFn>) -> Span {
         Self {
-            span_id: Uuid::new_v4().to_string(),
+            span_id: new_id(id_generator),
             ..Default::default()
         }
     }
@@ -161,6 +598,77 @@ impl Span {
     pub fn add_field(&mut self, key: &str, value: Value) {
         if let Some(ref mut ev) = self.ev {
             ev.add_field(key, value);
+            if !self.removed_keys.is_empty() {
+                self.removed_keys.retain(|removed| removed != key);
+            }
+        }
+    }
+
+    /// `add_field_if_absent` adds a key/value pair to this span, unless it already has a
+    /// field `key`, including the client level ones. It returns whether the field was
+    /// added. Trace level fields are only added when the span is sent, so they don't count,
+    /// and are overwritten.
+    pub fn add_field_if_absent(&mut self, key: &str, value: Value) -> bool {
+        let present = match self.ev {
+            Some(ref mut ev) => ev.get_fields_mut().contains_key(key),
+            None => true,
+        };
+        if !present {
+            self.add_field(key, value);
+        }
+        !present
+    }
+
+    /// `remove_field` removes the field `key` from this span, including a client level
+    /// one, and returns its value. A trace level field `key` isn't added to the span either.
+    pub fn remove_field(&mut self, key: &str) -> Option<Value> {
+        let value = self.ev.as_mut()?.get_fields_mut().remove(key);
+        if !self.removed_keys.iter().any(|removed| removed == key) {
+            self.removed_keys.push(key.to_string());
+        }
+        value
+    }
+
+    /// `start_timer` starts timing a phase of the work of this span, until the returned
+    /// guard is dropped. The time is sent as `timers.<name>_ms`, summed over all the timers
+    /// named `name`, so a span can time several phases without a child span for each.
+    /// Timers still running when the span is sent are left out.
+    pub fn start_timer(&self, name: &str) -> TimerGuard {
+        TimerGuard {
+            name: name.to_string(),
+            timer: timer::Timer::start(),
+            timers: self.timers.clone(),
+        }
+    }
+
+    /// `add_rollup_field` adds `value` to the field `key` of this span, and to the total of
+    /// the trace, sent as `rollup.<key>` on the root span
+    pub fn add_rollup_field(&mut self, key: &str, value: f64) {
+        *self
+            .rollup_fields
+            .lock()
+            .entry(key.to_string())
+            .or_insert(0f64) += value;
+        if let Some(trace) = self.trace_handle.upgrade() {
+            trace.lock().add_rollup_field(key, value);
+        }
+    }
+
+    /// `record_error` sets `error` to the message of `error` and marks the span as failed,
+    /// see `set_status`
+    pub fn record_error(&mut self, error: &dyn fmt::Display) {
+        self.add_field(keys::ERROR, json!(error.to_string()));
+        self.set_status(SpanStatus::Error);
+    }
+
+    /// `set_status` records the outcome of the span as `span.status`. The spans failing
+    /// are counted once each in the `error_count` rollup field, and the root span of a
+    /// trace with any failed span gets `app.error` set.
+    pub fn set_status(&mut self, status: SpanStatus) {
+        self.add_field(keys::SPAN_STATUS, json!(status.as_str()));
+        if status == SpanStatus::Error && !self.has_error && self.ev.is_some() {
+            self.has_error = true;
+            self.add_rollup_field(keys::ERROR_COUNT, 1f64);
         }
     }
 
@@ -170,79 +678,265 @@ impl Span {
         self.children.to_vec()
     }
 
+    /// `baggage` returns the baggage of the trace this span belongs to, including the
+    /// items received from upstream services
+    pub fn baggage(&self) -> HashMap<String, String> {
+        self.baggage.lock().clone()
+    }
+
+    /// `add_baggage_field` adds the baggage item `key`, if set, as a field of this span
+    pub fn add_baggage_field(&mut self, key: &str) {
+        let value = self.baggage.lock().get(key).cloned();
+        if let Some(value) = value {
+            self.add_field(key, Value::String(value));
+        }
+    }
+
+    /// `is_sampled_out` returns whether this span belongs to a trace dropped by the head
+    /// sampler. Such spans have no event: adding fields and creating children does close
+    /// to nothing, and sending them is a no-op.
+    pub fn is_sampled_out(&self) -> bool {
+        self.sampled_out
+    }
+
+    pub(crate) fn span_id(&self) -> &str {
+        &self.span_id
+    }
+
+    /// `trace` returns the trace this span belongs to, unless it's gone
+    pub(crate) fn trace(&self) -> Option<SafeTrace> {
+        self.trace_handle.upgrade()
+    }
+
     pub fn send<T: Sender>(&mut self, client: &mut Client<T>) {
         if !self.is_sent {
-            self.send_locked(client);
+            self.send_locked(client, false);
+        }
+    }
+
+    /// `send_async` sends the span like `send`, but leaves running the hooks and
+    /// transmitting the events to a background thread. The returned future resolves once
+    /// that is done; it doesn't need to be awaited for the span to be sent.
+    pub fn send_async<T>(&mut self, client: &mut Client<T>) -> SendFuture
+    where
+        T: Sender + Send + Sync + 'static,
+    {
+        async_send::start(client);
+        if !self.is_sent {
+            self.send_locked(client, true);
         }
+        async_send::notify(client)
     }
 
-    fn send_by_parent<T: Sender>(&mut self, client: &mut Client<T>) {
+    fn send_by_parent<T: Sender>(&mut self, client: &mut Client<T>, deferred: bool) {
         if !self.is_sent {
-            self.add_field("meta.sent_by_parent", json!(true));
-            self.send_locked(client);
+            self.add_field(keys::META_SENT_BY_PARENT, json!(true));
+            self.send_locked(client, deferred);
         }
     }
 
-    fn send_locked<T: Sender>(&mut self, client: &mut Client<T>) {
+    /// `send_locked` sends the span and its synchronous children. When `deferred`, the
+    /// events are handed over to the sender thread rather than sent right away.
+    fn send_locked<T: Sender>(&mut self, client: &mut Client<T>, deferred: bool) {
         if self.ev.is_none() {
             return;
         }
 
         // finish the timer for this span
-        self.add_field("duration_ms", json!(self.timer.finish())); // TODO: dangerous
+        self.add_field(keys::DURATION_MS, json!(self.timer.finish())); // TODO: dangerous
 
         if !self.parent_id.is_empty() {
-            self.add_field("trace.parent_id", json!(self.parent_id.clone()));
+            self.add_field(keys::TRACE_PARENT_ID, json!(&*self.parent_id));
         }
 
         if let Some(ref mut ev) = self.ev {
             // set trace IDs for this span
             if let Some(ref trace_id) = self.trace {
-                ev.add_field("trace.trace_id", json!(trace_id));
+                ev.add_field(keys::TRACE_TRACE_ID, json!(&**trace_id));
             }
-            ev.add_field("trace.span_id", json!(self.span_id.clone()));
+            ev.add_field(keys::TRACE_SPAN_ID, json!(&*self.span_id));
         }
 
         // add this span's rollup fields to the event
         for (k, v) in self.rollup_fields.clone().lock().iter() {
             self.add_field(k, json!(v));
         }
+        for (name, ms) in self.timers.clone().lock().iter() {
+            self.add_field(&format!("timers.{}_ms", name), json!(ms));
+        }
 
-        let mut children: Vec<SafeSpan> = Vec::new();
+        if !self.is_root {
+            self.count_in_trace();
+        }
+        if self.is_async {
+            self.track_async_send();
+        }
 
-        for v in self.children.iter() {
-            if !v.lock().is_async {
-                // queue children up to be sent. We'd deadlock if we actually sent the
-                // child here.
-                children.push(v.clone());
+        let policy = if self.children.is_empty() {
+            UnsentChildPolicy::default()
+        } else {
+            client.0.read().config.unsent_child_policy
+        };
+        for child in self.children.iter() {
+            // lock each child only once, checking and sending it under the same guard
+            let mut child = child.lock();
+            if child.is_async || child.is_sent {
+                continue;
+            }
+            match policy {
+                UnsentChildPolicy::SendWithFlag => child.send_by_parent(client, deferred),
+                UnsentChildPolicy::Discard => child.discard(client),
+                UnsentChildPolicy::LogOnly => {
+                    if child.ev.is_some() {
+                        client.diagnostic(
+                            Level::Error,
+                            format_args!(
+                                "span {} was not sent before its parent, discarding it",
+                                child.name()
+                            ),
+                        );
+                    }
+                    child.discard(client);
+                }
             }
         }
 
-        for child in children.iter_mut() {
-            child.lock().send_by_parent(client);
+        self.final_send(client, deferred);
+        self.is_sent = true;
+
+        if let Some(trace) = self.trace.as_ref().and_then(|id| client.trace(id)) {
+            trace.lock().remove_child_span(&self.span_id);
         }
+    }
 
-        self.final_send(client);
+    /// `discard` drops the span and its unsent synchronous children without sending them
+    fn discard<T: Sender>(&mut self, client: &Client<T>) {
+        if self.ev.take().is_none() {
+            return;
+        }
         self.is_sent = true;
+        for child in self.children.iter() {
+            let mut child = child.lock();
+            if !child.is_async && !child.is_sent {
+                child.discard(client);
+            }
+        }
+        if let Some(trace) = self.trace.as_ref().and_then(|id| client.trace(id)) {
+            trace.lock().remove_child_span(&self.span_id);
+        }
+    }
+
+    /// `name` returns the name of the span, or its id when it has none
+    fn name(&mut self) -> String {
+        let name = self
+            .ev
+            .as_mut()
+            .and_then(|ev| ev.get_fields_mut().get(keys::NAME).cloned());
+        match name {
+            Some(Value::String(name)) => name,
+            _ => self.span_id.to_string(),
+        }
+    }
+
+    /// `track_async_send` stops counting the asynchronous span as pending and, when the
+    /// root span is already sent, records how late the span is
+    fn track_async_send(&mut self) {
+        let root_sent_at = {
+            let mut async_spans = self.async_spans.lock();
+            async_spans.pending = async_spans.pending.saturating_sub(1);
+            async_spans.root_sent_at
+        };
+        if let Some(root_sent_at) = root_sent_at {
+            let delay_ms = root_sent_at.elapsed().as_nanos() as f64 / 1_000_000f64;
+            self.add_field(keys::META_SENT_AFTER_ROOT, json!(true));
+            self.add_field(keys::META_SENT_AFTER_ROOT_MS, json!(delay_ms));
+        }
+    }
+
+    /// `count_in_trace` counts the span in the `spans.` rollup fields of its trace, by type
+    fn count_in_trace(&mut self) {
+        let trace = match self.trace_handle.upgrade() {
+            Some(trace) => trace,
+            None => return,
+        };
+        let meta_type =
+            self.ev
+                .as_mut()
+                .and_then(|ev| match ev.get_fields_mut().get(keys::META_TYPE) {
+                    Some(Value::String(meta_type)) => Some(format!("spans.{}", meta_type)),
+                    _ => None,
+                });
+        let mut trace = trace.lock();
+        trace.add_rollup_field(keys::SPANS_TOTAL, 1f64);
+        if self.is_async {
+            trace.add_rollup_field(keys::SPANS_ASYNC, 1f64);
+        }
+        if let Some(meta_type) = meta_type {
+            trace.add_rollup_field(&meta_type, 1f64);
+        }
+    }
 
-        if let Some(ref trace_id) = self.trace {
-            client.remove_child_span_from_trace(trace_id.to_string(), self.span_id.clone());
+    /// `add_trace_field` adds a trace level field to the event, unless the span has a field
+    /// `key` of its own or removed it. The event holds the client level fields too: the
+    /// ones still set to the value they have in `builder`, which the event was built from,
+    /// are overwritten. `client_fields` caches the fields of `builder` across calls.
+    fn add_trace_field(
+        &mut self,
+        builder: Option<&Builder>,
+        client_fields: &mut Option<HashMap<String, Value>>,
+        key: &str,
+        value: Value,
+    ) {
+        if self.removed_keys.iter().any(|removed| removed == key) {
+            return;
+        }
+        if let Some(ref mut ev) = self.ev {
+            let fields = ev.get_fields_mut();
+            let overwrite = match (fields.get(key), builder) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(current), Some(builder)) => {
+                    client_fields
+                        .get_or_insert_with(|| builder.new_event().fields())
+                        .get(key)
+                        == Some(current)
+                }
+            };
+            if overwrite {
+                fields.insert(key.to_string(), value);
+            }
         }
     }
 
     /// send gets all the trace level fields and does pre-send hooks, then sends the span.
-    fn final_send<T: Sender>(&mut self, client: &mut Client<T>) {
+    fn final_send<T: Sender>(&mut self, client: &mut Client<T>, deferred: bool) {
         // add all the trace level fields to the event as late as possible - when the
-        // trace is all getting sent
-        if let Some(trace_id) = &self.trace {
-            if let Some(trace) = client.get_trace(trace_id.to_string()) {
-                if let Some(fields) = trace.lock().trace_level_fields.clone().as_object() {
-                    for (k, v) in fields.into_iter() {
-                        self.add_field(k, v.clone());
-                    }
+        // trace is all getting sent - but without overwriting the fields of the span
+        let registered = self.trace.as_ref().and_then(|id| client.trace(id));
+        let closed = registered.is_none() && self.trace.is_some();
+        match registered.or_else(|| self.trace_handle.upgrade()) {
+            Some(trace) => {
+                let trace = trace.lock();
+                let mut client_fields = None;
+                for (k, v) in trace.trace_level_fields.iter() {
+                    self.add_trace_field(Some(&trace.builder), &mut client_fields, k, v.clone());
+                }
+                let sample_rate = *trace.sample_rate.lock();
+                if let Some(sample_rate) = sample_rate {
+                    self.add_field(keys::META_TRACE_SAMPLE_RATE, json!(sample_rate));
+                }
+            }
+            None => {
+                // the builder of the trace is gone along with it, so the client level
+                // fields can't be told apart from the ones of the span and are kept
+                for (k, v) in self.trace_fields_snapshot.take().unwrap_or_default() {
+                    self.add_trace_field(None, &mut None, &k, v);
                 }
             }
         }
+        if closed {
+            self.add_field(keys::META_TRACE_CLOSED, json!(true));
+        }
 
         let span_type = if self.is_root {
             if self.parent_id.is_empty() {
@@ -258,36 +952,142 @@ impl Span {
             "mid"
         };
 
-        self.add_field("meta.span_type", Value::String(span_type.to_string()));
+        self.add_field(keys::META_SPAN_TYPE, Value::String(span_type.to_string()));
         if span_type == "root" {
-            for (k, v) in self.rollup_fields.clone().lock().iter() {
+            let rollup_fields = match self.trace_handle.upgrade() {
+                Some(trace) => trace.lock().rollup_fields.clone(),
+                None => HashMap::new(),
+            };
+            for (k, v) in rollup_fields.iter() {
                 self.add_field(&format!("rollup.{}", k), json!(v))
             }
+            if rollup_fields.contains_key(keys::ERROR_COUNT) {
+                self.add_field(keys::APP_ERROR, json!(true));
+            }
         }
-        if let Some(ref mut ev) = self.ev {
-            let sampler_hook = client.0.clone().read().config.sampler_hook.clone();
-            let (should_keep, sample_rate) = sampler_hook(ev.fields());
-            ev.set_sample_rate(sample_rate);
-
-            if should_keep {
-                let presend_hook = client.0.clone().read().config.presend_hook.clone();
-                let presend_hook = &mut *presend_hook.lock();
-                presend_hook(ev.get_fields_mut());
+        if self.is_root {
+            let pending = {
+                let mut async_spans = self.async_spans.lock();
+                async_spans.root_sent_at = Some(Instant::now());
+                async_spans.pending
+            };
+            if pending > 0 {
+                self.add_field(keys::META_ASYNC_SPANS_PENDING, json!(pending));
+            }
+        }
+        if self.is_root {
+            self.check_slow(client);
+        }
+        let flamegraph = self.record_frame();
+        if deferred {
+            if let Some(ev) = self.ev.take() {
+                enqueue(client, ev);
+            }
+        } else if let Some(ref mut ev) = self.ev {
+            dispatch(client, ev);
+        }
+        if let Some(mut ev) = flamegraph {
+            if deferred {
+                enqueue(client, ev);
+            } else {
+                dispatch(client, &mut ev);
+            }
+        }
+    }
 
-                if let Err(e) = ev.send_presampled(&mut client.0.write().client) {
-                    error!("Error sending event: {}", e);
-                }
+    /// `check_slow` hands the trace to `Config::slow_trace_hook` when this root span lasted
+    /// longer than `Config::slow_trace_threshold`
+    fn check_slow<T: Sender>(&mut self, client: &Client<T>) {
+        let (threshold, hook) = {
+            let client = client.0.read();
+            match (
+                client.config.slow_trace_threshold,
+                client.config.slow_trace_hook.clone(),
+            ) {
+                (Some(threshold), Some(hook)) => (threshold, hook),
+                _ => return,
             }
+        };
+        let fields = match self.ev {
+            Some(ref mut ev) => ev.get_fields_mut(),
+            None => return,
+        };
+        let duration_ms = fields
+            .get(keys::DURATION_MS)
+            .and_then(Value::as_f64)
+            .unwrap_or_default();
+        let duration = Duration::from_secs_f64(duration_ms.max(0f64) / 1000f64);
+        if duration <= threshold {
+            return;
+        }
+        let snapshot = TraceSnapshot {
+            trace_id: self.trace.as_deref().unwrap_or_default().to_string(),
+            duration,
+            fields: fields.clone(),
+        };
+        if panic::catch_unwind(AssertUnwindSafe(|| hook(&snapshot))).is_err() {
+            client.diagnostic(
+                Level::Error,
+                format_args!("slow trace hook panicked on trace {}", snapshot.trace_id),
+            );
+        }
+    }
+
+    /// `record_frame` adds the span to the frames of its trace, with `Config::flamegraph`.
+    /// For the root span, it returns the event carrying the folded stacks of the trace.
+    fn record_frame(&mut self) -> Option<Event> {
+        let trace = self.trace_handle.upgrade()?;
+        let mut trace = trace.lock();
+        trace.frames.as_ref()?;
+        let duration_ms = self
+            .ev
+            .as_mut()?
+            .get_fields_mut()
+            .get(keys::DURATION_MS)
+            .and_then(Value::as_f64)
+            .unwrap_or_default();
+        let frame = flamegraph::Frame {
+            span_id: self.span_id.clone(),
+            parent_id: self.parent_id.clone(),
+            name: self.name(),
+            duration_ms,
+        };
+        trace.frames.as_mut()?.push(frame);
+        if !self.is_root {
+            return None;
         }
+
+        let folded = flamegraph::fold(&trace.frames.take().unwrap_or_default());
+        let mut ev = trace.builder.new_event();
+        ev.add_field(keys::META_TYPE, json!("flamegraph"));
+        ev.add_field(keys::TRACE_TRACE_ID, json!(trace.trace_id));
+        ev.add_field(keys::NAME, json!(self.name()));
+        ev.add_field(keys::DURATION_MS, json!(duration_ms));
+        ev.add_field(keys::FLAMEGRAPH_FOLDED, json!(folded));
+        Some(ev)
     }
 
     /// `create_async_child` creates a child of the current span that is expected to
     /// outlive the current span (and trace). Async spans are not automatically sent when
-    /// their parent finishes, but are otherwise identical to synchronous spans.
+    /// their parent finishes, but are otherwise identical to synchronous spans. Sent after
+    /// their trace is closed, they get the trace level fields the trace had when they were
+    /// created, and `meta.trace_closed`.
     pub fn create_async_child<T: Sender>(&mut self, client: &mut Client<T>) -> Option<SafeSpan> {
         self.create_child_span(client, true)
     }
 
+    /// `async_scope` creates an async child named `name` and returns a guard owning it.
+    /// The guard can be moved into a spawned task or another thread, and sends the span
+    /// when dropped, which happens even when the task is cancelled.
+    pub fn async_scope<T: Sender>(&mut self, client: &Client<T>, name: &str) -> SpanGuard<T> {
+        let mut client = Client(client.0.clone());
+        let span = self.create_async_child(&mut client);
+        if let Some(span) = &span {
+            span.lock().add_field(keys::NAME, json!(name));
+        }
+        SpanGuard { span, client }
+    }
+
     /// Span creates a synchronous child of the current span. Spans must finish before
     /// their parents.
     pub fn create_child<T: Sender>(&mut self, client: &mut Client<T>) -> Option<SafeSpan> {
@@ -299,15 +1099,28 @@ impl Span {
     /// put in an HTTP (or other protocol) header to transmit to downstream services so
     /// they may start a new trace that will be connected to this trace.  The serialized
     /// form may be passed to NewTrace() in order to create a new trace that will be
-    /// connected to this trace.
-    pub fn serialize_headers<T: Sender>(&self, client: &mut Client<T>) -> String {
-        match &self.trace {
-            Some(trace_id) => match client.get_trace(trace_id.to_string()) {
-                Some(trace) => trace.lock().serialize_headers(&self.span_id),
-                None => "".to_string(),
-            },
-            None => "".to_string(),
+    /// connected to this trace. See `Trace::propagation_for` for what is left out; the
+    /// settings are the ones the client had when the trace started. It returns an empty
+    /// string once the trace is gone.
+    pub fn serialize_headers(&mut self) -> String {
+        self.serialize(false)
+    }
+
+    /// `serialize_compact_headers` is like `serialize_headers`, with the header in the
+    /// compact form of `Trace::compact_propagation_for`
+    pub fn serialize_compact_headers(&mut self) -> String {
+        self.serialize(true)
+    }
+
+    fn serialize(&mut self, compact: bool) -> String {
+        let headers = match self.trace_handle.upgrade() {
+            Some(trace) => trace.lock().headers_for(&self.span_id, compact),
+            None => return "".to_string(),
+        };
+        if headers.truncated {
+            self.add_field(keys::META_PROPAGATION_TRUNCATED, json!(true));
         }
+        headers.value
     }
 
     fn create_child_span<T: Sender>(
@@ -315,81 +1128,409 @@ impl Span {
         client: &mut Client<T>,
         is_async: bool,
     ) -> Option<SafeSpan> {
-        if let Some(trace_id) = &self.trace {
-            let span_id = Uuid::new_v4().to_string();
-            let ev = if let Some(trace) = client.get_trace(trace_id.to_string()) {
-                Some(trace.lock().builder.new_event())
-            } else {
-                None
-            };
-            let new_span = Span {
-                span_id: span_id.clone(),
-                parent_id: self.span_id.clone(),
-                trace: Some(trace_id.to_string()),
-                ev,
+        if self.sampled_out {
+            // the span will never be sent, so it doesn't need an id, an event or to be
+            // tracked by its parent and trace: it shares the parent's ids
+            return Some(Arc::new(Mutex::new(Span {
+                span_id: self.span_id.clone(),
+                parent_id: self.parent_id.clone(),
+                trace: self.trace.clone(),
+                trace_handle: self.trace_handle.clone(),
+                baggage: self.baggage.clone(),
                 is_async,
+                sampled_out: true,
                 ..Default::default()
-            };
-            let span = Arc::new(Mutex::new(new_span));
-            self.children.push(span.clone());
-            if let Some(trace) = client.get_trace(trace_id.to_string()) {
-                trace
-                    .lock()
-                    .child_spans
-                    .insert(span_id, (*span).lock().clone());
-                Some(span)
-            } else {
-                None
+            })));
+        }
+
+        let trace_id = self.trace.clone()?;
+        let trace = client.trace(&trace_id);
+        let id_generator = client.0.read().config.id_generator.clone();
+        let span_id = new_id(id_generator.as_deref());
+        let new_span = Span {
+            span_id: span_id.clone(),
+            parent_id: self.span_id.clone(),
+            trace: Some(trace_id),
+            trace_handle: self.trace_handle.clone(),
+            baggage: self.baggage.clone(),
+            async_spans: self.async_spans.clone(),
+            ev: trace.as_ref().map(|trace| trace.lock().builder.new_event()),
+            trace_fields_snapshot: trace
+                .as_ref()
+                .filter(|_| is_async)
+                .map(|trace| trace.lock().trace_level_fields.clone()),
+            is_async,
+            ..Default::default()
+        };
+        if is_async {
+            self.async_spans.lock().pending += 1;
+        }
+        let span = Arc::new(Mutex::new(new_span));
+        self.children.push(span.clone());
+        trace?.lock().child_spans.insert(span_id);
+        Some(span)
+    }
+}
+
+/// `enqueue` hands `ev` over to the sender thread, or sends it right away when the thread
+/// is gone
+fn enqueue<T: Sender>(client: &mut Client<T>, ev: Event) {
+    if let Err(Job::Send(mut ev)) = async_send::enqueue(client, Job::Send(Box::new(ev))) {
+        client.diagnostic(
+            Level::Warn,
+            format_args!("sender thread is gone, sending the event synchronously"),
+        );
+        dispatch(client, &mut ev);
+    }
+}
+
+/// `dispatch` runs the sampler and presend hooks on a finished span event and, unless the
+/// sampler drops it, sends it
+pub(crate) fn dispatch<T: Sender>(client: &mut Client<T>, ev: &mut Event) {
+    let (sampler_hook, slow, always_keep, trace_rate) = {
+        let client = client.0.read();
+        let fields = ev.get_fields_mut();
+        let slow = client
+            .config
+            .slow_thresholds
+            .as_ref()
+            .is_some_and(|thresholds| thresholds.is_slow(fields));
+        let always_keep = client
+            .config
+            .always_keep
+            .iter()
+            .any(|(key, value)| fields.get(key) == Some(value));
+        let trace_rate = fields
+            .get(keys::META_TRACE_SAMPLE_RATE)
+            .and_then(Value::as_u64)
+            .filter(|rate| *rate > 0)
+            .map(|rate| rate as usize);
+        (
+            client.config.sampler_hook.clone(),
+            slow,
+            always_keep,
+            trace_rate,
+        )
+    };
+    let decision = if slow {
+        ev.add_field(keys::META_SLOW, json!(true));
+        SamplerDecision::keep(1).with_reason(sampler::REASON_SLOW)
+    } else if always_keep {
+        SamplerDecision::keep(1).with_reason(sampler::REASON_ALWAYS_KEEP)
+    } else if let Some(rate) = trace_rate {
+        let keep = match ev.get_fields_mut().get(keys::TRACE_TRACE_ID) {
+            Some(Value::String(trace_id)) => sampler::keep_trace(trace_id, rate),
+            _ => true,
+        };
+        SamplerDecision {
+            keep,
+            sample_rate: rate,
+            reason: Some(sampler::REASON_TRACE_RATE.to_string()),
+        }
+    } else {
+        let fields = ev.fields();
+        match panic::catch_unwind(AssertUnwindSafe(|| sampler_hook(fields))) {
+            Ok(decision) => decision,
+            Err(_) => {
+                client.diagnostic(
+                    Level::Error,
+                    format_args!("sampler hook panicked, dropping the event"),
+                );
+                SamplerDecision::drop(1).with_reason(sampler::REASON_HOOK_FAILURE)
             }
-        } else {
-            None
         }
+    };
+    if !decision.keep {
+        client
+            .0
+            .write()
+            .stats
+            .record_dropped(decision.reason.as_deref());
+    } else {
+        if let Some(reason) = decision.reason {
+            ev.add_field(keys::META_SAMPLE_REASON, Value::String(reason));
+        }
+        client.0.write().stats.record_kept();
+
+        let presend_hook = client.0.clone().read().config.presend_hook.clone();
+        let presend_hook = &mut *presend_hook.lock();
+        let fields = ev.get_fields_mut();
+        if panic::catch_unwind(AssertUnwindSafe(|| presend_hook(fields))).is_err() {
+            // the hook may have been halfway through scrubbing the event
+            client.diagnostic(
+                Level::Error,
+                format_args!("presend hook panicked, dropping the event"),
+            );
+            return;
+        }
+
+        // set last, so the presend hook can't leave the weight and the rate out of sync
+        ev.add_field(keys::META_SAMPLE_RATE_WEIGHT, json!(decision.sample_rate));
+        ev.set_sample_rate(decision.sample_rate);
+        client.transmit(ev);
     }
 }
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
-    use crate::tests::new_client;
-    use crate::Config;
+/// `SpanGuard` sends its span when dropped, so the span times the scope the guard lives
+/// in. A guard without a span, e.g. because the trace was already gone, does nothing.
+#[derive(Debug)]
+#[must_use = "the span is sent as soon as the guard is dropped"]
+pub struct SpanGuard<T: Sender> {
+    span: Option<SafeSpan>,
+    client: Client<T>,
+}
 
-    #[test]
-    fn test_new_span() {
-        let span = Span::new();
-        assert_eq!(span.span_id.len(), 36);
-        assert_eq!(span.get_children().len(), 0);
+impl<T: Sender> SpanGuard<T> {
+    /// `new` returns a guard sending `span` with `client`
+    pub fn new(client: &Client<T>, span: Option<SafeSpan>) -> Self {
+        Self {
+            span,
+            client: Client(client.0.clone()),
+        }
     }
 
-    #[test]
-    fn test_new_trace() {
-        let client = new_client(Config::default());
-        let cloned = Trace::new(&client, None);
-        let trace = cloned.lock();
-        assert!(!trace.trace_id.is_empty());
-        assert!(trace.parent_id.is_empty());
-        assert!(trace.rollup_fields.is_empty());
-        assert_eq!(trace.trace_level_fields, json!({}));
-        assert_eq!(trace.root_span.lock().is_root, true);
+    /// `child` creates a synchronous child of `parent` named `name` and returns a guard
+    /// sending it
+    pub fn child(client: &Client<T>, parent: &SafeSpan, name: &str) -> Self {
+        let mut client = Client(client.0.clone());
+        let span = parent.lock().create_child(&mut client);
+        if let Some(span) = &span {
+            span.lock().add_field(keys::NAME, json!(name));
+        }
+        Self { span, client }
     }
 
-    #[test]
-    fn test_new_trace_with_serialized_headers() {
-        let client = new_client(Config::default());
-        let serialized_headers = "1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=eyJlcnJvck1zZyI6ImZhaWxlZCB0byBzaWduIG9uIiwidG9SZXRyeSI6dHJ1ZSwidXNlcklEIjoxfQ==".to_string();
-        let cloned = Trace::new(&client, Some(serialized_headers));
-        let trace = cloned.lock();
+    /// `span` returns the guarded span, if any
+    pub fn span(&self) -> Option<&SafeSpan> {
+        self.span.as_ref()
+    }
+
+    /// `add_field` adds a field to the guarded span
+    pub fn add_field(&self, key: &str, value: Value) {
+        if let Some(span) = &self.span {
+            span.lock().add_field(key, value);
+        }
+    }
+
+    /// `record_error` records `error` on the guarded span, see `Span::record_error`
+    pub fn record_error(&self, error: &dyn fmt::Display) {
+        if let Some(span) = &self.span {
+            span.lock().record_error(error);
+        }
+    }
+
+    /// `set_status` sets the status of the guarded span, see `Span::set_status`
+    pub fn set_status(&self, status: SpanStatus) {
+        if let Some(span) = &self.span {
+            span.lock().set_status(status);
+        }
+    }
+
+    /// `start_timer` starts a timer on the guarded span, see `Span::start_timer`
+    pub fn start_timer(&self, name: &str) -> TimerGuard {
+        match &self.span {
+            Some(span) => span.lock().start_timer(name),
+            None => Span::default().start_timer(name),
+        }
+    }
+}
+
+impl<T: Sender> Drop for SpanGuard<T> {
+    fn drop(&mut self) {
+        if let Some(span) = self.span.take() {
+            span.lock().send(&mut self.client);
+        }
+    }
+}
+
+/// `TimerGuard` times a phase of the work of a span until dropped, see `Span::start_timer`
+#[derive(Debug)]
+#[must_use = "the timer stops as soon as the guard is dropped"]
+pub struct TimerGuard {
+    name: String,
+    timer: timer::Timer,
+    timers: Arc<Mutex<HashMap<String, f64>>>,
+}
+
+impl Drop for TimerGuard {
+    fn drop(&mut self) {
+        let elapsed = self.timer.finish();
+        *self
+            .timers
+            .lock()
+            .entry(std::mem::take(&mut self.name))
+            .or_insert(0f64) += elapsed;
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::tests::new_client;
+    use crate::{BeelineError, Config, PropagationErrorKind};
+
+    #[test]
+    fn test_new_span() {
+        let span = Span::new(None);
+        assert_eq!(span.span_id.len(), 36);
+        assert_eq!(span.get_children().len(), 0);
+    }
+
+    #[test]
+    fn test_new_trace() {
+        let client = new_client(Config::default());
+        let cloned = Trace::new(&client, None);
+        let trace = cloned.lock();
+        assert!(!trace.trace_id.is_empty());
+        assert!(trace.parent_id.is_empty());
+        assert!(trace.rollup_fields.is_empty());
+        assert!(trace.trace_level_fields.is_empty());
+        assert_eq!(trace.root_span.lock().is_root, true);
+    }
+
+    #[test]
+    fn test_id_generator() {
+        let ids = || {
+            let mut client = new_client(Config {
+                id_generator: Some(Arc::new(crate::test::sequential_ids(7))),
+                ..Config::default()
+            });
+            let trace = Trace::new(&client, None);
+            let root_span = trace.lock().get_root_span();
+            let child = root_span.lock().create_child(&mut client).unwrap();
+            let trace_id = trace.lock().trace_id.clone();
+            let root_span_id = root_span.lock().span_id.to_string();
+            let child_span_id = child.lock().span_id.to_string();
+            (trace_id, root_span_id, child_span_id)
+        };
+        let (trace_id, root_span_id, child_span_id) = ids();
+        assert_eq!(trace_id, "00000007-0000-4000-8000-000000000001");
+        assert_eq!(root_span_id, "00000007-0000-4000-8000-000000000002");
+        assert_eq!(child_span_id, "00000007-0000-4000-8000-000000000003");
+        assert_eq!(ids(), (trace_id, root_span_id, child_span_id));
+    }
+
+    #[test]
+    fn test_new_trace_with_serialized_headers() {
+        let client = new_client(Config::default());
+        let serialized_headers = "1;trace_id=weofijwoeifj,parent_id=owefjoweifj,context=eyJlcnJvck1zZyI6ImZhaWxlZCB0byBzaWduIG9uIiwidG9SZXRyeSI6dHJ1ZSwidXNlcklEIjoxfQ==".to_string();
+        let cloned = Trace::new(&client, Some(serialized_headers));
+        let trace = cloned.lock();
 
         assert_eq!(trace.trace_id, "weofijwoeifj");
         assert_eq!(trace.parent_id, "owefjoweifj");
 
-        match trace.trace_level_fields.as_object() {
-            Some(tlf) => {
-                assert_eq!(tlf["userID"], json!(1));
-                assert_eq!(tlf["toRetry"], json!(true));
-                assert_eq!(tlf["errorMsg"], json!("failed to sign on"));
+        let tlf = trace.trace_level_fields();
+        assert_eq!(tlf["userID"], json!(1));
+        assert_eq!(tlf["toRetry"], json!(true));
+        assert_eq!(tlf["errorMsg"], json!("failed to sign on"));
+    }
+
+    #[test]
+    fn test_propagated_dataset() {
+        let mut client = new_client(Config::default());
+        for dataset in &["team/checkout", "données", "日本", ""] {
+            let headers = format!(
+                "1;trace_id=abc,parent_id=def,dataset={},context=e30=",
+                dataset
+            );
+            client.new_trace(Some(headers)).send(&mut client);
+        }
+
+        // libhoney doesn't expose the options of an event, so look at its debug output
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        assert!(format!("{:?}", events[0]).contains("dataset: \"team-checkout\""));
+        assert!(format!("{:?}", events[1]).contains("dataset: \"donn-es\""));
+        assert!(format!("{:?}", events[2]).contains("dataset: \"beeline-rust\""));
+        assert!(format!("{:?}", events[3]).contains("dataset: \"beeline-rust\""));
+    }
+
+    #[test]
+    fn test_propagation_version() {
+        let mut config = Config::default();
+        config.propagation_parsers.insert(
+            "9".to_string(),
+            Arc::new(|payload| {
+                Propagation::unmarshal_trace_context(&format!("1;{}", payload)).ok()
+            }),
+        );
+        let mut client = new_client(config);
+        client
+            .new_trace(Some("1;trace_id=a,context=e30=".to_string()))
+            .send(&mut client);
+        client
+            .new_trace(Some("2;trace_id=b,context=e30=,sampled=1".to_string()))
+            .send(&mut client);
+        client
+            .new_trace(Some("9;trace_id=c,context=e30=".to_string()))
+            .send(&mut client);
+        client
+            .new_trace(Some("9;context=!!".to_string()))
+            .send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        let versions: Vec<_> = events
+            .iter()
+            .map(|event| event.fields().get(keys::META_PROPAGATION_VERSION).cloned())
+            .collect();
+        assert_eq!(
+            versions,
+            vec![Some(json!("1")), Some(json!("2")), Some(json!("9")), None]
+        );
+        assert_eq!(events[2].fields()["trace.trace_id"], json!("c"));
+    }
+
+    #[test]
+    fn test_try_new_trace() {
+        let client = new_client(Config::default());
+        let trace = client
+            .try_new_trace(Some(
+                "1;trace_id=abc,parent_id=def,context=e30=".to_string(),
+            ))
+            .unwrap();
+        assert_eq!(trace.lock().trace_id, "abc");
+        assert!(client.get_trace("abc".to_string()).is_some());
+
+        let err = client
+            .try_new_trace(Some("1;parent_id=def,context=e30=".to_string()))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BeelineError::PropagationError {
+                kind: PropagationErrorKind::MissingTraceId,
+                ..
             }
-            None => panic!("expected fields from serialized headers"),
-        };
+        ));
+        assert_eq!(client.0.read().traces.lock().len(), 1);
+    }
+
+    #[test]
+    fn test_trace_baggage() {
+        let mut client = new_client(Config::default());
+        let trace = client.new_trace(None);
+        trace.lock().set_baggage("tenant", "acme");
+        let root_span = trace.lock().get_root_span();
+        let child = root_span.lock().create_child(&mut client).unwrap();
+        assert_eq!(child.lock().baggage()["tenant"], "acme");
+
+        // baggage is propagated downstream...
+        let headers = child.lock().serialize_headers();
+        let downstream = client.new_trace(Some(headers));
+        let downstream_root = downstream.lock().get_root_span();
+        assert_eq!(downstream_root.lock().baggage()["tenant"], "acme");
+        downstream_root.lock().add_baggage_field("tenant");
+        downstream.send(&mut client);
+
+        // ...but only added to the spans that ask for it
+        trace.send(&mut client);
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].fields()["tenant"], json!("acme"));
+        assert!(events[1..]
+            .iter()
+            .all(|event| !event.fields().contains_key("tenant")));
     }
 
     #[test]
@@ -397,12 +1538,8 @@ pub mod tests {
         let client = new_client(Config::default());
         let cloned = Trace::new(&client, None);
         let mut trace = cloned.lock();
-        assert!(trace.trace_level_fields.is_object());
         trace.add_field("nor", json!({"a": 1}));
-        match trace.trace_level_fields.as_object() {
-            Some(tlf) => assert_eq!(tlf["nor"], json!({"a": 1})),
-            None => panic!("expected field"),
-        };
+        assert_eq!(trace.trace_level_fields()["nor"], json!({"a": 1}));
     }
 
     #[test]
@@ -475,10 +1612,8 @@ pub mod tests {
 
     #[test]
     fn test_send_trace_sampler_hook() {
-        let config = crate::Config {
-            sampler_hook: Arc::new(|_| (false, 1)),
-            ..Default::default()
-        };
+        // hooks returning a `(bool, usize)` still work through `with_sampler_hook`
+        let config = crate::Config::default().with_sampler_hook(|_| (false, 1));
 
         let mut client = new_client(config);
 
@@ -493,4 +1628,644 @@ pub mod tests {
         // This ends up being true because we set the sampler_hook to drop the event
         assert!(events.is_empty())
     }
+
+    #[test]
+    fn test_slow_spans_bypass_sampling() {
+        let mut client = new_client(crate::Config {
+            sampler_hook: Arc::new(|_| (false, 1).into()),
+            slow_thresholds: Some(
+                sampler::SlowThresholds::new().name("slow", Duration::from_millis(10)),
+            ),
+            ..Default::default()
+        });
+
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        drop(SpanGuard::child(&client, &root_span, "fast"));
+        {
+            let _slow = SpanGuard::child(&client, &root_span, "slow");
+            std::thread::sleep(Duration::from_millis(15));
+        }
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        let fields = events[0].fields();
+        assert_eq!(fields[keys::NAME], json!("slow"));
+        assert_eq!(fields[keys::META_SLOW], json!(true));
+        assert_eq!(
+            fields[keys::META_SAMPLE_REASON],
+            json!(sampler::REASON_SLOW)
+        );
+        assert_eq!(fields[keys::META_SAMPLE_RATE_WEIGHT], json!(1));
+    }
+
+    #[test]
+    fn test_always_keep() {
+        let mut client = new_client(crate::Config {
+            sampler_hook: Arc::new(|_| (false, 1).into()),
+            always_keep: vec![("user.tier".to_string(), json!("enterprise"))],
+            ..Default::default()
+        });
+
+        for tier in &["free", "enterprise"] {
+            let trace = client.new_trace(None);
+            trace.lock().add_field("user.tier", json!(tier));
+            let root_span = trace.lock().get_root_span();
+            drop(SpanGuard::child(&client, &root_span, "child"));
+            trace.send(&mut client);
+        }
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        for event in events {
+            let fields = event.fields();
+            assert_eq!(fields["user.tier"], json!("enterprise"));
+            assert_eq!(
+                fields[keys::META_SAMPLE_REASON],
+                json!(sampler::REASON_ALWAYS_KEEP)
+            );
+        }
+    }
+
+    #[test]
+    fn test_propagate_sample_rate() {
+        let upstream = new_client(crate::Config {
+            sampler_hook: Arc::new(|fields| {
+                let rate = if fields.get("tenant") == Some(&json!("noisy")) {
+                    4
+                } else {
+                    1
+                };
+                SamplerDecision::keep(rate)
+            }),
+            propagate_sample_rate: true,
+            ..Default::default()
+        });
+        let trace = upstream.new_trace(None);
+        trace.lock().add_field("tenant", json!("noisy"));
+        let root_span = trace.lock().get_root_span();
+        let headers = root_span.lock().serialize_headers();
+        assert!(headers.ends_with(",sample_rate=4"));
+        // the rate is chosen once for the trace
+        trace.lock().add_field("tenant", json!("quiet"));
+        let headers = root_span.lock().serialize_headers();
+        assert!(headers.ends_with(",sample_rate=4"));
+
+        // downstream, the spans are sampled on their trace id at the upstream rate
+        let mut downstream = new_client(crate::Config {
+            sampler_hook: Arc::new(|_| (false, 1).into()),
+            propagate_sample_rate: true,
+            ..Default::default()
+        });
+        let (kept, dropped): (Vec<_>, Vec<_>) = (0..20)
+            .map(|i| i.to_string())
+            .partition(|id| sampler::keep_trace(id, 4));
+        for trace_id in &[&kept[0], &dropped[0]] {
+            let headers = format!("1;trace_id={},context=e30=,sample_rate=4", trace_id);
+            downstream.new_trace(Some(headers)).send(&mut downstream);
+        }
+        let events = downstream.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        let fields = events[0].fields();
+        assert_eq!(fields[keys::TRACE_TRACE_ID], json!(kept[0]));
+        assert_eq!(fields[keys::META_SAMPLE_RATE_WEIGHT], json!(4));
+        assert_eq!(
+            fields[keys::META_SAMPLE_REASON],
+            json!(sampler::REASON_TRACE_RATE)
+        );
+    }
+
+    #[test]
+    fn test_send_trace_sample_reason() {
+        let config = crate::Config {
+            sampler_hook: Arc::new(|fields| {
+                if fields.contains_key("error") {
+                    SamplerDecision::keep(1).with_rule("errors")
+                } else {
+                    SamplerDecision::drop(10).with_reason(sampler::REASON_RATE_LIMIT)
+                }
+            }),
+            ..Default::default()
+        };
+        let mut client = new_client(config);
+
+        for error in &[true, false, false] {
+            let trace = client.new_trace(None);
+            {
+                let rs = trace.lock().get_root_span();
+                let mut rs_guard = rs.lock();
+                if *error {
+                    rs_guard.add_field("error", json!("boom"));
+                }
+            }
+            trace.send(&mut client);
+        }
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].fields()["meta.sample.reason"],
+            json!("rule:errors")
+        );
+
+        let stats = client.stats();
+        assert_eq!(stats.sampled_in, 1);
+        assert_eq!(stats.sampled_out[sampler::REASON_RATE_LIMIT], 2);
+    }
+
+    #[test]
+    fn test_send_trace_sample_rate_weight() {
+        let config = crate::Config {
+            sampler_hook: Arc::new(|_| SamplerDecision::keep(20)),
+            presend_hook: Arc::new(Mutex::new(|fields: &mut HashMap<String, Value>| {
+                fields.insert(keys::META_SAMPLE_RATE_WEIGHT.to_string(), json!(1));
+            })),
+            ..Default::default()
+        };
+        let mut client = new_client(config);
+        client.new_trace(None).send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events[0].fields()[keys::META_SAMPLE_RATE_WEIGHT], json!(20));
+    }
+
+    #[test]
+    fn test_send_trace_panicking_hooks() {
+        let config = crate::Config {
+            sampler_hook: Arc::new(|fields| {
+                if fields.contains_key("bad_sampler") {
+                    panic!("sampler failure");
+                }
+                SamplerDecision::keep(1)
+            }),
+            presend_hook: Arc::new(Mutex::new(|fields: &mut HashMap<String, Value>| {
+                if fields.contains_key("bad_presend") {
+                    panic!("presend failure");
+                }
+            })),
+            ..Default::default()
+        };
+        let mut client = new_client(config);
+
+        for field in &["bad_sampler", "bad_presend", "fine"] {
+            let trace = client.new_trace(None);
+            trace
+                .lock()
+                .get_root_span()
+                .lock()
+                .add_field(field, json!(true));
+            trace.send(&mut client);
+        }
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields()["fine"], json!(true));
+        assert_eq!(client.stats().sampled_out[sampler::REASON_HOOK_FAILURE], 1);
+    }
+
+    #[test]
+    fn test_head_sampler_hook() {
+        let config = crate::Config {
+            head_sampler_hook: Some(Arc::new(|trace_id| trace_id.starts_with("keep"))),
+            ..Default::default()
+        };
+        let mut client = new_client(config);
+
+        let dropped = client.new_trace(None);
+        assert!(dropped.lock().is_sampled_out());
+        let root_span = dropped.lock().get_root_span();
+        let child = root_span.lock().create_child(&mut client).unwrap();
+        let grandchild = child.lock().create_async_child(&mut client).unwrap();
+        assert!(grandchild.lock().is_sampled_out());
+        grandchild.lock().add_field("name", json!("grandchild"));
+        assert!(root_span.lock().get_children().is_empty());
+        assert!(dropped.lock().child_spans.is_empty());
+
+        // downstream services are still handed the trace
+        let headers = child.lock().serialize_headers();
+        let kept = client.new_trace(Some(headers.replacen("trace_id=", "trace_id=keep", 1)));
+        assert!(!kept.lock().is_sampled_out());
+
+        grandchild.lock().send(&mut client);
+        dropped.send(&mut client);
+        kept.send(&mut client);
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(client.stats().sampled_out[sampler::REASON_HEAD], 1);
+    }
+
+    #[test]
+    fn test_set_api_key() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        root_span.lock().add_field("tenant", json!("acme"));
+        Trace::set_api_key(&trace, "acme-api-key");
+        let child = root_span.lock().create_child(&mut client).unwrap();
+        let headers = child.lock().serialize_headers();
+        assert!(!headers.contains("acme-api-key"));
+        trace.send(&mut client);
+        client.new_trace(None).send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        // libhoney doesn't expose the options of an event, so look at its debug output
+        assert!(format!("{:?}", events[0]).contains("\"acme-api-key\""));
+        assert!(format!("{:?}", events[1]).contains("\"acme-api-key\""));
+        assert_eq!(events[1].fields()["tenant"], json!("acme"));
+        assert!(!format!("{:?}", events[2]).contains("acme-api-key"));
+    }
+
+    #[test]
+    fn test_propagation_filter() {
+        let config = crate::Config {
+            propagation_filter: Some(Arc::new(|name, _| {
+                !crate::sanitize::is_sensitive_field(name)
+            })),
+            ..Default::default()
+        };
+        let client = new_client(config);
+        let trace = client.new_trace(None);
+        trace.lock().add_field("app.user_id", json!(42));
+        trace.lock().add_field("app.session_token", json!("s3cr3t"));
+        let root_span = trace.lock().get_root_span();
+        let headers = root_span.lock().serialize_headers();
+
+        let downstream = client.new_trace(Some(headers));
+        let fields = downstream.lock().trace_level_fields().clone();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields["app.user_id"], json!(42));
+        // the upstream trace keeps it
+        assert_eq!(trace.lock().trace_level_fields().len(), 2);
+    }
+
+    #[test]
+    fn test_propagation_for() {
+        let client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        trace.lock().add_field("app.tenant", json!("acme"));
+        let headers = trace.lock().propagation_for("job-42");
+        assert_eq!(headers.name, "X-Honeycomb-Trace");
+        assert!(!headers.truncated);
+
+        let text = headers.value;
+
+        let compact = trace.lock().compact_propagation_for("job-42");
+        assert!(compact.value.len() < text.len());
+        for header in [text, compact.value] {
+            let downstream = client.new_trace(Some(header));
+            let downstream = downstream.lock();
+            assert_eq!(downstream.trace_id, trace.lock().trace_id);
+            assert_eq!(downstream.parent_id, "job-42");
+            assert_eq!(downstream.trace_level_fields()["app.tenant"], json!("acme"));
+        }
+    }
+
+    #[test]
+    fn test_max_propagation_header_size() {
+        let config = crate::Config {
+            max_propagation_header_size: Some(256),
+            ..Default::default()
+        };
+        let mut client = new_client(config);
+        let downstream_client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        trace.lock().add_field("app.small", json!(1));
+        let root_span = trace.lock().get_root_span();
+        let headers = root_span.lock().serialize_headers();
+        let downstream = downstream_client.new_trace(Some(headers));
+        assert_eq!(downstream.lock().trace_level_fields().len(), 1);
+
+        trace.lock().add_field("app.large", json!("x".repeat(512)));
+        let headers = root_span.lock().serialize_headers();
+        assert!(headers.len() <= 256);
+        let downstream = downstream_client.new_trace(Some(headers));
+        assert_eq!(downstream.lock().trace_id, trace.lock().trace_id);
+        assert!(downstream.lock().trace_level_fields().is_empty());
+
+        trace.send(&mut client);
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(
+            events[0].fields()[keys::META_PROPAGATION_TRUNCATED],
+            json!(true)
+        );
+    }
+
+    #[test]
+    fn test_add_field_local() {
+        let mut client = new_client(crate::Config::default());
+        let downstream_client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        trace.lock().add_field("app.tenant", json!("acme"));
+        trace.lock().add_field_local("app.user_id", json!(42));
+        trace.lock().add_field_local("app.region", json!("eu"));
+        trace.lock().add_field("app.region", json!("us"));
+        let root_span = trace.lock().get_root_span();
+        let headers = root_span.lock().serialize_headers();
+
+        let downstream = downstream_client.new_trace(Some(headers));
+        let mut propagated = downstream
+            .lock()
+            .trace_level_fields()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        propagated.sort();
+        assert_eq!(propagated, vec!["app.region", "app.tenant"]);
+
+        trace.send(&mut client);
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events[0].fields()["app.user_id"], json!(42));
+        assert_eq!(events[0].fields()["app.region"], json!("us"));
+    }
+
+    #[test]
+    fn test_field_precedence() {
+        let mut client = new_client(crate::Config::default());
+        client.add_field("app.team", json!("client"));
+        client.add_field("app.region", json!("client"));
+        client.add_field("app.env", json!("client"));
+        let trace = client.new_trace(None);
+        trace.lock().add_field("app.route", json!("trace"));
+        trace.lock().add_field("app.env", json!("trace"));
+        trace.lock().add_field("app.region", json!("trace"));
+        trace.lock().add_field("duration_ms", json!(-1));
+        let root_span = trace.lock().get_root_span();
+        {
+            let mut span = root_span.lock();
+            assert!(!span.add_field_if_absent("app.team", json!("span")));
+            assert!(span.add_field_if_absent("app.user", json!("span")));
+            assert!(!span.add_field_if_absent("app.user", json!("hook")));
+            assert_eq!(span.remove_field("app.region"), Some(json!("client")));
+            assert_eq!(span.remove_field("app.region"), None);
+            span.add_field("app.route", json!("span"));
+        }
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert_eq!(fields["app.team"], json!("client"));
+        assert_eq!(fields["app.user"], json!("span"));
+        // removed fields aren't added back by the trace level ones
+        assert!(!fields.contains_key("app.region"));
+        // span fields win over trace level fields, which win over client level ones
+        assert_eq!(fields["app.route"], json!("span"));
+        assert_eq!(fields["app.env"], json!("trace"));
+        assert_ne!(fields["duration_ms"], json!(-1));
+    }
+
+    #[test]
+    fn test_start_timer() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        for _ in 0..2 {
+            let _timer = root_span.lock().start_timer("phase.db");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        drop(root_span.lock().start_timer("phase.render"));
+        let running = root_span.lock().start_timer("phase.late");
+        trace.send(&mut client);
+        drop(running);
+
+        let events = client.0.write().client.transmission.events();
+        let fields = events[0].fields();
+        assert!(fields["timers.phase.db_ms"].as_f64().unwrap() >= 10f64);
+        assert!(fields.contains_key("timers.phase.render_ms"));
+        assert!(!fields.contains_key("timers.phase.late_ms"));
+    }
+
+    #[test]
+    fn test_flamegraph() {
+        let mut client = new_client(crate::Config {
+            flamegraph: true,
+            ..Default::default()
+        });
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        root_span.lock().add_field(keys::NAME, json!("GET /users"));
+        {
+            let load = SpanGuard::child(&client, &root_span, "load");
+            let _db = SpanGuard::child(&client, load.span().unwrap(), "db");
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        let fields = events[3].fields();
+        assert_eq!(fields[keys::META_TYPE], json!("flamegraph"));
+        assert_eq!(fields[keys::NAME], json!("GET /users"));
+        assert_eq!(
+            fields[keys::TRACE_TRACE_ID],
+            events[2].fields()[keys::TRACE_TRACE_ID]
+        );
+        let folded = fields[keys::FLAMEGRAPH_FOLDED].as_str().unwrap();
+        assert!(folded
+            .lines()
+            .any(|line| line.starts_with("GET /users;load;db ")));
+
+        // without the setting, no event is added
+        let mut client = new_client(crate::Config::default());
+        client.new_trace(None).send(&mut client);
+        assert_eq!(client.0.write().client.transmission.events().len(), 1);
+    }
+
+    #[test]
+    fn test_slow_trace_hook() {
+        let slow = Arc::new(Mutex::new(Vec::new()));
+        let recorded = slow.clone();
+        let mut client = new_client(crate::Config {
+            slow_trace_threshold: Some(Duration::from_millis(20)),
+            slow_trace_hook: Some(Arc::new(move |snapshot: &TraceSnapshot| {
+                recorded.lock().push(snapshot.clone())
+            })),
+            ..Default::default()
+        });
+
+        let fast = client.new_trace(None);
+        fast.send(&mut client);
+        let trace = client.new_trace(None);
+        trace.lock().add_field("app.tenant", json!("acme"));
+        std::thread::sleep(Duration::from_millis(25));
+        trace.send(&mut client);
+
+        let slow = slow.lock();
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].trace_id, trace.lock().trace_id);
+        assert!(slow[0].duration > Duration::from_millis(20));
+        assert_eq!(slow[0].fields["app.tenant"], json!("acme"));
+        assert_eq!(client.0.write().client.transmission.events().len(), 2);
+    }
+
+    #[test]
+    fn test_span_guard() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        {
+            let guard = SpanGuard::child(&client, &root_span, "validate_input");
+            guard.add_field("valid", json!(true));
+            assert_eq!(client.0.write().client.transmission.events().len(), 0);
+        }
+        assert_eq!(client.0.write().client.transmission.events().len(), 1);
+        drop(SpanGuard::new(&client, None));
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].fields()["name"], json!("validate_input"));
+        assert_eq!(events[0].fields()["valid"], json!(true));
+        assert!(events[0].fields().contains_key(keys::DURATION_MS));
+        assert_eq!(
+            events[0].fields()["trace.parent_id"],
+            events[1].fields()["trace.span_id"]
+        );
+    }
+
+    #[test]
+    fn test_error_rollup() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        {
+            let guard = SpanGuard::child(&client, &root_span, "fetch_user");
+            guard.record_error(&"connection refused");
+            // the same span failing twice counts once
+            guard.set_status(SpanStatus::Error);
+        }
+        {
+            let guard = SpanGuard::child(&client, &root_span, "fetch_cart");
+            guard.set_status(SpanStatus::Error);
+        }
+        SpanGuard::child(&client, &root_span, "render").set_status(SpanStatus::Ok);
+        trace.send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].fields()[keys::ERROR], json!("connection refused"));
+        assert_eq!(events[0].fields()[keys::SPAN_STATUS], json!("error"));
+        assert_eq!(events[2].fields()[keys::SPAN_STATUS], json!("ok"));
+        assert!(!events[2].fields().contains_key(keys::APP_ERROR));
+        let root = events[3].fields();
+        assert_eq!(root["rollup.error_count"], json!(2f64));
+        assert_eq!(root[keys::APP_ERROR], json!(true));
+
+        let trace = client.new_trace(None);
+        trace.send(&mut client);
+        let events = client.0.write().client.transmission.events();
+        assert!(!events[4].fields().contains_key(keys::APP_ERROR));
+    }
+
+    #[test]
+    fn test_span_count_rollup() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        for _ in 0..3 {
+            let guard = SpanGuard::child(&client, &root_span, "query");
+            guard.add_field(keys::META_TYPE, json!("db"));
+        }
+        let background = root_span.lock().create_async_child(&mut client).unwrap();
+        background.lock().send(&mut client);
+        let late = root_span.lock().create_async_child(&mut client).unwrap();
+        root_span.lock().create_child(&mut client).unwrap();
+        trace.send(&mut client);
+        late.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        let root = events
+            .iter()
+            .map(|event| event.fields())
+            .find(|fields| fields[keys::META_SPAN_TYPE] == json!("root"))
+            .unwrap();
+        assert_eq!(root["rollup.spans.total"], json!(5f64));
+        assert_eq!(root["rollup.spans.db"], json!(3f64));
+        assert_eq!(root["rollup.spans.async"], json!(1f64));
+    }
+
+    #[test]
+    fn test_async_spans_sent_after_root() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        let early = root_span.lock().create_async_child(&mut client).unwrap();
+        let late = root_span.lock().create_async_child(&mut client).unwrap();
+        let leaked = root_span.lock().create_async_child(&mut client).unwrap();
+        early.lock().send(&mut client);
+        trace.send(&mut client);
+        late.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 3);
+        assert!(!events[0].fields().contains_key(keys::META_SENT_AFTER_ROOT));
+        assert_eq!(events[1].fields()[keys::META_ASYNC_SPANS_PENDING], json!(2));
+        assert_eq!(events[2].fields()[keys::META_SENT_AFTER_ROOT], json!(true));
+        assert!(events[2].fields()[keys::META_SENT_AFTER_ROOT_MS].is_f64());
+        assert!(!leaked.lock().is_sent);
+    }
+
+    #[test]
+    fn test_span_sent_after_trace_closed() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        trace.lock().add_field("tenant", json!("acme"));
+        let root_span = trace.lock().get_root_span();
+        let late = root_span.lock().create_async_child(&mut client).unwrap();
+        trace.send(&mut client);
+        client.flush().unwrap();
+        drop(trace);
+        drop(root_span);
+        late.lock().send(&mut client);
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert!(!events[0].fields().contains_key(keys::META_TRACE_CLOSED));
+        assert_eq!(events[1].fields()["tenant"], json!("acme"));
+        assert_eq!(events[1].fields()[keys::META_TRACE_CLOSED], json!(true));
+    }
+
+    #[test]
+    fn test_unsent_child_policy() {
+        for policy in &[UnsentChildPolicy::Discard, UnsentChildPolicy::LogOnly] {
+            let mut client = new_client(crate::Config {
+                unsent_child_policy: *policy,
+                ..Default::default()
+            });
+            let trace = client.new_trace(None);
+            let root_span = trace.lock().get_root_span();
+            let sent = root_span.lock().create_child(&mut client).unwrap();
+            sent.lock().send(&mut client);
+            let forgotten = root_span.lock().create_child(&mut client).unwrap();
+            forgotten.lock().create_child(&mut client).unwrap();
+            trace.send(&mut client);
+            forgotten.lock().send(&mut client);
+
+            let events = client.0.write().client.transmission.events();
+            assert_eq!(events.len(), 2);
+            assert!(events
+                .iter()
+                .all(|event| !event.fields().contains_key(keys::META_SENT_BY_PARENT)));
+            assert!(trace.lock().child_spans.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_async_scope() {
+        let mut client = new_client(crate::Config::default());
+        let trace = client.new_trace(None);
+        let root_span = trace.lock().get_root_span();
+        let guard = root_span.lock().async_scope(&client, "background_job");
+        trace.send(&mut client);
+        std::thread::spawn(move || guard.add_field("items", json!(3)))
+            .join()
+            .unwrap();
+
+        let events = client.0.write().client.transmission.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].fields()["name"], json!("background_job"));
+        assert_eq!(events[1].fields()["items"], json!(3));
+        assert_eq!(events[1].fields()[keys::META_SPAN_TYPE], json!("async"));
+    }
 }
@@ -0,0 +1,165 @@
+/*! W3C `tracestate` handling.
+
+`tracestate` carries vendor specific propagation state as a list of `key=value` members.
+A tracer must forward the members it doesn't own untouched, and move its own member to the
+front of the list when it updates it. `TraceState` implements those rules so beeline
+services don't destroy other vendors' state in mixed environments; our own member uses the
+`hny` key.
+
+*/
+use std::fmt;
+
+/// key of the beeline member
+pub const VENDOR_KEY: &str = "hny";
+/// maximum number of members a `tracestate` may hold
+pub const MAX_MEMBERS: usize = 32;
+const MAX_VALUE_LEN: usize = 256;
+
+/// `TraceState` is an ordered list of `tracestate` members, most recently updated first
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TraceState {
+    members: Vec<(String, String)>,
+}
+
+impl TraceState {
+    /// `parse` reads a `tracestate` header. Invalid members are dropped, as are duplicate
+    /// keys and members beyond the maximum.
+    pub fn parse(header: &str) -> Self {
+        let mut state = Self::default();
+        for member in header.split(',') {
+            let member = member.trim();
+            let mut kv = member.splitn(2, '=');
+            if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+                if is_valid_key(key)
+                    && is_valid_value(value)
+                    && state.get(key).is_none()
+                    && state.members.len() < MAX_MEMBERS
+                {
+                    state.members.push((key.to_string(), value.to_string()));
+                }
+            }
+        }
+        state
+    }
+
+    /// `get` returns the value of the member `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.members
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// `set` adds or updates the member `key`, moving it to the front of the list. Invalid
+    /// keys or values are ignored; if the list is full, the last member is evicted.
+    pub fn set(&mut self, key: &str, value: &str) {
+        if !is_valid_key(key) || !is_valid_value(value) {
+            return;
+        }
+        self.remove(key);
+        self.members.insert(0, (key.to_string(), value.to_string()));
+        self.members.truncate(MAX_MEMBERS);
+    }
+
+    /// `remove` removes the member `key`
+    pub fn remove(&mut self, key: &str) {
+        self.members.retain(|(k, _)| k != key);
+    }
+
+    /// `vendor_value` returns the value of the beeline member
+    pub fn vendor_value(&self) -> Option<&str> {
+        self.get(VENDOR_KEY)
+    }
+
+    /// `set_vendor_value` updates the beeline member
+    pub fn set_vendor_value(&mut self, value: &str) {
+        self.set(VENDOR_KEY, value)
+    }
+
+    /// `is_empty` is true when there are no members, in which case the header should not
+    /// be sent
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+/// `TraceState` formats as the value of a `tracestate` header
+impl fmt::Display for TraceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let members: Vec<String> = self
+            .members
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        write!(f, "{}", members.join(","))
+    }
+}
+
+/// `is_valid_key` accepts both simple (`vendor`) and multi-tenant (`tenant@vendor`) keys
+fn is_valid_key(key: &str) -> bool {
+    let valid_part = |part: &str, max_len: usize| {
+        !part.is_empty()
+            && part.len() <= max_len
+            && part
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-*/".contains(c))
+    };
+    match key.split_once('@') {
+        Some((tenant, vendor)) => {
+            valid_part(tenant, 241)
+                && tenant.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && valid_part(vendor, 14)
+                && vendor.starts_with(|c: char| c.is_ascii_lowercase())
+        }
+        None => valid_part(key, 256) && key.starts_with(|c: char| c.is_ascii_lowercase()),
+    }
+}
+
+fn is_valid_value(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_VALUE_LEN
+        && !value.ends_with(' ')
+        && value
+            .chars()
+            .all(|c| (' '..='~').contains(&c) && c != ',' && c != '=')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let state =
+            TraceState::parse("congo=t61rcWkgMzE, rojo=00f067aa0ba902b7,BAD=1,x=,t@vendor=v");
+        assert_eq!(state.get("congo"), Some("t61rcWkgMzE"));
+        assert_eq!(state.get("t@vendor"), Some("v"));
+        assert_eq!(state.get("BAD"), None);
+        assert_eq!(
+            state.to_string(),
+            "congo=t61rcWkgMzE,rojo=00f067aa0ba902b7,t@vendor=v"
+        );
+    }
+
+    #[test]
+    fn test_vendor_value() {
+        let mut state = TraceState::parse("congo=t61rcWkgMzE,hny=old,rojo=1");
+        assert_eq!(state.vendor_value(), Some("old"));
+        state.set_vendor_value("new");
+        // our member moves to the front, the others are forwarded untouched
+        assert_eq!(state.to_string(), "hny=new,congo=t61rcWkgMzE,rojo=1");
+        state.set_vendor_value("not,valid");
+        assert_eq!(state.vendor_value(), Some("new"));
+    }
+
+    #[test]
+    fn test_max_members() {
+        let header: Vec<String> = (0..40).map(|i| format!("k{}=v", i)).collect();
+        let mut state = TraceState::parse(&header.join(","));
+        assert_eq!(state.members.len(), MAX_MEMBERS);
+        state.set_vendor_value("1");
+        assert_eq!(state.members.len(), MAX_MEMBERS);
+        assert_eq!(state.get("k31"), None);
+        assert_eq!(state.get("k0"), Some("v"));
+    }
+}